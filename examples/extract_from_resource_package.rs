@@ -60,7 +60,7 @@ fn main() {
         std::process::exit(0)
     });
 
-    let resource_info = rpkg.resources().get(&rrid).unwrap_or_else(|| {
+    let resource_info = rpkg.resources().ok().and_then(|resources| resources.get(&rrid)).unwrap_or_else(|| {
         println!("Failed to get resource info.");
         std::process::exit(0)
     });
@@ -85,7 +85,7 @@ fn main() {
         println!("    Reference type: {:?}", flags.reference_type());
     }
 
-    match std::str::from_utf8(&*file) {
+    match std::str::from_utf8(&file) {
         Ok(s) => {
             println!("{}...", s.chars().take(100).collect::<String>())
         }
@@ -45,7 +45,7 @@ fn main() {
     });
 
     println!("Resource extracted!");
-    match std::str::from_utf8(&*file) {
+    match std::str::from_utf8(&file) {
         Ok(s) => {
             println!("{}...", s.chars().take(100).collect::<String>())
         }
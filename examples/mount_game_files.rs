@@ -6,6 +6,7 @@ use std::{env, io};
 
 use rpkg_rs::misc::resource_id::ResourceID;
 use rpkg_rs::resource::partition_manager::{PartitionManager, PartitionState};
+use rpkg_rs::resource::pdefs::game_detection::GameDetection;
 use rpkg_rs::resource::pdefs::{GamePaths, PackageDefinitionSource};
 use rpkg_rs::resource::resource_info::ResourceInfo;
 use rpkg_rs::resource::resource_partition::PatchId;
@@ -15,21 +16,37 @@ use rpkg_rs::WoaVersion;
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 3 {
-        eprintln!("Usage: cargo run --example <example_name> -- <path to a retail directory> <game version (H2016 | HM2 | HM3)>");
+    if args.len() < 2 {
+        eprintln!("Usage: cargo run --example <example_name> -- <path to a retail directory> [game version (HM2016 | HM2 | HM3 | Bond)]");
         return;
     }
 
     let retail_path = PathBuf::from(&args[1]);
 
-    let game_version = match args[2].as_str() {
-        "HM2016" => WoaVersion::HM2016,
-        "HM2" => WoaVersion::HM2,
-        "HM3" => WoaVersion::HM3,
-        e => {
+    let game_version = match args.get(2).map(String::as_str) {
+        Some("HM2016") => WoaVersion::HM2016,
+        Some("HM2") => WoaVersion::HM2,
+        Some("HM3") => WoaVersion::HM3,
+        Some("Bond") => WoaVersion::Bond,
+        Some(e) => {
             eprintln!("invalid game version: {}", e);
             std::process::exit(0);
         }
+        None => {
+            let detection =
+                GameDetection::from_retail_directory(&retail_path).unwrap_or_else(|e| {
+                    eprintln!("failed to detect the game version: {}", e);
+                    std::process::exit(0);
+                });
+            println!(
+                "Detected {:?} with {:?} confidence:",
+                detection.version, detection.confidence
+            );
+            for evidence in &detection.evidence {
+                println!("\t- {}", evidence);
+            }
+            detection.version
+        }
     };
 
     // Discover the game paths.
@@ -108,9 +125,7 @@ fn main() {
 
         let mut input_string = String::new();
         stdin()
-            .read_line(&mut input_string)
-            .ok()
-            .expect("Failed to read line");
+            .read_line(&mut input_string).expect("Failed to read line");
 
         let rid = ResourceID::from_str(input_string.as_str()).unwrap_or_else(|_| {
             println!("Given ResourceID is invalid");
@@ -131,7 +146,7 @@ fn main() {
             let occurrences = changes
                 .clone()
                 .into_iter()
-                .chain(deletions.clone().into_iter())
+                .chain(deletions.clone())
                 .collect::<Vec<PatchId>>();
 
             for occurence in occurrences.iter().sorted() {
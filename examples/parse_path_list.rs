@@ -29,9 +29,7 @@ fn main() {
 
         let mut input_string = String::new();
         stdin()
-            .read_line(&mut input_string)
-            .ok()
-            .expect("Failed to read line");
+            .read_line(&mut input_string).expect("Failed to read line");
 
         if let Ok(rrid) = RuntimeResourceID::from_hex_string(input_string.as_str().trim_end()) {
             println!("{:?}", path_list.get(&rrid));
@@ -10,7 +10,7 @@ fn main() {
     let args: Vec<String> = env::args().collect();
 
     if args.len() < 4 {
-        eprintln!("Usage: cargo run --example <example_name> -- <path to a retail directory> <game version (H2016 | HM2 | HM3)> <output path>");
+        eprintln!("Usage: cargo run --example <example_name> -- <path to a retail directory> <game version (HM2016 | HM2 | HM3 | Bond)> <output path>");
         return;
     }
 
@@ -20,6 +20,7 @@ fn main() {
         "HM2016" => WoaVersion::HM2016,
         "HM2" => WoaVersion::HM2,
         "HM3" => WoaVersion::HM3,
+        "Bond" => WoaVersion::Bond,
         e => {
             eprintln!("invalid game version: {}", e);
             std::process::exit(0);
@@ -52,7 +53,7 @@ fn main() {
             let output_name = partition.partition_info().filename(*patch_id);
             println!("Rebuilding package '{}'", output_name);
 
-            let mut builder = PackageBuilder::from_resource_package(&package).unwrap_or_else(|e| {
+            let builder = PackageBuilder::from_resource_package_lossless(package).unwrap_or_else(|e| {
                 eprintln!(
                     "failed to create package builder for package '{}': {}",
                     output_name, e
@@ -60,12 +61,6 @@ fn main() {
                 std::process::exit(0);
             });
 
-            builder.with_patch_id(patch_id);
-
-            if package.has_legacy_references() {
-                builder.use_legacy_references();
-            }
-
             builder
                 .build_to_file(package.version(), output_path.join(&output_name).as_path())
                 .unwrap_or_else(|e| {
@@ -74,9 +69,9 @@ fn main() {
                 });
 
             // After it's built, check if the generated file is the same as the original.
-            let original_file = match &package.source() {
-                Some(ResourcePackageSource::File(path)) => path,
-                _ => panic!(
+            let original_file = match package.source().and_then(ResourcePackageSource::path) {
+                Some(path) => path,
+                None => panic!(
                     "Package '{}' of game '{:?}' has no source",
                     output_name, game_version
                 ),
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpkg_rs::resource::pdefs::PackageDefinitionSource;
+
+// Runs every packagedefinition parser over the same input, encrypted inputs exercise the XTEA path as well.
+fuzz_target!(|data: &[u8]| {
+    let _ = PackageDefinitionSource::HM2016(data.to_vec()).read();
+    let _ = PackageDefinitionSource::HM2(data.to_vec()).read();
+    let _ = PackageDefinitionSource::HM3(data.to_vec()).read();
+    let _ = PackageDefinitionSource::Bond(data.to_vec()).read();
+});
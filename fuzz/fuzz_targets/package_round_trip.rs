@@ -0,0 +1,150 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::pdefs::{PartitionId, PartitionType};
+use rpkg_rs::resource::resource_package::{
+    PackageVersion, ReferenceType, ResourcePackage, ResourceReferenceFlags, ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+
+#[derive(Debug, Arbitrary)]
+struct Reference {
+    rrid: u64,
+    reference_type: u8,
+    runtime_acquired: bool,
+    language_code: u8,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Resource {
+    rrid: u64,
+    data_type: [u8; 4],
+    data: Vec<u8>,
+    compression_level: Option<u8>,
+    should_scramble: bool,
+    memory_requirements: (u32, u32),
+    references: Vec<Reference>,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Package {
+    is_v2: bool,
+    patch: Option<u8>,
+    legacy_references: bool,
+    resources: Vec<Resource>,
+    unneeded_resources: Vec<u64>,
+}
+
+fn to_rrid(id: u64) -> RuntimeResourceID {
+    RuntimeResourceID::from(id & 0x00FF_FFFF_FFFF_FFFE)
+}
+
+fn flags(reference: &Reference) -> ResourceReferenceFlags {
+    let reference_type = match reference.reference_type % 3 {
+        0 => ReferenceType::INSTALL,
+        1 => ReferenceType::NORMAL,
+        _ => ReferenceType::WEAK,
+    };
+    ResourceReferenceFlags::Standard(
+        ResourceReferenceFlagsStandard::new()
+            .with_reference_type(reference_type)
+            .with_runtime_acquired(reference.runtime_acquired)
+            .with_language_code(reference.language_code & 0x1F),
+    )
+}
+
+// Building a package and parsing it again must give back exactly what was put in,
+// and rebuilding the parsed package must give back the same resources.
+fuzz_target!(|input: Package| {
+    let version = match input.is_v2 {
+        true => PackageVersion::RPKGv2,
+        false => PackageVersion::RPKGv1,
+    };
+    let patch_id = match input.patch {
+        Some(patch) => PatchId::Patch(patch.max(1) as usize),
+        None => PatchId::Base,
+    };
+    let partition_id = PartitionId {
+        part_type: PartitionType::Standard,
+        index: 0,
+    };
+
+    let mut builder = PackageBuilder::new_with_patch_id(partition_id, patch_id);
+    if input.legacy_references {
+        builder.use_legacy_references();
+    }
+
+    let mut resources = vec![];
+    for resource in &input.resources {
+        let rrid = to_rrid(resource.rrid);
+        if resources.iter().any(|(id, _)| *id == rrid) {
+            continue;
+        }
+
+        let data_type = resource.data_type.iter().map(|c| (b'A' + c % 26) as char).collect::<String>();
+        let Ok(mut package_resource) = PackageResourceBuilder::from_memory(
+            rrid,
+            &data_type,
+            resource.data.clone(),
+            resource.compression_level.map(|level| (level % 12) as i32 + 1),
+            resource.should_scramble,
+        ) else {
+            return;
+        };
+        package_resource.with_memory_requirements(resource.memory_requirements.0, resource.memory_requirements.1);
+        for reference in &resource.references {
+            package_resource.with_reference(to_rrid(reference.rrid), flags(reference));
+        }
+        builder.with_resource(package_resource);
+        resources.push((rrid, resource));
+    }
+
+    let unneeded_resources = match patch_id {
+        PatchId::Patch(_) => input.unneeded_resources.iter().map(|id| to_rrid(*id)).collect::<Vec<_>>(),
+        PatchId::Base => vec![],
+    };
+    builder.with_unneeded_resources(unneeded_resources.iter().copied());
+
+    // Too many resources or references are rejected by the builder, that is not a failure.
+    let Ok(data) = builder.build_to_vec(version) else {
+        return;
+    };
+    let package =
+        ResourcePackage::from_memory(data, patch_id.is_patch()).expect("a built package should parse");
+
+    assert_eq!(package.verify().expect("the package has a source"), vec![]);
+    let parsed_resources = package.resources().expect("the metadata was decoded while parsing");
+    assert_eq!(parsed_resources.len(), resources.len());
+    let mut expected_unneeded = vec![];
+    for rrid in unneeded_resources {
+        if !expected_unneeded.contains(&rrid) {
+            expected_unneeded.push(rrid);
+        }
+    }
+    assert_eq!(package.unneeded_resource_ids().into_iter().copied().collect::<Vec<_>>(), expected_unneeded);
+
+    for ((rrid, resource), (parsed_rrid, info)) in resources.iter().zip(parsed_resources) {
+        assert_eq!(rrid, parsed_rrid);
+        assert_eq!(info.size(), resource.data.len() as u32);
+        assert_eq!(info.system_memory_requirement(), resource.memory_requirements.0);
+        assert_eq!(info.video_memory_requirement(), resource.memory_requirements.1);
+        assert_eq!(package.read_resource(rrid).expect("resource should be readable"), resource.data);
+
+        let references = info.references().iter().map(|(rrid, flags)| (*rrid, flags.to_legacy()));
+        let expected = resource.references.iter().map(|reference| (to_rrid(reference.rrid), flags(reference).to_legacy()));
+        assert!(references.eq(expected));
+    }
+
+    let mut rebuilder = PackageBuilder::from_resource_package(&package).expect("the package has a source");
+    rebuilder.with_patch_id(&patch_id);
+    if package.has_legacy_references().expect("the metadata was decoded while parsing") {
+        rebuilder.use_legacy_references();
+    }
+    let rebuilt = rebuilder.build_to_vec(version).expect("rebuilding a package should succeed");
+    let rebuilt = ResourcePackage::from_memory(rebuilt, patch_id.is_patch()).expect("a rebuilt package should parse");
+    let rebuilt_resources = rebuilt.resources().expect("the metadata was decoded while parsing");
+    assert!(rebuilt_resources.values().eq(parsed_resources.values()));
+});
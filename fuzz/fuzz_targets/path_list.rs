@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpkg_rs::misc::hash_path_list::PathList;
+
+fuzz_target!(|data: &str| {
+    let mut path_list = PathList::new();
+    let _ = path_list.parse_from_str(data);
+});
@@ -0,0 +1,43 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpkg_rs::resource::package_builder::PackageBuilder;
+use rpkg_rs::resource::resource_package::ResourcePackage;
+use std::io::Read;
+
+// Parses arbitrary bytes as a base and as a patch package, then touches everything derived from the parsed tables.
+fuzz_target!(|data: &[u8]| {
+    for is_patch in [false, true] {
+        let Ok(package) = ResourcePackage::from_memory(data.to_vec(), is_patch) else {
+            continue;
+        };
+
+        let _ = package.version();
+        let _ = package.verify();
+        for (rrid, resource) in package.resources().into_iter().flatten() {
+            let _ = resource.to_string();
+            let _ = package.read_resource(rrid);
+            if let Ok(mut reader) = package.resource_reader(rrid) {
+                let _ = reader.read_to_end(&mut vec![]);
+            }
+        }
+
+        if let Ok(builder) = PackageBuilder::from_resource_package(&package) {
+            let _ = builder.build_to_vec(package.version());
+        }
+    }
+
+    // A lazily parsed package must agree with the eagerly parsed one on every resource it can decode.
+    for is_patch in [false, true] {
+        let Ok(package) = ResourcePackage::from_memory_lazy(data.to_vec(), is_patch) else {
+            continue;
+        };
+        let rrids = package.resource_ids().copied().collect::<Vec<_>>();
+        let single = rrids.iter().map(|rrid| package.resource_info(rrid).map(|info| info.into_owned())).collect::<Vec<_>>();
+        if let Ok(resources) = package.load_metadata() {
+            for (rrid, info) in rrids.iter().zip(single) {
+                assert_eq!(info.as_ref().ok(), resources.get(rrid));
+            }
+        }
+    }
+});
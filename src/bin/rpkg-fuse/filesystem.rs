@@ -0,0 +1,246 @@
+//! The inode table of the mounted filesystem, built from resource views.
+//!
+//! The root holds an `all` directory with the resources the game would load, and a directory per partition with
+//! the resources of that partition alone.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rpkg_rs::resource::resource_reader::ResourceReader;
+use rpkg_rs::resource::resource_vfs::{ResourceVfs, VfsFileType, VfsStat};
+
+pub const ROOT_INO: u64 = 1;
+
+/// The attributes of a file or directory, as reported to the kernel.
+pub struct Attributes {
+    pub ino: u64,
+    pub size: u64,
+    pub is_directory: bool,
+    pub nlink: u32,
+    pub time: u64,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+enum NodeKind {
+    Directory(BTreeMap<String, u64>),
+    File { view: usize, path: String, size: u64 },
+}
+
+struct Node {
+    parent: u64,
+    kind: NodeKind,
+}
+
+pub struct ResourceFilesystem<'a> {
+    views: Vec<ResourceVfs<'a>>,
+    nodes: Vec<Node>,
+    handles: HashMap<u64, ResourceReader<'a>>,
+    next_handle: u64,
+    time: u64,
+    uid: u32,
+    gid: u32,
+}
+
+impl<'a> ResourceFilesystem<'a> {
+    /// Lays out every view as a directory below the root.
+    ///
+    /// # Arguments
+    /// - `views` - The name of the top level directory and the view shown inside it.
+    pub fn new(views: Vec<(String, ResourceVfs<'a>)>) -> Self {
+        let mut filesystem = Self {
+            views: vec![],
+            nodes: vec![Node {
+                parent: ROOT_INO,
+                kind: NodeKind::Directory(BTreeMap::new()),
+            }],
+            handles: HashMap::new(),
+            next_handle: 1,
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        };
+
+        for (name, view) in views {
+            let ino = filesystem.add_node(ROOT_INO, name, NodeKind::Directory(BTreeMap::new()));
+            filesystem.add_directory(&view, filesystem.views.len(), ino, "");
+            filesystem.views.push(view);
+        }
+        filesystem
+    }
+
+    fn add_node(&mut self, parent: u64, name: String, kind: NodeKind) -> u64 {
+        self.nodes.push(Node { parent, kind });
+        let ino = self.nodes.len() as u64;
+        if let NodeKind::Directory(children) = &mut self.nodes[parent as usize - 1].kind {
+            children.insert(name, ino);
+        }
+        ino
+    }
+
+    fn add_directory(&mut self, view: &ResourceVfs, view_index: usize, ino: u64, path: &str) {
+        for entry in view.read_dir(path).unwrap_or_default() {
+            let child_path = match path {
+                "" => entry.name.clone(),
+                _ => format!("{path}/{}", entry.name),
+            };
+            match entry.file_type {
+                VfsFileType::Directory => {
+                    let child = self.add_node(ino, entry.name, NodeKind::Directory(BTreeMap::new()));
+                    self.add_directory(view, view_index, child, &child_path);
+                }
+                VfsFileType::File => {
+                    let size = view.stat(&child_path).map_or(0, |stat: VfsStat| stat.size());
+                    let kind = NodeKind::File {
+                        view: view_index,
+                        path: child_path,
+                        size,
+                    };
+                    self.add_node(ino, entry.name, kind);
+                }
+            }
+        }
+    }
+
+    fn node(&self, ino: u64) -> Result<&Node, i32> {
+        ino.checked_sub(1)
+            .and_then(|index| self.nodes.get(index as usize))
+            .ok_or(libc::ENOENT)
+    }
+
+    pub fn attributes(&self, ino: u64) -> Result<Attributes, i32> {
+        let (size, is_directory, nlink) = match &self.node(ino)?.kind {
+            NodeKind::Directory(children) => {
+                let subdirectories = children
+                    .values()
+                    .filter(|child| matches!(self.nodes[**child as usize - 1].kind, NodeKind::Directory(_)))
+                    .count();
+                (0, true, 2 + subdirectories as u32)
+            }
+            NodeKind::File { size, .. } => (*size, false, 1),
+        };
+        Ok(Attributes {
+            ino,
+            size,
+            is_directory,
+            nlink,
+            time: self.time,
+            uid: self.uid,
+            gid: self.gid,
+        })
+    }
+
+    pub fn lookup(&self, parent: u64, name: &[u8]) -> Result<Attributes, i32> {
+        let NodeKind::Directory(children) = &self.node(parent)?.kind else {
+            return Err(libc::ENOTDIR);
+        };
+        let name = std::str::from_utf8(name).map_err(|_| libc::ENOENT)?;
+        let ino = *children.get(name).ok_or(libc::ENOENT)?;
+        self.attributes(ino)
+    }
+
+    pub fn open(&mut self, ino: u64, flags: i32) -> Result<u64, i32> {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EROFS);
+        }
+        let NodeKind::File { view, path, .. } = &self.node(ino)?.kind else {
+            return Err(libc::EISDIR);
+        };
+        let reader = self.views[*view].open(path).map_err(|_| libc::EIO)?;
+
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(handle, reader);
+        Ok(handle)
+    }
+
+    pub fn read(&mut self, handle: u64, offset: u64, size: usize) -> Result<Vec<u8>, i32> {
+        let reader = self.handles.get_mut(&handle).ok_or(libc::EBADF)?;
+        // Reads at or past the end of the file read nothing.
+        if offset >= reader.size() {
+            return Ok(vec![]);
+        }
+        reader.seek(SeekFrom::Start(offset)).map_err(|_| libc::EIO)?;
+
+        let mut data = Vec::with_capacity(size);
+        reader.take(size as u64).read_to_end(&mut data).map_err(|_| libc::EIO)?;
+        Ok(data)
+    }
+
+    pub fn release(&mut self, handle: u64) {
+        self.handles.remove(&handle);
+    }
+
+    pub fn open_dir(&self, ino: u64) -> Result<(), i32> {
+        match self.node(ino)?.kind {
+            NodeKind::Directory(_) => Ok(()),
+            NodeKind::File { .. } => Err(libc::ENOTDIR),
+        }
+    }
+
+    /// Lists a directory, including the `.` and `..` entries.
+    pub fn read_dir(&self, ino: u64) -> Result<Vec<(u64, String, bool)>, i32> {
+        let node = self.node(ino)?;
+        let NodeKind::Directory(children) = &node.kind else {
+            return Err(libc::ENOTDIR);
+        };
+
+        let mut entries = vec![(ino, ".".to_string(), true), (node.parent, "..".to_string(), true)];
+        for (name, child) in children {
+            let is_directory = matches!(self.nodes[*child as usize - 1].kind, NodeKind::Directory(_));
+            entries.push((*child, name.clone(), is_directory));
+        }
+        Ok(entries)
+    }
+
+    /// Returns the amount of inodes and the total size of the files in 512 byte blocks.
+    pub fn statistics(&self) -> (u64, u64) {
+        let size = self
+            .nodes
+            .iter()
+            .map(|node| match node.kind {
+                NodeKind::File { size, .. } => size.div_ceil(512),
+                NodeKind::Directory(_) => 0,
+            })
+            .sum();
+        (self.nodes.len() as u64, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+    use rpkg_rs::resource::partition_manager::PartitionManager;
+    use rpkg_rs::resource::pdefs::{PackageDefinitionSource, PartitionInfo};
+    use rpkg_rs::resource::resource_package::{ChunkType, PackageVersion};
+    use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+
+    #[test]
+    fn test_read_past_the_end() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let rrid = RuntimeResourceID::from(1);
+        let mut builder = PackageBuilder::new(0, ChunkType::Standard);
+        builder.with_resource(PackageResourceBuilder::from_memory(rrid, "TEMP", vec![1; 16], None, false)?);
+        builder.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+        let mut partition_manager = PartitionManager::new(
+            temp_dir.path().to_path_buf(),
+            &PackageDefinitionSource::Custom(vec![PartitionInfo::from_id("chunk0")?]),
+        )?;
+        partition_manager.mount_partitions(|_, _| {})?;
+        let view = ResourceVfs::new(&partition_manager, |_| None)?;
+        let mut filesystem = ResourceFilesystem::new(vec![("all".to_string(), view)]);
+
+        let mut ino = ROOT_INO;
+        for name in ["all", "unknown", "TEMP", &rrid.to_string()] {
+            ino = filesystem.lookup(ino, name.as_bytes()).map_err(|e| format!("lookup failed: {e}"))?.ino;
+        }
+        let handle = filesystem.open(ino, libc::O_RDONLY).map_err(|e| format!("open failed: {e}"))?;
+        assert_eq!(filesystem.read(handle, 8, 16), Ok(vec![1; 8]));
+        assert_eq!(filesystem.read(handle, 16, 16), Ok(vec![]));
+        assert_eq!(filesystem.read(handle, 1000, 16), Ok(vec![]));
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+//! Mounts the resources of a game as a read-only FUSE filesystem.
+//!
+//! ```sh
+//! rpkg-fuse <retail directory> <mount point> [--game-version HM3] [--path-list hash_list.txt]
+//! ```
+//!
+//! The `all` directory shows the resources the game would load, every partition gets a directory with its own
+//! resources. Resources are laid out by their resolved path, or as `unknown/<TYPE>/<hash>` without a path list.
+//! Stop the process or run `fusermount3 -u <mount point>` to unmount.
+
+mod filesystem;
+mod protocol;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use rpkg_rs::misc::hash_path_list::PathList;
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::resource_vfs::ResourceVfs;
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use rpkg_rs::WoaVersion;
+
+use crate::filesystem::ResourceFilesystem;
+use crate::protocol::FuseMount;
+
+#[derive(Parser)]
+#[command(version, about = "Mounts the resources of a Glacier game as a read-only filesystem")]
+struct Arguments {
+    /// The retail directory of the game.
+    retail_directory: PathBuf,
+
+    /// An empty directory to mount the resources at.
+    mount_point: PathBuf,
+
+    /// The game version (HM2016, HM2, HM3 or Bond), detected from the retail directory when omitted.
+    #[arg(long)]
+    game_version: Option<WoaVersion>,
+
+    /// A path list used to lay resources out by their resource path.
+    #[arg(long)]
+    path_list: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    match run(Arguments::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(arguments: Arguments) -> Result<(), Box<dyn std::error::Error>> {
+    let partition_manager = match arguments.game_version {
+        Some(version) => PartitionManager::from_game(arguments.retail_directory, version, true)?,
+        None => PartitionManager::from_game_auto(arguments.retail_directory, true)?,
+    };
+
+    let mut path_list = PathList::new();
+    if let Some(path) = &arguments.path_list {
+        path_list.parse_into(path)?;
+    }
+    let resolve = |rrid: &RuntimeResourceID| path_list.get(rrid).map(|rid| rid.uri().to_string());
+
+    let mut views = vec![("all".to_string(), ResourceVfs::new(&partition_manager, resolve)?)];
+    for partition in &partition_manager.partitions {
+        let partition_id = &partition.partition_info().id;
+        views.push((partition_id.to_string(), ResourceVfs::for_partition(&partition_manager, partition_id, resolve)?));
+    }
+    let mut filesystem = ResourceFilesystem::new(views);
+
+    let mount = FuseMount::mount(&arguments.mount_point, "rpkg-rs")?;
+    eprintln!("Mounted at {}", arguments.mount_point.display());
+    mount.serve(&mut filesystem)?;
+    Ok(())
+}
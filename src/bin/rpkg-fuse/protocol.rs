@@ -0,0 +1,425 @@
+//! A minimal implementation of the FUSE kernel protocol.
+//!
+//! Only the requests a read-only filesystem needs are handled, everything else is answered with `ENOSYS`. The
+//! filesystem is mounted directly when running as root, otherwise through the setuid `fusermount3` helper.
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::filesystem::{Attributes, ResourceFilesystem};
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+const FOPEN_KEEP_CACHE: u32 = 1 << 1;
+
+const IN_HEADER_SIZE: usize = 40;
+const MAX_WRITE: u32 = 0x20000;
+/// The kernel requires room for the largest write request plus its headers.
+const BUFFER_SIZE: usize = MAX_WRITE as usize + 0x1000;
+/// How long the kernel may cache lookups and attributes, the view never changes while mounted.
+const CACHE_SECONDS: u64 = 60;
+
+/// Set by the signal handler to stop serving requests.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_stop(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// A mounted FUSE filesystem, unmounted when dropped.
+pub struct FuseMount {
+    device: File,
+    mount_point: PathBuf,
+    with_fusermount: bool,
+}
+
+impl FuseMount {
+    /// Mounts an empty, read-only FUSE filesystem at the mount point.
+    pub fn mount(mount_point: &Path, fs_name: &str) -> io::Result<Self> {
+        let mount_point = mount_point.canonicalize()?;
+        match Self::mount_directly(&mount_point, fs_name) {
+            Ok(mount) => Ok(mount),
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => Self::mount_with_fusermount(&mount_point, fs_name),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn mount_directly(mount_point: &Path, fs_name: &str) -> io::Result<Self> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC)
+            .open("/dev/fuse")?;
+
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let options = format!("fd={},rootmode=40000,user_id={uid},group_id={gid},default_permissions", device.as_raw_fd());
+        let source = c_string(fs_name.as_bytes())?;
+        let target = c_string(mount_point.as_os_str().as_bytes())?;
+        let fs_type = c_string(b"fuse.rpkg-rs")?;
+        let options = c_string(options.as_bytes())?;
+
+        let flags = libc::MS_NOSUID | libc::MS_NODEV | libc::MS_RDONLY;
+        let result = unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                fs_type.as_ptr(),
+                flags,
+                options.as_ptr() as *const libc::c_void,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            device,
+            mount_point: mount_point.to_path_buf(),
+            with_fusermount: false,
+        })
+    }
+
+    fn mount_with_fusermount(mount_point: &Path, fs_name: &str) -> io::Result<Self> {
+        let mut sockets = [0; 2];
+        if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, sockets.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (ours, theirs) = unsafe { (OwnedFd::from_raw_fd(sockets[0]), OwnedFd::from_raw_fd(sockets[1])) };
+
+        let status = Command::new("fusermount3")
+            .arg("-o")
+            .arg(format!("ro,nosuid,nodev,default_permissions,fsname={fs_name},subtype=rpkg-rs"))
+            .arg("--")
+            .arg(mount_point)
+            .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+            .status()?;
+        drop(theirs);
+        if !status.success() {
+            return Err(io::Error::other(format!("fusermount3 failed with {status}")));
+        }
+
+        Ok(Self {
+            device: File::from(receive_fd(ours.as_raw_fd())?),
+            mount_point: mount_point.to_path_buf(),
+            with_fusermount: true,
+        })
+    }
+
+    /// Answers requests until the filesystem is unmounted or the process receives SIGINT or SIGTERM.
+    pub fn serve(&self, filesystem: &mut ResourceFilesystem) -> io::Result<()> {
+        install_signal_handlers()?;
+
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        while !STOP.load(Ordering::SeqCst) {
+            let read = unsafe {
+                libc::read(self.device.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+            };
+            if read < 0 {
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    // The request was interrupted before we read it, or a signal arrived.
+                    Some(libc::ENOENT) | Some(libc::EINTR) | Some(libc::EAGAIN) => continue,
+                    // The filesystem was unmounted.
+                    Some(libc::ENODEV) => return Ok(()),
+                    _ => return Err(error),
+                }
+            }
+
+            let request = &buffer[..read as usize];
+            if request.len() < IN_HEADER_SIZE {
+                continue;
+            }
+            if let Some(reply) = handle_request(filesystem, request) {
+                self.write(&reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, reply: &[u8]) -> io::Result<()> {
+        let written =
+            unsafe { libc::write(self.device.as_raw_fd(), reply.as_ptr() as *const libc::c_void, reply.len()) };
+        if written < 0 {
+            let error = io::Error::last_os_error();
+            // The request was interrupted and the kernel no longer waits for the answer.
+            if error.raw_os_error() == Some(libc::ENOENT) {
+                return Ok(());
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FuseMount {
+    fn drop(&mut self) {
+        if self.with_fusermount {
+            let _ = Command::new("fusermount3").arg("-u").arg("-z").arg("--").arg(&self.mount_point).status();
+        } else if let Ok(target) = c_string(self.mount_point.as_os_str().as_bytes()) {
+            unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) };
+        }
+    }
+}
+
+fn c_string(bytes: &[u8]) -> io::Result<CString> {
+    CString::new(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Receives the file descriptor of /dev/fuse which fusermount3 passes over the socket.
+fn receive_fd(socket: RawFd) -> io::Result<OwnedFd> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: byte.len(),
+    };
+    let mut control = [0u64; 8];
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    if unsafe { libc::recvmsg(socket, &mut message, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+        if header.is_null() || (*header).cmsg_level != libc::SOL_SOCKET || (*header).cmsg_type != libc::SCM_RIGHTS {
+            return Err(io::Error::other("fusermount3 did not pass a file descriptor"));
+        }
+        let fd = std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const libc::c_int);
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+fn install_signal_handlers() -> io::Result<()> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // Without SA_RESTART, so the blocking read returns and the stop flag is checked.
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = request_stop as *const () as libc::sighandler_t;
+        if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// A request read from the kernel.
+struct Request<'r> {
+    opcode: u32,
+    unique: u64,
+    node_id: u64,
+    body: &'r [u8],
+}
+
+impl<'r> Request<'r> {
+    fn parse(data: &'r [u8]) -> Self {
+        let length = (u32_at(data, 0) as usize).min(data.len());
+        Self {
+            opcode: u32_at(data, 4),
+            unique: u64_at(data, 8),
+            node_id: u64_at(data, 16),
+            body: &data[IN_HEADER_SIZE.min(length)..length],
+        }
+    }
+
+    /// The nul terminated name at the start of the body.
+    fn name(&self) -> &'r [u8] {
+        self.body.split(|c| *c == 0).next().unwrap_or_default()
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    data.get(offset..offset + 4).map_or(0, |bytes| u32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    data.get(offset..offset + 8).map_or(0, |bytes| u64::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+/// A reply to a request, starting with the out header whose length is filled in by [Reply::finish].
+struct Reply(Vec<u8>);
+
+impl Reply {
+    fn new(unique: u64) -> Self {
+        let mut reply = Self(Vec::with_capacity(128));
+        reply.u32(0).u32(0).u64(unique);
+        reply
+    }
+
+    fn error(unique: u64, errno: i32) -> Vec<u8> {
+        let mut reply = Self::new(unique);
+        reply.0[4..8].copy_from_slice(&(-errno).to_ne_bytes());
+        reply.finish()
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.0.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn u64(&mut self, value: u64) -> &mut Self {
+        self.0.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn zeros(&mut self, count: usize) -> &mut Self {
+        self.0.resize(self.0.len() + count, 0);
+        self
+    }
+
+    /// Appends a `fuse_attr`.
+    fn attributes(&mut self, attributes: &Attributes) -> &mut Self {
+        let mode = match attributes.is_directory {
+            true => libc::S_IFDIR | 0o555,
+            false => libc::S_IFREG | 0o444,
+        };
+        self.u64(attributes.ino)
+            .u64(attributes.size)
+            .u64(attributes.size.div_ceil(512))
+            .u64(attributes.time)
+            .u64(attributes.time)
+            .u64(attributes.time)
+            .u32(0)
+            .u32(0)
+            .u32(0)
+            .u32(mode)
+            .u32(attributes.nlink)
+            .u32(attributes.uid)
+            .u32(attributes.gid)
+            .u32(0)
+            .u32(4096)
+            .u32(0)
+    }
+
+    /// Appends a `fuse_entry_out`.
+    fn entry(&mut self, attributes: &Attributes) -> &mut Self {
+        self.u64(attributes.ino).u64(0).u64(CACHE_SECONDS).u64(CACHE_SECONDS).u32(0).u32(0).attributes(attributes)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let length = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&length.to_ne_bytes());
+        self.0
+    }
+}
+
+/// Answers a single request, returns None for requests which don't expect an answer.
+fn handle_request(filesystem: &mut ResourceFilesystem, data: &[u8]) -> Option<Vec<u8>> {
+    let request = Request::parse(data);
+    let unique = request.unique;
+    let mut reply = Reply::new(unique);
+
+    let result = match request.opcode {
+        FUSE_INIT => {
+            let major = u32_at(request.body, 0);
+            let minor = u32_at(request.body, 4);
+            let max_readahead = u32_at(request.body, 8);
+            if major < FUSE_KERNEL_VERSION {
+                return Some(Reply::error(unique, libc::EPROTO));
+            }
+
+            // Newer kernels wait for a reply with our version before sending the actual init request.
+            reply.u32(FUSE_KERNEL_VERSION).u32(FUSE_KERNEL_MINOR_VERSION.min(minor));
+            if major > FUSE_KERNEL_VERSION {
+                return Some(reply.finish());
+            }
+            reply.u32(max_readahead).u32(0).u16(0).u16(0).u32(MAX_WRITE);
+            if minor >= 23 {
+                reply.u32(1).u16(0).u16(0).u32(0).zeros(4 * 7);
+            }
+            Ok(())
+        }
+        FUSE_DESTROY | FUSE_FLUSH => Ok(()),
+        FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+        FUSE_LOOKUP => filesystem
+            .lookup(request.node_id, request.name())
+            .map(|attributes| {
+                reply.entry(&attributes);
+            }),
+        FUSE_GETATTR => filesystem.attributes(request.node_id).map(|attributes| {
+            reply.u64(CACHE_SECONDS).u32(0).u32(0).attributes(&attributes);
+        }),
+        FUSE_OPEN => filesystem.open(request.node_id, u32_at(request.body, 0) as i32).map(|handle| {
+            reply.u64(handle).u32(FOPEN_KEEP_CACHE).u32(0);
+        }),
+        FUSE_READ => {
+            let handle = u64_at(request.body, 0);
+            let offset = u64_at(request.body, 8);
+            let size = u32_at(request.body, 16).min(MAX_WRITE);
+            filesystem.read(handle, offset, size as usize).map(|data| {
+                reply.bytes(&data);
+            })
+        }
+        FUSE_RELEASE => {
+            filesystem.release(u64_at(request.body, 0));
+            Ok(())
+        }
+        FUSE_OPENDIR => filesystem.open_dir(request.node_id).map(|_| {
+            reply.u64(0).u32(0).u32(0);
+        }),
+        FUSE_READDIR => {
+            let offset = u64_at(request.body, 8) as usize;
+            let size = u32_at(request.body, 16) as usize;
+            filesystem.read_dir(request.node_id).map(|entries| {
+                for (index, (ino, name, is_directory)) in entries.iter().enumerate().skip(offset) {
+                    // A `fuse_dirent` padded to 8 bytes.
+                    let entry_size = (24 + name.len()).next_multiple_of(8);
+                    if reply.0.len() - 16 + entry_size > size {
+                        break;
+                    }
+                    let file_type = if *is_directory { libc::DT_DIR } else { libc::DT_REG };
+                    reply.u64(*ino).u64(index as u64 + 1).u32(name.len() as u32).u32(file_type as u32);
+                    reply.bytes(name.as_bytes()).zeros(entry_size - 24 - name.len());
+                }
+            })
+        }
+        FUSE_RELEASEDIR => Ok(()),
+        FUSE_STATFS => {
+            let (files, blocks) = filesystem.statistics();
+            reply.u64(blocks).u64(0).u64(0).u64(files).u64(0).u32(512).u32(255).u32(512).u32(0).zeros(4 * 6);
+            Ok(())
+        }
+        _ => Err(libc::ENOSYS),
+    };
+
+    Some(match result {
+        Ok(()) => reply.finish(),
+        Err(errno) => Reply::error(unique, errno),
+    })
+}
@@ -0,0 +1,437 @@
+//! The implementation of every command. Each command builds a report, which is printed as text or as JSON.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+use rpkg_rs::misc::hash_path_list::PathList;
+use rpkg_rs::resource::dependency_graph::{DependencyGraph, ReferenceFilter};
+use rpkg_rs::resource::package_builder::PackageBuilder;
+use rpkg_rs::resource::package_manifest::PackageManifest;
+use rpkg_rs::resource::pdefs::PartitionId;
+use rpkg_rs::resource::resource_diff::{DiffOptions, GameDiff, ResourceChange, ResourceDiff, ResourceModification};
+use rpkg_rs::resource::resource_package::{PackageVersion, ResourcePackage};
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use rpkg_rs::WoaVersion;
+use serde::Serialize;
+
+use crate::glob::Glob;
+use crate::source::{Entry, Source};
+
+/// The name of the manifest written by `unpack`.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// The options shared by every command.
+pub struct Context {
+    pub json: bool,
+    pub game_version: Option<WoaVersion>,
+    pub path_list: PathList,
+}
+
+impl Context {
+    fn open(&self, path: &Path) -> Result<Source, Box<dyn Error>> {
+        Source::open(path, self.game_version)
+    }
+
+    fn resolve(&self, rrid: &RuntimeResourceID) -> Option<String> {
+        self.path_list.get(rrid).map(|rid| rid.uri().to_string())
+    }
+
+    /// Prints the report as JSON, or as text using the given function.
+    fn print<T: Serialize>(&self, report: &T, text: impl FnOnce(&T)) -> Result<(), Box<dyn Error>> {
+        match self.json {
+            true => println!("{}", serde_json::to_string_pretty(report)?),
+            false => text(report),
+        }
+        Ok(())
+    }
+}
+
+/// Selects the resources to extract. Every given kind of filter has to match.
+pub struct Selection {
+    pub types: Vec<String>,
+    pub hashes: Vec<RuntimeResourceID>,
+    pub globs: Vec<Glob>,
+}
+
+impl Selection {
+    fn matches(&self, entry: &Entry) -> bool {
+        (self.types.is_empty() || matches_type(&self.types, &entry.data_type))
+            && (self.hashes.is_empty() || self.hashes.contains(&entry.rrid))
+            && (self.globs.is_empty() || self.globs.iter().any(|glob| glob.matches(&entry.path)))
+    }
+}
+
+fn matches_type(types: &[String], data_type: &str) -> bool {
+    types.iter().any(|t| t.eq_ignore_ascii_case(data_type))
+}
+
+#[derive(Default, Serialize)]
+struct TypeSummary {
+    count: usize,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct PackageSummary {
+    version: String,
+    is_patch: bool,
+    resource_count: usize,
+    unneeded_resource_count: usize,
+    legacy_references: bool,
+    compressed_count: usize,
+    scrambled_count: usize,
+    size: u64,
+    packaged_size: u64,
+    types: BTreeMap<String, TypeSummary>,
+}
+
+#[derive(Serialize)]
+struct PartitionSummary {
+    id: String,
+    name: Option<String>,
+    parent: Option<String>,
+    packages: Vec<String>,
+    resource_count: usize,
+    types: BTreeMap<String, TypeSummary>,
+}
+
+pub fn info(context: &Context, source: &Path) -> Result<ExitCode, Box<dyn Error>> {
+    match context.open(source)? {
+        Source::Package(package) => {
+            let resources = package.load_metadata()?;
+            let mut types = BTreeMap::<String, TypeSummary>::new();
+            for info in resources.values() {
+                let summary = types.entry(info.data_type()).or_default();
+                summary.count += 1;
+                summary.size += info.size() as u64;
+            }
+            let summary = PackageSummary {
+                version: format!("{:?}", package.version()),
+                is_patch: package.is_patch(),
+                resource_count: resources.len(),
+                unneeded_resource_count: package.unneeded_resource_ids().len(),
+                legacy_references: package.has_legacy_references()?,
+                compressed_count: resources.values().filter(|info| info.is_compressed()).count(),
+                scrambled_count: resources.values().filter(|info| info.is_scrambled()).count(),
+                size: resources.values().map(|info| info.size() as u64).sum(),
+                packaged_size: resources.values().map(|info| info.packaged_size() as u64).sum(),
+                types,
+            };
+            context.print(&summary, |summary| {
+                println!("Version: {}{}", summary.version, if summary.is_patch { " (patch)" } else { "" });
+                println!(
+                    "Resources: {} ({} compressed, {} scrambled)",
+                    summary.resource_count, summary.compressed_count, summary.scrambled_count
+                );
+                println!("Unneeded resources: {}", summary.unneeded_resource_count);
+                println!("Size: {} bytes, {} bytes packaged", summary.size, summary.packaged_size);
+                print_types(&summary.types);
+            })?;
+        }
+        Source::Game(partition_manager) => {
+            let mut partitions = vec![];
+            for partition in &partition_manager.partitions {
+                let info = partition.partition_info();
+                let mut packages = partition.packages.keys().collect::<Vec<_>>();
+                packages.sort();
+
+                let resources = partition.latest_resources()?;
+                let mut types = BTreeMap::<String, TypeSummary>::new();
+                for (info, _) in &resources {
+                    let summary = types.entry(info.data_type()).or_default();
+                    summary.count += 1;
+                    summary.size += info.size() as u64;
+                }
+                partitions.push(PartitionSummary {
+                    id: info.id.to_string(),
+                    name: info.name.clone(),
+                    parent: info.parent.as_ref().map(|parent| parent.to_string()),
+                    packages: packages.into_iter().map(|patch_id| info.id.to_filename(*patch_id)).collect(),
+                    resource_count: resources.len(),
+                    types,
+                });
+            }
+            context.print(&partitions, |partitions| {
+                for partition in partitions {
+                    let name = partition.name.as_deref().unwrap_or("unnamed");
+                    println!("{} ({name}), {} resources", partition.id, partition.resource_count);
+                    if let Some(parent) = &partition.parent {
+                        println!("  Parent: {parent}");
+                    }
+                    println!("  Packages: {}", partition.packages.join(", "));
+                }
+            })?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_types(types: &BTreeMap<String, TypeSummary>) {
+    println!("Types:");
+    for (data_type, summary) in types {
+        println!("  {data_type:<6}{:>8} resources {:>12} bytes", summary.count, summary.size);
+    }
+}
+
+pub fn ls(
+    context: &Context,
+    source: &Path,
+    types: &[String],
+    partition: Option<&PartitionId>,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let source = context.open(source)?;
+    let mut entries = source.entries(partition, &context.path_list)?;
+    entries.retain(|entry| types.is_empty() || matches_type(types, &entry.data_type));
+
+    context.print(&entries, |entries| {
+        for entry in entries {
+            let package = entry.package.as_deref().map(|package| format!("  {package}")).unwrap_or_default();
+            println!("{}  {:<6}{:>12}{package}  {}", entry.rrid, entry.data_type, entry.size, entry.path);
+        }
+    })?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(Serialize)]
+struct ExtractedResource {
+    rrid: RuntimeResourceID,
+    path: String,
+    size: usize,
+}
+
+pub fn extract(
+    context: &Context,
+    source: &Path,
+    output: &Path,
+    selection: &Selection,
+    partition: Option<&PartitionId>,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let source = context.open(source)?;
+    let mut extracted = vec![];
+    for entry in source.entries(partition, &context.path_list)? {
+        if !selection.matches(&entry) {
+            continue;
+        }
+        let data = source.read(&entry).map_err(|e| format!("failed to read {}: {e}", entry.rrid))?;
+        let path = output.join(&entry.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &data)?;
+        extracted.push(ExtractedResource {
+            rrid: entry.rrid,
+            path: entry.path,
+            size: data.len(),
+        });
+    }
+
+    context.print(&extracted, |extracted| {
+        println!("Extracted {} resources to {}", extracted.len(), output.display());
+    })?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(Serialize)]
+struct BuiltPackage {
+    output: String,
+    resource_count: usize,
+}
+
+pub fn build(
+    context: &Context,
+    directory: &Path,
+    manifest: &Path,
+    output: &Path,
+    version: PackageVersion,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let manifest: PackageManifest = serde_json::from_str(&fs::read_to_string(manifest)?)?;
+    PackageBuilder::from_manifest(&manifest, directory)?.build_to_file(version, output)?;
+
+    let built = BuiltPackage {
+        output: output.display().to_string(),
+        resource_count: manifest.resources.len(),
+    };
+    context.print(&built, |built| {
+        println!("Built {} with {} resources", built.output, built.resource_count);
+    })?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(Serialize)]
+struct UnpackedPackage {
+    manifest: String,
+    resource_count: usize,
+}
+
+pub fn unpack(context: &Context, package: &Path, output: &Path) -> Result<ExitCode, Box<dyn Error>> {
+    let package = ResourcePackage::from_file(package)?;
+    let manifest = PackageManifest::from_resource_package_with_paths(&package, output, |rrid| context.resolve(rrid))?;
+    let manifest_path = output.join(MANIFEST_FILE_NAME);
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+
+    let unpacked = UnpackedPackage {
+        manifest: manifest_path.display().to_string(),
+        resource_count: manifest.resources.len(),
+    };
+    context.print(&unpacked, |unpacked| {
+        println!("Unpacked {} resources, described by {}", unpacked.resource_count, unpacked.manifest);
+    })?;
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn diff(context: &Context, old: &Path, new: &Path, content: bool) -> Result<ExitCode, Box<dyn Error>> {
+    let options = DiffOptions::default().with_content(content);
+    match (context.open(old)?, context.open(new)?) {
+        (Source::Package(old), Source::Package(new)) => {
+            let diff = ResourceDiff::from_packages(&old, &new, options)?;
+            context.print(&diff, |diff| print_diff(context, diff, ""))?;
+        }
+        (Source::Game(old), Source::Game(new)) => {
+            let diff = GameDiff::from_partition_managers(&old, &new, options)?;
+            context.print(&diff, |diff| {
+                for partition in &diff.partitions {
+                    println!("{} ({:?})", partition.partition_id, partition.status);
+                    print_diff(context, &partition.diff, "  ");
+                }
+            })?;
+        }
+        _ => return Err("a package can only be compared to a package, and a game to a game".into()),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_diff(context: &Context, diff: &ResourceDiff, indent: &str) {
+    for change in &diff.changes {
+        let path = context.resolve(change.rrid()).map(|path| format!(" {path}")).unwrap_or_default();
+        let marker = match change {
+            ResourceChange::Added { .. } => '+',
+            ResourceChange::Removed { .. } => '-',
+            ResourceChange::Modified { .. } => '~',
+        };
+        println!("{indent}{marker} {} {}{path}", change.rrid(), change.data_type());
+
+        if let ResourceChange::Modified { modifications, .. } = change {
+            for modification in modifications {
+                println!("{indent}    {}", describe_modification(modification));
+            }
+        }
+    }
+}
+
+fn describe_modification(modification: &ResourceModification) -> String {
+    match modification {
+        ResourceModification::DataType { old, new } => format!("type {old} -> {new}"),
+        ResourceModification::Size { old, new } => format!("size {old} -> {new}"),
+        ResourceModification::SystemMemoryRequirement { old, new } => format!("system memory {old} -> {new}"),
+        ResourceModification::VideoMemoryRequirement { old, new } => format!("video memory {old} -> {new}"),
+        ResourceModification::ReferenceAdded { rrid, .. } => format!("added reference to {rrid}"),
+        ResourceModification::ReferenceRemoved { rrid, .. } => format!("removed reference to {rrid}"),
+        ResourceModification::ReferenceFlags { rrid, .. } => format!("changed the flags of the reference to {rrid}"),
+        ResourceModification::ReferenceOrder => "reordered references".to_string(),
+        ResourceModification::Content { old, new } => format!("content {old} -> {new}"),
+    }
+}
+
+#[derive(Serialize)]
+struct VerifiedPackage {
+    package: String,
+    issues: Vec<String>,
+}
+
+pub fn verify(context: &Context, source: &Path) -> Result<ExitCode, Box<dyn Error>> {
+    let verify = |name: String, package: &ResourcePackage| -> Result<VerifiedPackage, Box<dyn Error>> {
+        let issues = package.verify()?.iter().map(|issue| issue.to_string()).collect();
+        Ok(VerifiedPackage { package: name, issues })
+    };
+
+    let mut packages = vec![];
+    match context.open(source)? {
+        Source::Package(package) => packages.push(verify(source.display().to_string(), &package)?),
+        Source::Game(partition_manager) => {
+            for partition in &partition_manager.partitions {
+                let mut patch_ids = partition.packages.keys().collect::<Vec<_>>();
+                patch_ids.sort();
+                for patch_id in patch_ids {
+                    let name = partition.partition_info().id.to_filename(*patch_id);
+                    packages.push(verify(name, &partition.packages[patch_id])?);
+                }
+            }
+        }
+    }
+
+    context.print(&packages, |packages| {
+        for package in packages {
+            match package.issues.is_empty() {
+                true => println!("{}: ok", package.package),
+                false => {
+                    println!("{}: {} issues", package.package, package.issues.len());
+                    for issue in &package.issues {
+                        println!("  {issue}");
+                    }
+                }
+            }
+        }
+    })?;
+
+    match packages.iter().all(|package| package.issues.is_empty()) {
+        true => Ok(ExitCode::SUCCESS),
+        false => Ok(ExitCode::from(2)),
+    }
+}
+
+#[derive(Serialize)]
+struct Dependency {
+    rrid: RuntimeResourceID,
+    /// The resource type, None if the resource is referenced but not available.
+    data_type: Option<String>,
+    path: Option<String>,
+}
+
+pub fn deps(
+    context: &Context,
+    source: &Path,
+    resource: &RuntimeResourceID,
+    reverse: bool,
+    install_only: bool,
+    dot: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let graph = match context.open(source)? {
+        Source::Package(package) => DependencyGraph::from_resource_package(&package)?,
+        Source::Game(partition_manager) => DependencyGraph::from_partition_manager(&partition_manager)?,
+    };
+    let filter = match install_only {
+        true => ReferenceFilter::install_only(),
+        false => ReferenceFilter::all(),
+    };
+
+    if dot {
+        let mut subgraph = graph.subgraph(&[*resource], filter)?;
+        subgraph.resolve_paths(|rrid| context.resolve(rrid));
+        print!("{}", subgraph.to_dot());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let rrids = match reverse {
+        true => graph.dependents(resource, filter)?,
+        false => graph.dependencies(resource, filter)?,
+    };
+    let dependencies = rrids
+        .into_iter()
+        .map(|rrid| Dependency {
+            rrid,
+            data_type: graph.node(&rrid).map(|node| node.data_type().to_string()),
+            path: context.resolve(&rrid),
+        })
+        .collect::<Vec<_>>();
+
+    context.print(&dependencies, |dependencies| {
+        for dependency in dependencies {
+            let data_type = dependency.data_type.as_deref().unwrap_or("-");
+            println!("{}  {data_type:<6}{}", dependency.rrid, dependency.path.as_deref().unwrap_or(""));
+        }
+    })?;
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,48 @@
+//! Matches resource paths against shell style glob patterns.
+//!
+//! `*` matches any characters inside a single path segment, `?` matches a single character and a `**` segment
+//! matches any amount of segments, including none.
+
+pub struct Glob {
+    segments: Vec<String>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let segments = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect();
+        Self { segments }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>();
+        match_segments(&self.segments, &path)
+    }
+}
+
+fn match_segments(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path)) => {
+                let pattern = first.chars().collect::<Vec<_>>();
+                let name = segment.chars().collect::<Vec<_>>();
+                match_segment(&pattern, &name) && match_segments(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_segment(rest, &name[1..]),
+    }
+}
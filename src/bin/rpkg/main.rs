@@ -0,0 +1,231 @@
+//! Inspects, extracts and builds Glacier resource packages.
+//!
+//! ```sh
+//! rpkg info chunk0.rpkg
+//! rpkg ls <retail directory> --partition chunk0 --type TEMP --path-list hash_list.txt
+//! rpkg extract chunk0.rpkg out --glob "assembly/_pro/scenes/**" --path-list hash_list.txt
+//! rpkg unpack chunk0patch1.rpkg resources --path-list hash_list.txt
+//! rpkg build resources --manifest resources/manifest.json --output chunk0patch1.rpkg
+//! rpkg diff old/chunk0.rpkg new/chunk0.rpkg --content
+//! rpkg verify chunk0.rpkg
+//! rpkg deps <retail directory> "[assembly:/_pro/scenes/test.entity].pc_entitytype"
+//! ```
+//!
+//! Commands which take a source accept a `.rpkg` file or the retail directory of a game. Every command prints JSON
+//! instead of text with `--json`. The exit code is 1 on errors, and 2 when `verify` finds problems.
+
+mod commands;
+mod glob;
+mod source;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use rpkg_rs::misc::hash_path_list::PathList;
+use rpkg_rs::misc::resource_id::ResourceID;
+use rpkg_rs::resource::pdefs::PartitionId;
+use rpkg_rs::resource::resource_package::PackageVersion;
+use rpkg_rs::resource::runtime_resource_id::{PlatformTag, RuntimeResourceID};
+use rpkg_rs::WoaVersion;
+
+#[derive(Parser)]
+#[command(version, about = "Inspects, extracts and builds Glacier resource packages")]
+struct Arguments {
+    /// Prints machine readable JSON instead of text.
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// The game version (HM2016, HM2, HM3 or Bond), detected from the retail directory when omitted.
+    #[arg(long, global = true)]
+    game_version: Option<WoaVersion>,
+
+    /// A path list used to resolve resource paths.
+    #[arg(long, global = true)]
+    path_list: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Shows a summary of a package, or of the partitions of a game.
+    Info {
+        /// A `.rpkg` file or a retail directory.
+        source: PathBuf,
+    },
+
+    /// Lists resources.
+    Ls {
+        /// A `.rpkg` file or a retail directory.
+        source: PathBuf,
+
+        #[command(flatten)]
+        filter: Filter,
+    },
+
+    /// Extracts resources to a directory, laid out by their resolved path.
+    Extract {
+        /// A `.rpkg` file or a retail directory.
+        source: PathBuf,
+
+        /// The directory to extract to.
+        output: PathBuf,
+
+        #[command(flatten)]
+        filter: Filter,
+
+        /// Only extracts these resources, given as a hash or a resource path.
+        #[arg(long = "hash", value_parser = parse_resource)]
+        hashes: Vec<RuntimeResourceID>,
+
+        /// Only extracts resources whose extracted path matches the pattern, `**` matches any directories.
+        #[arg(long = "glob")]
+        globs: Vec<String>,
+    },
+
+    /// Writes the resources of a package to a directory, together with a manifest to build it from.
+    Unpack {
+        /// The `.rpkg` file to unpack.
+        package: PathBuf,
+
+        /// The directory to write the resources and `manifest.json` to.
+        output: PathBuf,
+    },
+
+    /// Builds a package from a directory of resources and a JSON manifest.
+    Build {
+        /// The directory the files of the manifest are relative to.
+        directory: PathBuf,
+
+        /// The manifest describing the package, `manifest.json` inside the directory when omitted.
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+
+        /// The package file to write, or a directory to write it to under its default name.
+        #[arg(long, short)]
+        output: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = Version::V2)]
+        package_version: Version,
+    },
+
+    /// Shows the differences between two packages, or between two games.
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+
+        /// Also compares the contents of the resources, which reads every resource.
+        #[arg(long)]
+        content: bool,
+    },
+
+    /// Checks that a package, or every package of a game, is well formed.
+    Verify {
+        /// A `.rpkg` file or a retail directory.
+        source: PathBuf,
+    },
+
+    /// Lists the dependencies of a resource.
+    Deps {
+        /// A `.rpkg` file or a retail directory.
+        source: PathBuf,
+
+        /// The resource, given as a hash or a resource path.
+        #[arg(value_parser = parse_resource)]
+        resource: RuntimeResourceID,
+
+        /// Lists the resources depending on the resource instead.
+        #[arg(long)]
+        reverse: bool,
+
+        /// Only follows install references.
+        #[arg(long)]
+        install_only: bool,
+
+        /// Prints the dependency graph as a Graphviz DOT document.
+        #[arg(long, conflicts_with = "reverse")]
+        dot: bool,
+    },
+}
+
+#[derive(clap::Args)]
+struct Filter {
+    /// Only includes resources of these types.
+    #[arg(long = "type")]
+    types: Vec<String>,
+
+    /// Only includes the resources of this partition of a game.
+    #[arg(long)]
+    partition: Option<PartitionId>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Version {
+    V1,
+    V2,
+}
+
+impl From<Version> for PackageVersion {
+    fn from(version: Version) -> Self {
+        match version {
+            Version::V1 => PackageVersion::RPKGv1,
+            Version::V2 => PackageVersion::RPKGv2,
+        }
+    }
+}
+
+fn parse_resource(resource: &str) -> Result<RuntimeResourceID, String> {
+    if resource.starts_with('[') {
+        let rid = ResourceID::from_str(resource).map_err(|e| e.to_string())?;
+        return Ok(RuntimeResourceID::from_resource_id_with_platform(&rid, "pc", PlatformTag::None));
+    }
+    RuntimeResourceID::from_hex_string(resource).map_err(|e| e.to_string())
+}
+
+fn main() -> ExitCode {
+    match run(Arguments::parse()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(arguments: Arguments) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let mut path_list = PathList::new();
+    if let Some(path) = &arguments.path_list {
+        path_list.parse_into(path)?;
+    }
+    let context = commands::Context {
+        json: arguments.json,
+        game_version: arguments.game_version,
+        path_list,
+    };
+
+    match arguments.command {
+        Command::Info { source } => commands::info(&context, &source),
+        Command::Ls { source, filter } => commands::ls(&context, &source, &filter.types, filter.partition.as_ref()),
+        Command::Extract { source, output, filter, hashes, globs } => {
+            let selection = commands::Selection {
+                types: filter.types,
+                hashes,
+                globs: globs.iter().map(|pattern| glob::Glob::new(pattern)).collect(),
+            };
+            commands::extract(&context, &source, &output, &selection, filter.partition.as_ref())
+        }
+        Command::Unpack { package, output } => commands::unpack(&context, &package, &output),
+        Command::Build { directory, manifest, output, package_version } => {
+            let manifest = manifest.unwrap_or_else(|| directory.join(commands::MANIFEST_FILE_NAME));
+            commands::build(&context, &directory, &manifest, &output, package_version.into())
+        }
+        Command::Diff { old, new, content } => commands::diff(&context, &old, &new, content),
+        Command::Verify { source } => commands::verify(&context, &source),
+        Command::Deps { source, resource, reverse, install_only, dot } => {
+            commands::deps(&context, &source, &resource, reverse, install_only, dot)
+        }
+    }
+}
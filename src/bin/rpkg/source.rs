@@ -0,0 +1,120 @@
+//! The input of a command, either a single resource package or the mounted partitions of a game.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+
+use rpkg_rs::misc::hash_path_list::PathList;
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::PartitionId;
+use rpkg_rs::resource::resource_package::ResourcePackage;
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::resource_vfs::{resource_file_path, unknown_file_path, ResourceVfs};
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use rpkg_rs::WoaVersion;
+use serde::Serialize;
+
+pub enum Source {
+    Package(Box<ResourcePackage>),
+    Game(PartitionManager),
+}
+
+/// A resource as listed and extracted by the tool.
+#[derive(Serialize)]
+pub struct Entry {
+    /// The path the resource is extracted to, see [ResourceVfs].
+    pub path: String,
+    pub rrid: RuntimeResourceID,
+    pub data_type: String,
+    pub size: u32,
+    /// The resolved resource path, if known.
+    pub resource_path: Option<String>,
+    /// The partition and the package the resource is loaded from, only for games.
+    pub partition: Option<String>,
+    pub package: Option<String>,
+    #[serde(skip)]
+    location: Option<(PartitionId, PatchId)>,
+}
+
+impl Source {
+    /// Opens a `.rpkg` file as a package, anything else as the retail directory of a game.
+    pub fn open(path: &Path, game_version: Option<WoaVersion>) -> Result<Self, Box<dyn Error>> {
+        if path.is_file() {
+            return Ok(Source::Package(Box::new(ResourcePackage::from_file(path)?)));
+        }
+        let partition_manager = match game_version {
+            Some(version) => PartitionManager::from_game(path.to_path_buf(), version, true)?,
+            None => PartitionManager::from_game_auto(path.to_path_buf(), true)?,
+        };
+        Ok(Source::Game(partition_manager))
+    }
+
+    /// Lists the resources of the source, for games only the version the game would load.
+    ///
+    /// # Arguments
+    /// - `partition` - Only lists the resources of a single partition of a game.
+    /// - `path_list` - Used to resolve the resource paths.
+    pub fn entries(&self, partition: Option<&PartitionId>, path_list: &PathList) -> Result<Vec<Entry>, Box<dyn Error>> {
+        let resolve = |rrid: &RuntimeResourceID| path_list.get(rrid).map(|rid| rid.uri().to_string());
+        match self {
+            Source::Package(_) if partition.is_some() => Err("only games can be filtered by partition".into()),
+            Source::Package(package) => {
+                let mut paths = HashSet::new();
+                let mut entries = vec![];
+                for (rrid, info) in package.load_metadata()? {
+                    let resource_path = resolve(rrid);
+                    let path = match resource_path.as_deref().and_then(resource_file_path) {
+                        Some(path) if !paths.contains(&path) => path,
+                        _ => unknown_file_path(&info.data_type(), rrid),
+                    };
+                    paths.insert(path.clone());
+                    entries.push(Entry {
+                        path,
+                        rrid: *rrid,
+                        data_type: info.data_type(),
+                        size: info.size(),
+                        resource_path,
+                        partition: None,
+                        package: None,
+                        location: None,
+                    });
+                }
+                Ok(entries)
+            }
+            Source::Game(partition_manager) => {
+                let vfs = match partition {
+                    Some(partition_id) => ResourceVfs::for_partition(partition_manager, partition_id, resolve)?,
+                    None => ResourceVfs::new(partition_manager, resolve)?,
+                };
+                let entries = vfs
+                    .files()
+                    .map(|(path, file)| Entry {
+                        path: path.to_string(),
+                        rrid: file.rrid,
+                        data_type: file.data_type.clone(),
+                        size: file.size,
+                        resource_path: resolve(&file.rrid),
+                        partition: Some(file.partition_id.to_string()),
+                        package: Some(file.partition_id.to_filename(file.patch_id)),
+                        location: Some((file.partition_id.clone(), file.patch_id)),
+                    })
+                    .collect();
+                Ok(entries)
+            }
+        }
+    }
+
+    /// Reads the decompressed and descrambled data of a listed resource.
+    pub fn read(&self, entry: &Entry) -> Result<Vec<u8>, Box<dyn Error>> {
+        match (self, &entry.location) {
+            (Source::Package(package), _) => Ok(package.read_resource(&entry.rrid)?),
+            (Source::Game(partition_manager), Some((partition_id, patch_id))) => {
+                let partition = partition_manager
+                    .find_partition(partition_id.clone())
+                    .ok_or_else(|| format!("partition {partition_id} is not mounted"))?;
+                Ok(partition.read_resource_from(&entry.rrid, *patch_id)?)
+            }
+            (Source::Game(_), None) => Err(format!("resource {} is not part of a partition", entry.rrid).into()),
+        }
+    }
+}
@@ -1 +1,2 @@
+pub mod scramble;
 pub mod xtea;
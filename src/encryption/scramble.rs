@@ -0,0 +1,155 @@
+//! The XOR scrambling applied to resource data inside of resource packages.
+//!
+//! Scrambling XORs the data with a repeating 8 byte key, so scrambling and descrambling are the same operation.
+//! The key is aligned to the start of the resource data, which is why a [Scrambler] keeps track of the key phase
+//! when data is processed in several parts.
+//!
+//! ```
+//! use rpkg_rs::encryption::scramble::{self, Scrambler};
+//!
+//! let mut data = b"some resource data".to_vec();
+//! scramble::scramble(&mut data);
+//!
+//! // Descrambling in parts gives the same result as descrambling everything at once.
+//! let mut scrambler = Scrambler::new();
+//! let (head, tail) = data.split_at_mut(5);
+//! scrambler.apply(head);
+//! scrambler.apply(tail);
+//! assert_eq!(data, b"some resource data");
+//! ```
+
+use std::io;
+use std::io::{Read, Write};
+
+/// The key resource data is scrambled with.
+pub const SCRAMBLE_KEY: [u8; 8] = [0xdc, 0x45, 0xa6, 0x9c, 0xd3, 0x72, 0x4c, 0xab];
+
+/// The size of the buffer a [ScrambleWriter] scrambles data in.
+const WRITE_BUFFER_SIZE: usize = 0x10000;
+
+/// Scrambles data in place, starting at the beginning of the key.
+pub fn scramble(data: &mut [u8]) {
+    Scrambler::new().apply(data);
+}
+
+/// Descrambles data in place, starting at the beginning of the key.
+pub fn descramble(data: &mut [u8]) {
+    Scrambler::new().apply(data);
+}
+
+/// An incremental XOR transform which keeps track of the key phase across calls.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Scrambler {
+    phase: usize,
+}
+
+impl Scrambler {
+    /// Creates a scrambler for data starting at the beginning of the key.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a scrambler for data starting at the given offset into the resource data.
+    pub fn at_offset(offset: u64) -> Self {
+        Self {
+            phase: (offset % SCRAMBLE_KEY.len() as u64) as usize,
+        }
+    }
+
+    /// The position in the key the next byte is scrambled with.
+    pub fn phase(&self) -> usize {
+        self.phase
+    }
+
+    /// Scrambles or descrambles the next part of the data in place.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        // Process bytes one by one until the key is aligned, then whole key sized chunks, which vectorizes well.
+        let head_length = ((SCRAMBLE_KEY.len() - self.phase) % SCRAMBLE_KEY.len()).min(data.len());
+        let (head, rest) = data.split_at_mut(head_length);
+        for byte in head {
+            *byte ^= SCRAMBLE_KEY[self.phase];
+            self.phase = (self.phase + 1) % SCRAMBLE_KEY.len();
+        }
+
+        let mut chunks = rest.chunks_exact_mut(SCRAMBLE_KEY.len());
+        for chunk in &mut chunks {
+            for (byte, key) in chunk.iter_mut().zip(SCRAMBLE_KEY) {
+                *byte ^= key;
+            }
+        }
+
+        for byte in chunks.into_remainder() {
+            *byte ^= SCRAMBLE_KEY[self.phase];
+            self.phase += 1;
+        }
+    }
+}
+
+/// A writer which scrambles everything written to it before passing it on.
+pub struct ScrambleWriter<W: Write> {
+    writer: W,
+    scrambler: Scrambler,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ScrambleWriter<W> {
+    /// Creates a writer for data starting at the beginning of the key.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            scrambler: Scrambler::new(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for ScrambleWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len().min(WRITE_BUFFER_SIZE);
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&buf[..length]);
+
+        self.scrambler.apply(&mut self.buffer);
+        self.writer.write_all(&self.buffer)?;
+
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A reader which descrambles everything read from it.
+pub struct ScrambleReader<R: Read> {
+    reader: R,
+    scrambler: Scrambler,
+}
+
+impl<R: Read> ScrambleReader<R> {
+    /// Creates a reader for data starting at the beginning of the key.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            scrambler: Scrambler::new(),
+        }
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for ScrambleReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.scrambler.apply(&mut buf[..read]);
+        Ok(read)
+    }
+}
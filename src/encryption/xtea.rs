@@ -64,7 +64,12 @@ impl Xtea {
             input_buffer.starts_with(&Self::BOND_ENCRYPTED_HEADER)
     }
 
-    /// Decrypts a text file given its buffer, uses the default xtea key.
+    /// Checks if a given buffer represents a text file encrypted with the 007 First Light key.
+    pub fn is_bond_encrypted_text_file(input_buffer: &[u8]) -> bool {
+        input_buffer.starts_with(&Self::BOND_ENCRYPTED_HEADER)
+    }
+
+    /// Decrypts a text file given its buffer, the key is picked based on the header of the file.
     pub fn decrypt_text_file(input_buffer: &[u8]) -> Result<String, XteaError> {
         let payload_start = Self::WOA_ENCRYPTED_HEADER.len() + 4;
 
@@ -85,7 +90,7 @@ impl Xtea {
         }
 
         let mut key = Self::WOA_KEY;
-        if input_buffer.starts_with(&Self::BOND_ENCRYPTED_HEADER){
+        if Self::is_bond_encrypted_text_file(input_buffer) {
             key = Self::BOND_KEY
         }
 
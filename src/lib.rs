@@ -1,4 +1,4 @@
-#![doc(html_root_url = "https://docs.rs/rpkg-rs/1.4.0")]
+#![doc(html_root_url = "https://docs.rs/rpkg-rs/2.0.0")]
 //! `rpkg-rs` provides comprehensive functionality for interacting with `ResourcePackage` (rpkg) files found within Hitman games.
 //! This crate facilitates parsing of these files, enabling seamless access to the contained resource files.
 //! By parsing configuration files such as `thumbs.ini` and `packagedefintion.txt`, rpkg-rs offers extensive support for reading and manipulating these packages.
@@ -11,6 +11,7 @@
 //!
 //! rpkg-rs aims to streamline the process of working with Hitman game resources, offering a robust set of features to read ResourcePackage files.
 
+use std::str::FromStr;
 use thiserror::Error;
 
 #[cfg(feature = "serde")]
@@ -38,6 +39,7 @@ impl From<WoaVersion> for GlacierGame {
             WoaVersion::HM2016 => GlacierGame::HM2016,
             WoaVersion::HM2 => GlacierGame::HM2,
             WoaVersion::HM3 => GlacierGame::HM3,
+            WoaVersion::Bond => GlacierGame::Bond,
         }
     }
 }
@@ -48,6 +50,27 @@ pub enum WoaVersion {
     HM2016,
     HM2,
     HM3,
+    Bond,
+}
+
+#[derive(Debug, Error)]
+pub enum WoaVersionError {
+    #[error("Unknown game version {0}, expected HM2016, HM2, HM3 or Bond")]
+    UnknownVersion(String),
+}
+
+impl FromStr for WoaVersion {
+    type Err = WoaVersionError;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        match version {
+            "HM2016" => Ok(WoaVersion::HM2016),
+            "HM2" => Ok(WoaVersion::HM2),
+            "HM3" => Ok(WoaVersion::HM3),
+            "Bond" => Ok(WoaVersion::Bond),
+            _ => Err(WoaVersionError::UnknownVersion(version.to_string())),
+        }
+    }
 }
 
 #[derive(Debug, Error)]
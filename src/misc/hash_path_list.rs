@@ -15,6 +15,9 @@ pub enum PathListError {
 
     #[error("Invalid RuntimeResourceID entry")]
     InvalidRuntimeResourceID,
+
+    #[error("Invalid entry on line {line}: {content:?}")]
+    InvalidLine { line: usize, content: String },
 }
 
 /// A rainbow table of hashed paths with associated paths.
@@ -45,38 +48,56 @@ impl PathList {
     /// * `path` - The path to the file to parse.
     pub fn parse_into<P: AsRef<Path>>(&mut self, path: P) -> Result<&Self, PathListError> {
         let file_as_string = read_to_string(path).map_err(PathListError::IoError)?;
-        let lines: Vec<_> = file_as_string.lines().map(String::from).collect();
+        self.parse_from_str(&file_as_string)
+    }
+
+    /// Parses the contents of a path list file into the PathList.
+    ///
+    /// Empty lines and comments are skipped. A line which doesn't start with a hexadecimal hash fails the whole parse
+    /// with [PathListError::InvalidLine] for the first such line, leaving the PathList unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of a path list file, see [PathList::parse_into].
+    pub fn parse_from_str(&mut self, data: &str) -> Result<&Self, PathListError> {
+        let lines: Vec<_> = data.lines().map(String::from).enumerate().collect();
 
         let lines_par = lines.into_par_iter();
 
-        self.entries = lines_par
-            .filter_map(|line_res| {
-                if line_res.starts_with('#') {
-                    return None;
+        let entries = lines_par
+            .map(|(index, line_res)| {
+                if line_res.is_empty() || line_res.starts_with('#') {
+                    return Ok(None);
                 };
 
                 let (hash, path) = match line_res.split_once(',') {
-                    Some((h, p)) => (h.split_once('.').unwrap().0, Some(p)),
+                    Some((h, p)) => (h, Some(p)),
                     None => (line_res.as_str(), None),
                 };
+                let hash = hash.split_once('.').map_or(hash, |(hash, _)| hash);
+
+                let Ok(id) = u64::from_str_radix(hash, 16) else {
+                    return Err(PathListError::InvalidLine {
+                        line: index + 1,
+                        content: line_res,
+                    });
+                };
 
-                if let Ok(id) = u64::from_str_radix(hash, 16) {
-                    if let Some(path) = path {
-                        if let Ok(rid) = ResourceID::from_str(path) {
-                            if rid.is_valid() {
-                                return Some((RuntimeResourceID::from(id), Some(rid)));
-                            }
+                if let Some(path) = path {
+                    if let Ok(rid) = ResourceID::from_str(path) {
+                        if rid.is_valid() {
+                            return Ok(Some((RuntimeResourceID::from(id), Some(rid))));
                         }
                     }
-                    Some((RuntimeResourceID::from(id), None))
-                } else {
-                    None
                 }
+                Ok(Some((RuntimeResourceID::from(id), None)))
             })
             .collect::<Vec<_>>()
             .into_iter()
-            .collect();
+            .filter_map(Result::transpose)
+            .collect::<Result<_, _>>()?;
 
+        self.entries = entries;
         Ok(self)
     }
 
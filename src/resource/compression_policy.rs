@@ -0,0 +1,191 @@
+//! Per-resource compression decisions made while building a package.
+//!
+//! Every resource is stored as a raw LZ4 block, which can be produced by the fast LZ4 compressor or by LZ4-HC.
+//! A [CompressionPolicy] decides for each resource whether it's compressed, with which backend and how hard, and
+//! whether the compressed data is worth keeping. It's set with [PackageBuilder::with_compression_policy], the
+//! default [RequestedCompression] policy compresses resources the way they were added to the builder.
+//!
+//! Policies apply to resources duplicated from another package as well. Their packaged data is copied as is when
+//! the policy agrees with how it's packaged, and is recompressed or decompressed otherwise.
+//!
+//! [PackageBuilder::with_compression_policy]: crate::resource::package_builder::PackageBuilder::with_compression_policy
+
+use std::collections::HashMap;
+
+use lzzzz::{lz4, lz4_hc};
+
+use crate::resource::package_builder::DEFAULT_COMPRESSION_LEVEL;
+use crate::resource::resource_package::{PackageVersion, ResourcePackage, ResourcePackageError};
+use crate::resource::runtime_resource_id::RuntimeResourceID;
+
+/// The compressor producing the LZ4 block of a resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionBackend {
+    /// The fast LZ4 compressor, the level is its acceleration.
+    Lz4,
+    /// The high compression LZ4-HC compressor.
+    Lz4Hc,
+}
+
+impl CompressionBackend {
+    /// The backend the builder uses for a package version, LZ4 for RPKGv1 and LZ4-HC for RPKGv2.
+    pub fn for_version(version: PackageVersion) -> Self {
+        match version {
+            PackageVersion::RPKGv1 => CompressionBackend::Lz4,
+            PackageVersion::RPKGv2 => CompressionBackend::Lz4Hc,
+        }
+    }
+
+    /// Compresses the data into a single LZ4 block.
+    pub fn compress(&self, data: &[u8], level: i32) -> Result<Vec<u8>, lzzzz::Error> {
+        let mut compressed_buffer = vec![0; lz4::max_compressed_size(data.len())];
+        let compressed_size = match self {
+            CompressionBackend::Lz4 => lz4::compress(data, &mut compressed_buffer, level)?,
+            CompressionBackend::Lz4Hc => lz4_hc::compress(data, &mut compressed_buffer, level)?,
+        };
+        compressed_buffer.truncate(compressed_size);
+        Ok(compressed_buffer)
+    }
+}
+
+/// How a resource is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub backend: CompressionBackend,
+    pub level: i32,
+}
+
+impl Compression {
+    pub fn new(backend: CompressionBackend, level: i32) -> Self {
+        Self { backend, level }
+    }
+
+    /// Compression with the default backend of the package version.
+    pub fn for_version(version: PackageVersion, level: i32) -> Self {
+        Self::new(CompressionBackend::for_version(version), level)
+    }
+}
+
+/// A resource the builder is about to store, as seen by a [CompressionPolicy].
+#[derive(Debug, Clone)]
+pub struct CompressionCandidate {
+    pub rrid: RuntimeResourceID,
+    pub resource_type: String,
+    /// The uncompressed size of the resource.
+    pub size: u32,
+    /// The version of the package being built.
+    pub version: PackageVersion,
+    /// The compression the resource was added to the builder with. Resources duplicated from another package
+    /// request level 12 of the default backend when they're packaged compressed, their packaged data is copied as is
+    /// when the policy picks this compression and recompressed when it picks another one.
+    pub requested: Option<Compression>,
+}
+
+/// Decides how the resources of a package are compressed.
+///
+/// Policies are shared between the threads compressing resources, so they have to be `Send` and `Sync`.
+/// Closures taking a [CompressionCandidate] and returning an `Option<Compression>` are policies as well.
+pub trait CompressionPolicy: Send + Sync {
+    /// Decides how a resource is compressed, None stores it uncompressed.
+    fn compression(&self, resource: &CompressionCandidate) -> Option<Compression>;
+
+    /// Decides whether the compressed data of a resource is kept, or the resource is stored uncompressed instead.
+    fn keep_compressed(&self, resource: &CompressionCandidate, compressed_size: usize) -> bool {
+        let _ = (resource, compressed_size);
+        true
+    }
+}
+
+impl<F> CompressionPolicy for F
+where
+    F: Fn(&CompressionCandidate) -> Option<Compression> + Send + Sync,
+{
+    fn compression(&self, resource: &CompressionCandidate) -> Option<Compression> {
+        self(resource)
+    }
+}
+
+/// Compresses resources the way they were added to the builder.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RequestedCompression;
+
+impl CompressionPolicy for RequestedCompression {
+    fn compression(&self, resource: &CompressionCandidate) -> Option<Compression> {
+        resource.requested
+    }
+}
+
+/// Compresses exactly the resources which are compressed in an original package.
+///
+/// Resources which were compressed in the original package use their requested compression, or level 12 of the
+/// default backend when none was requested. Resources the original package doesn't contain are compressed as
+/// requested.
+#[derive(Debug, Clone)]
+pub struct MimicPackage {
+    compressed: HashMap<RuntimeResourceID, bool>,
+}
+
+impl MimicPackage {
+    /// Creates a policy following the given package.
+    ///
+    /// Fails when the metadata of a lazily parsed package can't be decoded.
+    pub fn new(package: &ResourcePackage) -> Result<Self, ResourcePackageError> {
+        Ok(Self {
+            compressed: package
+                .load_metadata()?
+                .iter()
+                .map(|(rrid, info)| (*rrid, info.is_compressed()))
+                .collect(),
+        })
+    }
+}
+
+impl CompressionPolicy for MimicPackage {
+    fn compression(&self, resource: &CompressionCandidate) -> Option<Compression> {
+        match self.compressed.get(&resource.rrid) {
+            Some(true) => resource
+                .requested
+                .or_else(|| Some(Compression::for_version(resource.version, DEFAULT_COMPRESSION_LEVEL))),
+            Some(false) => None,
+            None => resource.requested,
+        }
+    }
+}
+
+/// Stores resources uncompressed when compressing them doesn't save at least the given percentage of their size.
+///
+/// Whether a resource is compressed at all is decided by the wrapped policy.
+#[derive(Debug, Clone)]
+pub struct MinimumSavings<P: CompressionPolicy = RequestedCompression> {
+    policy: P,
+    percent: u8,
+}
+
+impl MinimumSavings {
+    /// Creates a policy compressing resources as requested, if it saves at least `percent` percent.
+    pub fn new(percent: u8) -> Self {
+        Self::with_policy(RequestedCompression, percent)
+    }
+}
+
+impl<P: CompressionPolicy> MinimumSavings<P> {
+    /// Creates a policy compressing resources as decided by `policy`, if it saves at least `percent` percent.
+    pub fn with_policy(policy: P, percent: u8) -> Self {
+        Self {
+            policy,
+            percent: percent.min(100),
+        }
+    }
+}
+
+impl<P: CompressionPolicy> CompressionPolicy for MinimumSavings<P> {
+    fn compression(&self, resource: &CompressionCandidate) -> Option<Compression> {
+        self.policy.compression(resource)
+    }
+
+    fn keep_compressed(&self, resource: &CompressionCandidate, compressed_size: usize) -> bool {
+        let maximum_size = resource.size as u64 * (100 - self.percent as u64);
+        compressed_size as u64 * 100 <= maximum_size
+            && self.policy.keep_compressed(resource, compressed_size)
+    }
+}
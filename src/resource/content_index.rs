@@ -0,0 +1,288 @@
+//! Content hashes of every resource occurrence inside a mounted game.
+//!
+//! The same data often ships more than once: in several partitions, under different resource ids, or in a
+//! patch package which re-ships a resource without changing it. A [ContentIndex] hashes every occurrence so
+//! these duplicates can be found and the disk space they waste can be measured.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::resource::partition_manager::PartitionManager;
+use crate::resource::pdefs::PartitionId;
+use crate::resource::resource_diff::ContentHash;
+use crate::resource::resource_package::{ResourcePackage, ResourcePackageError};
+use crate::resource::resource_partition::PatchId;
+use crate::resource::runtime_resource_id::RuntimeResourceID;
+
+#[derive(Debug, Error)]
+pub enum ContentIndexError {
+    #[error("Failed to read resource {1} from {0}: {2}")]
+    ReadResourceError(String, RuntimeResourceID, ResourcePackageError),
+
+    #[error("Failed to read the metadata of {0}: {1}")]
+    ReadPackageError(String, ResourcePackageError),
+}
+
+/// Controls what is hashed while indexing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContentIndexOptions {
+    /// Whether the stored bytes are hashed as well, before decompressing and descrambling them.
+    pub hash_raw_data: bool,
+}
+
+impl ContentIndexOptions {
+    pub fn with_raw_data(mut self, hash_raw_data: bool) -> Self {
+        self.hash_raw_data = hash_raw_data;
+        self
+    }
+}
+
+/// Where a single occurrence of a resource is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceLocation {
+    pub partition_id: PartitionId,
+    pub patch_id: PatchId,
+    pub rrid: RuntimeResourceID,
+}
+
+/// A single occurrence of a resource together with its hashes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceOccurrence {
+    pub location: ResourceLocation,
+    /// The hash of the decompressed and descrambled data.
+    pub content_hash: ContentHash,
+    /// The hash of the bytes as they are stored in the package, if requested.
+    pub raw_hash: Option<ContentHash>,
+    pub size: u32,
+    /// The amount of bytes the occurrence takes up inside its package.
+    pub packaged_size: u32,
+}
+
+/// A set of occurrences which all contain the same data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    pub content_hash: ContentHash,
+    pub size: u32,
+    pub locations: Vec<ResourceLocation>,
+    /// The bytes which could be saved by only storing the smallest packaged occurrence.
+    pub wasted_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ContentIndex {
+    occurrences: Vec<ResourceOccurrence>,
+    by_content: HashMap<ContentHash, Vec<usize>>,
+    by_raw_data: HashMap<ContentHash, Vec<usize>>,
+}
+
+impl ContentIndex {
+    /// Hashes every resource inside every mounted package, including versions which are overridden by a later patch.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - A partition manager with mounted partitions.
+    /// - `options` - Controls what is hashed.
+    pub fn from_partition_manager(
+        partition_manager: &PartitionManager,
+        options: ContentIndexOptions,
+    ) -> Result<Self, ContentIndexError> {
+        let mut packages = partition_manager
+            .partitions
+            .iter()
+            .enumerate()
+            .flat_map(|(index, partition)| {
+                let info = partition.partition_info();
+                partition
+                    .packages
+                    .iter()
+                    .map(move |(patch_id, package)| (index, info, *patch_id, package))
+            })
+            .collect::<Vec<_>>();
+        packages.sort_by_key(|(index, _, patch_id, _)| (*index, *patch_id));
+
+        #[cfg(feature = "rayon")]
+        let packages = packages.into_par_iter();
+        #[cfg(not(feature = "rayon"))]
+        let packages = packages.into_iter();
+
+        let occurrences = packages
+            .map(|(_, info, patch_id, package)| {
+                hash_package(package, &info.id, patch_id, &info.filename(patch_id), options)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut index = Self::default();
+        for occurrence in occurrences.into_iter().flatten() {
+            index.insert(occurrence);
+        }
+        Ok(index)
+    }
+
+    fn insert(&mut self, occurrence: ResourceOccurrence) {
+        let position = self.occurrences.len();
+        self.by_content
+            .entry(occurrence.content_hash)
+            .or_default()
+            .push(position);
+        if let Some(raw_hash) = occurrence.raw_hash {
+            self.by_raw_data.entry(raw_hash).or_default().push(position);
+        }
+        self.occurrences.push(occurrence);
+    }
+
+    /// All indexed occurrences, ordered by partition mount order, patch and position inside the package.
+    pub fn occurrences(&self) -> &[ResourceOccurrence] {
+        &self.occurrences
+    }
+
+    /// Returns every occurrence of the given resource id.
+    pub fn occurrences_of(&self, rrid: &RuntimeResourceID) -> Vec<&ResourceOccurrence> {
+        self.occurrences
+            .iter()
+            .filter(|occurrence| occurrence.location.rrid == *rrid)
+            .collect()
+    }
+
+    /// Returns every location whose decompressed data has the given hash.
+    pub fn locations(&self, content_hash: &ContentHash) -> Vec<&ResourceLocation> {
+        self.lookup(&self.by_content, content_hash)
+    }
+
+    /// Returns every location whose stored bytes have the given hash.
+    ///
+    /// This is only populated when the index was built with [ContentIndexOptions::hash_raw_data].
+    pub fn raw_locations(&self, raw_hash: &ContentHash) -> Vec<&ResourceLocation> {
+        self.lookup(&self.by_raw_data, raw_hash)
+    }
+
+    fn lookup(
+        &self,
+        map: &HashMap<ContentHash, Vec<usize>>,
+        hash: &ContentHash,
+    ) -> Vec<&ResourceLocation> {
+        map.get(hash)
+            .map(|positions| {
+                positions
+                    .iter()
+                    .map(|position| &self.occurrences[*position].location)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns every set of occurrences which share the same data, the most wasteful groups come first.
+    pub fn duplicates(&self) -> Vec<DuplicateGroup> {
+        let mut groups = self
+            .by_content
+            .iter()
+            .filter(|(_, positions)| positions.len() > 1)
+            .map(|(content_hash, positions)| {
+                let occurrences = positions
+                    .iter()
+                    .map(|position| &self.occurrences[*position])
+                    .collect::<Vec<_>>();
+                let total = occurrences
+                    .iter()
+                    .map(|occurrence| occurrence.packaged_size as u64)
+                    .sum::<u64>();
+                let smallest = occurrences
+                    .iter()
+                    .map(|occurrence| occurrence.packaged_size as u64)
+                    .min()
+                    .unwrap_or_default();
+
+                DuplicateGroup {
+                    content_hash: *content_hash,
+                    size: occurrences[0].size,
+                    locations: occurrences
+                        .iter()
+                        .map(|occurrence| occurrence.location.clone())
+                        .collect(),
+                    wasted_bytes: total - smallest,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        groups.sort_by(|a, b| {
+            b.wasted_bytes
+                .cmp(&a.wasted_bytes)
+                .then_with(|| a.content_hash.0.cmp(&b.content_hash.0))
+        });
+        groups
+    }
+
+    /// The total amount of bytes wasted by duplicate data.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.duplicates()
+            .iter()
+            .map(|group| group.wasted_bytes)
+            .sum()
+    }
+
+    /// Returns the occurrences inside patch packages which ship the exact same data as the
+    /// previous version of the resource in the same partition.
+    pub fn reshipped(&self) -> Vec<&ResourceOccurrence> {
+        let mut previous = HashMap::<(&PartitionId, &RuntimeResourceID), &ResourceOccurrence>::new();
+        let mut reshipped = vec![];
+
+        // Occurrences are ordered by partition and patch, so the previous version is always seen first.
+        for occurrence in &self.occurrences {
+            let key = (&occurrence.location.partition_id, &occurrence.location.rrid);
+            if let Some(earlier) = previous.insert(key, occurrence) {
+                if occurrence.location.patch_id.is_patch()
+                    && earlier.content_hash == occurrence.content_hash
+                {
+                    reshipped.push(occurrence);
+                }
+            }
+        }
+
+        reshipped
+    }
+}
+
+fn hash_package(
+    package: &ResourcePackage,
+    partition_id: &PartitionId,
+    patch_id: PatchId,
+    filename: &str,
+    options: ContentIndexOptions,
+) -> Result<Vec<ResourceOccurrence>, ContentIndexError> {
+    let read_error = |rrid: &RuntimeResourceID, e| {
+        ContentIndexError::ReadResourceError(filename.to_string(), *rrid, e)
+    };
+
+    package
+        .load_metadata()
+        .map_err(|e| ContentIndexError::ReadPackageError(filename.to_string(), e))?
+        .iter()
+        .map(|(rrid, info)| {
+            let data = package
+                .read_resource_borrowed(rrid)
+                .map_err(|e| read_error(rrid, e))?;
+            let raw_hash = match options.hash_raw_data {
+                true => Some(ContentHash::from_data(
+                    package
+                        .raw_resource_data(rrid)
+                        .map_err(|e| read_error(rrid, e))?,
+                )),
+                false => None,
+            };
+
+            Ok(ResourceOccurrence {
+                location: ResourceLocation {
+                    partition_id: partition_id.clone(),
+                    patch_id,
+                    rrid: *rrid,
+                },
+                content_hash: ContentHash::from_data(&data),
+                raw_hash,
+                size: info.size(),
+                packaged_size: info.packaged_size(),
+            })
+        })
+        .collect()
+}
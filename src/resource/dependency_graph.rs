@@ -0,0 +1,623 @@
+//! Reference graph between the resources of a mounted game.
+//!
+//! Every resource lists the resources it references inside its metadata. The [DependencyGraph] indexes these
+//! references in both directions, so it can answer which resources a resource needs, which resources
+//! depend on it, and which resources are part of a reference cycle or aren't referenced at all.
+//!
+//! A [DependencySubgraph] can be extracted from the graph and exported to Graphviz DOT, or serialized to JSON.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+use thiserror::Error;
+
+#[cfg(feature = "path-list")]
+use crate::misc::hash_path_list::PathList;
+use crate::resource::partition_manager::PartitionManager;
+use crate::resource::pdefs::{PartitionId, PartitionInfo};
+use crate::resource::resource_info::ResourceInfo;
+use crate::resource::resource_package::{ReferenceType, ResourcePackage, ResourcePackageError, ResourceReferenceFlags};
+use crate::resource::runtime_resource_id::{PlatformTag, RuntimeResourceID};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Error)]
+pub enum DependencyGraphError {
+    #[error("Resource {0} is not part of the dependency graph")]
+    ResourceNotFound(RuntimeResourceID),
+
+    #[error("Could not read the metadata of the package: {0}")]
+    ReadPackageError(ResourcePackageError),
+}
+
+/// Selects which references are followed while walking the graph.
+///
+/// The default filter follows every reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceFilter {
+    install: bool,
+    normal: bool,
+    weak: bool,
+    runtime_acquired: bool,
+}
+
+impl Default for ReferenceFilter {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl ReferenceFilter {
+    /// A filter which follows every reference.
+    pub fn all() -> Self {
+        Self {
+            install: true,
+            normal: true,
+            weak: true,
+            runtime_acquired: true,
+        }
+    }
+
+    /// A filter which only follows references that have to be installed together with the resource.
+    pub fn install_only() -> Self {
+        Self::all().with_normal(false).with_weak(false)
+    }
+
+    pub fn with_install(mut self, follow: bool) -> Self {
+        self.install = follow;
+        self
+    }
+
+    pub fn with_normal(mut self, follow: bool) -> Self {
+        self.normal = follow;
+        self
+    }
+
+    pub fn with_weak(mut self, follow: bool) -> Self {
+        self.weak = follow;
+        self
+    }
+
+    /// Whether references which are acquired by the game at runtime are followed.
+    pub fn with_runtime_acquired(mut self, follow: bool) -> Self {
+        self.runtime_acquired = follow;
+        self
+    }
+
+    /// Returns true if a reference with the given flags passes the filter.
+    pub fn matches(&self, flags: &ResourceReferenceFlags) -> bool {
+        if flags.is_acquired() && !self.runtime_acquired {
+            return false;
+        }
+
+        match flags.reference_type() {
+            ReferenceType::INSTALL => self.install,
+            ReferenceType::NORMAL => self.normal,
+            ReferenceType::WEAK => self.weak,
+        }
+    }
+}
+
+/// A single resource inside the dependency graph.
+#[derive(Debug, Clone)]
+pub struct DependencyNode {
+    pub(crate) rrid: RuntimeResourceID,
+    pub(crate) data_type: String,
+    pub(crate) size: u32,
+    pub(crate) partitions: Vec<PartitionId>,
+    pub(crate) references: Vec<(RuntimeResourceID, ResourceReferenceFlags)>,
+}
+
+impl DependencyNode {
+    pub fn rrid(&self) -> &RuntimeResourceID {
+        &self.rrid
+    }
+
+    pub fn data_type(&self) -> &str {
+        &self.data_type
+    }
+
+    /// The size of the most recently mounted version of the resource.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The partitions containing the resource, in mount order.
+    pub fn partitions(&self) -> &[PartitionId] {
+        &self.partitions
+    }
+
+    /// The outgoing references of the resource.
+    ///
+    /// When a resource is present in multiple partitions the references of every version are merged.
+    pub fn references(&self) -> &[(RuntimeResourceID, ResourceReferenceFlags)] {
+        &self.references
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    nodes: HashMap<RuntimeResourceID, DependencyNode>,
+    referenced_by: HashMap<RuntimeResourceID, Vec<(RuntimeResourceID, ResourceReferenceFlags)>>,
+}
+
+impl DependencyGraph {
+    /// Builds the dependency graph of all resources mounted by the partition manager.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - A partition manager with mounted partitions.
+    pub fn from_partition_manager(partition_manager: &PartitionManager) -> Result<Self, DependencyGraphError> {
+        let mut graph = Self::default();
+        for partition in &partition_manager.partitions {
+            let partition_id = &partition.partition_info().id;
+            let resources = partition.latest_resources().map_err(DependencyGraphError::ReadPackageError)?;
+            for (info, _) in resources {
+                graph.add_resource(info, Some(partition_id));
+            }
+        }
+        graph.index_references();
+        Ok(graph)
+    }
+
+    /// Builds the dependency graph of the resources inside a single resource package.
+    ///
+    /// # Arguments
+    /// - `package` - The resource package to index.
+    pub fn from_resource_package(package: &ResourcePackage) -> Result<Self, DependencyGraphError> {
+        let mut graph = Self::default();
+        let resources = package.load_metadata().map_err(DependencyGraphError::ReadPackageError)?;
+        for info in resources.values() {
+            graph.add_resource(info, None);
+        }
+        graph.index_references();
+        Ok(graph)
+    }
+
+    fn add_resource(&mut self, info: &ResourceInfo, partition_id: Option<&PartitionId>) {
+        let node = self
+            .nodes
+            .entry(*info.rrid())
+            .or_insert_with(|| DependencyNode {
+                rrid: *info.rrid(),
+                data_type: String::new(),
+                size: 0,
+                partitions: vec![],
+                references: vec![],
+            });
+
+        node.data_type = info.data_type();
+        node.size = info.size();
+        if let Some(partition_id) = partition_id {
+            if !node.partitions.contains(partition_id) {
+                node.partitions.push(partition_id.clone());
+            }
+        }
+        for reference in info.references() {
+            if !node.references.contains(reference) {
+                node.references.push(*reference);
+            }
+        }
+    }
+
+    fn index_references(&mut self) {
+        self.referenced_by.clear();
+        for node in self.nodes.values() {
+            for (target, flags) in &node.references {
+                self.referenced_by
+                    .entry(*target)
+                    .or_default()
+                    .push((node.rrid, *flags));
+            }
+        }
+
+        for referrers in self.referenced_by.values_mut() {
+            referrers.sort_by_key(|(rrid, flags)| (u64::from(*rrid), flags.as_byte()));
+        }
+    }
+
+    /// The amount of resources inside the graph.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, rrid: &RuntimeResourceID) -> bool {
+        self.nodes.contains_key(rrid)
+    }
+
+    pub fn node(&self, rrid: &RuntimeResourceID) -> Option<&DependencyNode> {
+        self.nodes.get(rrid)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &DependencyNode> {
+        self.nodes.values()
+    }
+
+    /// Returns the resources which directly reference the given resource, together with the reference flags.
+    ///
+    /// A resource which isn't mounted can still be referenced, so this also works for unknown resources.
+    pub fn referenced_by(
+        &self,
+        rrid: &RuntimeResourceID,
+    ) -> &[(RuntimeResourceID, ResourceReferenceFlags)] {
+        self.referenced_by
+            .get(rrid)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns all resources the root transitively depends on, in breadth first order.
+    ///
+    /// Referenced resources which aren't part of the graph are included, but can't be walked any further.
+    ///
+    /// # Arguments
+    /// - `root` - The resource to start from, it is not part of the result.
+    /// - `filter` - Selects the references which are followed.
+    pub fn dependencies(
+        &self,
+        root: &RuntimeResourceID,
+        filter: ReferenceFilter,
+    ) -> Result<Vec<RuntimeResourceID>, DependencyGraphError> {
+        if !self.contains(root) {
+            return Err(DependencyGraphError::ResourceNotFound(*root));
+        }
+
+        Ok(self.walk(root, |rrid| {
+            self.nodes
+                .get(rrid)
+                .map(|node| node.references.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .filter(|(_, flags)| filter.matches(flags))
+                .map(|(target, _)| *target)
+                .collect()
+        }))
+    }
+
+    /// Returns all resources which transitively depend on the given resource, in breadth first order.
+    ///
+    /// These are the resources which can be affected by a change to the given resource.
+    ///
+    /// # Arguments
+    /// - `rrid` - The resource to start from, it is not part of the result.
+    /// - `filter` - Selects the references which are followed.
+    pub fn dependents(
+        &self,
+        rrid: &RuntimeResourceID,
+        filter: ReferenceFilter,
+    ) -> Result<Vec<RuntimeResourceID>, DependencyGraphError> {
+        if !self.contains(rrid) && !self.referenced_by.contains_key(rrid) {
+            return Err(DependencyGraphError::ResourceNotFound(*rrid));
+        }
+
+        Ok(self.walk(rrid, |rrid| {
+            self.referenced_by(rrid)
+                .iter()
+                .filter(|(_, flags)| filter.matches(flags))
+                .map(|(source, _)| *source)
+                .collect()
+        }))
+    }
+
+    fn walk<F>(&self, start: &RuntimeResourceID, neighbours: F) -> Vec<RuntimeResourceID>
+    where
+        F: Fn(&RuntimeResourceID) -> Vec<RuntimeResourceID>,
+    {
+        let mut visited = HashSet::from([*start]);
+        let mut queue = VecDeque::from([*start]);
+        let mut result = vec![];
+
+        while let Some(rrid) = queue.pop_front() {
+            for neighbour in neighbours(&rrid) {
+                if visited.insert(neighbour) {
+                    result.push(neighbour);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Finds all reference cycles in the graph.
+    ///
+    /// Every returned cycle is a group of resources which can all reach each other, it starts at the lowest id.
+    /// A resource which references itself is returned as a cycle of one.
+    ///
+    /// # Arguments
+    /// - `filter` - Selects the references which are followed.
+    pub fn cycles(&self, filter: ReferenceFilter) -> Vec<Vec<RuntimeResourceID>> {
+        let edges = |rrid: &RuntimeResourceID| -> Vec<RuntimeResourceID> {
+            self.nodes[rrid]
+                .references
+                .iter()
+                .filter(|(target, flags)| filter.matches(flags) && self.nodes.contains_key(target))
+                .map(|(target, _)| *target)
+                .collect()
+        };
+
+        // Tarjan's strongly connected components, iterative so large graphs can't overflow the stack.
+        let mut index = HashMap::<RuntimeResourceID, usize>::new();
+        let mut low_link = HashMap::<RuntimeResourceID, usize>::new();
+        let mut on_stack = HashSet::new();
+        let mut stack = vec![];
+        let mut cycles = vec![];
+
+        let mut roots = self.nodes.keys().copied().collect::<Vec<_>>();
+        roots.sort_by_key(|rrid| u64::from(*rrid));
+
+        for root in roots {
+            if index.contains_key(&root) {
+                continue;
+            }
+
+            let mut call_stack = vec![(root, edges(&root), 0usize)];
+            index.insert(root, index.len());
+            low_link.insert(root, index[&root]);
+            stack.push(root);
+            on_stack.insert(root);
+
+            while let Some((rrid, neighbours, next)) = call_stack.last_mut() {
+                let rrid = *rrid;
+                if let Some(&neighbour) = neighbours.get(*next) {
+                    *next += 1;
+                    if !index.contains_key(&neighbour) {
+                        index.insert(neighbour, index.len());
+                        low_link.insert(neighbour, index[&neighbour]);
+                        stack.push(neighbour);
+                        on_stack.insert(neighbour);
+                        call_stack.push((neighbour, edges(&neighbour), 0));
+                    } else if on_stack.contains(&neighbour) {
+                        let low = low_link[&rrid].min(index[&neighbour]);
+                        low_link.insert(rrid, low);
+                    }
+                    continue;
+                }
+
+                call_stack.pop();
+                if let Some((parent, _, _)) = call_stack.last() {
+                    let low = low_link[parent].min(low_link[&rrid]);
+                    low_link.insert(*parent, low);
+                }
+
+                if low_link[&rrid] != index[&rrid] {
+                    continue;
+                }
+
+                let mut component = vec![];
+                while let Some(member) = stack.pop() {
+                    on_stack.remove(&member);
+                    component.push(member);
+                    if member == rrid {
+                        break;
+                    }
+                }
+
+                let is_cycle = component.len() > 1 || edges(&rrid).contains(&rrid);
+                if is_cycle {
+                    component.sort_by_key(|rrid| u64::from(*rrid));
+                    cycles.push(component);
+                }
+            }
+        }
+
+        cycles.sort_by_key(|cycle| u64::from(cycle[0]));
+        cycles
+    }
+
+    /// Returns the resources which aren't referenced by any other resource in the graph, sorted by id.
+    ///
+    /// Top level resources such as scenes are never referenced, so they are always part of this list.
+    pub fn orphans(&self) -> Vec<RuntimeResourceID> {
+        let mut orphans = self
+            .nodes
+            .keys()
+            .filter(|rrid| {
+                self.referenced_by(rrid)
+                    .iter()
+                    .all(|(source, _)| source == *rrid)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        orphans.sort_by_key(|rrid| u64::from(*rrid));
+        orphans
+    }
+
+    /// Returns the references which point to resources that aren't part of the graph, sorted by id.
+    pub fn unresolved_references(&self) -> Vec<RuntimeResourceID> {
+        let mut unresolved = self
+            .referenced_by
+            .keys()
+            .filter(|rrid| !self.nodes.contains_key(rrid))
+            .copied()
+            .collect::<Vec<_>>();
+        unresolved.sort_by_key(|rrid| u64::from(*rrid));
+        unresolved
+    }
+
+    /// Extracts the part of the graph which is reachable from the given roots.
+    ///
+    /// # Arguments
+    /// - `roots` - The resources to start from.
+    /// - `filter` - Selects the references which are followed and exported.
+    pub fn subgraph(
+        &self,
+        roots: &[RuntimeResourceID],
+        filter: ReferenceFilter,
+    ) -> Result<DependencySubgraph, DependencyGraphError> {
+        let mut included = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut subgraph = DependencySubgraph::default();
+
+        for root in roots {
+            if !self.contains(root) {
+                return Err(DependencyGraphError::ResourceNotFound(*root));
+            }
+            if included.insert(*root) {
+                queue.push_back(*root);
+            }
+        }
+
+        while let Some(rrid) = queue.pop_front() {
+            let node = self.nodes.get(&rrid);
+            subgraph.nodes.push(SubgraphNode {
+                rrid,
+                data_type: node.map(|node| node.data_type.clone()),
+                size: node.map(|node| node.size),
+                partitions: node.map(|node| node.partitions.clone()).unwrap_or_default(),
+                path: None,
+            });
+
+            let references = node.map(|node| node.references.as_slice()).unwrap_or_default();
+            for (target, flags) in references.iter().filter(|(_, flags)| filter.matches(flags)) {
+                subgraph.edges.push(SubgraphEdge {
+                    from: rrid,
+                    to: *target,
+                    reference_type: flags.reference_type(),
+                    runtime_acquired: flags.is_acquired(),
+                    language_code: flags.language_code(),
+                });
+                if included.insert(*target) {
+                    queue.push_back(*target);
+                }
+            }
+        }
+
+        Ok(subgraph)
+    }
+
+    /// Extracts the part of the graph which is reachable from the roots of a partition.
+    ///
+    /// Roots which aren't part of the graph, for example because the partition isn't installed, are skipped.
+    ///
+    /// # Arguments
+    /// - `partition_info` - The partition whose roots are used.
+    /// - `filter` - Selects the references which are followed and exported.
+    pub fn partition_subgraph(
+        &self,
+        partition_info: &PartitionInfo,
+        filter: ReferenceFilter,
+    ) -> Result<DependencySubgraph, DependencyGraphError> {
+        let roots = partition_info
+            .roots
+            .iter()
+            .map(|root| RuntimeResourceID::from_resource_id_with_platform(root, "pc", PlatformTag::None))
+            .filter(|rrid| self.contains(rrid))
+            .collect::<Vec<_>>();
+        self.subgraph(&roots, filter)
+    }
+}
+
+/// A resource inside an exported [DependencySubgraph].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SubgraphNode {
+    pub rrid: RuntimeResourceID,
+    /// The resource type, None if the resource is referenced but not mounted.
+    pub data_type: Option<String>,
+    /// The resource size, None if the resource is referenced but not mounted.
+    pub size: Option<u32>,
+    pub partitions: Vec<PartitionId>,
+    /// The resolved resource path, if known.
+    pub path: Option<String>,
+}
+
+/// A reference between two resources inside an exported [DependencySubgraph].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SubgraphEdge {
+    pub from: RuntimeResourceID,
+    pub to: RuntimeResourceID,
+    pub reference_type: ReferenceType,
+    pub runtime_acquired: bool,
+    pub language_code: u8,
+}
+
+/// A self-contained part of a [DependencyGraph], ready to be exported.
+///
+/// Nodes are ordered breadth first from the roots, the roots come first.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DependencySubgraph {
+    pub nodes: Vec<SubgraphNode>,
+    pub edges: Vec<SubgraphEdge>,
+}
+
+impl DependencySubgraph {
+    /// Fills in the path of every node the resolver knows about.
+    ///
+    /// # Arguments
+    /// - `resolver` - Returns the path of a resource, or None if it is unknown.
+    pub fn resolve_paths<F>(&mut self, resolver: F) -> &mut Self
+    where
+        F: Fn(&RuntimeResourceID) -> Option<String>,
+    {
+        for node in &mut self.nodes {
+            node.path = resolver(&node.rrid);
+        }
+        self
+    }
+
+    /// Fills in the path of every node which is present in the path list.
+    ///
+    /// # Arguments
+    /// - `path_list` - A parsed path list.
+    #[cfg(feature = "path-list")]
+    pub fn resolve_paths_from_path_list(&mut self, path_list: &PathList) -> &mut Self {
+        self.resolve_paths(|rrid| path_list.get(rrid).map(|rid| rid.uri().to_string()))
+    }
+
+    /// Renders the subgraph as a Graphviz DOT document.
+    ///
+    /// Install references are drawn solid, normal references dashed and weak references dotted.
+    /// Resources which are referenced but not mounted are drawn grey.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n    node [shape=box];\n");
+
+        for node in &self.nodes {
+            let mut label = vec![node.path.clone().unwrap_or_else(|| node.rrid.to_string())];
+            if node.path.is_some() {
+                label.push(node.rrid.to_string());
+            }
+            match (&node.data_type, node.size) {
+                (Some(data_type), Some(size)) => label.push(format!("{data_type}, {size} bytes")),
+                _ => label.push("not mounted".to_string()),
+            }
+            if !node.partitions.is_empty() {
+                label.push(node.partitions.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "));
+            }
+
+            let label = label.iter().map(|line| escape_dot(line)).collect::<Vec<_>>().join("\\n");
+            let style = match node.data_type {
+                Some(_) => "",
+                None => ", style=filled, fillcolor=lightgrey",
+            };
+            let _ = writeln!(dot, "    \"{}\" [label=\"{}\"{}];", node.rrid, label, style);
+        }
+
+        for edge in &self.edges {
+            let (name, style) = match edge.reference_type {
+                ReferenceType::INSTALL => ("install", "solid"),
+                ReferenceType::NORMAL => ("normal", "dashed"),
+                ReferenceType::WEAK => ("weak", "dotted"),
+            };
+            let acquired = if edge.runtime_acquired { ", acquired" } else { "" };
+            let _ = writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"{}{}\", style={}];",
+                edge.from, edge.to, name, acquired, style
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
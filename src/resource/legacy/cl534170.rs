@@ -6,8 +6,9 @@ use std::fs::File;
 use std::io::{Cursor};
 use std::iter::zip;
 use std::path::Path;
+use std::sync::OnceLock;
 use std::{fmt};
-use crate::resource::resource_package::{PackageHeader, PackageOffsetFlags, ResourceHeader, ResourcePackageError, ResourcePackageSource};
+use crate::resource::resource_package::{PackageHeader, PackageOffsetFlags, ParseReader, ResourceHeader, ResourcePackageError, ResourcePackageSource};
 use crate::resource::runtime_resource_id::RuntimeResourceID;
 
 #[allow(dead_code)]
@@ -57,15 +58,18 @@ impl ResourcePackage {
     pub fn from_file<P: AsRef<Path> + Copy>(package_path: P) -> Result<Self, ResourcePackageError> {
         let file = File::open(package_path).map_err(ResourcePackageError::IoError)?;
         let mmap = unsafe { Mmap::map(&file).map_err(ResourcePackageError::IoError)? };
-        let mut reader = Cursor::new(&mmap[..]);
+        let mut reader = ParseReader::new(Cursor::new(&mmap[..]));
 
         let package_path = package_path.as_ref();
 
         let mut package = reader
             .read_ne_args::<ResourcePackage>(())
-            .map_err(ResourcePackageError::ParsingError)?;
+            .map_err(|e| ResourcePackageError::from_parse_error(e, reader.read_offset()))?;
 
-        package.source = Some(ResourcePackageSource::File(package_path.to_path_buf()));
+        package.source = Some(ResourcePackageSource::MappedFile(
+            package_path.to_path_buf(),
+            mmap,
+        ));
 
         Ok(package)
     }
@@ -75,10 +79,10 @@ impl ResourcePackage {
     /// # Arguments
     /// * `data` - The data to parse.
     pub fn from_memory(data: Vec<u8>) -> Result<Self, ResourcePackageError> {
-        let mut reader = Cursor::new(&data);
+        let mut reader = ParseReader::new(Cursor::new(&data));
         let mut package = reader
             .read_ne_args::<ResourcePackage>(())
-            .map_err(ResourcePackageError::ParsingError)?;
+            .map_err(|e| ResourcePackageError::from_parse_error(e, reader.read_offset()))?;
 
         package.source = Some(ResourcePackageSource::Memory(data));
         Ok(package)
@@ -119,12 +123,16 @@ impl From<ResourcePackage> for crate::resource::resource_package::ResourcePackag
     fn from(value: ResourcePackage) -> Self {
         Self{
             source: value.source,
+            is_patch_package: false,
             magic: value.magic,
             metadata: None,
             header: value.header,
             unneeded_resource_count: 0,
             unneeded_resources: None,
-            resources: value.resources,
+            offset_table: value.resources.values().map(|resource| resource.entry).collect(),
+            offset_indices: value.resources.keys().enumerate().map(|(index, rrid)| (*rrid, index)).collect(),
+            resources: OnceLock::from(value.resources),
+            metadata_positions: OnceLock::new(),
         }
     }
 }
@@ -1,8 +1,21 @@
+pub mod compression_policy;
+pub mod content_index;
+pub mod dependency_graph;
+pub mod mount_cache;
 pub mod package_builder;
+#[cfg(feature = "serde")]
+pub mod package_manifest;
+pub mod package_patcher;
+pub mod package_verification;
 pub mod partition_manager;
 pub mod pdefs;
+pub mod resource_diff;
 pub mod resource_info;
 pub mod resource_package;
 pub mod resource_partition;
+pub mod resource_reader;
+pub mod resource_vfs;
 pub mod runtime_resource_id;
+#[cfg(all(feature = "watch", target_os = "linux"))]
+pub mod runtime_watcher;
 pub mod legacy;
@@ -0,0 +1,509 @@
+//! A persistent cache of the mounted state of a game.
+//!
+//! Mounting a game reads the offset and metadata tables of every package in the runtime directory. A
+//! [MountCache] stores the decoded tables, together with the partition infos, in a single file. Packages are
+//! cached by their path, with the size and modification time they had when they were mounted. Mounting with the
+//! cache only parses packages whose size or modification time changed since then, every other package is
+//! restored from its cached metadata without reading its tables.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+use binrw::{binrw, BinRead, BinWrite};
+use indexmap::IndexMap;
+use memmap2::Mmap;
+use thiserror::Error;
+
+use crate::misc::resource_id::ResourceID;
+use crate::resource::partition_manager::PartitionManager;
+use crate::resource::pdefs::{PartitionId, PartitionInfo, PartitionType};
+use crate::resource::resource_info::ResourceInfo;
+use crate::resource::resource_package::{
+    PackageHeader, PackageMetadata, PackageOffsetInfo, ResourceHeader, ResourcePackage, ResourcePackageError,
+    ResourcePackageSource, ResourceReferenceFlags, ResourceReferenceFlagsLegacy, ResourceReferenceFlagsStandard,
+};
+use crate::resource::runtime_resource_id::RuntimeResourceID;
+
+/// The version of the cache file format, caches written by another version are rejected.
+pub const MOUNT_CACHE_VERSION: u32 = 2;
+
+#[derive(Debug, Error)]
+pub enum MountCacheError {
+    #[error("Error accessing the cache file: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Error parsing the cache file: {0}")]
+    ParsingError(#[from] binrw::Error),
+
+    #[error("The cache file has version {0}, expected version {MOUNT_CACHE_VERSION}")]
+    UnsupportedVersion(u32),
+
+    #[error("Package {0} is not backed by a file and cannot be cached")]
+    PackageNotOnDisk(String),
+
+    #[error("Error reading the metadata of package {1}: {0}")]
+    ReadPackageError(ResourcePackageError, String),
+}
+
+/// The size and modification time of a file, used to detect changed packages.
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+}
+
+impl FileStamp {
+    /// Reads the stamp of the file at the given path.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_err(io::Error::other)?;
+        Ok(Self {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+/// The decoded tables of a single package, as they were when the package was mounted.
+#[binrw]
+#[brw(little)]
+#[derive(Clone)]
+pub struct CachedPackage {
+    pub stamp: FileStamp,
+    #[br(map = |is_patch: u8| is_patch != 0)]
+    #[bw(map = |is_patch: &bool| *is_patch as u8)]
+    pub is_patch: bool,
+    magic: [u8; 4],
+    #[br(if(magic == *b"2KPR"))]
+    #[bw(if(magic == b"2KPR"))]
+    metadata: Option<PackageMetadata>,
+    offset_table_size: u32,
+    metadata_table_size: u32,
+    #[bw(calc = unneeded_resources.len() as u32)]
+    unneeded_resource_count: u32,
+    #[br(count = unneeded_resource_count)]
+    unneeded_resources: Vec<RuntimeResourceID>,
+    /// The metadata of every entry of the offset table, in order.
+    #[bw(calc = resources.len() as u32)]
+    resource_count: u32,
+    #[br(count = resource_count)]
+    resources: Vec<CachedResource>,
+}
+
+impl CachedPackage {
+    /// Collects the decoded tables of a package, decoding its metadata table if that hasn't happened yet.
+    fn new(package: &ResourcePackage, stamp: FileStamp) -> Result<Self, ResourcePackageError> {
+        let resources = package.load_metadata()?;
+        Ok(Self {
+            stamp,
+            is_patch: package.is_patch(),
+            magic: package.magic,
+            metadata: package.metadata,
+            offset_table_size: package.header.offset_table_size,
+            metadata_table_size: package.header.metadata_table_size,
+            unneeded_resources: package.unneeded_resource_ids().into_iter().copied().collect(),
+            resources: package
+                .offset_table
+                .iter()
+                .filter_map(|entry| resources.get(&entry.runtime_resource_id))
+                .map(CachedResource::from)
+                .collect(),
+        })
+    }
+
+    /// Rebuilds the package from the cached tables, reading resources from the file at `package_path`.
+    fn restore(&self, package_path: &Path) -> Result<ResourcePackage, ResourcePackageError> {
+        let offset_table = self.resources.iter().map(|resource| resource.entry).collect::<Vec<_>>();
+        let offset_indices = offset_table
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| (entry.runtime_resource_id, index))
+            .collect();
+        let resources = self
+            .resources
+            .iter()
+            .map(|resource| (resource.entry.runtime_resource_id, ResourceInfo::from(resource)))
+            .collect::<IndexMap<_, _>>();
+
+        let file = File::open(package_path)?;
+        // SAFETY: See ResourcePackage::from_file_lazy, package files are replaced instead of modified in place.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(ResourcePackage {
+            source: Some(ResourcePackageSource::MappedFile(package_path.to_path_buf(), mmap)),
+            is_patch_package: self.is_patch,
+            magic: self.magic,
+            metadata: self.metadata,
+            header: PackageHeader {
+                file_count: self.resources.len() as u32,
+                offset_table_size: self.offset_table_size,
+                metadata_table_size: self.metadata_table_size,
+            },
+            unneeded_resource_count: self.unneeded_resources.len() as u32,
+            unneeded_resources: match self.unneeded_resources.len() {
+                0 => None,
+                _ => Some(self.unneeded_resources.clone()),
+            },
+            offset_table,
+            offset_indices,
+            resources: OnceLock::from(resources),
+            metadata_positions: OnceLock::new(),
+        })
+    }
+}
+
+/// The mounted state of a game: the partition infos and the tables of every mounted package.
+#[derive(Default)]
+pub struct MountCache {
+    partition_infos: Vec<PartitionInfo>,
+    packages: HashMap<PathBuf, CachedPackage>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl MountCache {
+    /// Collects the decoded tables of every package mounted by the partition manager.
+    ///
+    /// Packages are stamped with the size and modification time they had when they were mounted, so a package
+    /// which changed on disk since then is parsed again when mounting with the cache. Packages which were inserted
+    /// into a partition by hand have no stamp and are left out. The metadata tables of lazily mounted packages are
+    /// decoded.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - A partition manager with mounted partitions.
+    pub fn from_partition_manager(partition_manager: &PartitionManager) -> Result<Self, MountCacheError> {
+        let mut cache = Self {
+            partition_infos: partition_manager.partition_infos.clone(),
+            ..Default::default()
+        };
+
+        for partition in &partition_manager.partitions {
+            for (patch_id, package) in &partition.packages {
+                let file_name = partition.partition_info().filename(*patch_id);
+                let Some(path) = package.source().and_then(|source| source.path()) else {
+                    return Err(MountCacheError::PackageNotOnDisk(file_name));
+                };
+                let Some(stamp) = partition.package_stamps.get(patch_id) else {
+                    continue;
+                };
+
+                let cached = CachedPackage::new(package, *stamp)
+                    .map_err(|e| MountCacheError::ReadPackageError(e, file_name))?;
+                cache.packages.insert(cache_key(path), cached);
+            }
+        }
+
+        Ok(cache)
+    }
+
+    /// Reads a cache from a file.
+    ///
+    /// # Arguments
+    /// - `path` - The path to the cache file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, MountCacheError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header = MountCacheHeader::read(&mut reader)?;
+        if header.version != MOUNT_CACHE_VERSION {
+            return Err(MountCacheError::UnsupportedVersion(header.version));
+        }
+        let file = MountCacheFile::read(&mut reader)?;
+
+        Ok(Self {
+            partition_infos: file.partitions.into_iter().map(PartitionInfo::from).collect(),
+            packages: file
+                .packages
+                .into_iter()
+                .map(|entry| (PathBuf::from(entry.path.value), entry.package))
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// Writes the cache to a file, replacing it if it exists.
+    ///
+    /// # Arguments
+    /// - `path` - The path to the cache file.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), MountCacheError> {
+        let mut packages = self.packages.iter().collect::<Vec<_>>();
+        packages.sort_by_key(|(path, _)| *path);
+
+        let file = MountCacheFile {
+            partitions: self.partition_infos.iter().map(CachedPartitionInfo::from).collect(),
+            packages: packages
+                .into_iter()
+                .map(|(path, package)| CachedPackageEntry {
+                    path: CacheString::from(path.to_string_lossy().into_owned()),
+                    package: package.clone(),
+                })
+                .collect(),
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        MountCacheHeader {
+            version: MOUNT_CACHE_VERSION,
+        }
+        .write(&mut writer)?;
+        file.write(&mut writer)?;
+        Ok(())
+    }
+
+    /// Returns the partition infos the cache was written with.
+    pub fn partition_infos(&self) -> &[PartitionInfo] {
+        &self.partition_infos
+    }
+
+    /// Returns the cached tables of the package at the given path.
+    pub fn package<P: AsRef<Path>>(&self, package_path: P) -> Option<&CachedPackage> {
+        self.packages.get(&cache_key(package_path.as_ref()))
+    }
+
+    /// Returns the number of packages which were restored from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of packages which had to be parsed because they changed or weren't cached.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Restores the package at `package_path` if its current stamp matches the one it was cached with.
+    ///
+    /// Restored packages always have their metadata decoded.
+    pub(crate) fn restore_package(&self, package_path: &Path, stamp: Option<&FileStamp>) -> Option<ResourcePackage> {
+        let package = self
+            .package(package_path)
+            .filter(|package| stamp == Some(&package.stamp))
+            .and_then(|package| package.restore(package_path).ok());
+
+        match package {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        package
+    }
+}
+
+#[binrw]
+#[brw(little, magic = b"RPMC")]
+struct MountCacheHeader {
+    version: u32,
+}
+
+#[binrw]
+#[brw(little)]
+struct MountCacheFile {
+    #[bw(calc = partitions.len() as u32)]
+    partition_count: u32,
+    #[br(count = partition_count)]
+    partitions: Vec<CachedPartitionInfo>,
+
+    #[bw(calc = packages.len() as u32)]
+    package_count: u32,
+    #[br(count = package_count)]
+    packages: Vec<CachedPackageEntry>,
+}
+
+/// Makes package paths absolute, so packages with the same file name in different directories don't collide.
+fn cache_key(package_path: &Path) -> PathBuf {
+    std::path::absolute(package_path).unwrap_or_else(|_| package_path.to_path_buf())
+}
+
+#[binrw]
+#[brw(little)]
+struct CachedPackageEntry {
+    path: CacheString,
+    package: CachedPackage,
+}
+
+/// The offset table entry and the decoded metadata of a resource.
+#[binrw]
+#[brw(little)]
+#[derive(Clone)]
+struct CachedResource {
+    entry: PackageOffsetInfo,
+    resource_type: [u8; 4],
+    references_chunk_size: u32,
+    states_chunk_size: u32,
+    data_size: u32,
+    system_memory_requirement: u32,
+    video_memory_requirement: u32,
+    #[bw(calc = references.len() as u32)]
+    reference_count: u32,
+    #[br(count = reference_count)]
+    references: Vec<CachedReference>,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Clone)]
+struct CachedReference {
+    rrid: RuntimeResourceID,
+    #[br(map = |is_legacy: u8| is_legacy != 0)]
+    #[bw(map = |is_legacy: &bool| *is_legacy as u8)]
+    is_legacy: bool,
+    flags: u8,
+}
+
+#[binrw]
+#[brw(little)]
+struct CachedPartitionInfo {
+    #[br(map = |name: CacheString| Some(name.value).filter(|name| !name.is_empty()))]
+    #[bw(map = |name: &Option<String>| CacheString::from(name.clone().unwrap_or_default()))]
+    name: Option<String>,
+    #[br(temp)]
+    #[bw(calc = parent.is_some() as u8)]
+    has_parent: u8,
+    #[br(if(has_parent != 0))]
+    parent: Option<CachedPartitionId>,
+    id: CachedPartitionId,
+    patch_level: u64,
+    #[bw(calc = roots.len() as u32)]
+    root_count: u32,
+    #[br(count = root_count)]
+    roots: Vec<CacheString>,
+}
+
+#[binrw]
+#[brw(little)]
+struct CachedPartitionId {
+    kind: u8,
+    index: u64,
+    language: CacheString,
+}
+
+/// A length prefixed utf-8 string.
+#[binrw]
+#[brw(little)]
+struct CacheString {
+    #[br(temp)]
+    #[bw(calc = value.len() as u32)]
+    length: u32,
+    #[br(count = length, try_map = String::from_utf8)]
+    #[bw(map = |value: &String| value.as_bytes().to_vec())]
+    value: String,
+}
+
+impl From<&ResourceInfo> for CachedResource {
+    fn from(resource: &ResourceInfo) -> Self {
+        let header = &resource.header;
+        Self {
+            entry: resource.entry,
+            resource_type: header.resource_type,
+            references_chunk_size: header.references_chunk_size,
+            states_chunk_size: header.states_chunk_size,
+            data_size: header.data_size,
+            system_memory_requirement: header.system_memory_requirement,
+            video_memory_requirement: header.video_memory_requirement,
+            references: header
+                .references
+                .iter()
+                .map(|(rrid, flags)| CachedReference {
+                    rrid: *rrid,
+                    is_legacy: matches!(flags, ResourceReferenceFlags::Legacy(_)),
+                    flags: flags.as_byte(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&CachedResource> for ResourceInfo {
+    fn from(resource: &CachedResource) -> Self {
+        let references = resource
+            .references
+            .iter()
+            .map(|reference| {
+                let flags = match reference.is_legacy {
+                    true => ResourceReferenceFlags::Legacy(ResourceReferenceFlagsLegacy::from_bits(reference.flags)),
+                    false => ResourceReferenceFlags::Standard(ResourceReferenceFlagsStandard::from_bits(reference.flags)),
+                };
+                (reference.rrid, flags)
+            })
+            .collect();
+
+        Self {
+            entry: resource.entry,
+            header: ResourceHeader {
+                resource_type: resource.resource_type,
+                references_chunk_size: resource.references_chunk_size,
+                states_chunk_size: resource.states_chunk_size,
+                data_size: resource.data_size,
+                system_memory_requirement: resource.system_memory_requirement,
+                video_memory_requirement: resource.video_memory_requirement,
+                references,
+            },
+        }
+    }
+}
+
+impl From<String> for CacheString {
+    fn from(value: String) -> Self {
+        Self { value }
+    }
+}
+
+impl From<&PartitionId> for CachedPartitionId {
+    fn from(id: &PartitionId) -> Self {
+        let (kind, language) = match &id.part_type {
+            PartitionType::Standard => (0, String::new()),
+            PartitionType::Addon => (1, String::new()),
+            PartitionType::Dlc => (2, String::new()),
+            PartitionType::LanguageStandard(language) => (3, language.clone()),
+            PartitionType::LanguageDlc(language) => (4, language.clone()),
+        };
+        Self {
+            kind,
+            index: id.index as u64,
+            language: CacheString::from(language),
+        }
+    }
+}
+
+impl From<CachedPartitionId> for PartitionId {
+    fn from(id: CachedPartitionId) -> Self {
+        let part_type = match id.kind {
+            1 => PartitionType::Addon,
+            2 => PartitionType::Dlc,
+            3 => PartitionType::LanguageStandard(id.language.value),
+            4 => PartitionType::LanguageDlc(id.language.value),
+            _ => PartitionType::Standard,
+        };
+        Self {
+            part_type,
+            index: id.index as usize,
+        }
+    }
+}
+
+impl From<&PartitionInfo> for CachedPartitionInfo {
+    fn from(info: &PartitionInfo) -> Self {
+        Self {
+            name: info.name.clone(),
+            parent: info.parent.as_ref().map(CachedPartitionId::from),
+            id: CachedPartitionId::from(&info.id),
+            patch_level: info.patch_level as u64,
+            roots: info.roots.iter().map(|root| CacheString::from(root.uri().to_string())).collect(),
+        }
+    }
+}
+
+impl From<CachedPartitionInfo> for PartitionInfo {
+    fn from(info: CachedPartitionInfo) -> Self {
+        Self {
+            name: info.name,
+            parent: info.parent.map(PartitionId::from),
+            id: PartitionId::from(info.id),
+            patch_level: info.patch_level as usize,
+            roots: info.roots.into_iter().filter_map(|root| root.value.parse::<ResourceID>().ok()).collect(),
+        }
+    }
+}
@@ -1,17 +1,24 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::{BufWriter, Read, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
+use crate::encryption::scramble::ScrambleWriter;
+use crate::resource::compression_policy::{
+    Compression, CompressionCandidate, CompressionPolicy, RequestedCompression,
+};
 use crate::resource::pdefs::{PartitionId, PartitionType};
 use crate::resource::resource_package::{
     ChunkType, PackageHeader, PackageMetadata, PackageOffsetFlags, PackageOffsetInfo,
-    PackageVersion, ResourceHeader, ResourcePackage, ResourcePackageSource,
+    PackageVersion, ResourceHeader, ResourcePackage, ResourcePackageError, ResourcePackageSource,
     ResourceReferenceCountAndFlags, ResourceReferenceFlags,
 };
-use crate::resource::resource_partition::PatchId;
+use crate::resource::resource_partition::{PatchId, ResourcePartition, ResourcePartitionError};
+use crate::resource::resource_reader::ResourceReader;
 use crate::resource::runtime_resource_id::RuntimeResourceID;
 use crate::{GlacierResource, GlacierResourceError, WoaVersion};
 use binrw::BinWrite;
@@ -19,9 +26,25 @@ use binrw::__private::Required;
 use binrw::io::Cursor;
 use binrw::meta::WriteEndian;
 use indexmap::{IndexMap, IndexSet};
-use lzzzz::{lz4, lz4_hc};
+#[cfg(feature = "rayon")]
+use lzzzz::lz4;
 use thiserror::Error;
 use crate::resource::resource_info::ResourceInfo;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// The compression level used for resources which were compressed in their original package.
+pub(crate) const DEFAULT_COMPRESSION_LEVEL: i32 = 12;
+
+/// The default limit of the memory used by compression buffers while building a package.
+const DEFAULT_COMPRESSION_MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+/// Data written instead of the data of a resource blob, produced while building.
+pub(crate) enum ResourceData {
+    Compressed(Vec<u8>),
+    /// The decompressed data of a blob which is packaged compressed.
+    Uncompressed(Vec<u8>),
+}
 
 /// `PackageResourceBlob` is an enum representing various types of package resource stores, which can
 /// include files, file sections, and memory buffers, optionally compressed or scrambled.
@@ -68,6 +91,58 @@ impl PackageResourceBlob {
             },
         }
     }
+
+    /// The compression level the blob was created with.
+    ///
+    /// Blobs which are already packaged compressed count as compressed with the default level.
+    fn compression_level(&self) -> Option<i32> {
+        match self {
+            PackageResourceBlob::File { compression_level, .. }
+            | PackageResourceBlob::Memory { compression_level, .. } => *compression_level,
+            _ => self.packaged_compressed_size().map(|_| DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+
+    /// The compressed size of a blob which is already packaged, None if it isn't packaged compressed.
+    fn packaged_compressed_size(&self) -> Option<u32> {
+        match self {
+            PackageResourceBlob::FileAtOffset { compressed_size, .. } => *compressed_size,
+            PackageResourceBlob::CompressedMemory {
+                data,
+                decompressed_size,
+                ..
+            } => decompressed_size.map(|_| data.len() as u32),
+            _ => None,
+        }
+    }
+
+    /// Whether the stored data of the blob is scrambled.
+    fn is_scrambled(&self) -> bool {
+        match self {
+            PackageResourceBlob::File { should_scramble, .. }
+            | PackageResourceBlob::Memory { should_scramble, .. } => *should_scramble,
+            PackageResourceBlob::FileAtOffset { is_scrambled, .. }
+            | PackageResourceBlob::CompressedMemory { is_scrambled, .. } => *is_scrambled,
+        }
+    }
+
+    /// Compresses the blob, returns None for blobs which are already packaged.
+    fn compress(&self, compression: Compression) -> Result<Option<Vec<u8>>, PackageBuilderError> {
+        match self {
+            PackageResourceBlob::File { path, size, .. } => {
+                // TODO: Switch to streaming API.
+                let mut decompressed_data = vec![0; *size as usize];
+                File::open(path)
+                    .and_then(|mut file| file.read_exact(&mut decompressed_data))
+                    .map_err(PackageBuilderError::IoError)?;
+                Ok(Some(compression.backend.compress(&decompressed_data, compression.level)?))
+            }
+            PackageResourceBlob::Memory { data, .. } => {
+                Ok(Some(compression.backend.compress(data, compression.level)?))
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 /// A builder for creating a resource within a ResourcePackage
@@ -77,6 +152,7 @@ pub struct PackageResourceBuilder {
     resource_type: [u8; 4],
     system_memory_requirement: u32,
     video_memory_requirement: u32,
+    pub(crate) states_chunk_size: u32,
     // We store references in a vector because their order is important and there can be duplicates.
     references: Vec<(RuntimeResourceID, ResourceReferenceFlags)>,
 }
@@ -142,6 +218,7 @@ impl PackageResourceBuilder {
 
         Ok(Self {
             rrid,
+            states_chunk_size: 0,
             resource_type: Self::resource_type_to_bytes(resource_type)?,
             system_memory_requirement: file_size as u32,
             video_memory_requirement: u32::MAX,
@@ -191,6 +268,7 @@ impl PackageResourceBuilder {
 
         Ok(Self {
             rrid,
+            states_chunk_size: 0,
             resource_type: Self::resource_type_to_bytes(resource_type)?,
             system_memory_requirement: size,
             video_memory_requirement: u32::MAX,
@@ -228,6 +306,7 @@ impl PackageResourceBuilder {
 
         Ok(Self {
             rrid,
+            states_chunk_size: 0,
             resource_type: Self::resource_type_to_bytes(resource_type)?,
             system_memory_requirement: real_size,
             video_memory_requirement: u32::MAX,
@@ -266,6 +345,7 @@ impl PackageResourceBuilder {
 
         Ok(Self {
             rrid,
+            states_chunk_size: 0,
             resource_type: Self::resource_type_to_bytes(resource_type)?,
             system_memory_requirement: real_size,
             video_memory_requirement: u32::MAX,
@@ -305,13 +385,14 @@ impl PackageResourceBuilder {
         } else {
             PackageResourceBlob::Memory {
                 data,
-                compression_level: if resource_info.is_compressed() {Some(12)} else {None},
+                compression_level: resource_info.is_compressed().then_some(DEFAULT_COMPRESSION_LEVEL),
                 should_scramble: resource_info.is_scrambled(),
             }
         };
 
         Ok(Self {
             rrid: resource_info.entry.runtime_resource_id,
+            states_chunk_size: resource_info.header.states_chunk_size,
             resource_type: resource_info.header.resource_type,
             system_memory_requirement: resource_info.header.system_memory_requirement,
             video_memory_requirement: resource_info.header.video_memory_requirement,
@@ -339,6 +420,7 @@ impl PackageResourceBuilder {
 
         Ok(Self {
             rrid,
+            states_chunk_size: 0,
             resource_type: G::resource_type().into_iter().rev().collect::<Vec<_>>().try_into().unwrap(),
             system_memory_requirement: u32::try_from(system_memory_requirement).unwrap_or(u32::MAX),
             video_memory_requirement: u32::try_from(video_memory_requirement).unwrap_or(u32::MAX),
@@ -396,6 +478,74 @@ impl PackageResourceBuilder {
         self.video_memory_requirement = video_memory_requirement;
         self
     }
+
+    /// Returns the resource ID of the resource.
+    pub fn rrid(&self) -> &RuntimeResourceID {
+        &self.rrid
+    }
+
+    /// Compresses the resource, reading and decompressing it first when it's already packaged.
+    fn compress(&self, compression: Compression) -> Result<Vec<u8>, PackageBuilderError> {
+        if let Some(compressed) = self.blob.compress(compression)? {
+            return Ok(compressed);
+        }
+
+        let data = self
+            .read_data()
+            .map_err(|e| PackageBuilderError::CannotReadResource(self.rrid, e))?;
+        Ok(compression.backend.compress(&data, compression.level)?)
+    }
+
+    /// Compresses the resource the way it was added, with the default backend of the package version.
+    /// Returns None for resources which are stored uncompressed or are already packaged.
+    pub(crate) fn compress_requested(&self, version: PackageVersion) -> Result<Option<Vec<u8>>, PackageBuilderError> {
+        match self.blob.compression_level() {
+            Some(level) => self.blob.compress(Compression::for_version(version, level)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the data of the resource, decompressing and descrambling it when needed.
+    pub fn read_data(&self) -> Result<Vec<u8>, PackageResourceBuilderError> {
+        let mut data = Vec::with_capacity(self.blob.size() as usize);
+        match &self.blob {
+            PackageResourceBlob::File { path, .. } => {
+                File::open(path)?.read_to_end(&mut data)?;
+            }
+            PackageResourceBlob::FileAtOffset {
+                path,
+                offset,
+                size,
+                compressed_size,
+                is_scrambled,
+            } => {
+                let file = File::open(path)?;
+                let packaged_size = compressed_size.unwrap_or(*size);
+                let decompressed_size = compressed_size.map(|_| *size);
+                ResourceReader::from_file(file, *offset, packaged_size, decompressed_size, *is_scrambled)?
+                    .read_to_end(&mut data)?;
+            }
+            PackageResourceBlob::Memory { data: blob, .. } => data.extend_from_slice(blob),
+            PackageResourceBlob::CompressedMemory {
+                data: blob,
+                decompressed_size,
+                is_scrambled,
+            } => {
+                ResourceReader::from_slice(blob, *decompressed_size, *is_scrambled)
+                    .read_to_end(&mut data)?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Returns whether the metadata of this resource matches an already packaged resource.
+    fn header_matches(&self, resource_info: &ResourceInfo) -> bool {
+        self.resource_type == resource_info.header.resource_type
+            && self.blob.size() == resource_info.header.data_size
+            && self.system_memory_requirement == resource_info.header.system_memory_requirement
+            && self.video_memory_requirement == resource_info.header.video_memory_requirement
+            && self.references == resource_info.header.references
+    }
 }
 
 /// A builder for creating a ResourcePackage.
@@ -425,6 +575,23 @@ pub struct PackageBuilder {
     use_legacy_references: bool,
     resources: IndexMap<RuntimeResourceID, PackageResourceBuilder>,
     unneeded_resources: IndexSet<RuntimeResourceID>,
+    compression_memory_limit: usize,
+    compression_policy: Box<dyn CompressionPolicy>,
+    layout: Option<PackageLayout>,
+}
+
+/// The parts of an original package which aren't described by its resources, reproduced when rebuilding it
+/// losslessly. See [PackageBuilder::from_resource_package_lossless].
+struct PackageLayout {
+    metadata: Option<PackageMetadata>,
+    is_patch: bool,
+    has_states_size: bool,
+    /// The bytes between the end of the tables and the data of the first resource.
+    leading_padding: Vec<u8>,
+    /// The resources in the order their data is stored in, with the bytes following the data of each of them.
+    data_order: IndexMap<RuntimeResourceID, Vec<u8>>,
+    /// The list of unneeded resources, including ids which are listed more than once.
+    unneeded_resources: Vec<RuntimeResourceID>,
 }
 
 #[derive(Debug, Error)]
@@ -453,9 +620,18 @@ pub enum PackageBuilderError {
     #[error("Cannot build from a resource package without a source")]
     NoSource,
 
+    #[error("Could not read the metadata of the source package: {0}")]
+    CannotReadSourceMetadata(ResourcePackageError),
+
     #[error("Could not duplicate resource {0} from the source package: {1}")]
     CannotDuplicateResource(RuntimeResourceID, PackageResourceBuilderError),
 
+    #[error("Could not read resource {0}: {1}")]
+    CannotReadResource(RuntimeResourceID, PackageResourceBuilderError),
+
+    #[error("Could not read resource {0} from the original partition: {1}")]
+    CannotReadPartitionResource(RuntimeResourceID, ResourcePartitionError),
+
     #[error("LZ4 compression error: {0}")]
     Lz4CompressionError(#[from] lzzzz::Error),
 
@@ -464,6 +640,9 @@ pub enum PackageBuilderError {
 
     #[error("Patch id cannot be greater than 255")]
     InvalidPatchId,
+
+    #[error("Resource {0} cannot be rebuilt losslessly, its data overlaps the data of another resource or the tables")]
+    OverlappingResourceData(RuntimeResourceID),
 }
 
 struct OffsetTableResult {
@@ -475,28 +654,6 @@ struct MetadataTableResult {
     metadata_table_size: u32,
 }
 
-/// A writer that xors the data with a predefined key.
-struct XorWriter<'a, W: Write + Seek> {
-    writer: &'a mut W,
-}
-
-impl<W: Write + Seek> Write for XorWriter<'_, W> {
-    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-        let str_xor = [0xdc, 0x45, 0xa6, 0x9c, 0xd3, 0x72, 0x4c, 0xab];
-
-        for (index, byte) in buf.iter().enumerate() {
-            let xored_byte = *byte ^ str_xor[index % str_xor.len()];
-            self.writer.write_all(&[xored_byte])?;
-        }
-
-        Ok(buf.len())
-    }
-
-    fn flush(&mut self) -> Result<(), io::Error> {
-        self.writer.flush()
-    }
-}
-
 impl PackageBuilder {
     /// Creates a new package builder.
     ///
@@ -516,6 +673,9 @@ impl PackageBuilder {
             patch_id: PatchId::Base,
             resources: IndexMap::new(),
             unneeded_resources: IndexSet::new(),
+            compression_memory_limit: DEFAULT_COMPRESSION_MEMORY_LIMIT,
+            compression_policy: Box::new(RequestedCompression),
+            layout: None,
         }
     }
 
@@ -531,6 +691,9 @@ impl PackageBuilder {
             use_legacy_references: false,
             resources: IndexMap::new(),
             unneeded_resources: IndexSet::new(),
+            compression_memory_limit: DEFAULT_COMPRESSION_MEMORY_LIMIT,
+            compression_policy: Box::new(RequestedCompression),
+            layout: None,
         }
     }
 
@@ -546,40 +709,25 @@ impl PackageBuilder {
             .as_ref()
             .ok_or(PackageBuilderError::NoSource)?;
 
+        let (partition_id, patch_id) = Self::package_ids(resource_package);
         let mut package = Self {
-            partition_id: PartitionId {
-                part_type: match resource_package
-                    .metadata
-                    .as_ref()
-                    .map(|m| m.chunk_type)
-                    .unwrap_or_default()
-                {
-                    ChunkType::Standard => PartitionType::Standard,
-                    ChunkType::Addon => PartitionType::Addon,
-                },
-                index: resource_package
-                    .metadata
-                    .as_ref()
-                    .map(|m| m.chunk_id)
-                    .unwrap_or_default() as usize,
-            },
-            patch_id: match resource_package
-                .metadata
-                .as_ref()
-                .map(|m| m.patch_id)
-                .unwrap_or_default()
-            {
-                0 => PatchId::Base,
-                x => PatchId::Patch(x as usize),
-            },
+            partition_id,
+            patch_id,
             use_legacy_references: false,
             resources: IndexMap::new(),
             unneeded_resources: IndexSet::new(),
+            compression_memory_limit: DEFAULT_COMPRESSION_MEMORY_LIMIT,
+            compression_policy: Box::new(RequestedCompression),
+            layout: None,
         };
 
-        for (rrid, resource) in &resource_package.resources {
+        let resources = resource_package
+            .load_metadata()
+            .map_err(PackageBuilderError::CannotReadSourceMetadata)?;
+        for (rrid, resource) in resources {
             let mut builder = match source {
-                ResourcePackageSource::File(source_path) => {
+                ResourcePackageSource::File(source_path)
+                | ResourcePackageSource::MappedFile(source_path, _) => {
                     PackageResourceBuilder::from_file_at_offset(
                         *rrid,
                         &resource.data_type(),
@@ -598,7 +746,13 @@ impl PackageBuilder {
                         .unwrap_or(resource.header.data_size);
 
                     let start_offset = resource.entry.data_offset as usize;
-                    let end_offset = start_offset + read_size as usize;
+                    let data = start_offset
+                        .checked_add(read_size as usize)
+                        .and_then(|end_offset| source_data.get(start_offset..end_offset))
+                        .ok_or(PackageBuilderError::CannotDuplicateResource(
+                            *rrid,
+                            PackageResourceBuilderError::InvalidFileBlobSize,
+                        ))?;
 
                     let decompressed_size = if resource.is_compressed() {
                         Some(resource.header.data_size)
@@ -609,7 +763,7 @@ impl PackageBuilder {
                     PackageResourceBuilder::from_compressed_memory(
                         *rrid,
                         &resource.data_type(),
-                        source_data[start_offset..end_offset].to_vec(),
+                        data.to_vec(),
                         decompressed_size,
                         resource.is_scrambled(),
                     )
@@ -621,6 +775,7 @@ impl PackageBuilder {
                 resource.system_memory_requirement(),
                 resource.video_memory_requirement(),
             );
+            builder.states_chunk_size = resource.header.states_chunk_size;
 
             for (rrid, flags) in resource.references() {
                 builder.with_reference(*rrid, *flags);
@@ -636,6 +791,169 @@ impl PackageBuilder {
         Ok(package)
     }
 
+    /// Creates a new package builder which rebuilds an existing ResourcePackage byte for byte.
+    ///
+    /// Besides the resources, the builder keeps the order of the resource data, the bytes between the data of the
+    /// resources, the states chunk sizes, the layout of the metadata table, the reference format and the package
+    /// metadata including its unknown field. Building it with the version of the original package without making
+    /// any changes produces an identical package.
+    ///
+    /// Modified resources keep their place in the data order, new resources are written after the original data.
+    ///
+    /// # Arguments
+    /// * `resource_package` - The ResourcePackage to rebuild.
+    pub fn from_resource_package_lossless(
+        resource_package: &ResourcePackage,
+    ) -> Result<Self, PackageBuilderError> {
+        let mut package = Self::from_resource_package(resource_package)?;
+        if resource_package
+            .has_legacy_references()
+            .map_err(PackageBuilderError::CannotReadSourceMetadata)?
+        {
+            package.use_legacy_references();
+        }
+
+        let data = resource_package
+            .package_data()
+            .map_err(PackageBuilderError::CannotReadSourceMetadata)?;
+        let has_states_size = resource_package
+            .has_states_size()
+            .map_err(PackageBuilderError::CannotReadSourceMetadata)?;
+
+        // Sort the resources by the position of their data.
+        let mut resources = resource_package
+            .load_metadata()
+            .map_err(PackageBuilderError::CannotReadSourceMetadata)?
+            .iter()
+            .map(|(rrid, resource)| {
+                let start = resource.entry.data_offset;
+                (start, start + resource.packaged_size() as u64, *rrid)
+            })
+            .collect::<Vec<_>>();
+        resources.sort_by_key(|(start, end, _)| (*start, *end));
+
+        // Keep the bytes following the data of every resource, up to the data of the next one.
+        let tables_end = resource_package.tables_size();
+        let mut position = tables_end;
+        let mut data_order = IndexMap::new();
+        for (index, (start, end, rrid)) in resources.iter().enumerate() {
+            if *start < position {
+                return Err(PackageBuilderError::OverlappingResourceData(*rrid));
+            }
+
+            let next_start = resources
+                .get(index + 1)
+                .map_or(data.len() as u64, |(next_start, _, _)| *next_start)
+                .max(*end);
+            let padding = data.get(*end as usize..next_start as usize).ok_or(
+                PackageBuilderError::CannotDuplicateResource(*rrid, PackageResourceBuilderError::InvalidFileBlobSize),
+            )?;
+            data_order.insert(*rrid, padding.to_vec());
+            position = *end;
+        }
+
+        // Keep the bytes in front of the data of the first resource, everything after the tables without resources.
+        let first_start = resources.first().map_or(data.len() as u64, |(start, _, _)| *start);
+        let leading_padding = data
+            .get(tables_end as usize..first_start as usize)
+            .unwrap_or_default()
+            .to_vec();
+
+        package.layout = Some(PackageLayout {
+            metadata: resource_package.metadata,
+            is_patch: resource_package.is_patch(),
+            has_states_size,
+            leading_padding,
+            data_order,
+            unneeded_resources: resource_package.unneeded_resource_ids().into_iter().copied().collect(),
+        });
+
+        Ok(package)
+    }
+
+    /// Returns the partition id and patch id stored in the metadata of a package.
+    ///
+    /// Packages without metadata are treated as the base package of chunk0.
+    pub(crate) fn package_ids(resource_package: &ResourcePackage) -> (PartitionId, PatchId) {
+        let metadata = resource_package.metadata.as_ref();
+        let partition_id = PartitionId {
+            part_type: match metadata.map(|m| m.chunk_type).unwrap_or_default() {
+                ChunkType::Standard => PartitionType::Standard,
+                ChunkType::Addon => PartitionType::Addon,
+            },
+            index: metadata.map(|m| m.chunk_id).unwrap_or_default() as usize,
+        };
+        let patch_id = match metadata.map(|m| m.patch_id).unwrap_or_default() {
+            0 => PatchId::Base,
+            x => PatchId::Patch(x as usize),
+        };
+        (partition_id, patch_id)
+    }
+
+    /// Creates a patch package which turns the current state of a partition into the desired set of resources.
+    ///
+    /// Only resources which are new or differ from the partition, either in their metadata or their content,
+    /// are added to the patch. Resources of the partition which aren't part of the desired set are marked as unneeded.
+    /// The patch id is one higher than the highest patch currently mounted in the partition.
+    ///
+    /// # Arguments
+    /// * `partition` - The mounted partition to patch.
+    /// * `resources` - Every resource the partition should contain after applying the patch.
+    pub fn patch_from_partition<I>(
+        partition: &ResourcePartition,
+        resources: I,
+    ) -> Result<Self, PackageBuilderError>
+    where
+        I: IntoIterator<Item = PackageResourceBuilder>,
+    {
+        let next_patch = partition
+            .packages
+            .keys()
+            .map(|patch_id| match patch_id {
+                PatchId::Base => 0,
+                PatchId::Patch(index) => *index,
+            })
+            .max()
+            .unwrap_or_default()
+            + 1;
+
+        let mut package = Self::new_with_patch_id(
+            partition.partition_info().id.clone(),
+            PatchId::Patch(next_patch),
+        );
+
+        let mut desired = IndexSet::new();
+        for resource in resources {
+            let rrid = resource.rrid;
+            desired.insert(rrid);
+            if let Ok(current) = partition.get_resource_info(&rrid) {
+                if resource.header_matches(current) {
+                    let current_data = partition
+                        .read_resource_borrowed(&rrid)
+                        .map_err(|e| PackageBuilderError::CannotReadPartitionResource(rrid, e))?;
+                    let desired_data = resource
+                        .read_data()
+                        .map_err(|e| PackageBuilderError::CannotReadResource(rrid, e))?;
+                    if *current_data == *desired_data {
+                        continue;
+                    }
+                }
+            }
+            package.with_resource(resource);
+        }
+
+        let mut removed = partition
+            .resources
+            .keys()
+            .filter(|rrid| !desired.contains(*rrid))
+            .copied()
+            .collect::<Vec<_>>();
+        removed.sort_by_key(|rrid| u64::from(*rrid));
+        package.with_unneeded_resources(removed);
+
+        Ok(package)
+    }
+
     /// Sets the partition ID of the package.
     pub fn with_partition_id(&mut self, partition_id: &PartitionId) -> &mut Self {
         self.partition_id = partition_id.clone();
@@ -654,6 +972,30 @@ impl PackageBuilder {
         self
     }
 
+    /// Limits the memory used by compression buffers while building, 256 MiB by default.
+    ///
+    /// With the `rayon` feature, resources are compressed in parallel in batches which fit in this limit and written
+    /// out in order. Resources read from files count with their size on top of their compression buffer. A batch
+    /// always holds at least one resource, and the built package does not depend on the limit.
+    ///
+    /// # Arguments
+    /// * `limit` - The limit in bytes.
+    pub fn with_compression_memory_limit(&mut self, limit: usize) -> &mut Self {
+        self.compression_memory_limit = limit;
+        self
+    }
+
+    /// Sets the policy deciding how each resource is compressed, see [CompressionPolicy].
+    ///
+    /// By default resources are compressed with the level they were created with.
+    ///
+    /// # Arguments
+    /// * `policy` - The compression policy.
+    pub fn with_compression_policy<P: CompressionPolicy + 'static>(&mut self, policy: P) -> &mut Self {
+        self.compression_policy = Box::new(policy);
+        self
+    }
+
     /// Adds a resource to the package.
     ///
     /// If a resource with the same resource ID already exists, it will be overwritten.
@@ -713,13 +1055,23 @@ impl PackageBuilder {
     where
         for<'a> T::Args<'a>: Required,
     {
+        Self::backpatch_with_args(writer, patch_offset, data, Required::args())
+    }
+
+    /// Patches data written with the given arguments at a given offset and returns to the previous position.
+    fn backpatch_with_args<'a, W: Write + Seek, T: BinWrite + WriteEndian>(
+        writer: &mut W,
+        patch_offset: u64,
+        data: &T,
+        args: T::Args<'a>,
+    ) -> Result<(), PackageBuilderError> {
         let current_offset = writer
             .stream_position()
             .map_err(PackageBuilderError::IoError)?;
         writer
             .seek(io::SeekFrom::Start(patch_offset))
             .map_err(PackageBuilderError::IoError)?;
-        data.write(writer)
+        data.write_args(writer, args)
             .map_err(PackageBuilderError::SerializationError)?;
         writer
             .seek(io::SeekFrom::Start(current_offset))
@@ -771,93 +1123,111 @@ impl PackageBuilder {
         })
     }
 
-    /// Writes the metadata table to the given writer.
-    fn write_metadata_table<W: Write + Seek>(
-        &self,
+    /// Writes the metadata table entry of a resource, followed by its references.
+    pub(crate) fn write_metadata_entry<W: Write + Seek>(
         writer: &mut W,
+        resource: &PackageResourceBuilder,
+        has_states_size: bool,
         legacy_references: bool,
-    ) -> Result<MetadataTableResult, PackageBuilderError> {
-        let metadata_table_start = writer
+    ) -> Result<(), PackageBuilderError> {
+        let metadata_offset = writer
             .stream_position()
             .map_err(PackageBuilderError::IoError)?;
 
-        for (_, resource) in &self.resources {
-            let metadata_offset = writer
+        // Write the resource metadata followed by the references table if there are any.
+        // We set the references chunk size to 0, and we'll patch it later.
+        let mut resource_metadata = ResourceHeader {
+            resource_type: resource.resource_type,
+            references_chunk_size: 0,
+            states_chunk_size: resource.states_chunk_size,
+            data_size: resource.blob.size(),
+            system_memory_requirement: resource.system_memory_requirement,
+            video_memory_requirement: resource.video_memory_requirement,
+            references: Vec::new(),
+        };
+
+        resource_metadata
+            .write_args(writer, (has_states_size,))
+            .map_err(PackageBuilderError::SerializationError)?;
+
+        // Write the references table if there are any.
+        if !resource.references.is_empty() {
+            let reference_table_start = writer
                 .stream_position()
                 .map_err(PackageBuilderError::IoError)?;
 
-            // Write the resource metadata followed by the references table if there are any.
-            // We set the references chunk size to 0, and we'll patch it later.
-            let mut resource_metadata = ResourceHeader {
-                resource_type: resource.resource_type,
-                references_chunk_size: 0,
-                states_chunk_size: 0,
-                data_size: resource.blob.size(),
-                system_memory_requirement: resource.system_memory_requirement,
-                video_memory_requirement: resource.video_memory_requirement,
-                references: Vec::new(),
-            };
+            let reference_count_and_flags = ResourceReferenceCountAndFlags::new()
+                .with_reference_count(resource.references.len() as u32)
+                .with_is_new_format(!legacy_references)
+                .with_always_true(true);
 
-            resource_metadata
+            reference_count_and_flags
                 .write(writer)
                 .map_err(PackageBuilderError::SerializationError)?;
 
-            // Write the references table if there are any.
-            if !resource.references.is_empty() {
-                let reference_table_start = writer
-                    .stream_position()
-                    .map_err(PackageBuilderError::IoError)?;
-
-                let reference_count_and_flags = ResourceReferenceCountAndFlags::new()
-                    .with_reference_count(resource.references.len() as u32)
-                    .with_is_new_format(!legacy_references)
-                    .with_always_true(true);
-
-                reference_count_and_flags
-                    .write(writer)
-                    .map_err(PackageBuilderError::SerializationError)?;
-
-                // In legacy mode, we write resource ids first, then flags.
-                // In new mode, we do the opposite. We also use the appropriate version of the flags.
-                if legacy_references {
-                    for (rrid, _) in &resource.references {
-                        rrid.write(writer)
-                            .map_err(PackageBuilderError::SerializationError)?;
-                    }
-
-                    for (_, flags) in &resource.references {
-                        flags
-                            .to_legacy()
-                            .write(writer)
-                            .map_err(PackageBuilderError::SerializationError)?;
-                    }
-                } else {
-                    for (_, flags) in &resource.references {
-                        flags
-                            .to_standard()
-                            .write(writer)
-                            .map_err(PackageBuilderError::SerializationError)?;
-                    }
-
-                    for (rrid, _) in &resource.references {
-                        rrid.write(writer)
-                            .map_err(PackageBuilderError::SerializationError)?;
-                    }
+            // In legacy mode, we write resource ids first, then flags.
+            // In new mode, we do the opposite. We also use the appropriate version of the flags.
+            if legacy_references {
+                for (rrid, _) in &resource.references {
+                    rrid.write(writer)
+                        .map_err(PackageBuilderError::SerializationError)?;
                 }
 
-                let reference_table_end = writer
-                    .stream_position()
-                    .map_err(PackageBuilderError::IoError)?;
-                let reference_table_size = reference_table_end - reference_table_start;
+                for (_, flags) in &resource.references {
+                    flags
+                        .to_legacy()
+                        .write(writer)
+                        .map_err(PackageBuilderError::SerializationError)?;
+                }
+            } else {
+                for (_, flags) in &resource.references {
+                    flags
+                        .to_standard()
+                        .write(writer)
+                        .map_err(PackageBuilderError::SerializationError)?;
+                }
 
-                if reference_table_size > u32::MAX as u64 {
-                    return Err(PackageBuilderError::TooManyReferences);
+                for (rrid, _) in &resource.references {
+                    rrid.write(writer)
+                        .map_err(PackageBuilderError::SerializationError)?;
                 }
+            }
+
+            let reference_table_end = writer
+                .stream_position()
+                .map_err(PackageBuilderError::IoError)?;
+            let reference_table_size = reference_table_end - reference_table_start;
 
-                // Calculate the size and patch the metadata.
-                resource_metadata.references_chunk_size = reference_table_size as u32;
-                PackageBuilder::backpatch(writer, metadata_offset, &resource_metadata)?;
+            if reference_table_size > u32::MAX as u64 {
+                return Err(PackageBuilderError::TooManyReferences);
             }
+
+            // Calculate the size and patch the metadata.
+            resource_metadata.references_chunk_size = reference_table_size as u32;
+            PackageBuilder::backpatch_with_args(
+                writer,
+                metadata_offset,
+                &resource_metadata,
+                (has_states_size,),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the metadata table to the given writer.
+    fn write_metadata_table<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        legacy_references: bool,
+    ) -> Result<MetadataTableResult, PackageBuilderError> {
+        let metadata_table_start = writer
+            .stream_position()
+            .map_err(PackageBuilderError::IoError)?;
+        let has_states_size = self.has_states_size();
+
+        for (_, resource) in &self.resources {
+            Self::write_metadata_entry(writer, resource, has_states_size, legacy_references)?;
         }
 
         // Write the metadata table size.
@@ -875,6 +1245,261 @@ impl PackageBuilder {
         })
     }
 
+    /// Splits the resources into consecutive batches whose compression buffers fit in the memory limit.
+    /// A batch always holds at least one resource.
+    #[cfg(feature = "rayon")]
+    fn compression_batches<T>(
+        resources: &[T],
+        memory_limit: usize,
+        buffer_size: impl Fn(&T) -> usize,
+    ) -> Vec<&[T]> {
+        let mut batches = vec![];
+        let mut batch_start = 0;
+        let mut batch_size = 0usize;
+
+        for (index, resource) in resources.iter().enumerate() {
+            let buffer_size = buffer_size(resource);
+            if index > batch_start && batch_size.saturating_add(buffer_size) > memory_limit {
+                batches.push(&resources[batch_start..index]);
+                batch_start = index;
+                batch_size = 0;
+            }
+            batch_size = batch_size.saturating_add(buffer_size);
+        }
+
+        if batch_start < resources.len() {
+            batches.push(&resources[batch_start..]);
+        }
+
+        batches
+    }
+
+    /// Asks the compression policy how a resource is compressed, None if it's stored as is.
+    fn resource_compression(
+        &self,
+        resource: &PackageResourceBuilder,
+        version: PackageVersion,
+    ) -> Option<(CompressionCandidate, Compression)> {
+        let candidate = CompressionCandidate {
+            rrid: resource.rrid,
+            resource_type: String::from_utf8_lossy(&resource.resource_type).chars().rev().collect(),
+            size: resource.blob.size(),
+            version,
+            requested: resource
+                .blob
+                .compression_level()
+                .map(|level| Compression::for_version(version, level)),
+        };
+        let compression = self.compression_policy.compression(&candidate)?;
+        Some((candidate, compression))
+    }
+
+    /// Compresses a resource as decided by [PackageBuilder::resource_compression], None if it's stored as is.
+    ///
+    /// Resources which are already packaged are copied as is when the policy agrees with how they're packaged,
+    /// they're recompressed or decompressed otherwise.
+    fn compress_resource(
+        &self,
+        resource: &PackageResourceBuilder,
+        compression: Option<&(CompressionCandidate, Compression)>,
+    ) -> Result<Option<ResourceData>, PackageBuilderError> {
+        let Some((candidate, compression)) = compression else {
+            return Self::decompress_packaged(resource);
+        };
+
+        // Packaged data is taken to be compressed the way the resource requests, see CompressionCandidate::requested.
+        let compressed = match resource.blob.packaged_compressed_size() {
+            Some(compressed_size) if candidate.requested == Some(*compression) => {
+                if self.compression_policy.keep_compressed(candidate, compressed_size as usize) {
+                    return Ok(None);
+                }
+                None
+            }
+            _ => Some(resource.compress(*compression)?).filter(|compressed| {
+                self.compression_policy
+                    .keep_compressed(candidate, compressed.len())
+            }),
+        };
+
+        match compressed {
+            Some(compressed) => Ok(Some(ResourceData::Compressed(compressed))),
+            None => Self::decompress_packaged(resource),
+        }
+    }
+
+    /// Decompresses a resource which is packaged compressed, None for every other resource.
+    fn decompress_packaged(resource: &PackageResourceBuilder) -> Result<Option<ResourceData>, PackageBuilderError> {
+        if resource.blob.packaged_compressed_size().is_none() {
+            return Ok(None);
+        }
+
+        resource
+            .read_data()
+            .map(|data| Some(ResourceData::Uncompressed(data)))
+            .map_err(|e| PackageBuilderError::CannotReadResource(resource.rrid, e))
+    }
+
+    /// Writes the data of a resource and patches its entry in the offset table.
+    ///
+    /// `data` is the data returned by [PackageBuilder::compress_resource] for the resource.
+    fn write_resource<W: Write + Seek>(
+        writer: &mut W,
+        resource: &PackageResourceBuilder,
+        data: Option<ResourceData>,
+        offset_table_result: &OffsetTableResult,
+    ) -> Result<(), PackageBuilderError> {
+        let offset_info = Self::write_resource_data(writer, resource, data)?;
+        let patch_offset = offset_table_result.resource_entry_offsets[&resource.rrid];
+        PackageBuilder::backpatch(writer, patch_offset, &offset_info)
+    }
+
+    /// Writes the data of a resource at the current position, returns its entry for the offset table.
+    ///
+    /// `data` replaces the data of the blob, None writes the blob as it is.
+    pub(crate) fn write_resource_data<W: Write + Seek>(
+        writer: &mut W,
+        resource: &PackageResourceBuilder,
+        data: Option<ResourceData>,
+    ) -> Result<PackageOffsetInfo, PackageBuilderError> {
+        let data_offset = writer
+            .stream_position()
+            .map_err(PackageBuilderError::IoError)?;
+
+        let (compressed_size, is_scrambled) = match (&resource.blob, data) {
+            (blob, Some(ResourceData::Compressed(compressed))) => {
+                Self::write_data(writer, &compressed, blob.is_scrambled())?;
+                (Some(compressed.len() as u32), blob.is_scrambled())
+            }
+
+            (blob, Some(ResourceData::Uncompressed(data))) => {
+                Self::write_data(writer, &data, blob.is_scrambled())?;
+                (None, blob.is_scrambled())
+            }
+
+            (PackageResourceBlob::File { path, should_scramble, .. }, None) => {
+                let mut file = File::open(path).map_err(PackageBuilderError::IoError)?;
+
+                // Wrap our writer in a ScrambleWriter if we should scramble.
+                let mut data_writer: Box<dyn Write> = match should_scramble {
+                    true => Box::new(ScrambleWriter::new(&mut *writer)),
+                    false => Box::new(&mut *writer),
+                };
+
+                io::copy(&mut file, &mut data_writer).map_err(PackageBuilderError::IoError)?;
+                (None, *should_scramble)
+            }
+
+            (
+                PackageResourceBlob::FileAtOffset {
+                    path,
+                    offset,
+                    size,
+                    compressed_size,
+                    is_scrambled,
+                },
+                None,
+            ) => {
+                let size_to_copy = compressed_size.unwrap_or_else(|| *size);
+
+                let mut file = File::open(path).map_err(PackageBuilderError::IoError)?;
+                file.seek(io::SeekFrom::Start(*offset))
+                    .map_err(PackageBuilderError::IoError)?;
+                io::copy(&mut file.take(size_to_copy as u64), writer)
+                    .map_err(PackageBuilderError::IoError)?;
+
+                (*compressed_size, *is_scrambled)
+            }
+
+            (
+                PackageResourceBlob::CompressedMemory {
+                    data,
+                    decompressed_size,
+                    is_scrambled,
+                },
+                None,
+            ) => {
+                writer
+                    .write_all(data)
+                    .map_err(PackageBuilderError::IoError)?;
+                let compressed_size = decompressed_size.map(|_| data.len() as u32);
+                (compressed_size, *is_scrambled)
+            }
+
+            (PackageResourceBlob::Memory { data, should_scramble, .. }, None) => {
+                Self::write_data(writer, data, *should_scramble)?;
+                (None, *should_scramble)
+            }
+        };
+
+        // Patch the offset info.
+        // If the resource is not compressed, we set the compressed size to 0.
+        let final_compressed_size = compressed_size.unwrap_or(0);
+
+        Ok(PackageOffsetInfo {
+            runtime_resource_id: resource.rrid,
+            data_offset,
+            flags: PackageOffsetFlags::new()
+                .with_compressed_size(final_compressed_size)
+                .with_is_scrambled(is_scrambled),
+        })
+    }
+
+    /// Writes the given data, scrambling it if requested.
+    fn write_data<W: Write + Seek>(
+        writer: &mut W,
+        data: &[u8],
+        should_scramble: bool,
+    ) -> Result<(), PackageBuilderError> {
+        // Wrap our writer in a ScrambleWriter if we should scramble.
+        let mut data_writer: Box<dyn Write> = match should_scramble {
+            true => Box::new(ScrambleWriter::new(&mut *writer)),
+            false => Box::new(&mut *writer),
+        };
+
+        data_writer
+            .write_all(data)
+            .map_err(PackageBuilderError::IoError)
+    }
+
+    /// Whether the package is built as a patch package, which contains the list of unneeded resources.
+    fn is_patch(&self) -> bool {
+        match &self.layout {
+            Some(layout) => layout.is_patch,
+            None => self.patch_id.is_patch(),
+        }
+    }
+
+    /// Whether the entries of the metadata table contain the size of the states chunk.
+    fn has_states_size(&self) -> bool {
+        self.layout.as_ref().is_none_or(|layout| layout.has_states_size)
+    }
+
+    /// The unneeded resources in the order they're written in.
+    ///
+    /// When rebuilding losslessly, the original list is kept, followed by the resources marked as unneeded since.
+    fn unneeded_resource_list(&self) -> Vec<RuntimeResourceID> {
+        let Some(layout) = &self.layout else {
+            return self.unneeded_resources.iter().copied().collect();
+        };
+
+        let original = layout.unneeded_resources.iter().collect::<HashSet<_>>();
+        layout
+            .unneeded_resources
+            .iter()
+            .filter(|rrid| self.unneeded_resources.contains(*rrid))
+            .chain(self.unneeded_resources.iter().filter(|rrid| !original.contains(rrid)))
+            .copied()
+            .collect()
+    }
+
+    /// The bytes written after the data of a resource.
+    fn padding_after(&self, rrid: &RuntimeResourceID) -> &[u8] {
+        self.layout
+            .as_ref()
+            .and_then(|layout| layout.data_order.get(rrid))
+            .map_or(&[], Vec::as_slice)
+    }
+
     /// Builds the package, writing it to the given writer.
     fn build_internal<W: Write + Seek>(
         &self,
@@ -882,20 +1507,23 @@ impl PackageBuilder {
         writer: &mut W,
     ) -> Result<(), PackageBuilderError> {
         // Perform some basic validation.
-        if !self.unneeded_resources.is_empty() && self.patch_id.is_base() {
+        if !self.unneeded_resources.is_empty() && !self.is_patch() {
             return Err(PackageBuilderError::UnneededResourcesNotSupported);
         }
 
         // First create a base header. We'll fill it and patch it later.
+        let unneeded_resources = self.unneeded_resource_list();
         let mut header = ResourcePackage {
             source: None,
+            is_patch_package: self.is_patch(),
             magic: match version {
                 PackageVersion::RPKGv1 => *b"GKPR",
                 PackageVersion::RPKGv2 => *b"2KPR",
             },
-            metadata: match version {
-                PackageVersion::RPKGv1 => None,
-                PackageVersion::RPKGv2 => Some(PackageMetadata {
+            metadata: match (version, self.layout.as_ref().and_then(|layout| layout.metadata)) {
+                (PackageVersion::RPKGv1, _) => None,
+                (PackageVersion::RPKGv2, Some(metadata)) => Some(metadata),
+                (PackageVersion::RPKGv2, None) => Some(PackageMetadata {
                     unknown: 1,
                     chunk_id: self.partition_id.index as u8,
                     chunk_type: match self.partition_id.part_type {
@@ -914,14 +1542,17 @@ impl PackageBuilder {
                 offset_table_size: 0,
                 metadata_table_size: 0,
             },
-            unneeded_resource_count: self.unneeded_resources.len() as u32,
-            unneeded_resources: Some(self.unneeded_resources.iter().copied().collect()),
-            resources: IndexMap::new(),
+            unneeded_resource_count: unneeded_resources.len() as u32,
+            unneeded_resources: Some(unneeded_resources),
+            offset_table: vec![],
+            offset_indices: IndexMap::new(),
+            resources: OnceLock::new(),
+            metadata_positions: OnceLock::new(),
         };
 
         // Write the header and the tables.
         header
-            .write_args(writer, (self.patch_id.is_patch(),))
+            .write_args(writer, (self.is_patch(),))
             .map_err(PackageBuilderError::SerializationError)?;
 
         let offset_table_result = self.write_offset_table(writer)?;
@@ -933,156 +1564,65 @@ impl PackageBuilder {
         header.header.metadata_table_size = metadata_table_result.metadata_table_size;
         PackageBuilder::backpatch(writer, 0, &header)?;
 
-        // Write the resource data.
-        for (rrid, resource) in &self.resources {
-            let data_offset = writer
-                .stream_position()
+        // Write the resource data. With rayon, the resources are compressed in parallel in batches which fit in the
+        // compression memory limit, then written out in order.
+        // When rebuilding losslessly, the data is written in its original order and followed by its original padding.
+        let mut resources = self
+            .resources
+            .values()
+            .map(|resource| (resource, self.resource_compression(resource, version)))
+            .collect::<Vec<_>>();
+
+        if let Some(layout) = &self.layout {
+            resources.sort_by_key(|(resource, _)| {
+                layout.data_order.get_index_of(&resource.rrid).unwrap_or(usize::MAX)
+            });
+            writer
+                .write_all(&layout.leading_padding)
                 .map_err(PackageBuilderError::IoError)?;
+        }
 
-            let (compressed_size, is_scrambled) = match &resource.blob {
-                PackageResourceBlob::File {
-                    path,
-                    size,
-                    compression_level,
-                    should_scramble,
-                } => {
-                    let mut file = File::open(path).map_err(PackageBuilderError::IoError)?;
-
-                    // Wrap our writer in a XorWriter if we should scramble.
-                    let mut data_writer: Box<dyn Write> = match should_scramble {
-                        true => Box::new(XorWriter { writer }),
-                        false => Box::new(&mut *writer),
-                    };
-
-                    let compressed_size = match compression_level {
-                        Some(level) => {
-                            // TODO: Switch to streaming API.
-                            let mut compressed_buffer =
-                                vec![0; lz4::max_compressed_size(*size as usize)];
-                            let mut decompressed_data = vec![0; *size as usize];
-                            file.read_exact(&mut decompressed_data)
-                                .map_err(PackageBuilderError::IoError)?;
-
-                            let compressed_size = match version {
-                                PackageVersion::RPKGv1 => lz4::compress(
-                                    &decompressed_data,
-                                    &mut compressed_buffer,
-                                    *level,
-                                )?,
-                                PackageVersion::RPKGv2 => lz4_hc::compress(
-                                    &decompressed_data,
-                                    &mut compressed_buffer,
-                                    *level,
-                                )?,
-                            };
-
-                            // Write the compressed data.
-                            data_writer
-                                .write_all(&compressed_buffer[..compressed_size])
-                                .map_err(PackageBuilderError::IoError)?;
-
-                            Some(compressed_size as u32)
-                        }
-
-                        None => {
-                            io::copy(&mut file, &mut data_writer)
-                                .map_err(PackageBuilderError::IoError)?;
-                            None
-                        }
-                    };
-
-                    (compressed_size, *should_scramble)
+        #[cfg(feature = "rayon")]
+        {
+            // Blobs are read into memory before compressing them, except memory blobs which already hold their data.
+            // Packaged blobs which are compressed are read into memory as well when they're stored uncompressed.
+            let buffer_size = |(resource, compression): &(&PackageResourceBuilder, Option<_>)| {
+                let size = resource.blob.size() as usize;
+                match compression {
+                    Some(_) => {
+                        let input_size = match resource.blob {
+                            PackageResourceBlob::Memory { .. } => 0,
+                            _ => size,
+                        };
+                        input_size.saturating_add(lz4::max_compressed_size(size))
+                    }
+                    None if resource.blob.packaged_compressed_size().is_some() => size,
+                    None => 0,
                 }
+            };
 
-                PackageResourceBlob::FileAtOffset {
-                    path,
-                    offset,
-                    size,
-                    compressed_size,
-                    is_scrambled,
-                } => {
-                    let size_to_copy = compressed_size.unwrap_or_else(|| *size);
-
-                    let mut file = File::open(path).map_err(PackageBuilderError::IoError)?;
-                    file.seek(io::SeekFrom::Start(*offset))
-                        .map_err(PackageBuilderError::IoError)?;
-                    io::copy(&mut file.take(size_to_copy as u64), writer)
-                        .map_err(PackageBuilderError::IoError)?;
-
-                    (*compressed_size, *is_scrambled)
-                }
+            for batch in Self::compression_batches(&resources, self.compression_memory_limit, buffer_size) {
+                let compressed = batch
+                    .par_iter()
+                    .map(|(resource, compression)| self.compress_resource(resource, compression.as_ref()))
+                    .collect::<Result<Vec<_>, _>>()?;
 
-                PackageResourceBlob::CompressedMemory {
-                    data,
-                    decompressed_size,
-                    is_scrambled,
-                } => {
+                for ((resource, _), compressed) in batch.iter().zip(compressed) {
+                    Self::write_resource(writer, resource, compressed, &offset_table_result)?;
                     writer
-                        .write_all(data)
+                        .write_all(self.padding_after(&resource.rrid))
                         .map_err(PackageBuilderError::IoError)?;
-                    let compressed_size = decompressed_size.map(|_| data.len() as u32);
-                    (compressed_size, *is_scrambled)
                 }
+            }
+        }
 
-                PackageResourceBlob::Memory {
-                    data,
-                    compression_level,
-                    should_scramble,
-                } => {
-                    // Wrap our writer in a XorWriter if we should scramble.
-                    let mut data_writer: Box<dyn Write> = match should_scramble {
-                        true => Box::new(XorWriter { writer }),
-                        false => Box::new(&mut *writer),
-                    };
-
-                    let compressed_size = match compression_level {
-                        Some(level) => {
-                            // TODO: Switch to streaming API.
-                            let mut compressed_buffer =
-                                vec![0; lz4::max_compressed_size(data.len())];
-                            let compressed_size = match version {
-                                PackageVersion::RPKGv1 => {
-                                    lz4::compress(data, &mut compressed_buffer, *level)?
-                                }
-                                PackageVersion::RPKGv2 => {
-                                    lz4_hc::compress(data, &mut compressed_buffer, *level)?
-                                }
-                            };
-
-                            // Write the compressed data.
-                            data_writer
-                                .write_all(&compressed_buffer[..compressed_size])
-                                .map_err(PackageBuilderError::IoError)?;
-
-                            Some(compressed_size as u32)
-                        }
-
-                        None => {
-                            data_writer
-                                .write_all(data)
-                                .map_err(PackageBuilderError::IoError)?;
-                            None
-                        }
-                    };
-
-                    (compressed_size, *should_scramble)
-                }
-            };
-
-            // Patch the offset info.
-            // If the resource is not compressed, we set the compressed size to 0.
-            let final_compressed_size = compressed_size.unwrap_or(0);
-
-            let offset_info = PackageOffsetInfo {
-                runtime_resource_id: *rrid,
-                data_offset,
-                flags: PackageOffsetFlags::new()
-                    .with_compressed_size(final_compressed_size)
-                    .with_is_scrambled(is_scrambled),
-            };
-
-            let patch_offset = offset_table_result.resource_entry_offsets[rrid];
-            PackageBuilder::backpatch(writer, patch_offset, &offset_info)?;
+        #[cfg(not(feature = "rayon"))]
+        for (resource, compression) in &resources {
+            let compressed = self.compress_resource(resource, compression.as_ref())?;
+            Self::write_resource(writer, resource, compressed, &offset_table_result)?;
+            writer
+                .write_all(self.padding_after(&resource.rrid))
+                .map_err(PackageBuilderError::IoError)?;
         }
 
         Ok(())
@@ -1108,6 +1648,10 @@ impl PackageBuilder {
     
     /// Builds the package for the given version and writes it to the given path.
     ///
+    /// The package is written to a temporary file next to the output file first, which then replaces it. An existing
+    /// package at that path is never truncated, so processes which have it mapped keep seeing the old package, and it
+    /// can be the source of the resources being built.
+    ///
     /// # Arguments
     /// * `version` - The version of the package to build.
     /// * `output_path` - The path to the output file.
@@ -1118,11 +1662,24 @@ impl PackageBuilder {
             false => output_path.to_path_buf(),
         };
 
-        let file = File::create(output_file).map_err(PackageBuilderError::IoError)?;
+        let mut file_name = output_file.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".building");
+        let temp_path = output_file.with_file_name(file_name);
+
+        let file = File::create(&temp_path).map_err(PackageBuilderError::IoError)?;
         let mut writer = BufWriter::new(file);
-        let result = self.build_internal(version, &mut writer);
-        writer.flush()?;
-        result
+        let result = self
+            .build_internal(version, &mut writer)
+            .and_then(|_| writer.flush().map_err(PackageBuilderError::IoError));
+        drop(writer);
+
+        match result {
+            Ok(()) => fs::rename(&temp_path, &output_file).map_err(PackageBuilderError::IoError),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(e)
+            }
+        }
     }
 
     #[deprecated(since = "1.1.1", note = "use `build_to_vec` instead")]
@@ -0,0 +1,360 @@
+//! A declarative description of a resource package, used to build packages from files on disk.
+//!
+//! A [PackageManifest] can be stored in any format supported by serde, which makes it possible to keep the layout
+//! of a package under version control. As JSON, a manifest looks like this:
+//!
+//! ```json
+//! {
+//!     "partition_id": "chunk0",
+//!     "patch_id": 1,
+//!     "legacy_references": false,
+//!     "resources": [
+//!         {
+//!             "path": "[assembly:/_pro/scenes/test.entity].pc_entitytype",
+//!             "type": "TEMP",
+//!             "file": "assembly/_pro/scenes/test.entity.pc_entitytype",
+//!             "compression_level": 12,
+//!             "scramble": true,
+//!             "references": [{ "rrid": "00FEDCBA98765432", "flags": { "Standard": 31 } }]
+//!         },
+//!         { "rrid": "00FEDCBA98765432", "type": "TEXT", "file": "wall.text", "system_memory_requirement": 300 }
+//!     ],
+//!     "unneeded_resources": ["00123456789ABCDE"]
+//! }
+//! ```
+//!
+//! Runtime resource ids are written as hexadecimal strings, resource files are relative to the directory the
+//! manifest is loaded from.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::misc::resource_id::{ResourceID, ResourceIDError};
+use crate::resource::package_builder::{
+    PackageBuilder, PackageResourceBuilder, PackageResourceBuilderError, DEFAULT_COMPRESSION_LEVEL,
+};
+use crate::resource::pdefs::PartitionId;
+use crate::resource::resource_package::{ResourcePackage, ResourcePackageError, ResourceReferenceFlags};
+use crate::resource::resource_partition::PatchId;
+use crate::resource::resource_vfs::{resource_file_path, unknown_file_path};
+use crate::resource::runtime_resource_id::{PlatformTag, RuntimeResourceID};
+
+#[derive(Debug, Error)]
+pub enum PackageManifestError {
+    #[error("Resource {0} has neither a runtime resource id nor a resource path")]
+    MissingResourceId(PathBuf),
+
+    #[error("A reference of resource {0} has neither a runtime resource id nor a resource path")]
+    MissingReferenceId(PathBuf),
+
+    #[error("Invalid resource path {0}: {1}")]
+    InvalidResourcePath(String, ResourceIDError),
+
+    #[error("Could not add resource {0}: {1}")]
+    ResourceError(PathBuf, PackageResourceBuilderError),
+
+    #[error("Could not read the package: {0}")]
+    ReadPackageError(ResourcePackageError),
+
+    #[error("Could not read resource {0}: {1}")]
+    ReadResourceError(RuntimeResourceID, ResourcePackageError),
+
+    #[error("Could not write {0}: {1}")]
+    WriteError(PathBuf, io::Error),
+}
+
+/// The layout of a resource package.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackageManifest {
+    #[serde(with = "partition_id_string")]
+    pub partition_id: PartitionId,
+    /// The patch level of the package, 0 for a base package.
+    #[serde(default = "base_patch_id", with = "patch_id_number")]
+    pub patch_id: PatchId,
+    /// Whether reference flags are written in the format used before Hitman 3.
+    #[serde(default)]
+    pub legacy_references: bool,
+    pub resources: Vec<ManifestResource>,
+    /// Resources of earlier packages which are removed by this patch package.
+    #[serde(default, with = "rrid_list", skip_serializing_if = "Vec::is_empty")]
+    pub unneeded_resources: Vec<RuntimeResourceID>,
+}
+
+/// A resource inside a [PackageManifest].
+///
+/// A resource is identified by its runtime resource id, or by its resource path when the id is omitted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestResource {
+    #[serde(default, with = "optional_rrid", skip_serializing_if = "Option::is_none")]
+    pub rrid: Option<RuntimeResourceID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    /// The file holding the data of the resource, relative to the manifest directory.
+    pub file: PathBuf,
+    /// The LZ4 compression level, the resource is stored uncompressed when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<i32>,
+    #[serde(default)]
+    pub scramble: bool,
+    /// Defaults to the size of the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_memory_requirement: Option<u32>,
+    /// Defaults to `u32::MAX`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_memory_requirement: Option<u32>,
+    /// The references of the resource, in the order the resource addresses them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<ManifestReference>,
+}
+
+/// A reference of a [ManifestResource], identified by a runtime resource id or a resource path.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestReference {
+    #[serde(default, with = "optional_rrid", skip_serializing_if = "Option::is_none")]
+    pub rrid: Option<RuntimeResourceID>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub flags: ResourceReferenceFlags,
+}
+
+impl ManifestResource {
+    /// Returns the runtime resource id, hashing the resource path when no id is given.
+    pub fn runtime_resource_id(&self) -> Result<RuntimeResourceID, PackageManifestError> {
+        resolve_rrid(&self.rrid, &self.path).ok_or_else(|| PackageManifestError::MissingResourceId(self.file.clone()))?
+    }
+}
+
+fn resolve_rrid(
+    rrid: &Option<RuntimeResourceID>,
+    path: &Option<String>,
+) -> Option<Result<RuntimeResourceID, PackageManifestError>> {
+    match (rrid, path) {
+        (Some(rrid), _) => Some(Ok(*rrid)),
+        (None, Some(path)) => Some(
+            ResourceID::from_str(path)
+                .map(|rid| RuntimeResourceID::from_resource_id_with_platform(&rid, "pc", PlatformTag::None))
+                .map_err(|e| PackageManifestError::InvalidResourcePath(path.clone(), e)),
+        ),
+        (None, None) => None,
+    }
+}
+
+impl PackageManifest {
+    /// Describes an existing package, writing the data of every resource to the given directory.
+    ///
+    /// Resources are stored as `unknown/<TYPE>/<hash>`. Building the returned manifest yields a package with the
+    /// same resources, metadata and references.
+    ///
+    /// # Arguments
+    /// - `package` - The package to describe.
+    /// - `directory` - The directory the resource files are written to.
+    pub fn from_resource_package(package: &ResourcePackage, directory: &Path) -> Result<Self, PackageManifestError> {
+        Self::from_resource_package_with_paths(package, directory, |_| None)
+    }
+
+    /// Describes an existing package like [PackageManifest::from_resource_package], laying the resource files out by
+    /// their resource path when it is known.
+    ///
+    /// # Arguments
+    /// - `package` - The package to describe.
+    /// - `directory` - The directory the resource files are written to.
+    /// - `resolver` - Returns the resource path of a resource, or None if it is unknown.
+    pub fn from_resource_package_with_paths<F>(
+        package: &ResourcePackage,
+        directory: &Path,
+        resolver: F,
+    ) -> Result<Self, PackageManifestError>
+    where
+        F: Fn(&RuntimeResourceID) -> Option<String>,
+    {
+        let (partition_id, patch_id) = PackageBuilder::package_ids(package);
+        let mut resources = vec![];
+        let mut files = HashSet::new();
+
+        for (rrid, info) in package.load_metadata().map_err(PackageManifestError::ReadPackageError)? {
+            let path = resolver(rrid);
+            let file = match path.as_deref().and_then(resource_file_path) {
+                Some(file) if !files.contains(&file) => file,
+                _ => unknown_file_path(&info.data_type(), rrid),
+            };
+            files.insert(file.clone());
+            let file = PathBuf::from(file);
+
+            let data = package
+                .read_resource(rrid)
+                .map_err(|e| PackageManifestError::ReadResourceError(*rrid, e))?;
+            let output_path = directory.join(&file);
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent).map_err(|e| PackageManifestError::WriteError(parent.to_path_buf(), e))?;
+            }
+            fs::write(&output_path, data).map_err(|e| PackageManifestError::WriteError(output_path.clone(), e))?;
+
+            resources.push(ManifestResource {
+                rrid: Some(*rrid),
+                path,
+                data_type: info.data_type(),
+                file,
+                compression_level: info.is_compressed().then_some(DEFAULT_COMPRESSION_LEVEL),
+                scramble: info.is_scrambled(),
+                system_memory_requirement: Some(info.system_memory_requirement()),
+                video_memory_requirement: Some(info.video_memory_requirement()),
+                references: info
+                    .references()
+                    .iter()
+                    .map(|(rrid, flags)| ManifestReference {
+                        rrid: Some(*rrid),
+                        path: resolver(rrid),
+                        flags: *flags,
+                    })
+                    .collect(),
+            });
+        }
+
+        Ok(Self {
+            partition_id,
+            patch_id,
+            legacy_references: package.has_legacy_references().map_err(PackageManifestError::ReadPackageError)?,
+            resources,
+            unneeded_resources: package.unneeded_resource_ids().into_iter().copied().collect(),
+        })
+    }
+}
+
+impl PackageBuilder {
+    /// Creates a package builder from a manifest.
+    ///
+    /// # Arguments
+    /// - `manifest` - The layout of the package.
+    /// - `directory` - The directory the resource files of the manifest are relative to.
+    pub fn from_manifest(manifest: &PackageManifest, directory: &Path) -> Result<Self, PackageManifestError> {
+        let mut builder = Self::new_with_patch_id(manifest.partition_id.clone(), manifest.patch_id);
+        if manifest.legacy_references {
+            builder.use_legacy_references();
+        }
+
+        for resource in &manifest.resources {
+            let rrid = resource.runtime_resource_id()?;
+            let mut resource_builder = PackageResourceBuilder::from_file(
+                rrid,
+                &resource.data_type,
+                &directory.join(&resource.file),
+                resource.compression_level,
+                resource.scramble,
+            )
+            .map_err(|e| PackageManifestError::ResourceError(resource.file.clone(), e))?;
+
+            if resource.system_memory_requirement.is_some() || resource.video_memory_requirement.is_some() {
+                let size = fs::metadata(directory.join(&resource.file))
+                    .map_err(|e| PackageManifestError::ResourceError(resource.file.clone(), e.into()))?
+                    .len();
+                resource_builder.with_memory_requirements(
+                    resource.system_memory_requirement.unwrap_or(size as u32),
+                    resource.video_memory_requirement.unwrap_or(u32::MAX),
+                );
+            }
+            for reference in &resource.references {
+                let reference_rrid = resolve_rrid(&reference.rrid, &reference.path)
+                    .ok_or_else(|| PackageManifestError::MissingReferenceId(resource.file.clone()))??;
+                resource_builder.with_reference(reference_rrid, reference.flags);
+            }
+            builder.with_resource(resource_builder);
+        }
+
+        builder.with_unneeded_resources(&manifest.unneeded_resources);
+        Ok(builder)
+    }
+}
+
+fn base_patch_id() -> PatchId {
+    PatchId::Base
+}
+
+mod partition_id_string {
+    use super::*;
+    use serde::de::Error;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(partition_id: &PartitionId, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&partition_id.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PartitionId, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        PartitionId::from_str(&id).map_err(D::Error::custom)
+    }
+}
+
+mod patch_id_number {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(patch_id: &PatchId, serializer: S) -> Result<S::Ok, S::Error> {
+        match patch_id {
+            PatchId::Base => serializer.serialize_u64(0),
+            PatchId::Patch(level) => serializer.serialize_u64(*level as u64),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PatchId, D::Error> {
+        match usize::deserialize(deserializer)? {
+            0 => Ok(PatchId::Base),
+            level => Ok(PatchId::Patch(level)),
+        }
+    }
+}
+
+/// Serializes runtime resource ids as plain hexadecimal strings, `0x` prefixes are accepted when reading.
+mod hex_rrid {
+    use super::*;
+    use serde::de::Error;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(rrid: &RuntimeResourceID, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&rrid.to_hex_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RuntimeResourceID, D::Error> {
+        let rrid = String::deserialize(deserializer)?;
+        RuntimeResourceID::from_hex_string(&rrid).map_err(D::Error::custom)
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(transparent)]
+    pub struct HexRrid(#[serde(with = "self")] pub RuntimeResourceID);
+}
+
+mod optional_rrid {
+    use super::hex_rrid::HexRrid;
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(rrid: &Option<RuntimeResourceID>, serializer: S) -> Result<S::Ok, S::Error> {
+        rrid.map(HexRrid).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<RuntimeResourceID>, D::Error> {
+        Ok(Option::<HexRrid>::deserialize(deserializer)?.map(|rrid| rrid.0))
+    }
+}
+
+mod rrid_list {
+    use super::hex_rrid::HexRrid;
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(rrids: &[RuntimeResourceID], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(rrids.iter().map(|rrid| HexRrid(*rrid)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<RuntimeResourceID>, D::Error> {
+        Ok(Vec::<HexRrid>::deserialize(deserializer)?.into_iter().map(|rrid| rrid.0).collect())
+    }
+}
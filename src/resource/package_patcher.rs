@@ -0,0 +1,348 @@
+//! Patching single resources inside of an existing package file.
+//!
+//! Rebuilding a package with the [PackageBuilder] rewrites all of its data. A [PackagePatcher] instead appends the
+//! new data of a resource to the end of the package and only rewrites the entries describing it, so replacing a
+//! resource takes time proportional to its own size.
+//!
+//! The data a resource used before it was replaced stays in the package as unused space. [PackagePatcher::compact]
+//! rewrites the package without it once enough has accumulated, see [PackagePatcher::unused_size].
+//!
+//! Unlike the builder, which replaces package files, the patcher writes to the package file in place. A package
+//! must not be mounted or otherwise parsed with [ResourcePackage::from_file] while it's patched: the parsed package
+//! maps the file and would read tables and data that changed underneath it. Unmount the package first and mount it
+//! again once patching is done, or patch a copy and move it in place of the mounted package.
+//!
+//! ```no_run
+//! use rpkg_rs::resource::package_builder::PackageResourceBuilder;
+//! use rpkg_rs::resource::package_patcher::PackagePatcher;
+//! use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+//!
+//! let mut patcher = PackagePatcher::open("chunk0patch1.rpkg").unwrap();
+//! let rrid = RuntimeResourceID::from(0x00123456789ABCDE);
+//! let texture = std::fs::read("texture.text").unwrap();
+//! patcher
+//!     .replace_resource(PackageResourceBuilder::from_memory(rrid, "TEXT", texture, None, true).unwrap())
+//!     .unwrap();
+//! ```
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use binrw::BinWrite;
+use thiserror::Error;
+
+use crate::resource::package_builder::{PackageBuilder, PackageBuilderError, PackageResourceBuilder, ResourceData};
+use crate::resource::resource_package::{PackageHeader, PackageOffsetInfo, ResourcePackage, ResourcePackageError};
+use crate::resource::runtime_resource_id::RuntimeResourceID;
+
+/// The size of a single entry inside the offset table.
+const OFFSET_ENTRY_SIZE: u64 = 0x14;
+
+#[derive(Debug, Error)]
+pub enum PackagePatcherError {
+    #[error("Error accessing the package file: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Could not read the package: {0}")]
+    PackageError(#[from] ResourcePackageError),
+
+    #[error("Could not write the resource: {0}")]
+    BuilderError(#[from] PackageBuilderError),
+
+    #[error("Error serializing the package tables: {0}")]
+    SerializationError(#[from] binrw::Error),
+
+    #[error("The package doesn't contain resource {0}")]
+    ResourceNotFound(RuntimeResourceID),
+
+    #[error("The metadata table of the package would exceed 4 GiB")]
+    MetadataTableTooLarge,
+}
+
+/// Replaces resources inside of an existing package file.
+///
+/// Only the header and the tables of the package are held in memory, resource data is read from and written to
+/// the file directly.
+pub struct PackagePatcher {
+    path: PathBuf,
+    file: File,
+    /// The package parsed from its header and tables.
+    package: ResourcePackage,
+}
+
+impl PackagePatcher {
+    /// Opens a package file for patching.
+    ///
+    /// Like [ResourcePackage::from_file], the package is treated as a patch package if its file name contains "patch".
+    ///
+    /// # Arguments
+    /// * `package_path` - The path to the package to patch.
+    pub fn open<P: AsRef<Path>>(package_path: P) -> Result<Self, PackagePatcherError> {
+        let path = package_path.as_ref().to_path_buf();
+
+        // Only keep the tables, so the file isn't mapped while it's being written to.
+        let (tables, is_patch) = {
+            let package = ResourcePackage::from_file_lazy(path.as_path())?;
+            let data = package
+                .source()
+                .and_then(|source| source.data())
+                .ok_or(ResourcePackageError::NoSource)?;
+            let tables_size = (package.tables_size() as usize).min(data.len());
+            (data[..tables_size].to_vec(), package.is_patch())
+        };
+
+        let package = ResourcePackage::from_memory(tables, is_patch)?;
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+
+        Ok(Self { path, file, package })
+    }
+
+    /// Returns the path of the package being patched.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the ids of the resources inside of the package.
+    pub fn resource_ids(&self) -> impl Iterator<Item = &RuntimeResourceID> {
+        self.package.resource_ids()
+    }
+
+    /// Replaces the data and metadata of a resource inside of the package.
+    ///
+    /// The data is appended to the end of the package, or after the tables if they grow past it, and the offset table
+    /// entry of the resource is rewritten to point at it. The metadata table entry is rewritten from the resource, it
+    /// keeps the states chunk size of the entry it replaces. When the references of the resource change the size of its entry, the rest of the metadata
+    /// table is moved, and the data of resources it would grow into is moved to the end of the package as well.
+    ///
+    /// Compressed resources are compressed with the default backend of the package version.
+    ///
+    /// The package file is written in place, it must not be mounted while it's patched, see the [module
+    /// documentation](self).
+    ///
+    /// # Arguments
+    /// * `resource` - The new version of the resource, its id has to be inside of the package already.
+    pub fn replace_resource(&mut self, mut resource: PackageResourceBuilder) -> Result<(), PackagePatcherError> {
+        let rrid = *resource.rrid();
+        let index = *self
+            .package
+            .offset_indices
+            .get(&rrid)
+            .ok_or(PackagePatcherError::ResourceNotFound(rrid))?;
+        let original = self.package.resource_info(&rrid)?.into_owned();
+        let has_states_size = self.package.has_states_size()?;
+        resource.states_chunk_size = original.header.states_chunk_size;
+
+        let mut entry = Cursor::new(Vec::new());
+        PackageBuilder::write_metadata_entry(
+            &mut entry,
+            &resource,
+            has_states_size,
+            self.package.has_legacy_references()?,
+        )?;
+        let entry = entry.into_inner();
+
+        // Splice the new metadata entry into the tables.
+        let mut tables = self.tables().to_vec();
+        let old_tables_end = tables.len() as u64;
+        let entry_start = self.package.metadata_positions(self.tables())?.positions[index] as usize;
+        let entry_size = if has_states_size { 0x18 } else { 0x14 };
+        let entry_end = entry_start + entry_size + original.header.references_chunk_size as usize;
+        let resized = entry.len() != entry_end - entry_start;
+        tables.splice(entry_start..entry_end, entry.iter().copied());
+
+        let metadata_table_size = tables.len() as u64 - self.package.metadata_table_offset();
+        if metadata_table_size > u32::MAX as u64 {
+            return Err(PackagePatcherError::MetadataTableTooLarge);
+        }
+
+        // Read the data the grown tables would overwrite, it's moved to the end of the package.
+        let tables_end = tables.len() as u64;
+        let mut relocated = vec![];
+        if tables_end > old_tables_end {
+            for (other_index, other) in self.package.offset_table.iter().enumerate() {
+                if other_index == index || other.data_offset >= tables_end {
+                    continue;
+                }
+
+                let size = self.package.resource_info(&other.runtime_resource_id)?.packaged_size();
+                let mut data = vec![0; size as usize];
+                let mut reader = &self.file;
+                reader.seek(SeekFrom::Start(other.data_offset))?;
+                reader.read_exact(&mut data)?;
+                relocated.push((other_index, data));
+            }
+        }
+
+        // Append the new data, followed by the moved data. When the grown tables reach past the end of the package,
+        // the data follows them instead, with the gap zero filled.
+        let compressed = resource
+            .compress_requested(self.package.version())?
+            .map(ResourceData::Compressed);
+        let mut writer = BufWriter::new(&self.file);
+        let end = writer.seek(SeekFrom::End(0))?;
+        if end < tables_end {
+            io::copy(&mut io::repeat(0).take(tables_end - end), &mut writer)?;
+        }
+        let mut offset_entries = vec![(index, PackageBuilder::write_resource_data(&mut writer, &resource, compressed)?)];
+        for (other_index, data) in relocated {
+            let data_offset = writer.stream_position()?;
+            writer.write_all(&data)?;
+            offset_entries.push((
+                other_index,
+                PackageOffsetInfo {
+                    data_offset,
+                    ..self.package.offset_table[other_index]
+                },
+            ));
+        }
+        writer.flush()?;
+        drop(writer);
+
+        // Update the tables and write the parts which changed.
+        let mut changed = Vec::new();
+        for (index, offset_info) in offset_entries {
+            let start = self.package.offset_table_offset() + index as u64 * OFFSET_ENTRY_SIZE;
+            let mut cursor = Cursor::new(&mut tables[..]);
+            cursor.set_position(start);
+            offset_info.write(&mut cursor)?;
+            changed.push(start as usize..(start + OFFSET_ENTRY_SIZE) as usize);
+        }
+
+        if resized {
+            let header = PackageHeader {
+                metadata_table_size: metadata_table_size as u32,
+                ..self.package.header
+            };
+            let start = self.header_offset();
+            let mut cursor = Cursor::new(&mut tables[..]);
+            cursor.set_position(start);
+            header.write_le(&mut cursor)?;
+            changed.push(start as usize..cursor.position() as usize);
+            changed.push(entry_start..tables.len());
+        } else {
+            changed.push(entry_start..entry_end);
+        }
+
+        self.write_tables(&tables, changed)?;
+        self.package = ResourcePackage::from_memory(tables, self.package.is_patch())?;
+        Ok(())
+    }
+
+    /// Returns the number of bytes in the package which don't belong to the tables or to the data of a resource.
+    ///
+    /// This includes the data resources used before they were replaced, which [PackagePatcher::compact] removes.
+    pub fn unused_size(&self) -> Result<u64, PackagePatcherError> {
+        let used = self.tables().len() as u64
+            + self
+                .data_spans()?
+                .iter()
+                .map(|span| span.end - span.start)
+                .sum::<u64>();
+        Ok(self.file.metadata()?.len().saturating_sub(used))
+    }
+
+    /// Rewrites the package without its unused space, returns the number of bytes it shrunk by.
+    ///
+    /// The data of the resources is moved to directly follow the tables, keeping its order. The package is written
+    /// to a temporary file next to it first, which then replaces the original.
+    pub fn compact(&mut self) -> Result<u64, PackagePatcherError> {
+        let spans = self.data_spans()?;
+        let mut tables = self.tables().to_vec();
+
+        // Move every span to directly follow the previous one, resources keep their offset inside of their span.
+        let mut new_starts = Vec::with_capacity(spans.len());
+        let mut position = tables.len() as u64;
+        for span in &spans {
+            new_starts.push(position);
+            position += span.end - span.start;
+        }
+
+        for (index, entry) in self.package.offset_table.iter().enumerate() {
+            let span = spans.partition_point(|span| span.end <= entry.data_offset).min(spans.len() - 1);
+            let offset_info = PackageOffsetInfo {
+                data_offset: new_starts[span] + entry.data_offset.saturating_sub(spans[span].start),
+                ..*entry
+            };
+
+            let mut cursor = Cursor::new(&mut tables[..]);
+            cursor.set_position(self.package.offset_table_offset() + index as u64 * OFFSET_ENTRY_SIZE);
+            offset_info.write(&mut cursor)?;
+        }
+
+        let mut file_name = self.path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".compacting");
+        let temp_path = self.path.with_file_name(file_name);
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(&tables)?;
+        for span in &spans {
+            let mut reader = &self.file;
+            reader.seek(SeekFrom::Start(span.start))?;
+            io::copy(&mut reader.take(span.end - span.start), &mut writer)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        let old_size = self.file.metadata()?.len();
+        self.file = OpenOptions::new().read(true).write(true).open(&temp_path)?;
+        fs::rename(&temp_path, &self.path)?;
+        self.package = ResourcePackage::from_memory(tables, self.package.is_patch())?;
+
+        Ok(old_size.saturating_sub(position))
+    }
+
+    /// The header and tables of the package.
+    fn tables(&self) -> &[u8] {
+        self.package.source().and_then(|source| source.data()).unwrap_or_default()
+    }
+
+    /// The position of the header, which follows the magic and the metadata.
+    fn header_offset(&self) -> u64 {
+        match self.package.metadata {
+            Some(_) => 4 + 9,
+            None => 4,
+        }
+    }
+
+    /// The ranges of the package containing resource data, sorted and with overlapping ranges merged.
+    /// Packages without resources have a single empty span at the end of the tables.
+    fn data_spans(&self) -> Result<Vec<Range<u64>>, PackagePatcherError> {
+        let mut ranges = self
+            .package
+            .offset_table
+            .iter()
+            .map(|entry| {
+                let size = self.package.resource_info(&entry.runtime_resource_id)?.packaged_size();
+                Ok(entry.data_offset..entry.data_offset + size as u64)
+            })
+            .collect::<Result<Vec<_>, PackagePatcherError>>()?;
+        ranges.sort_by_key(|range| (range.start, range.end));
+
+        let mut spans: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match spans.last_mut() {
+                Some(span) if range.start <= span.end => span.end = span.end.max(range.end),
+                _ => spans.push(range),
+            }
+        }
+
+        if spans.is_empty() {
+            let tables_end = self.tables().len() as u64;
+            spans.push(tables_end..tables_end);
+        }
+        Ok(spans)
+    }
+
+    /// Writes the given ranges of the tables to the package file.
+    fn write_tables(&self, tables: &[u8], ranges: Vec<Range<usize>>) -> Result<(), PackagePatcherError> {
+        let mut writer = &self.file;
+        for range in ranges {
+            writer.seek(SeekFrom::Start(range.start as u64))?;
+            writer.write_all(&tables[range])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+//! Structural checks for resource packages.
+//!
+//! Parsing a package only validates its header. [ResourcePackage::verify] additionally checks the offset and
+//! metadata tables against each other and against the package data, and decompresses every compressed resource.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use indexmap::IndexMap;
+use lzzzz::lz4;
+
+use crate::encryption::scramble;
+use crate::resource::resource_info::ResourceInfo;
+use crate::resource::resource_package::{ResourcePackage, ResourcePackageError, ResourcePackageSource};
+use crate::resource::runtime_resource_id::RuntimeResourceID;
+
+/// The size of a single entry inside the offset table.
+const OFFSET_ENTRY_SIZE: u64 = 0x14;
+
+/// The size of a metadata entry without its references, with and without the states chunk size field.
+const METADATA_ENTRY_SIZE: u64 = 0x18;
+const LEGACY_METADATA_ENTRY_SIZE: u64 = 0x14;
+
+type Resources = IndexMap<RuntimeResourceID, ResourceInfo>;
+
+/// A single problem found while verifying a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackageIssue {
+    UnknownMagic([u8; 4]),
+    /// The header declares more resources than there are unique ids in the offset table.
+    DuplicateResourceIds { file_count: u32, unique_count: u32 },
+    OffsetTableSizeMismatch { declared: u32, computed: u64 },
+    /// Neither the layout with nor the layout without the states chunk size matches the declared size.
+    MetadataTableSizeMismatch { declared: u32, computed: u64, computed_legacy: u64 },
+    ReferencesChunkSizeMismatch { rrid: RuntimeResourceID, declared: u32, computed: u32 },
+    InvalidResourceId(RuntimeResourceID),
+    InvalidReference { rrid: RuntimeResourceID, index: usize },
+    InvalidUnneededResourceId(RuntimeResourceID),
+    ResourceOutOfBounds { rrid: RuntimeResourceID, offset: u64, size: u32, package_size: u64 },
+    /// The resource data starts inside the header or the tables.
+    ResourceInsideTables { rrid: RuntimeResourceID, offset: u64, tables_end: u64 },
+    OverlappingResources { first: RuntimeResourceID, second: RuntimeResourceID },
+    DecompressionFailed { rrid: RuntimeResourceID, message: String },
+    DecompressedSizeMismatch { rrid: RuntimeResourceID, expected: u32, actual: u32 },
+    /// The metadata table can't be decoded, only the resources whose own metadata can be read are checked.
+    UnreadableMetadata { message: String },
+}
+
+impl fmt::Display for PackageIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageIssue::UnknownMagic(magic) => write!(f, "unknown package magic {:?}", String::from_utf8_lossy(magic)),
+            PackageIssue::DuplicateResourceIds { file_count, unique_count } => write!(
+                f,
+                "the header declares {} resources, but only {} have a unique id",
+                file_count, unique_count
+            ),
+            PackageIssue::OffsetTableSizeMismatch { declared, computed } => write!(
+                f,
+                "the offset table is declared as {} bytes, but its entries take up {} bytes",
+                declared, computed
+            ),
+            PackageIssue::MetadataTableSizeMismatch { declared, computed, computed_legacy } => write!(
+                f,
+                "the metadata table is declared as {} bytes, but its entries take up {} (or {} without states) bytes",
+                declared, computed, computed_legacy
+            ),
+            PackageIssue::ReferencesChunkSizeMismatch { rrid, declared, computed } => write!(
+                f,
+                "resource {} declares a {} byte references chunk, but its references take up {} bytes",
+                rrid, declared, computed
+            ),
+            PackageIssue::InvalidResourceId(rrid) => write!(f, "resource id {} is not valid", rrid),
+            PackageIssue::InvalidReference { rrid, index } => {
+                write!(f, "reference {} of resource {} is not a valid id", index, rrid)
+            }
+            PackageIssue::InvalidUnneededResourceId(rrid) => {
+                write!(f, "unneeded resource id {} is not valid", rrid)
+            }
+            PackageIssue::ResourceOutOfBounds { rrid, offset, size, package_size } => write!(
+                f,
+                "resource {} spans {} bytes at offset {}, past the end of the {} byte package",
+                rrid, size, offset, package_size
+            ),
+            PackageIssue::ResourceInsideTables { rrid, offset, tables_end } => write!(
+                f,
+                "resource {} starts at offset {}, inside of the tables which end at {}",
+                rrid, offset, tables_end
+            ),
+            PackageIssue::OverlappingResources { first, second } => {
+                write!(f, "the data of resources {} and {} overlaps", first, second)
+            }
+            PackageIssue::DecompressionFailed { rrid, message } => {
+                write!(f, "resource {} failed to decompress: {}", rrid, message)
+            }
+            PackageIssue::UnreadableMetadata { message } => {
+                write!(f, "the metadata table can't be decoded: {}", message)
+            }
+            PackageIssue::DecompressedSizeMismatch { rrid, expected, actual } => write!(
+                f,
+                "resource {} decompresses to {} bytes instead of {}",
+                rrid, actual, expected
+            ),
+        }
+    }
+}
+
+impl ResourcePackage {
+    /// Checks that the package is well formed.
+    ///
+    /// Every problem which is found is reported, an empty list means the package is valid.
+    /// An error is only returned when the package data itself can't be accessed. A metadata table which can't be
+    /// decoded is reported as an issue, the checks continue with the metadata of the resources which can be read.
+    pub fn verify(&self) -> Result<Vec<PackageIssue>, ResourcePackageError> {
+        let package_size = match self.source.as_ref().ok_or(ResourcePackageError::NoSource)? {
+            ResourcePackageSource::File(path) => std::fs::metadata(path)?.len(),
+            ResourcePackageSource::Memory(data) => data.len() as u64,
+            ResourcePackageSource::MappedFile(_, mmap) => mmap.len() as u64,
+        };
+
+        let mut issues = vec![];
+        let resources = match self.load_metadata() {
+            Ok(resources) => Cow::Borrowed(resources),
+            Err(e @ (ResourcePackageError::IoError(_) | ResourcePackageError::NoSource)) => return Err(e),
+            Err(e) => {
+                issues.push(PackageIssue::UnreadableMetadata { message: e.to_string() });
+                Cow::Owned(
+                    self.resource_ids()
+                        .filter_map(|rrid| Some((*rrid, self.resource_info(rrid).ok()?.into_owned())))
+                        .collect(),
+                )
+            }
+        };
+        let resources = resources.as_ref();
+
+        self.verify_tables(resources, &mut issues);
+        self.verify_ids(resources, &mut issues);
+        let in_bounds = self.verify_layout(resources, package_size, &mut issues);
+        for rrid in in_bounds {
+            self.verify_data(&resources[&rrid], &mut issues)?;
+        }
+        Ok(issues)
+    }
+
+    fn verify_tables(&self, resources: &Resources, issues: &mut Vec<PackageIssue>) {
+        if &self.magic != b"GKPR" && &self.magic != b"2KPR" {
+            issues.push(PackageIssue::UnknownMagic(self.magic));
+        }
+
+        let file_count = self.header.file_count;
+        if self.resource_count() as u32 != file_count {
+            issues.push(PackageIssue::DuplicateResourceIds {
+                file_count,
+                unique_count: self.resource_count() as u32,
+            });
+        }
+
+        let offset_table_size = file_count as u64 * OFFSET_ENTRY_SIZE;
+        if self.header.offset_table_size as u64 != offset_table_size {
+            issues.push(PackageIssue::OffsetTableSizeMismatch {
+                declared: self.header.offset_table_size,
+                computed: offset_table_size,
+            });
+        }
+
+        // The sizes of the metadata table can only be compared when all of it could be decoded.
+        if resources.len() != self.resource_count() {
+            return;
+        }
+
+        let references_size = resources
+            .values()
+            .map(|resource| resource.header.references_chunk_size as u64)
+            .sum::<u64>();
+        let computed = file_count as u64 * METADATA_ENTRY_SIZE + references_size;
+        let computed_legacy = file_count as u64 * LEGACY_METADATA_ENTRY_SIZE + references_size;
+        let declared = self.header.metadata_table_size;
+        if declared as u64 != computed && declared as u64 != computed_legacy {
+            issues.push(PackageIssue::MetadataTableSizeMismatch {
+                declared,
+                computed,
+                computed_legacy,
+            });
+        }
+
+        for (rrid, resource) in resources {
+            let computed = resource.reference_chunk_size() as u32;
+            if resource.header.references_chunk_size != computed {
+                issues.push(PackageIssue::ReferencesChunkSizeMismatch {
+                    rrid: *rrid,
+                    declared: resource.header.references_chunk_size,
+                    computed,
+                });
+            }
+        }
+    }
+
+    fn verify_ids(&self, resources: &Resources, issues: &mut Vec<PackageIssue>) {
+        for rrid in self.resource_ids() {
+            if !rrid.is_valid() {
+                issues.push(PackageIssue::InvalidResourceId(*rrid));
+            }
+        }
+        for (rrid, resource) in resources {
+            for (index, (reference, _)) in resource.references().iter().enumerate() {
+                if !reference.is_valid() {
+                    issues.push(PackageIssue::InvalidReference { rrid: *rrid, index });
+                }
+            }
+        }
+
+        for rrid in self.unneeded_resource_ids() {
+            if !rrid.is_valid() {
+                issues.push(PackageIssue::InvalidUnneededResourceId(*rrid));
+            }
+        }
+    }
+
+    /// Checks where the resource data lives, returns the resources which lie within the package.
+    fn verify_layout(&self, resources: &Resources, package_size: u64, issues: &mut Vec<PackageIssue>) -> Vec<RuntimeResourceID> {
+        let tables_end = self.offset_table_offset()
+            + self.header.offset_table_size as u64
+            + self.header.metadata_table_size as u64;
+
+        let mut ranges = vec![];
+        for (rrid, resource) in resources {
+            let offset = resource.data_offset();
+            let size = resource.packaged_size();
+
+            if offset < tables_end {
+                issues.push(PackageIssue::ResourceInsideTables {
+                    rrid: *rrid,
+                    offset,
+                    tables_end,
+                });
+            }
+
+            match offset.checked_add(size as u64) {
+                Some(end) if end <= package_size => ranges.push((offset, end, *rrid)),
+                _ => issues.push(PackageIssue::ResourceOutOfBounds {
+                    rrid: *rrid,
+                    offset,
+                    size,
+                    package_size,
+                }),
+            }
+        }
+
+        // Only the offset of resources whose metadata can't be decoded is known.
+        for rrid in self.resource_ids().filter(|rrid| !resources.contains_key(*rrid)) {
+            let offset = self.offset_info(rrid).map_or(tables_end, |entry| entry.data_offset);
+            if offset < tables_end {
+                issues.push(PackageIssue::ResourceInsideTables {
+                    rrid: *rrid,
+                    offset,
+                    tables_end,
+                });
+            }
+        }
+
+        // Compare every range with the one reaching furthest so far, which also catches ranges overlapping a
+        // large resource after smaller ones in between ended.
+        ranges.sort_by_key(|(start, end, _)| (*start, *end));
+        let mut furthest: Option<(u64, RuntimeResourceID)> = None;
+        for &(start, end, second) in &ranges {
+            if let Some((furthest_end, first)) = furthest {
+                if start < furthest_end {
+                    issues.push(PackageIssue::OverlappingResources { first, second });
+                }
+            }
+            if furthest.is_none_or(|(furthest_end, _)| end > furthest_end) {
+                furthest = Some((end, second));
+            }
+        }
+
+        ranges.into_iter().map(|(_, _, rrid)| rrid).collect()
+    }
+
+    fn verify_data(&self, resource: &ResourceInfo, issues: &mut Vec<PackageIssue>) -> Result<(), ResourcePackageError> {
+        let rrid = resource.rrid();
+        let Some(_) = resource.compressed_size() else {
+            return Ok(());
+        };
+
+        let mut data = self.packaged_resource_data(resource)?;
+        if resource.is_scrambled() {
+            scramble::descramble(data.to_mut());
+        }
+
+        let expected = resource.size();
+        let mut decompressed = vec![0; expected as usize];
+        match lz4::decompress(&data, &mut decompressed) {
+            Ok(actual) if actual as u32 != expected => issues.push(PackageIssue::DecompressedSizeMismatch {
+                rrid: *rrid,
+                expected,
+                actual: actual as u32,
+            }),
+            Ok(_) => {}
+            Err(e) => issues.push(PackageIssue::DecompressionFailed {
+                rrid: *rrid,
+                message: e.to_string(),
+            }),
+        }
+
+        Ok(())
+    }
+}
@@ -7,6 +7,8 @@ use rayon::prelude::IntoParallelRefIterator;
 use thiserror::Error;
 use crate::resource::partition_manager::PartitionManagerError::PartitionNotFound;
 
+use crate::resource::mount_cache::MountCache;
+use crate::resource::pdefs::game_detection::{GameDetection, GameDetectionError};
 use crate::resource::pdefs::{
     GameDiscoveryError, GamePaths, PackageDefinitionError, PackageDefinitionSource, PartitionId,
     PartitionInfo,
@@ -15,7 +17,7 @@ use crate::resource::resource_info::ResourceInfo;
 use crate::resource::runtime_resource_id::RuntimeResourceID;
 use crate::WoaVersion;
 
-use super::resource_partition::{PatchId, ResourcePartition, ResourcePartitionError};
+use super::resource_partition::{PatchId, RemountChanges, ResourcePartition, ResourcePartitionError};
 
 #[derive(Debug, Error)]
 pub enum PartitionManagerError {
@@ -33,12 +35,18 @@ pub enum PartitionManagerError {
 
     #[error("Could not discover game paths: {0}")]
     GameDiscoveryError(#[from] GameDiscoveryError),
+
+    #[error("Could not detect the game version: {0}")]
+    GameDetectionError(#[from] GameDetectionError),
     
     #[error("Could not locate runtime directory: {0}")]
     RuntimeDirectoryNotFound(PathBuf),
     
     #[error("Could not find a root partition")]
     NoRootPartition(),
+
+    #[error("The partitions were not read from a package definition file")]
+    NoPackageDefinitionFile,
 }
 
 #[allow(dead_code)]
@@ -51,8 +59,10 @@ pub struct PartitionState {
 
 pub struct PartitionManager {
     runtime_directory: PathBuf,
-    partition_infos: Vec<PartitionInfo>, //All potential partitions which could be mounted with this manager
+    pub(crate) partition_infos: Vec<PartitionInfo>, //All potential partitions which could be mounted with this manager
     pub partitions: Vec<ResourcePartition>, //All mounted partitions
+    lazy_metadata: bool,
+    package_definition: Option<(PathBuf, WoaVersion)>, //The file the partition infos were read from, if any
 }
 
 #[cfg(feature = "rayon")]
@@ -101,9 +111,29 @@ impl PartitionManager {
             runtime_directory,
             partition_infos,
             partitions: vec![],
+            lazy_metadata: false,
+            package_definition: None,
         })
     }
 
+    /// Create a new PartitionManager for the partitions stored in a mount cache, without reading a package definition.
+    ///
+    /// The partitions still have to be mounted, see [`PartitionManager::mount_partitions_with_cache`]. Use
+    /// [`PartitionManager::new`] instead when the package definition may have changed since the cache was written.
+    ///
+    /// # Arguments
+    /// - `runtime_directory` - The path to the game's runtime directory.
+    /// - `cache` - The mount cache to take the partitions from.
+    pub fn from_mount_cache(
+        runtime_directory: PathBuf,
+        cache: &MountCache,
+    ) -> Result<Self, PartitionManagerError> {
+        Self::new(
+            runtime_directory,
+            &PackageDefinitionSource::Custom(cache.partition_infos().to_vec()),
+        )
+    }
+
     /// Create a new PartitionManager by mounting the game at the given path.
     ///
     /// # Arguments
@@ -136,7 +166,7 @@ impl PartitionManager {
     {
         let game_paths = GamePaths::from_retail_directory(retail_directory)?;
         let package_definition =
-            PackageDefinitionSource::from_file(game_paths.package_definition_path, game_version)?;
+            PackageDefinitionSource::from_file(game_paths.package_definition_path.clone(), game_version)?;
 
         // And read all the partition infos.
         let partition_infos = package_definition
@@ -147,6 +177,8 @@ impl PartitionManager {
             runtime_directory: game_paths.runtime_path,
             partition_infos,
             partitions: vec![],
+            lazy_metadata: false,
+            package_definition: Some((game_paths.package_definition_path, game_version)),
         };
 
         // If the user requested auto mounting, do it.
@@ -157,15 +189,44 @@ impl PartitionManager {
         Ok(package_manager)
     }
 
+    /// Create a new PartitionManager by mounting the game at the given path, detecting the game version automatically.
+    ///
+    /// See [`GameDetection::from_retail_directory`] to inspect how the game version was inferred.
+    ///
+    /// # Arguments
+    /// - `retail_path` - The path to the game's retail directory.
+    /// - `mount` - Indicates whether to automatically mount the partitions, can eliminate the need to call `mount_partitions` separately
+    pub fn from_game_auto(
+        retail_directory: PathBuf,
+        mount: bool,
+    ) -> Result<Self, PartitionManagerError> {
+        let detection = GameDetection::from_retail_directory(&retail_directory)?;
+        Self::from_game(retail_directory, detection.version, mount)
+    }
+
+    /// Sets whether partitions are mounted without decoding the metadata tables of their packages.
+    ///
+    /// Only applies to partitions mounted afterwards. See [`ResourcePartition::set_lazy_metadata`].
+    ///
+    /// # Arguments
+    /// - `lazy_metadata` - Whether the metadata tables should be decoded on demand.
+    pub fn set_lazy_metadata(&mut self, lazy_metadata: bool) -> &mut Self {
+        self.lazy_metadata = lazy_metadata;
+        self
+    }
+
     fn try_read_partition<F>(
         runtime_directory: &Path,
         partition_info: PartitionInfo,
+        lazy_metadata: bool,
+        cache: Option<&MountCache>,
         mut progress_callback: F,
     ) -> Result<Option<ResourcePartition>, PartitionManagerError>
     where
         F: FnMut(&PartitionState),
     {
         let mut partition = ResourcePartition::new(partition_info.clone());
+        partition.set_lazy_metadata(lazy_metadata);
         let mut state_result: PartitionState = PartitionState {
             installing: false,
             mounted: false,
@@ -177,9 +238,11 @@ impl PartitionManager {
             state_result = *state;
         };
 
-        partition
-            .mount_resource_packages_in_partition_with_callback(runtime_directory, callback)
-            .map_err(|e| PartitionManagerError::PartitionError(partition_info.id, e))?;
+        match cache {
+            Some(cache) => partition.mount_resource_packages_in_partition_with_cache(runtime_directory, cache, callback),
+            None => partition.mount_resource_packages_in_partition_with_callback(runtime_directory, callback),
+        }
+        .map_err(|e| PartitionManagerError::PartitionError(partition_info.id, e))?;
 
         if state_result.mounted {
             Ok(Some(partition))
@@ -194,6 +257,35 @@ impl PartitionManager {
     /// - `progress_callback` - A callback function that will be called with the current mounting progress.
     pub fn mount_partitions<F>(
         &mut self,
+        progress_callback: F,
+    ) -> Result<(), PartitionManagerError>
+    where
+        F: FnMut(usize, &PartitionState),
+    {
+        self.mount_partitions_internal(None, progress_callback)
+    }
+
+    /// Mount all the partitions in the game, restoring packages which didn't change from a mount cache.
+    ///
+    /// Only packages whose size or modification time differs from the cached stamp are parsed, see [`MountCache`].
+    ///
+    /// # Arguments
+    /// - `cache` - The mount cache, usually read with [`MountCache::from_file`].
+    /// - `progress_callback` - A callback function that will be called with the current mounting progress.
+    pub fn mount_partitions_with_cache<F>(
+        &mut self,
+        cache: &MountCache,
+        progress_callback: F,
+    ) -> Result<(), PartitionManagerError>
+    where
+        F: FnMut(usize, &PartitionState),
+    {
+        self.mount_partitions_internal(Some(cache), progress_callback)
+    }
+
+    fn mount_partitions_internal<F>(
+        &mut self,
+        cache: Option<&MountCache>,
         mut progress_callback: F,
     ) -> Result<(), PartitionManagerError>
     where
@@ -208,7 +300,7 @@ impl PartitionManager {
                     progress_callback(index + 1, state);
                 };
 
-                Self::try_read_partition(&self.runtime_directory, partition_info.clone(), callback)
+                Self::try_read_partition(&self.runtime_directory, partition_info.clone(), self.lazy_metadata, cache, callback)
             })
             .collect::<Result<Vec<Option<ResourcePartition>>, PartitionManagerError>>()?
             .into_iter()
@@ -236,7 +328,7 @@ impl PartitionManager {
         F: FnMut(&PartitionState),
     {
         if let Some(partition) =
-            Self::try_read_partition(&self.runtime_directory, partition_info, progress_callback)?
+            Self::try_read_partition(&self.runtime_directory, partition_info, self.lazy_metadata, None, progress_callback)?
         {
             self.partitions.push(partition)
         }
@@ -244,6 +336,151 @@ impl PartitionManager {
         Ok(())
     }
 
+    /// Brings a single partition in line with the packages in the runtime directory.
+    ///
+    /// A partition which wasn't mounted yet is mounted, and a mounted partition whose base package disappeared
+    /// is unmounted. Otherwise new patch packages are mounted, removed ones are unmounted and changed ones are
+    /// read again, see [`ResourcePartition::remount`].
+    ///
+    /// # Arguments
+    /// - `partition_id` - The id of the partition to remount, it has to be part of the package definition.
+    pub fn remount_partition(&mut self, partition_id: &PartitionId) -> Result<RemountChanges, PartitionManagerError> {
+        let mounted = self
+            .partitions
+            .iter()
+            .position(|partition| partition.partition_info().id == *partition_id);
+
+        if let Some(index) = mounted {
+            let partition = &mut self.partitions[index];
+            return match partition.remount(&self.runtime_directory) {
+                Ok(changes) => Ok(changes),
+                Err(ResourcePartitionError::BasePackageNotFound(_)) => {
+                    let partition = self.partitions.remove(index);
+                    Ok(RemountChanges {
+                        unmounted: partition.packages.keys().copied().sorted().collect(),
+                        ..Default::default()
+                    })
+                }
+                Err(e) => Err(PartitionManagerError::PartitionError(partition_id.clone(), e)),
+            };
+        }
+
+        let partition_info = self
+            .partition_infos
+            .iter()
+            .find(|info| info.id == *partition_id)
+            .cloned()
+            .ok_or_else(|| PartitionNotFound(partition_id.to_string()))?;
+
+        let Some(partition) =
+            Self::try_read_partition(&self.runtime_directory, partition_info, self.lazy_metadata, None, |_| {})?
+        else {
+            return Ok(RemountChanges::default());
+        };
+
+        let changes = RemountChanges {
+            mounted: partition.packages.keys().copied().sorted().collect(),
+            ..Default::default()
+        };
+
+        // Keep the mounted partitions in the order of the package definition.
+        let order = |id: &PartitionId| self.partition_infos.iter().position(|info| info.id == *id);
+        let index = self
+            .partitions
+            .iter()
+            .position(|mounted| order(&mounted.partition_info().id) > order(partition_id))
+            .unwrap_or(self.partitions.len());
+        self.partitions.insert(index, partition);
+        Ok(changes)
+    }
+
+    /// Replaces the partition infos, mounting and unmounting partitions to match them.
+    ///
+    /// Partitions which are no longer defined are unmounted, partitions whose definition changed are mounted
+    /// again and newly defined partitions are mounted. Returns the changes for every partition which was touched.
+    ///
+    /// # Arguments
+    /// - `partition_infos` - The new partition infos, usually read from an updated package definition.
+    pub fn update_partition_infos(
+        &mut self,
+        partition_infos: Vec<PartitionInfo>,
+    ) -> Result<Vec<(PartitionId, RemountChanges)>, PartitionManagerError> {
+        let stale = self
+            .partitions
+            .iter()
+            .filter(|partition| !partition_infos.contains(partition.partition_info()))
+            .map(|partition| partition.partition_info().id.clone())
+            .collect::<Vec<_>>();
+
+        let mut changes = vec![];
+        for partition_id in stale {
+            if let Some(partition) = self.unmount_partition(&partition_id) {
+                let unmounted = partition.packages.keys().copied().sorted().collect();
+                changes.push((partition_id, RemountChanges { unmounted, ..Default::default() }));
+            }
+        }
+
+        self.partition_infos = partition_infos;
+        let unmounted = self
+            .partition_infos
+            .iter()
+            .map(|info| info.id.clone())
+            .filter(|id| self.find_partition(id.clone()).is_none())
+            .collect::<Vec<_>>();
+
+        for partition_id in unmounted {
+            let mounted = self.remount_partition(&partition_id)?;
+            if mounted.is_empty() {
+                continue;
+            }
+            match changes.iter_mut().find(|(id, _)| *id == partition_id) {
+                // The partition was mounted again, report the packages it kept as remounted.
+                Some((_, changes)) => {
+                    changes.remounted = mounted.mounted.iter().filter(|id| changes.unmounted.contains(id)).copied().collect();
+                    changes.unmounted.retain(|id| !mounted.mounted.contains(id));
+                    changes.mounted = mounted.mounted.into_iter().filter(|id| !changes.remounted.contains(id)).collect();
+                }
+                None => changes.push((partition_id, mounted)),
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Reads the package definition file again and applies it, see [`PartitionManager::update_partition_infos`].
+    ///
+    /// Only works for partition managers created from a game directory.
+    pub fn reload_package_definition(&mut self) -> Result<Vec<(PartitionId, RemountChanges)>, PartitionManagerError> {
+        let (path, game_version) = self
+            .package_definition
+            .clone()
+            .ok_or(PartitionManagerError::NoPackageDefinitionFile)?;
+        let partition_infos = PackageDefinitionSource::from_file(path, game_version)?.read()?;
+        self.update_partition_infos(partition_infos)
+    }
+
+    /// Returns the path of the package definition file the partitions were read from, if any.
+    pub fn package_definition_path(&self) -> Option<&Path> {
+        self.package_definition.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    /// Returns the runtime directory of the game.
+    pub fn runtime_directory(&self) -> &Path {
+        &self.runtime_directory
+    }
+
+    /// Unmounts a single partition and returns it.
+    ///
+    /// # Arguments
+    /// - `partition_id` - The id of the partition to unmount.
+    pub fn unmount_partition(&mut self, partition_id: &PartitionId) -> Option<ResourcePartition> {
+        let index = self
+            .partitions
+            .iter()
+            .position(|partition| partition.partition_info().id == *partition_id)?;
+        Some(self.partitions.remove(index))
+    }
+
     pub fn read_resource_from(
         &self,
         partition_id: PartitionId,
@@ -488,7 +725,7 @@ impl PartitionManagerPar for PartitionManager {
     {
         let game_paths = GamePaths::from_retail_directory(retail_directory)?;
         let package_definition =
-            PackageDefinitionSource::from_file(game_paths.package_definition_path, game_version)?;
+            PackageDefinitionSource::from_file(game_paths.package_definition_path.clone(), game_version)?;
 
         // And read all the partition infos.
         let partition_infos = package_definition
@@ -499,6 +736,8 @@ impl PartitionManagerPar for PartitionManager {
             runtime_directory: game_paths.runtime_path,
             partition_infos,
             partitions: vec![],
+            lazy_metadata: false,
+            package_definition: Some((game_paths.package_definition_path, game_version)),
         };
 
         // If the user requested auto mounting, do it.
@@ -520,12 +759,13 @@ impl PartitionManagerPar for PartitionManager {
         let progress_callback = Arc::new(Mutex::new(progress_callback));
 
         let runtime_directory = self.runtime_directory.clone(); // Clone if needed
+        let lazy_metadata = self.lazy_metadata;
 
         let results: Result<Vec<_>, PartitionManagerError> = self.partition_infos
             .par_iter()
             .enumerate()
             .map(|(index, partition_info)| {
-                Self::try_read_partition(&runtime_directory, partition_info.clone(), |state| {
+                Self::try_read_partition(&runtime_directory, partition_info.clone(), lazy_metadata, None, |state| {
                     let mut cb = progress_callback.lock().unwrap();
                     cb(index, state)
                 })
@@ -25,12 +25,12 @@ impl PackageDefinitionParser for BondParser {
             regex!(r"@partition name=(.+?) parent=(.+?) type=(.+?) patchlevel=(.\d*)");
 
         //try to match the regex on a per-line basis
-        for line in deciphered_data.lines() {
+        for (line_index, line) in deciphered_data.lines().enumerate() {
             if partition_regex.is_match(line) {
                 if let Some(m) = partition_regex.captures_iter(line).next() {
                     partitions.push(PartitionInfo {
                         name: m[1].parse().ok(),
-                        parent: find_parent_id(&partitions, m[2].parse().unwrap()),
+                        parent: find_parent_id(&partitions, m[2].to_string()),
                         id: PartitionId {
                             part_type: match &m[3] {
                                 "standard" => PartitionType::Standard,
@@ -39,7 +39,10 @@ impl PackageDefinitionParser for BondParser {
                             },
                             index: partitions.len(),
                         },
-                        patch_level: m[4].parse().unwrap(),
+                        patch_level: m[4].parse().map_err(|_| PackageDefinitionError::InvalidPatchLevel {
+                            line: line_index + 1,
+                            value: m[4].to_string(),
+                        })?,
                         roots: vec![],
                     });
                 }
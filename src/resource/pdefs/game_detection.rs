@@ -0,0 +1,290 @@
+//! Infers which game a retail directory belongs to.
+//!
+//! Every game leaves a few fingerprints on disk: the key used to encrypt thumbs.dat and packagedefinition.txt,
+//! the syntax of the partitions inside packagedefinition.txt and the magic of the rpkg files.
+//! Each fingerprint narrows down the set of possible games, and the detection succeeds when they agree.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use lazy_regex::regex;
+use thiserror::Error;
+
+use crate::encryption::xtea::{Xtea, XteaError};
+use crate::resource::pdefs::{GameDiscoveryError, GamePaths};
+use crate::{utils, WoaVersion};
+
+const ALL_VERSIONS: [WoaVersion; 4] = [
+    WoaVersion::HM2016,
+    WoaVersion::HM2,
+    WoaVersion::HM3,
+    WoaVersion::Bond,
+];
+
+/// The order in which games are picked when the evidence can't tell them apart.
+const PREFERENCE: [WoaVersion; 4] = [
+    WoaVersion::HM3,
+    WoaVersion::HM2,
+    WoaVersion::HM2016,
+    WoaVersion::Bond,
+];
+
+#[derive(Debug, Error)]
+pub enum GameDetectionError {
+    #[error("Could not discover game paths: {0}")]
+    GameDiscoveryError(#[from] GameDiscoveryError),
+
+    #[error("Failed to read {0}: {1}")]
+    IoError(PathBuf, std::io::Error),
+
+    #[error("Failed to decrypt packagedefinition.txt: {0}")]
+    DecryptionError(#[from] XteaError),
+
+    #[error("Nothing in the retail directory identifies the game")]
+    NoEvidence,
+
+    #[error("The retail directory contains conflicting evidence: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "))]
+    ConflictingEvidence(Vec<DetectionEvidence>),
+}
+
+/// The XTEA key family a text file was encrypted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionKey {
+    Woa,
+    Bond,
+}
+
+/// The style of partition declarations found inside a packagedefinition.txt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageDefinitionSyntax {
+    /// `#chunk patchlevel=N` lines, used by Hitman 2016.
+    HashChunk,
+    /// `@chunk patchlevel=N` lines, used by Hitman 2.
+    AtChunk,
+    /// `@partition name=... parent=... type=... patchlevel=N` lines, used by Hitman 3 and 007 First Light.
+    Partition,
+}
+
+/// A single fingerprint found inside the retail directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectionEvidence {
+    ThumbsEncryption(EncryptionKey),
+    PackageDefinitionEncryption(EncryptionKey),
+    PackageDefinitionSyntax(PackageDefinitionSyntax),
+    PackageMagic([u8; 4]),
+}
+
+impl DetectionEvidence {
+    /// The games which are consistent with this piece of evidence.
+    pub fn candidates(&self) -> &'static [WoaVersion] {
+        match self {
+            DetectionEvidence::ThumbsEncryption(key)
+            | DetectionEvidence::PackageDefinitionEncryption(key) => match key {
+                EncryptionKey::Woa => &[WoaVersion::HM2016, WoaVersion::HM2, WoaVersion::HM3],
+                EncryptionKey::Bond => &[WoaVersion::Bond],
+            },
+            DetectionEvidence::PackageDefinitionSyntax(syntax) => match syntax {
+                PackageDefinitionSyntax::HashChunk => &[WoaVersion::HM2016],
+                PackageDefinitionSyntax::AtChunk => &[WoaVersion::HM2],
+                PackageDefinitionSyntax::Partition => &[WoaVersion::HM3, WoaVersion::Bond],
+            },
+            DetectionEvidence::PackageMagic(magic) => match magic {
+                b"GKPR" => &[WoaVersion::HM2016, WoaVersion::HM2],
+                b"2KPR" => &[WoaVersion::HM3, WoaVersion::Bond],
+                _ => &ALL_VERSIONS,
+            },
+        }
+    }
+}
+
+impl Display for DetectionEvidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let key_name = |key: &EncryptionKey| match key {
+            EncryptionKey::Woa => "World of Assassination",
+            EncryptionKey::Bond => "007 First Light",
+        };
+
+        match self {
+            DetectionEvidence::ThumbsEncryption(key) => {
+                write!(f, "thumbs.dat is encrypted with the {} key", key_name(key))
+            }
+            DetectionEvidence::PackageDefinitionEncryption(key) => write!(
+                f,
+                "packagedefinition.txt is encrypted with the {} key",
+                key_name(key)
+            ),
+            DetectionEvidence::PackageDefinitionSyntax(syntax) => write!(
+                f,
+                "packagedefinition.txt declares partitions using {}",
+                match syntax {
+                    PackageDefinitionSyntax::HashChunk => "#chunk lines",
+                    PackageDefinitionSyntax::AtChunk => "@chunk lines",
+                    PackageDefinitionSyntax::Partition => "@partition lines",
+                }
+            ),
+            DetectionEvidence::PackageMagic(magic) => write!(
+                f,
+                "resource packages start with the {} magic",
+                String::from_utf8_lossy(magic)
+            ),
+        }
+    }
+}
+
+/// How certain the detection is about the game version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DetectionConfidence {
+    /// Several games are still possible, the preferred one was picked.
+    Low,
+    /// A single piece of evidence identified the game.
+    Medium,
+    /// Multiple independent pieces of evidence agree on the game.
+    High,
+}
+
+/// The result of detecting the game inside a retail directory.
+#[derive(Debug, Clone)]
+pub struct GameDetection {
+    /// The detected game version.
+    pub version: WoaVersion,
+    pub confidence: DetectionConfidence,
+    /// All games which are consistent with the evidence, `version` is one of these.
+    pub candidates: Vec<WoaVersion>,
+    /// The fingerprints the detection is based on.
+    pub evidence: Vec<DetectionEvidence>,
+}
+
+impl GameDetection {
+    /// Inspects a retail directory to infer which game it belongs to.
+    ///
+    /// # Arguments
+    /// - `retail_directory` - The path to the game's retail directory.
+    pub fn from_retail_directory(retail_directory: &Path) -> Result<Self, GameDetectionError> {
+        let mut evidence = vec![];
+
+        let thumbs_path = retail_directory.join("thumbs.dat");
+        let thumbs_data = std::fs::read(&thumbs_path)
+            .map_err(|e| GameDetectionError::IoError(thumbs_path, e))?;
+        evidence.extend(encryption_key(&thumbs_data).map(DetectionEvidence::ThumbsEncryption));
+
+        let game_paths = GamePaths::from_retail_directory(retail_directory.to_path_buf())?;
+
+        if let Ok(package_definition) = std::fs::read(&game_paths.package_definition_path) {
+            evidence.extend(Self::package_definition_evidence(&package_definition)?);
+        }
+
+        if let Some(magic) = read_package_magic(&game_paths.runtime_path) {
+            evidence.push(DetectionEvidence::PackageMagic(magic));
+        }
+
+        Self::from_evidence(evidence)
+    }
+
+    /// Infers the game from the contents of a packagedefinition.txt file alone.
+    ///
+    /// # Arguments
+    /// - `data` - The raw, possibly encrypted, contents of the file.
+    pub fn from_package_definition(data: &[u8]) -> Result<Self, GameDetectionError> {
+        Self::from_evidence(Self::package_definition_evidence(data)?)
+    }
+
+    fn package_definition_evidence(
+        data: &[u8],
+    ) -> Result<Vec<DetectionEvidence>, GameDetectionError> {
+        let mut evidence = vec![];
+        evidence.extend(encryption_key(data).map(DetectionEvidence::PackageDefinitionEncryption));
+
+        let text = match Xtea::is_encrypted_text_file(data) {
+            true => Xtea::decrypt_text_file(data)?,
+            false => String::from_utf8_lossy(data).into_owned(),
+        };
+
+        let partition_regex = regex!(r"^@partition name=");
+        let at_chunk_regex = regex!(r"^@([A-z]+) patchlevel=([0-9]+)");
+        let hash_chunk_regex = regex!(r"^#([A-z]+) patchlevel=([0-9]+)");
+
+        for line in text.lines().map(str::trim) {
+            let syntax = if partition_regex.is_match(line) {
+                PackageDefinitionSyntax::Partition
+            } else if at_chunk_regex.is_match(line) {
+                PackageDefinitionSyntax::AtChunk
+            } else if hash_chunk_regex.is_match(line) {
+                PackageDefinitionSyntax::HashChunk
+            } else {
+                continue;
+            };
+
+            let syntax = DetectionEvidence::PackageDefinitionSyntax(syntax);
+            if !evidence.contains(&syntax) {
+                evidence.push(syntax);
+            }
+        }
+
+        Ok(evidence)
+    }
+
+    fn from_evidence(evidence: Vec<DetectionEvidence>) -> Result<Self, GameDetectionError> {
+        let mut candidates = ALL_VERSIONS.to_vec();
+        let mut deciding_evidence = 0;
+
+        for item in &evidence {
+            let item_candidates = item.candidates();
+            if item_candidates.len() < ALL_VERSIONS.len() {
+                deciding_evidence += 1;
+            }
+            candidates.retain(|version| item_candidates.contains(version));
+        }
+
+        if deciding_evidence == 0 {
+            return Err(GameDetectionError::NoEvidence);
+        }
+
+        let version = PREFERENCE
+            .into_iter()
+            .find(|version| candidates.contains(version))
+            .ok_or_else(|| GameDetectionError::ConflictingEvidence(evidence.clone()))?;
+
+        let confidence = match (candidates.len(), deciding_evidence) {
+            (1, 1) => DetectionConfidence::Medium,
+            (1, _) => DetectionConfidence::High,
+            _ => DetectionConfidence::Low,
+        };
+
+        Ok(Self {
+            version,
+            confidence,
+            candidates,
+            evidence,
+        })
+    }
+}
+
+fn encryption_key(data: &[u8]) -> Option<EncryptionKey> {
+    if Xtea::is_bond_encrypted_text_file(data) {
+        Some(EncryptionKey::Bond)
+    } else if Xtea::is_encrypted_text_file(data) {
+        Some(EncryptionKey::Woa)
+    } else {
+        None
+    }
+}
+
+/// Reads the magic of the first base package in the runtime directory, preferring chunk0.rpkg.
+fn read_package_magic(runtime_path: &Path) -> Option<[u8; 4]> {
+    let mut package_names = utils::read_file_names(runtime_path)
+        .into_iter()
+        .filter_map(|name| name.into_string().ok())
+        .filter(|name| name.ends_with(".rpkg") && !name.contains("patch"))
+        .collect::<Vec<_>>();
+    package_names.sort_by_key(|name| (name != "chunk0.rpkg", name.clone()));
+
+    package_names.iter().find_map(|name| {
+        let mut magic = [0u8; 4];
+        File::open(runtime_path.join(name))
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .ok()
+            .map(|_| magic)
+    })
+}
@@ -25,7 +25,7 @@ impl PackageDefinitionParser for H2016Parser {
 
         let langdlc_regex = regex!(r"#langdlc ([A-z]+)");
 
-        for line in deciphered_data.lines() {
+        for (line_index, line) in deciphered_data.lines().enumerate() {
             let trimmed_line = line.trim();
 
             match trimmed_line {
@@ -48,7 +48,10 @@ impl PackageDefinitionParser for H2016Parser {
                                     .filter(|&p| p.id.part_type == part_type)
                                     .count(),
                             },
-                            patch_level: m[2].parse().unwrap(),
+                            patch_level: m[2].parse().map_err(|_| PackageDefinitionError::InvalidPatchLevel {
+                                line: line_index + 1,
+                                value: m[2].to_string(),
+                            })?,
                             roots: vec![],
                         });
                     }
@@ -64,13 +67,13 @@ impl PackageDefinitionParser for H2016Parser {
                                 id: PartitionId {
                                     part_type: match partition.id.part_type {
                                         PartitionType::Standard => PartitionType::LanguageStandard(
-                                            language_code.parse().unwrap(),
+                                            language_code.to_string(),
                                         ),
                                         PartitionType::Dlc => PartitionType::LanguageDlc(
-                                            language_code.parse().unwrap(),
+                                            language_code.to_string(),
                                         ),
                                         _ => PartitionType::LanguageDlc(
-                                            language_code.parse().unwrap(),
+                                            language_code.to_string(),
                                         ),
                                     },
                                     index: partition.id.index,
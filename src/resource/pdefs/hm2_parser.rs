@@ -23,7 +23,7 @@ impl PackageDefinitionParser for HM2Parser {
 
         let partition_regex = regex!(r"@([A-z]+) patchlevel=([0-9]+)");
 
-        for line in deciphered_data.lines() {
+        for (line_index, line) in deciphered_data.lines().enumerate() {
             let trimmed_line = line.trim();
 
             match trimmed_line {
@@ -46,7 +46,10 @@ impl PackageDefinitionParser for HM2Parser {
                                     .filter(|&p| p.id.part_type == part_type)
                                     .count(),
                             },
-                            patch_level: m[2].parse().unwrap(),
+                            patch_level: m[2].parse().map_err(|_| PackageDefinitionError::InvalidPatchLevel {
+                                line: line_index + 1,
+                                value: m[2].to_string(),
+                            })?,
                             roots: vec![],
                         });
                     }
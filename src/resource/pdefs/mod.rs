@@ -1,17 +1,18 @@
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use glacier_ini::ini_file::IniFileError;
+use glacier_ini::ini_file::{IniFile, IniFileError};
 use glacier_ini::IniFileSystem;
 use lazy_regex::{Lazy, Regex, regex};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::encryption::xtea::XteaError;
+use crate::encryption::xtea::{Xtea, XteaError};
 use crate::misc::resource_id::ResourceID;
+use crate::resource::pdefs::game_detection::{GameDetection, GameDetectionError};
 use crate::resource::pdefs::GameDiscoveryError::InvalidRuntimePath;
-use crate::resource::pdefs::PackageDefinitionSource::{HM2, HM2016, HM3};
+use crate::resource::pdefs::PackageDefinitionSource::{Bond, HM2, HM2016, HM3};
 use crate::resource::pdefs::PartitionType::{Dlc, LanguageDlc, LanguageStandard, Standard};
 use crate::resource::resource_partition::PatchId;
 use crate::{utils, WoaVersion};
@@ -20,6 +21,7 @@ pub mod h2016_parser;
 pub mod hm2_parser;
 pub mod hm3_parser;
 pub mod bond_parser;
+pub mod game_detection;
 
 const RESOURCE_PATH_REGEX: &Lazy<Regex> = regex!(r"(\[[A-z]+:/.+?]).([A-z]+)");
 
@@ -34,8 +36,14 @@ pub enum PackageDefinitionError {
     #[error("Invalid packagedefintiion file: ({0})")]
     UnexpectedFormat(String),
 
+    #[error("Invalid patch level {value:?} on line {line}")]
+    InvalidPatchLevel { line: usize, value: String },
+
     #[error("Failed to read packagedefinition.txt: {0}")]
     FailedToRead(#[from] std::io::Error),
+
+    #[error("Could not detect the game version: {0}")]
+    GameDetectionError(#[from] GameDetectionError),
 }
 
 #[derive(Debug, Error)]
@@ -163,7 +171,7 @@ impl Display for PartitionId {
 }
 
 /// Represents information about a resource partition.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PartitionInfo {
     /// The name of the partition, if available.
@@ -242,6 +250,7 @@ pub enum PackageDefinitionSource {
     HM3(Vec<u8>),
     HM2(Vec<u8>),
     HM2016(Vec<u8>),
+    Bond(Vec<u8>),
     Custom(Vec<PartitionInfo>),
 }
 
@@ -251,6 +260,7 @@ impl PackageDefinitionSource {
             WoaVersion::HM2016 => HM2016(data),
             WoaVersion::HM2 => HM2(data),
             WoaVersion::HM3 => HM3(data),
+            WoaVersion::Bond => Bond(data),
         }
     }
 
@@ -270,17 +280,31 @@ impl PackageDefinitionSource {
             WoaVersion::HM2016 => PackageDefinitionSource::HM2016(package_definition_data),
             WoaVersion::HM2 => PackageDefinitionSource::HM2(package_definition_data),
             WoaVersion::HM3 => PackageDefinitionSource::HM3(package_definition_data),
+            WoaVersion::Bond => PackageDefinitionSource::Bond(package_definition_data),
         };
 
         Ok(package_definition)
     }
 
+    /// Parses a packagedefinition.txt file, inferring the game version from its contents.
+    ///
+    /// # Arguments
+    /// - `path` - The path to the packagedefinition.txt file.
+    pub fn from_file_auto(path: PathBuf) -> Result<Self, PackageDefinitionError> {
+        let package_definition_data =
+            std::fs::read(path.as_path()).map_err(PackageDefinitionError::FailedToRead)?;
+
+        let detection = GameDetection::from_package_definition(&package_definition_data)?;
+        Ok(Self::from_version(detection.version, package_definition_data))
+    }
+
     pub fn read(&self) -> Result<Vec<PartitionInfo>, PackageDefinitionError> {
         match self {
             PackageDefinitionSource::Custom(vec) => Ok(vec.clone()),
             PackageDefinitionSource::HM3(vec) => hm3_parser::HM3Parser::parse(vec),
             PackageDefinitionSource::HM2(vec) => hm2_parser::HM2Parser::parse(vec),
             PackageDefinitionSource::HM2016(vec) => h2016_parser::H2016Parser::parse(vec),
+            PackageDefinitionSource::Bond(vec) => bond_parser::BondParser::parse(vec),
         }
     }
 }
@@ -307,6 +331,9 @@ pub enum GameDiscoveryError {
 
     #[error("Failed to parse the thumbs.dat file: {0}")]
     FailedToParseThumbsFile(#[from] IniFileError),
+
+    #[error("Failed to decrypt the thumbs.dat file: {0}")]
+    FailedToDecryptThumbsFile(XteaError),
 }
 
 impl GamePaths {
@@ -318,10 +345,12 @@ impl GamePaths {
         let thumbs_path = retail_directory.join("thumbs.dat");
 
         // Parse the thumbs file, so we can find the runtime path.
-        let thumbs = IniFileSystem::from_path(thumbs_path.as_path())
-            .map_err(GameDiscoveryError::FailedToParseThumbsFile)?;
+        let thumbs = Self::read_thumbs_file(thumbs_path.as_path())?;
 
-        let app_options = &thumbs.root()["application"];
+        let app_options = thumbs
+            .root()
+            .section("application")
+            .ok_or(GameDiscoveryError::NoProjectPath)?;
         let project_path = app_options
             .options()
             .get("PROJECT_PATH")
@@ -350,4 +379,34 @@ impl GamePaths {
             package_definition_path,
         })
     }
+
+    /// Reads a thumbs.dat file.
+    /// glacier-ini only knows the World of Assassination key, so 007 First Light files are decrypted here.
+    fn read_thumbs_file(thumbs_path: &Path) -> Result<IniFileSystem, GameDiscoveryError> {
+        let data = std::fs::read(thumbs_path)
+            .map_err(|e| GameDiscoveryError::FailedToParseThumbsFile(IniFileError::IoError(e)))?;
+
+        if !Xtea::is_bond_encrypted_text_file(&data) {
+            return IniFileSystem::from_path(thumbs_path)
+                .map_err(GameDiscoveryError::FailedToParseThumbsFile);
+        }
+
+        let content = Xtea::decrypt_text_file(&data)
+            .map_err(GameDiscoveryError::FailedToDecryptThumbsFile)?;
+
+        let mut thumbs = IniFile::new("thumbs.dat");
+        let mut active_section: Option<&str> = None;
+        for line in content.trim_end_matches('\0').lines() {
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                thumbs.add_new_section(section, None);
+                active_section = Some(section);
+            } else if let Some((key, value)) = line.split_once('=') {
+                if let Some(section) = active_section.and_then(|s| thumbs.section_mut(s)) {
+                    section.insert(key, value);
+                }
+            }
+        }
+
+        Ok(IniFileSystem::new(thumbs))
+    }
 }
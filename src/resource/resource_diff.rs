@@ -0,0 +1,425 @@
+//! Structured comparison of two package states.
+//!
+//! A [ResourceDiff] describes how one set of resources turned into another: which resources were added,
+//! which were removed and which were modified. Modifications are reported per header field, and optionally
+//! by comparing a hash of the resource contents, so changes which don't affect the header are caught as well.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+use crate::resource::partition_manager::PartitionManager;
+use crate::resource::pdefs::PartitionId;
+use crate::resource::resource_info::ResourceInfo;
+use crate::resource::resource_package::{ResourcePackage, ResourcePackageError, ResourceReferenceFlags};
+use crate::resource::resource_partition::{ResourcePartition, ResourcePartitionError};
+use crate::resource::runtime_resource_id::RuntimeResourceID;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Error)]
+pub enum ResourceDiffError {
+    #[error("Failed to read resource {0}: {1}")]
+    ReadPackageResourceError(RuntimeResourceID, ResourcePackageError),
+
+    #[error("Failed to read resource {0}: {1}")]
+    ReadPartitionResourceError(RuntimeResourceID, ResourcePartitionError),
+
+    #[error("Failed to read the metadata of a package: {0}")]
+    ReadPackageError(ResourcePackageError),
+}
+
+/// Controls how resources are compared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Whether the contents of resources present on both sides are hashed and compared.
+    /// This reads every resource, so it is a lot slower than only comparing the headers.
+    pub compare_content: bool,
+}
+
+impl DiffOptions {
+    pub fn with_content(mut self, compare_content: bool) -> Self {
+        self.compare_content = compare_content;
+        self
+    }
+}
+
+/// The MD5 digest of the decompressed and descrambled data of a resource.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ContentHash(pub [u8; 16]);
+
+impl ContentHash {
+    pub fn from_data(data: &[u8]) -> Self {
+        Self(Md5::digest(data).into())
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ContentHash({})", self)
+    }
+}
+
+/// A single difference between two versions of the same resource.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ResourceModification {
+    DataType { old: String, new: String },
+    Size { old: u32, new: u32 },
+    SystemMemoryRequirement { old: u32, new: u32 },
+    VideoMemoryRequirement { old: u32, new: u32 },
+    ReferenceAdded { rrid: RuntimeResourceID, flags: ResourceReferenceFlags },
+    ReferenceRemoved { rrid: RuntimeResourceID, flags: ResourceReferenceFlags },
+    ReferenceFlags { rrid: RuntimeResourceID, old: ResourceReferenceFlags, new: ResourceReferenceFlags },
+    /// The same references are present, but in a different order. Resources address their references by index.
+    ReferenceOrder,
+    Content { old: ContentHash, new: ContentHash },
+}
+
+/// How a resource differs between the old and the new state.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ResourceChange {
+    Added { rrid: RuntimeResourceID, data_type: String, size: u32 },
+    Removed { rrid: RuntimeResourceID, data_type: String, size: u32 },
+    Modified { rrid: RuntimeResourceID, data_type: String, modifications: Vec<ResourceModification> },
+}
+
+impl ResourceChange {
+    pub fn rrid(&self) -> &RuntimeResourceID {
+        match self {
+            ResourceChange::Added { rrid, .. }
+            | ResourceChange::Removed { rrid, .. }
+            | ResourceChange::Modified { rrid, .. } => rrid,
+        }
+    }
+
+    pub fn data_type(&self) -> &str {
+        match self {
+            ResourceChange::Added { data_type, .. }
+            | ResourceChange::Removed { data_type, .. }
+            | ResourceChange::Modified { data_type, .. } => data_type,
+        }
+    }
+}
+
+/// The differences between two sets of resources, sorted by resource id.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ResourceDiff {
+    pub changes: Vec<ResourceChange>,
+}
+
+impl ResourceDiff {
+    /// Compares the resources inside two resource packages.
+    ///
+    /// # Arguments
+    /// * `old` - The original package.
+    /// * `new` - The package to compare against the original.
+    /// * `options` - Controls how resources are compared.
+    pub fn from_packages(
+        old: &ResourcePackage,
+        new: &ResourcePackage,
+        options: DiffOptions,
+    ) -> Result<Self, ResourceDiffError> {
+        Self::from_resources(
+            old.load_metadata().map_err(ResourceDiffError::ReadPackageError)?.values(),
+            new.load_metadata().map_err(ResourceDiffError::ReadPackageError)?.values(),
+            options,
+            |rrid| {
+                old.read_resource_borrowed(rrid)
+                    .map_err(|e| ResourceDiffError::ReadPackageResourceError(*rrid, e))
+            },
+            |rrid| {
+                new.read_resource_borrowed(rrid)
+                    .map_err(|e| ResourceDiffError::ReadPackageResourceError(*rrid, e))
+            },
+        )
+    }
+
+    /// Compares the latest version of the resources inside two mounted partitions.
+    ///
+    /// Resources removed by a patch package are treated as absent.
+    ///
+    /// # Arguments
+    /// * `old` - The original partition.
+    /// * `new` - The partition to compare against the original.
+    /// * `options` - Controls how resources are compared.
+    pub fn from_partitions(
+        old: &ResourcePartition,
+        new: &ResourcePartition,
+        options: DiffOptions,
+    ) -> Result<Self, ResourceDiffError> {
+        let old_resources = old.latest_resources().map_err(ResourceDiffError::ReadPackageError)?;
+        let new_resources = new.latest_resources().map_err(ResourceDiffError::ReadPackageError)?;
+        Self::from_resources(
+            old_resources.into_iter().map(|(info, _)| info),
+            new_resources.into_iter().map(|(info, _)| info),
+            options,
+            |rrid| {
+                old.read_resource_borrowed(rrid)
+                    .map_err(|e| ResourceDiffError::ReadPartitionResourceError(*rrid, e))
+            },
+            |rrid| {
+                new.read_resource_borrowed(rrid)
+                    .map_err(|e| ResourceDiffError::ReadPartitionResourceError(*rrid, e))
+            },
+        )
+    }
+
+    fn from_resources<'o, 'n, O, N, RO, RN>(
+        old: O,
+        new: N,
+        options: DiffOptions,
+        read_old: RO,
+        read_new: RN,
+    ) -> Result<Self, ResourceDiffError>
+    where
+        O: Iterator<Item = &'o ResourceInfo>,
+        N: Iterator<Item = &'n ResourceInfo>,
+        RO: Fn(&RuntimeResourceID) -> Result<Cow<'o, [u8]>, ResourceDiffError>,
+        RN: Fn(&RuntimeResourceID) -> Result<Cow<'n, [u8]>, ResourceDiffError>,
+    {
+        let old = old.map(|info| (*info.rrid(), info)).collect::<HashMap<_, _>>();
+        let new = new.map(|info| (*info.rrid(), info)).collect::<HashMap<_, _>>();
+        let mut changes = vec![];
+
+        for (rrid, old_info) in &old {
+            if !new.contains_key(rrid) {
+                changes.push(ResourceChange::Removed {
+                    rrid: *rrid,
+                    data_type: old_info.data_type(),
+                    size: old_info.size(),
+                });
+            }
+        }
+
+        for (rrid, new_info) in &new {
+            let Some(old_info) = old.get(rrid) else {
+                changes.push(ResourceChange::Added {
+                    rrid: *rrid,
+                    data_type: new_info.data_type(),
+                    size: new_info.size(),
+                });
+                continue;
+            };
+
+            let mut modifications = compare_headers(old_info, new_info);
+            if options.compare_content {
+                let old_hash = ContentHash::from_data(&read_old(rrid)?);
+                let new_hash = ContentHash::from_data(&read_new(rrid)?);
+                if old_hash != new_hash {
+                    modifications.push(ResourceModification::Content {
+                        old: old_hash,
+                        new: new_hash,
+                    });
+                }
+            }
+
+            if !modifications.is_empty() {
+                changes.push(ResourceChange::Modified {
+                    rrid: *rrid,
+                    data_type: new_info.data_type(),
+                    modifications,
+                });
+            }
+        }
+
+        changes.sort_by_key(|change| u64::from(*change.rrid()));
+        Ok(Self { changes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = &ResourceChange> {
+        self.changes
+            .iter()
+            .filter(|change| matches!(change, ResourceChange::Added { .. }))
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &ResourceChange> {
+        self.changes
+            .iter()
+            .filter(|change| matches!(change, ResourceChange::Removed { .. }))
+    }
+
+    pub fn modified(&self) -> impl Iterator<Item = &ResourceChange> {
+        self.changes
+            .iter()
+            .filter(|change| matches!(change, ResourceChange::Modified { .. }))
+    }
+
+    /// Returns the change to the given resource, if it changed at all.
+    pub fn change(&self, rrid: &RuntimeResourceID) -> Option<&ResourceChange> {
+        self.changes
+            .binary_search_by_key(&u64::from(*rrid), |change| u64::from(*change.rrid()))
+            .ok()
+            .map(|index| &self.changes[index])
+    }
+}
+
+fn compare_headers(old: &ResourceInfo, new: &ResourceInfo) -> Vec<ResourceModification> {
+    let mut modifications = vec![];
+
+    if old.data_type() != new.data_type() {
+        modifications.push(ResourceModification::DataType {
+            old: old.data_type(),
+            new: new.data_type(),
+        });
+    }
+    if old.size() != new.size() {
+        modifications.push(ResourceModification::Size {
+            old: old.size(),
+            new: new.size(),
+        });
+    }
+    if old.system_memory_requirement() != new.system_memory_requirement() {
+        modifications.push(ResourceModification::SystemMemoryRequirement {
+            old: old.system_memory_requirement(),
+            new: new.system_memory_requirement(),
+        });
+    }
+    if old.video_memory_requirement() != new.video_memory_requirement() {
+        modifications.push(ResourceModification::VideoMemoryRequirement {
+            old: old.video_memory_requirement(),
+            new: new.video_memory_requirement(),
+        });
+    }
+
+    let old_references = old.references();
+    let new_references = new.references();
+    let old_flags = old_references.iter().copied().collect::<HashMap<_, _>>();
+    let new_flags = new_references.iter().copied().collect::<HashMap<_, _>>();
+
+    for (rrid, flags) in old_references {
+        if !new_flags.contains_key(rrid) {
+            modifications.push(ResourceModification::ReferenceRemoved { rrid: *rrid, flags: *flags });
+        }
+    }
+    for (rrid, flags) in new_references {
+        match old_flags.get(rrid) {
+            None => modifications.push(ResourceModification::ReferenceAdded { rrid: *rrid, flags: *flags }),
+            Some(old) if old != flags => modifications.push(ResourceModification::ReferenceFlags {
+                rrid: *rrid,
+                old: *old,
+                new: *flags,
+            }),
+            Some(_) => {}
+        }
+    }
+
+    let same_set = old_references.len() == new_references.len()
+        && old_references.iter().all(|(rrid, _)| new_flags.contains_key(rrid));
+    let same_order = old_references
+        .iter()
+        .zip(new_references)
+        .all(|((old, _), (new, _))| old == new);
+    if same_set && !same_order {
+        modifications.push(ResourceModification::ReferenceOrder);
+    }
+
+    modifications
+}
+
+/// Whether a partition exists in both games or only in one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PartitionStatus {
+    Added,
+    Removed,
+    Present,
+}
+
+/// The differences inside a single partition of a game.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PartitionDiff {
+    pub partition_id: PartitionId,
+    pub status: PartitionStatus,
+    pub diff: ResourceDiff,
+}
+
+/// The differences between two mounted games, per partition.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GameDiff {
+    /// Only partitions which changed are listed, in the mount order of the new game
+    /// followed by the partitions which only exist in the old game.
+    pub partitions: Vec<PartitionDiff>,
+}
+
+impl GameDiff {
+    /// Compares all mounted partitions of two games.
+    ///
+    /// Partitions are matched by their id, a partition which is only mounted on one side is
+    /// reported as entirely added or removed.
+    ///
+    /// # Arguments
+    /// - `old` - The partition manager of the original game.
+    /// - `new` - The partition manager of the updated game.
+    /// - `options` - Controls how resources are compared.
+    pub fn from_partition_managers(
+        old: &PartitionManager,
+        new: &PartitionManager,
+        options: DiffOptions,
+    ) -> Result<Self, ResourceDiffError> {
+        let empty = |partition: &ResourcePartition| ResourcePartition::new(partition.partition_info().clone());
+        let mut partitions = vec![];
+
+        for new_partition in &new.partitions {
+            let partition_id = new_partition.partition_info().id.clone();
+            let (status, diff) = match old.find_partition(partition_id.clone()) {
+                Some(old_partition) => (
+                    PartitionStatus::Present,
+                    ResourceDiff::from_partitions(old_partition, new_partition, options)?,
+                ),
+                None => (
+                    PartitionStatus::Added,
+                    ResourceDiff::from_partitions(&empty(new_partition), new_partition, options)?,
+                ),
+            };
+            partitions.push(PartitionDiff { partition_id, status, diff });
+        }
+
+        for old_partition in &old.partitions {
+            let partition_id = old_partition.partition_info().id.clone();
+            if new.find_partition(partition_id.clone()).is_none() {
+                partitions.push(PartitionDiff {
+                    partition_id,
+                    status: PartitionStatus::Removed,
+                    diff: ResourceDiff::from_partitions(old_partition, &empty(old_partition), options)?,
+                });
+            }
+        }
+
+        partitions.retain(|partition| partition.status != PartitionStatus::Present || !partition.diff.is_empty());
+        Ok(Self { partitions })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partitions.is_empty()
+    }
+
+    /// Returns the diff of a single partition, None if the partition didn't change.
+    pub fn partition(&self, partition_id: &PartitionId) -> Option<&PartitionDiff> {
+        self.partitions
+            .iter()
+            .find(|partition| partition.partition_id == *partition_id)
+    }
+}
@@ -2,7 +2,7 @@ use super::resource_package::*;
 use crate::resource::runtime_resource_id::RuntimeResourceID;
 use std::fmt;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ResourceInfo {
     pub(crate) entry: PackageOffsetInfo,
     pub(crate) header: ResourceHeader,
@@ -51,12 +51,17 @@ impl ResourceInfo {
         self.entry.compressed_size()
     }
 
+    /// The size of the resource as it is stored in the package, this is the compressed size for compressed resources.
+    pub fn packaged_size(&self) -> u32 {
+        self.entry.compressed_size().unwrap_or(self.header.data_size)
+    }
+
     pub fn data_offset(&self) -> u64 {
         self.entry.data_offset
     }
 
     pub fn states_chunk_size(&self) -> usize {
-        0
+        self.header.states_chunk_size as usize
     }
 
     pub fn reference_chunk_size(&self) -> usize {
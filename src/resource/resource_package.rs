@@ -1,20 +1,27 @@
+use crate::encryption::scramble;
 use crate::resource::resource_info::ResourceInfo;
 use crate::resource::resource_package::ReferenceType::{INSTALL, NORMAL, WEAK};
-use binrw::{binrw, parser, BinRead, BinReaderExt, BinResult};
+use binrw::error::{Backtrace, BacktraceFrame, ContextExt};
+use binrw::{binrw, parser, BinRead, BinReaderExt, BinResult, Endian};
 use bitfield_struct::bitfield;
 use indexmap::IndexMap;
 use itertools::Itertools;
 use lzzzz::lz4;
 use memmap2::Mmap;
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::{Cursor, Read, Seek, SeekFrom};
-use std::iter::zip;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::{fmt, io};
 use thiserror::Error;
 
+use crate::resource::resource_reader::ResourceReader;
 use crate::resource::runtime_resource_id::RuntimeResourceID;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Error)]
 pub enum ResourcePackageError {
     #[error("Error opening the file: {0}")]
@@ -31,17 +38,146 @@ pub enum ResourcePackageError {
 
     #[error("LZ4 decompression error: {0}")]
     Lz4DecompressionError(#[from] lzzzz::Error),
+
+    #[error("Resource {0} lies outside of the package data")]
+    ResourceOutOfBounds(RuntimeResourceID),
+
+    #[error("Unknown package magic {:?}", String::from_utf8_lossy(.0))]
+    UnknownMagic([u8; 4]),
+
+    /// The package ended inside of a value, `offset` is where that value starts.
+    #[error("The package data ends inside of the value at offset {offset:#x} while reading the {context}")]
+    UnexpectedEnd { offset: u64, context: String },
+}
+
+impl ResourcePackageError {
+    /// Converts an error raised by binrw while parsing a package, `read_offset` is where the last read started,
+    /// see [ParseReader].
+    ///
+    /// Errors raised by the parser itself are unwrapped, running out of data is reported together with the part
+    /// of the package which was being read.
+    pub(crate) fn from_parse_error(error: binrw::Error, read_offset: u64) -> Self {
+        let (error, frames) = match error {
+            binrw::Error::Backtrace(backtrace) => (*backtrace.error, backtrace.frames),
+            error => (error, vec![]),
+        };
+
+        let error = match error {
+            binrw::Error::Custom { pos, err } => match err.downcast::<ResourcePackageError>() {
+                Ok(error) => return *error,
+                Err(err) => binrw::Error::Custom { pos, err },
+            },
+            error => error,
+        };
+
+        if error.is_eof() {
+            let context = frames.iter().find_map(|frame| match frame {
+                BacktraceFrame::Custom(context) => context.downcast_ref::<ParseContext>(),
+                _ => None,
+            });
+            if let Some(context) = context {
+                return Self::UnexpectedEnd {
+                    offset: read_offset,
+                    context: context.0.clone(),
+                };
+            }
+        }
+
+        match frames.is_empty() {
+            true => Self::ParsingError(error),
+            false => Self::ParsingError(binrw::Error::Backtrace(Backtrace::new(error, frames))),
+        }
+    }
+}
+
+/// Names the part of a package which was being read, attached to parsing errors.
+#[derive(Debug)]
+pub(crate) struct ParseContext(pub(crate) String);
+
+impl ParseContext {
+    pub(crate) fn new(context: impl Into<String>) -> Self {
+        Self(context.into())
+    }
+}
+
+/// A reader which remembers where the last read started, so running out of data can be reported at the offset of
+/// the value which was being read.
+pub(crate) struct ParseReader<R> {
+    reader: R,
+    read_offset: u64,
+}
+
+impl<R: Read + Seek> ParseReader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self { reader, read_offset: 0 }
+    }
+
+    /// The position the last read started at.
+    pub(crate) fn read_offset(&self) -> u64 {
+        self.read_offset
+    }
+}
+
+impl<R: Read + Seek> Read for ParseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_offset = self.reader.stream_position()?;
+        self.reader.read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.read_offset = self.reader.stream_position()?;
+        self.reader.read_exact(buf)
+    }
+}
+
+impl<R: Seek> Seek for ParseReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        self.reader.stream_position()
+    }
+}
+
+impl fmt::Display for ParseContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "While reading the {}", self.0)
+    }
 }
 
 pub enum ResourcePackageSource {
     File(PathBuf),
     Memory(Vec<u8>),
+    /// A file which stays mapped into memory for as long as the package is alive.
+    MappedFile(PathBuf, Mmap),
+}
+
+impl ResourcePackageSource {
+    /// Returns the path of the file backing this source, if there is one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ResourcePackageSource::File(path) => Some(path),
+            ResourcePackageSource::MappedFile(path, _) => Some(path),
+            ResourcePackageSource::Memory(_) => None,
+        }
+    }
+
+    /// Returns the bytes of the package if they are directly addressable, i.e. held in memory or mapped.
+    pub fn data(&self) -> Option<&[u8]> {
+        match self {
+            ResourcePackageSource::File(_) => None,
+            ResourcePackageSource::Memory(data) => Some(data),
+            ResourcePackageSource::MappedFile(_, mmap) => Some(mmap),
+        }
+    }
 }
 
 /// The version of the package.
 ///
 /// `RPKGv1` is the original version of the package format used in Hitman 2016 and Hitman 2.
 /// `RPKGv2` is the updated version of the package format used in Hitman 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PackageVersion {
     RPKGv1,
     RPKGv2,
@@ -54,18 +190,27 @@ pub struct ResourcePackage {
     #[brw(ignore)]
     pub(crate) source: Option<ResourcePackageSource>,
 
+    #[br(calc = is_patch)]
+    #[bw(ignore)]
+    pub(crate) is_patch_package: bool,
+
+    #[br(err_context(ParseContext::new("package magic")))]
+    #[br(assert(magic == *b"GKPR" || magic == *b"2KPR", ResourcePackageError::UnknownMagic(magic)))]
     pub(crate) magic: [u8; 4],
 
-    #[br(if (magic == *b"2KPR"))]
+    #[br(if (magic == *b"2KPR"), err_context(ParseContext::new("package metadata")))]
     #[bw(if (magic == b"2KPR"))]
     pub(crate) metadata: Option<PackageMetadata>,
 
+    #[br(err_context(ParseContext::new("package header")))]
     pub(crate) header: PackageHeader,
 
     #[brw(if(is_patch))]
+    #[br(err_context(ParseContext::new("unneeded resource count")))]
     pub(crate) unneeded_resource_count: u32,
 
     #[brw(if(is_patch))]
+    #[br(err_context(ParseContext::new("unneeded resource list")))]
     #[br(count = unneeded_resource_count, map = |ids: Vec<u64>| {
     match unneeded_resource_count{
         0 => None,
@@ -74,55 +219,114 @@ pub struct ResourcePackage {
     })]
     pub(crate) unneeded_resources: Option<Vec<RuntimeResourceID>>,
 
-    #[br(parse_with = resource_parser, args(header.file_count))]
+    #[br(parse_with = offset_table_parser, args(header.file_count))]
     #[bw(write_with = empty_writer)]
-    pub(crate) resources: IndexMap<RuntimeResourceID, ResourceInfo>,
+    pub(crate) offset_table: Vec<PackageOffsetInfo>,
+
+    #[br(calc = offset_table.iter().enumerate().map(|(index, entry)| (entry.runtime_resource_id, index)).collect())]
+    #[bw(ignore)]
+    pub(crate) offset_indices: IndexMap<RuntimeResourceID, usize>,
+
+    /// The decoded metadata table, filled on first use.
+    #[brw(ignore)]
+    pub(crate) resources: OnceLock<IndexMap<RuntimeResourceID, ResourceInfo>>,
+
+    /// The position of every entry in the metadata table, used to decode single entries.
+    #[brw(ignore)]
+    pub(crate) metadata_positions: OnceLock<MetadataPositions>,
 }
 
 #[parser(reader: reader, endian)]
-fn resource_parser(file_count: u32) -> BinResult<IndexMap<RuntimeResourceID, ResourceInfo>> {
-    let mut map = IndexMap::new();
-    let mut resource_entries = vec![];
-    for _ in 0..file_count {
-        resource_entries.push(PackageOffsetInfo::read_options(reader, endian, ())?);
+fn offset_table_parser(file_count: u32) -> BinResult<Vec<PackageOffsetInfo>> {
+    let mut offset_table = vec![];
+    for index in 0..file_count {
+        offset_table.push(
+            PackageOffsetInfo::read_options(reader, endian, ())
+                .map_err(|e| e.with_context(ParseContext(format!("offset table entry {index}"))))?,
+        );
     }
+    Ok(offset_table)
+}
 
+/// Detects whether the entries of the metadata table contain the size of the states chunk.
+///
+/// The layout whose entries add up to the declared size of the table is used. When neither does, the entries are
+/// assumed to contain the size unless one of them is non-zero or a resource type isn't ASCII.
+///
+/// The table starts at the current position of the reader, which is restored afterwards.
+fn has_states_size<R: Read + Seek>(reader: &mut R, file_count: usize, metadata_table_size: u32) -> BinResult<bool> {
     let position = reader.stream_position()?;
-    let has_states_size = !(0..file_count).any(|_|{
-        if let Ok(probe) = ResourceHeaderProbe::read_options(reader, endian, ()){
-            probe.states_chunk_size != 0 || (!probe.resource_type.is_ascii())
-        } else { true }
-    });
-
-    reader.seek(SeekFrom::Start(position))?;
-    let mut resource_metadata = vec![];
-    for _ in 0..file_count {
-        resource_metadata.push(ResourceHeader::read_options(reader, endian, (has_states_size,))?);
+    for (entry_size, has_states_size) in [(0x18, true), (0x14, false)] {
+        let walk_size = metadata_table_walk_size(reader, position, file_count, entry_size);
+        reader.seek(SeekFrom::Start(position))?;
+        if walk_size == Some(metadata_table_size as u64) {
+            return Ok(has_states_size);
+        }
     }
 
-    let resources = zip(resource_entries, resource_metadata)
-        .map(|(entry, header)| ResourceInfo { entry, header })
-        .collect::<Vec<ResourceInfo>>();
+    let has_states_size = !(0..file_count).any(|_| {
+        if let Ok(probe) = ResourceHeaderProbe::read_options(reader, Endian::Little, ()) {
+            // Skip the rest of the entry and its references, corrupted reference sizes can't overflow in an i64.
+            let skipped = reader.seek(SeekFrom::Current(12 + probe.references_chunk_size as i64));
+            skipped.is_err() || probe.states_chunk_size != 0 || (!probe.resource_type.is_ascii())
+        } else {
+            true
+        }
+    });
+    reader.seek(SeekFrom::Start(position))?;
+    Ok(has_states_size)
+}
 
-    for resource in resources {
-        map.insert(resource.entry.runtime_resource_id, resource);
+/// The size of a metadata table starting at `start` if its entries without references take up `entry_size` bytes.
+fn metadata_table_walk_size<R: Read + Seek>(reader: &mut R, start: u64, file_count: usize, entry_size: u64) -> Option<u64> {
+    let mut position = start;
+    for _ in 0..file_count {
+        reader.seek(SeekFrom::Start(position + 4)).ok()?;
+        let mut references_chunk_size = [0; 4];
+        reader.read_exact(&mut references_chunk_size).ok()?;
+        position += entry_size + u32::from_le_bytes(references_chunk_size) as u64;
     }
+    Some(position - start)
+}
 
-    Ok(map)
+pub(crate) struct MetadataPositions {
+    pub(crate) has_states_size: bool,
+    pub(crate) positions: Vec<u64>,
 }
 
 impl ResourcePackage {
     /// Parses a ResourcePackage from a file.
     ///
+    /// The file stays mapped into memory, so resources can be read without reopening the file. It must not be
+    /// modified in place while the package is alive, replace it with a new file instead, like
+    /// [PackageBuilder::build_to_file](crate::resource::package_builder::PackageBuilder::build_to_file) does.
+    ///
     /// # Arguments
     /// * `package_path` - The path to the file to parse.
     pub fn from_file<P: AsRef<Path> + Copy>(package_path: P) -> Result<Self, ResourcePackageError> {
+        let package = Self::from_file_lazy(package_path)?;
+        package.load_metadata()?;
+        Ok(package)
+    }
+
+    /// Parses a ResourcePackage from a file, leaving the metadata table to be decoded on demand.
+    ///
+    /// Only the header and the offset table are read upfront. The metadata of a resource is decoded from the
+    /// mapped file when it is first needed, see [`ResourcePackage::load_metadata`] and [`ResourcePackage::resource_info`].
+    ///
+    /// # Arguments
+    /// * `package_path` - The path to the file to parse.
+    pub fn from_file_lazy<P: AsRef<Path> + Copy>(package_path: P) -> Result<Self, ResourcePackageError> {
         let file = File::open(package_path).map_err(ResourcePackageError::IoError)?;
+        // SAFETY: The map assumes the file isn't modified while the package is alive, writing to it in place would
+        // change the resources and metadata read from it or fault when it shrinks. The builder and the game replace
+        // package files with a new file instead, which leaves the mapped one intact. The package patcher does write
+        // in place, it requires packages not to be mounted while they're patched.
         let mmap = unsafe { Mmap::map(&file).map_err(ResourcePackageError::IoError)? };
-        let mut reader = Cursor::new(&mmap[..]);
-        
+        let mut reader = ParseReader::new(Cursor::new(&mmap[..]));
+
         let package_path = package_path.as_ref();
-        
+
         let is_patch = package_path
             .file_name()
             .and_then(|f| f.to_str())
@@ -131,9 +335,12 @@ impl ResourcePackage {
 
         let mut package = reader
             .read_ne_args::<ResourcePackage>((is_patch,))
-            .map_err(ResourcePackageError::ParsingError)?;
+            .map_err(|e| ResourcePackageError::from_parse_error(e, reader.read_offset()))?;
 
-        package.source = Some(ResourcePackageSource::File(package_path.to_path_buf()));
+        package.source = Some(ResourcePackageSource::MappedFile(
+            package_path.to_path_buf(),
+            mmap,
+        ));
 
         Ok(package)
     }
@@ -144,10 +351,21 @@ impl ResourcePackage {
     /// * `data` - The data to parse.
     /// * `is_patch` - Whether the package is a patch package.
     pub fn from_memory(data: Vec<u8>, is_patch: bool) -> Result<Self, ResourcePackageError> {
-        let mut reader = Cursor::new(&data);
+        let package = Self::from_memory_lazy(data, is_patch)?;
+        package.load_metadata()?;
+        Ok(package)
+    }
+
+    /// Parses a ResourcePackage from a memory buffer, leaving the metadata table to be decoded on demand.
+    ///
+    /// # Arguments
+    /// * `data` - The data to parse.
+    /// * `is_patch` - Whether the package is a patch package.
+    pub fn from_memory_lazy(data: Vec<u8>, is_patch: bool) -> Result<Self, ResourcePackageError> {
+        let mut reader = ParseReader::new(Cursor::new(&data));
         let mut package = reader
             .read_ne_args::<ResourcePackage>((is_patch,))
-            .map_err(ResourcePackageError::ParsingError)?;
+            .map_err(|e| ResourcePackageError::from_parse_error(e, reader.read_offset()))?;
 
         package.source = Some(ResourcePackageSource::Memory(data));
 
@@ -155,32 +373,200 @@ impl ResourcePackage {
     }
 
     /// Returns the version of the package.
+    ///
+    /// Parsing rejects unknown magics, packages converted from a legacy format are reported as v1.
     pub fn version(&self) -> PackageVersion {
         match &self.magic {
-            b"GKPR" => PackageVersion::RPKGv1,
             b"2KPR" => PackageVersion::RPKGv2,
-            _ => panic!("Unknown package version"),
+            _ => PackageVersion::RPKGv1,
         }
     }
 
+    /// Returns whether the package was parsed as a patch package.
+    pub fn is_patch(&self) -> bool {
+        self.is_patch_package
+    }
+
     /// Returns the source of the package.
     pub fn source(&self) -> Option<&ResourcePackageSource> {
         self.source.as_ref()
     }
 
     /// Returns a map of the RuntimeResourceIds and their resource information.
-    pub fn resources(&self) -> &IndexMap<RuntimeResourceID, ResourceInfo> {
-        &self.resources
+    ///
+    /// Packages parsed with [`ResourcePackage::from_file`] or [`ResourcePackage::from_memory`] decode their metadata
+    /// table while parsing, so this can't fail for them. For lazily parsed packages this is the same as
+    /// [`ResourcePackage::load_metadata`], and fails when the metadata table can't be decoded.
+    pub fn resources(&self) -> Result<&IndexMap<RuntimeResourceID, ResourceInfo>, ResourcePackageError> {
+        self.load_metadata()
+    }
+
+    /// Decodes the metadata table if that hasn't happened yet, and returns the information of every resource.
+    pub fn load_metadata(&self) -> Result<&IndexMap<RuntimeResourceID, ResourceInfo>, ResourcePackageError> {
+        if let Some(resources) = self.resources.get() {
+            return Ok(resources);
+        }
+        let resources = self.decode_metadata()?;
+        Ok(self.resources.get_or_init(|| resources))
+    }
+
+    /// Returns whether the metadata table has been decoded.
+    pub fn is_metadata_loaded(&self) -> bool {
+        self.resources.get().is_some()
+    }
+
+    /// Returns the ids of all resources in the package, without decoding the metadata table.
+    pub fn resource_ids(&self) -> impl Iterator<Item = &RuntimeResourceID> {
+        self.offset_indices.keys()
+    }
+
+    /// Returns the number of resources in the package.
+    pub fn resource_count(&self) -> usize {
+        self.offset_indices.len()
+    }
+
+    /// Returns whether the package contains the given resource, without decoding the metadata table.
+    ///
+    /// # Arguments
+    /// * `rrid` - The resource ID to look for.
+    pub fn contains_resource(&self, rrid: &RuntimeResourceID) -> bool {
+        self.offset_indices.contains_key(rrid)
+    }
+
+    /// Returns the offset table entry of a resource, without decoding the metadata table.
+    ///
+    /// # Arguments
+    /// * `rrid` - The resource ID to look up.
+    pub fn offset_info(&self, rrid: &RuntimeResourceID) -> Option<&PackageOffsetInfo> {
+        self.offset_indices.get(rrid).map(|&index| &self.offset_table[index])
+    }
+
+    /// Returns the information of a single resource.
+    ///
+    /// When the metadata table hasn't been decoded, only the metadata of this resource is read.
+    ///
+    /// # Arguments
+    /// * `rrid` - The resource ID to look up.
+    pub fn resource_info(&self, rrid: &RuntimeResourceID) -> Result<Cow<'_, ResourceInfo>, ResourcePackageError> {
+        if let Some(resources) = self.resources.get() {
+            return resources.get(rrid).map(Cow::Borrowed).ok_or(ResourcePackageError::ResourceNotFound);
+        }
+
+        let index = *self.offset_indices.get(rrid).ok_or(ResourcePackageError::ResourceNotFound)?;
+        let entry = self.offset_table[index];
+        let data = self.package_data()?;
+        let positions = self.metadata_positions(&data)?;
+
+        let mut reader = ParseReader::new(Cursor::new(data.as_ref()));
+        reader.seek(SeekFrom::Start(positions.positions[index]))?;
+        let header = ResourceHeader::read_options(&mut reader, Endian::Little, (positions.has_states_size,))
+            .map_err(|e| {
+                let e = e.with_context(ParseContext(format!("metadata of resource {}", rrid)));
+                ResourcePackageError::from_parse_error(e, reader.read_offset())
+            })?;
+
+        Ok(Cow::Owned(ResourceInfo { entry, header }))
+    }
+
+    /// The size of everything in front of the offset table.
+    pub(crate) fn offset_table_offset(&self) -> u64 {
+        let metadata_size = match self.metadata {
+            Some(_) => 9,
+            None => 0,
+        };
+        let patch_list_size = match self.is_patch_package {
+            true => 4 + 8 * self.unneeded_resource_count as u64,
+            false => 0,
+        };
+        4 + metadata_size + 0xC + patch_list_size
+    }
+
+    pub(crate) fn metadata_table_offset(&self) -> u64 {
+        self.offset_table_offset() + 0x14 * self.offset_table.len() as u64
+    }
+
+    /// The size of the package data up to the end of the metadata table, as declared by the header.
+    pub(crate) fn tables_size(&self) -> u64 {
+        self.metadata_table_offset() + self.header.metadata_table_size as u64
+    }
+
+    /// Returns the package bytes, reading them from disk if the source isn't held in memory.
+    pub(crate) fn package_data(&self) -> Result<Cow<'_, [u8]>, ResourcePackageError> {
+        match &self.source {
+            Some(ResourcePackageSource::File(package_path)) => Ok(Cow::Owned(std::fs::read(package_path)?)),
+            Some(ResourcePackageSource::Memory(data)) => Ok(Cow::Borrowed(data)),
+            Some(ResourcePackageSource::MappedFile(_, mmap)) => Ok(Cow::Borrowed(mmap)),
+            None => Err(ResourcePackageError::NoSource),
+        }
+    }
+
+    fn decode_metadata(&self) -> Result<IndexMap<RuntimeResourceID, ResourceInfo>, ResourcePackageError> {
+        let data = self.package_data()?;
+        let mut reader = ParseReader::new(Cursor::new(data.as_ref()));
+        reader.seek(SeekFrom::Start(self.metadata_table_offset()))?;
+
+        let has_states_size = has_states_size(&mut reader, self.offset_table.len(), self.header.metadata_table_size)
+            .map_err(|e| ResourcePackageError::from_parse_error(e, reader.read_offset()))?;
+
+        let mut resources = IndexMap::with_capacity(self.offset_indices.len());
+        for entry in &self.offset_table {
+            let header = ResourceHeader::read_options(&mut reader, Endian::Little, (has_states_size,))
+                .map_err(|e| {
+                    let e = e.with_context(ParseContext(format!("metadata of resource {}", entry.runtime_resource_id)));
+                    ResourcePackageError::from_parse_error(e, reader.read_offset())
+                })?;
+            resources.insert(entry.runtime_resource_id, ResourceInfo { entry: *entry, header });
+        }
+
+        Ok(resources)
+    }
+
+    /// Walks the metadata table once to find where each entry starts.
+    pub(crate) fn metadata_positions(&self, data: &[u8]) -> Result<&MetadataPositions, ResourcePackageError> {
+        if let Some(positions) = self.metadata_positions.get() {
+            return Ok(positions);
+        }
+
+        let mut reader = ParseReader::new(Cursor::new(data));
+        reader.seek(SeekFrom::Start(self.metadata_table_offset()))?;
+        let has_states_size = has_states_size(&mut reader, self.offset_table.len(), self.header.metadata_table_size)
+            .map_err(|e| ResourcePackageError::from_parse_error(e, reader.read_offset()))?;
+        let entry_size = if has_states_size { 0x18 } else { 0x14 };
+
+        let mut position = self.metadata_table_offset();
+        let mut positions = Vec::with_capacity(self.offset_table.len());
+        for entry in &self.offset_table {
+            let references_chunk_size = usize::try_from(position + 4)
+                .ok()
+                .and_then(|start| data.get(start..start.checked_add(4)?))
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .ok_or_else(|| ResourcePackageError::UnexpectedEnd {
+                    offset: position + 4,
+                    context: format!("metadata of resource {}", entry.runtime_resource_id),
+                })?;
+            positions.push(position);
+            position += entry_size + references_chunk_size as u64;
+        }
+
+        Ok(self.metadata_positions.get_or_init(|| MetadataPositions { has_states_size, positions }))
+    }
+
+    /// Returns whether the entries of the metadata table contain the size of the states chunk.
+    pub(crate) fn has_states_size(&self) -> Result<bool, ResourcePackageError> {
+        let data = self.package_data()?;
+        Ok(self.metadata_positions(&data)?.has_states_size)
     }
 
     /// Returns whether the package uses the legacy references format.
-    pub fn has_legacy_references(&self) -> bool {
-        self.resources.iter().any(|(_, resource)| {
+    ///
+    /// Decodes the metadata table of a lazily parsed package.
+    pub fn has_legacy_references(&self) -> Result<bool, ResourcePackageError> {
+        Ok(self.load_metadata()?.iter().any(|(_, resource)| {
             resource.references().iter().any(|(_, flags)| match flags {
                 ResourceReferenceFlags::Legacy(_) => true,
                 ResourceReferenceFlags::Standard(_) => false,
             })
-        })
+        }))
     }
 
     /// Returns whether the given resource is an unneeded resource.
@@ -210,54 +596,133 @@ impl ResourcePackage {
     /// # Arguments
     /// * `rrid` - The resource ID of the resource to read.
     pub fn read_resource(&self, rrid: &RuntimeResourceID) -> Result<Vec<u8>, ResourcePackageError> {
-        let resource = self
-            .resources
-            .get(rrid)
-            .ok_or(ResourcePackageError::ResourceNotFound)?;
+        self.read_resource_borrowed(rrid).map(Cow::into_owned)
+    }
+
+    /// Reads the data of a resource, borrowing it from the package when possible.
+    ///
+    /// Resources which are neither compressed nor scrambled are returned as a slice of the
+    /// in-memory or mapped package without copying. Compressed resources are decompressed
+    /// directly from the package data.
+    ///
+    /// # Arguments
+    /// * `rrid` - The resource ID of the resource to read.
+    pub fn read_resource_borrowed(
+        &self,
+        rrid: &RuntimeResourceID,
+    ) -> Result<Cow<'_, [u8]>, ResourcePackageError> {
+        let resource = self.resource_info(rrid)?;
+
+        let mut buffer = self.packaged_resource_data(&resource)?;
+
+        if resource.is_scrambled() {
+            scramble::descramble(buffer.to_mut());
+        }
+
+        if resource.is_compressed() {
+            let mut decompressed_buffer = vec![0; resource.header.data_size as usize];
+            lz4::decompress(&buffer, &mut decompressed_buffer)?;
+            return Ok(Cow::Owned(decompressed_buffer));
+        }
+
+        Ok(buffer)
+    }
 
-        let final_size = resource
-            .compressed_size()
-            .unwrap_or(resource.header.data_size);
+    /// Opens a streaming reader over the data of a resource.
+    ///
+    /// The data is descrambled and decompressed incrementally while reading, so the resource
+    /// never has to be held in memory as a whole.
+    ///
+    /// # Arguments
+    /// * `rrid` - The resource ID of the resource to read.
+    pub fn resource_reader(
+        &self,
+        rrid: &RuntimeResourceID,
+    ) -> Result<ResourceReader<'_>, ResourcePackageError> {
+        let resource = self.resource_info(rrid)?;
 
-        let is_lz4ed = resource.is_compressed();
-        let is_scrambled = resource.is_scrambled();
+        let decompressed_size = resource.compressed_size().map(|_| resource.size());
 
-        // Extract the resource bytes from the resourcePackage
-        let mut buffer = match &self.source {
+        match &self.source {
+            Some(ResourcePackageSource::File(package_path)) => {
+                let file = File::open(package_path).map_err(ResourcePackageError::IoError)?;
+                ResourceReader::from_file(
+                    file,
+                    resource.entry.data_offset,
+                    resource.packaged_size(),
+                    decompressed_size,
+                    resource.is_scrambled(),
+                )
+                .map_err(ResourcePackageError::IoError)
+            }
+
+            Some(ResourcePackageSource::Memory(data)) => Ok(ResourceReader::from_slice(
+                Self::resource_slice(data, &resource)?,
+                decompressed_size,
+                resource.is_scrambled(),
+            )),
+
+            Some(ResourcePackageSource::MappedFile(_, mmap)) => Ok(ResourceReader::from_slice(
+                Self::resource_slice(mmap, &resource)?,
+                decompressed_size,
+                resource.is_scrambled(),
+            )),
+
+            None => Err(ResourcePackageError::NoSource),
+        }
+    }
+
+    /// Returns the stored bytes of a resource exactly as they appear in the package.
+    ///
+    /// The data is not descrambled or decompressed. Only works for packages which are held in memory or mapped.
+    ///
+    /// # Arguments
+    /// * `rrid` - The resource ID of the resource to read.
+    pub fn raw_resource_data(&self, rrid: &RuntimeResourceID) -> Result<&[u8], ResourcePackageError> {
+        let resource = self.resource_info(rrid)?;
+
+        let data = self
+            .source
+            .as_ref()
+            .ok_or(ResourcePackageError::NoSource)?
+            .data()
+            .ok_or(ResourcePackageError::NoSource)?;
+
+        Self::resource_slice(data, &resource)
+    }
+
+    /// Extracts the packaged (possibly compressed and scrambled) bytes of a resource from the source.
+    pub(crate) fn packaged_resource_data(&self, resource: &ResourceInfo) -> Result<Cow<'_, [u8]>, ResourcePackageError> {
+        match &self.source {
             Some(ResourcePackageSource::File(package_path)) => {
                 let mut file = File::open(package_path).map_err(ResourcePackageError::IoError)?;
                 file.seek(io::SeekFrom::Start(resource.entry.data_offset))
                     .map_err(ResourcePackageError::IoError)?;
 
-                let mut buffer = vec![0; final_size as usize];
+                let mut buffer = vec![0; resource.packaged_size() as usize];
                 file.read_exact(&mut buffer)
                     .map_err(ResourcePackageError::IoError)?;
-                buffer
+                Ok(Cow::Owned(buffer))
             }
 
             Some(ResourcePackageSource::Memory(data)) => {
-                let start_offset = resource.entry.data_offset as usize;
-                let end_offset = start_offset + final_size as usize;
-                data[start_offset..end_offset].to_vec()
+                Self::resource_slice(data, resource).map(Cow::Borrowed)
             }
 
-            None => return Err(ResourcePackageError::NoSource),
-        };
+            Some(ResourcePackageSource::MappedFile(_, mmap)) => {
+                Self::resource_slice(mmap, resource).map(Cow::Borrowed)
+            }
 
-        if is_scrambled {
-            let str_xor = [0xdc, 0x45, 0xa6, 0x9c, 0xd3, 0x72, 0x4c, 0xab];
-            buffer.iter_mut().enumerate().for_each(|(index, byte)| {
-                *byte ^= str_xor[index % str_xor.len()];
-            });
-        }
-
-        if is_lz4ed {
-            let mut decompressed_buffer = vec![0; resource.header.data_size as usize];
-            lz4::decompress(&buffer, &mut decompressed_buffer)?;
-            return Ok(decompressed_buffer);
+            None => Err(ResourcePackageError::NoSource),
         }
+    }
 
-        Ok(buffer)
+    fn resource_slice<'a>(data: &'a [u8], resource: &ResourceInfo) -> Result<&'a [u8], ResourcePackageError> {
+        let start_offset = resource.entry.data_offset as usize;
+        start_offset
+            .checked_add(resource.packaged_size() as usize)
+            .and_then(|end_offset| data.get(start_offset..end_offset))
+            .ok_or(ResourcePackageError::ResourceOutOfBounds(*resource.rrid()))
     }
 }
 
@@ -272,6 +737,7 @@ pub enum ChunkType {
 
 #[allow(dead_code)]
 #[binrw]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct PackageMetadata {
     pub unknown: u32,
     pub chunk_id: u8,
@@ -298,7 +764,7 @@ pub struct PackageOffsetFlags {
 }
 
 #[allow(dead_code)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[binrw]
 #[brw(little)]
 pub struct PackageOffsetInfo {
@@ -321,17 +787,15 @@ impl PackageOffsetInfo {
 }
 
 #[allow(dead_code)]
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[binrw]
-#[brw(little)]
-#[br(import(has_states_size: bool))]
+#[brw(little, import(has_states_size: bool))]
 pub struct ResourceHeader {
     pub(crate) resource_type: [u8; 4],
     pub(crate) references_chunk_size: u32,
-    #[br(calc=0)]
+    #[brw(if(has_states_size))]
     pub(crate) states_chunk_size: u32,
 
-    #[br(pad_before(if has_states_size {4} else {0}))]
     pub(crate) data_size: u32,
     pub(crate) system_memory_requirement: u32,
     pub(crate) video_memory_requirement: u32,
@@ -346,7 +810,6 @@ pub struct ResourceHeader {
 struct ResourceHeaderProbe {
     pub(crate) resource_type: [u8; 4],
     references_chunk_size: u32,
-    #[br(pad_after(references_chunk_size + 12))]
     pub(crate) states_chunk_size: u32,
 }
 
@@ -354,6 +817,7 @@ struct ResourceHeaderProbe {
 #[binrw]
 #[bw(map = |&x| Self::into_bits(x))]
 #[derive(Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ResourceReferenceFlagsLegacy {
     pub __: bool,
     pub runtime_acquired: bool,
@@ -369,6 +833,7 @@ pub struct ResourceReferenceFlagsLegacy {
 #[binrw]
 #[derive(Eq, PartialEq)]
 #[bw(map = |&x: &Self| x.into_bits())]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ResourceReferenceFlagsStandard {
     #[bits(5, default = 0x1F)]
     pub language_code: u8,
@@ -378,6 +843,7 @@ pub struct ResourceReferenceFlagsStandard {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ReferenceType {
     INSTALL = 0,
     NORMAL = 1,
@@ -400,6 +866,7 @@ impl ReferenceType {
 
 /// Reference flags for a given resource, defines the metadata of a reference
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ResourceReferenceFlags {
     Legacy(ResourceReferenceFlagsLegacy),
     Standard(ResourceReferenceFlagsStandard),
@@ -552,7 +1019,7 @@ impl fmt::Display for ResourceHeader {
         write!(
             f,
             "type: {}, reference_num: {}, size: {}, num_reqs: ({} {})",
-            std::str::from_utf8(&res_type).unwrap(),
+            String::from_utf8_lossy(&res_type),
             self.references_chunk_size,
             self.data_size,
             self.system_memory_requirement,
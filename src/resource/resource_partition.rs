@@ -1,8 +1,10 @@
 use crate::resource::partition_manager::PartitionState;
+use crate::resource::mount_cache::{FileStamp, MountCache};
 use crate::resource::pdefs::PartitionInfo;
 use crate::resource::resource_info::ResourceInfo;
 use crate::{utils, GlacierResource, GlacierResourceError, WoaVersion};
-use lazy_regex::regex::Regex;
+use itertools::Itertools;
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::{collections::HashMap, path::Path};
@@ -10,6 +12,7 @@ use std::{fmt, io};
 use thiserror::Error;
 
 use crate::resource::resource_package::{ResourcePackage, ResourcePackageError};
+use crate::resource::resource_reader::ResourceReader;
 
 use super::runtime_resource_id::RuntimeResourceID;
 
@@ -80,6 +83,27 @@ pub struct ResourcePartition {
     info: PartitionInfo,
     pub packages: HashMap<PatchId, ResourcePackage>,
     pub(crate) resources: HashMap<RuntimeResourceID, PatchId>,
+    /// The size and modification time of every package when it was read.
+    pub(crate) package_stamps: HashMap<PatchId, FileStamp>,
+    lazy_metadata: bool,
+}
+
+/// The packages which were touched by remounting a partition.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RemountChanges {
+    /// Packages which weren't mounted before.
+    pub mounted: Vec<PatchId>,
+    /// Packages which changed on disk and were read again.
+    pub remounted: Vec<PatchId>,
+    /// Packages which are no longer present and were unmounted.
+    pub unmounted: Vec<PatchId>,
+}
+
+impl RemountChanges {
+    /// Returns whether remounting changed nothing.
+    pub fn is_empty(&self) -> bool {
+        self.mounted.is_empty() && self.remounted.is_empty() && self.unmounted.is_empty()
+    }
 }
 
 impl ResourcePartition {
@@ -88,9 +112,20 @@ impl ResourcePartition {
             info,
             packages: Default::default(),
             resources: Default::default(),
+            package_stamps: Default::default(),
+            lazy_metadata: false,
         }
     }
 
+    /// Sets whether packages are mounted without decoding their metadata tables.
+    ///
+    /// Lazily mounted packages only read their offset tables, the metadata of a package is decoded
+    /// the first time it is needed. See [`ResourcePackage::from_file_lazy`].
+    pub fn set_lazy_metadata(&mut self, lazy_metadata: bool) -> &mut Self {
+        self.lazy_metadata = lazy_metadata;
+        self
+    }
+
     /// search through the package_dir to figure out which patch indices are there.
     /// We have to use this instead of using the patchlevel inside the PartitionInfo.
     fn read_patch_indices(
@@ -104,15 +139,19 @@ impl ResourcePartition {
             return Err(ResourcePartitionError::BasePackageNotFound(filename));
         }
 
-        let regex_str = format!(r"^(?:{}patch([0-9]+).rpkg)$", self.info.id);
-        let patch_package_re = Regex::new(regex_str.as_str()).unwrap();
+        let patch_prefix = format!("{}patch", self.info.id);
 
         for file_name in utils::read_file_names(package_dir)
             .iter()
             .flat_map(|file_name| file_name.to_str())
         {
-            if let Some(cap) = patch_package_re.captures(file_name) {
-                let patch_level = cap[1].parse::<usize>()?;
+            let patch_level = file_name
+                .strip_prefix(patch_prefix.as_str())
+                .and_then(|name| name.strip_suffix(".rpkg"))
+                .filter(|level| !level.is_empty() && level.bytes().all(|c| c.is_ascii_digit()));
+
+            if let Some(patch_level) = patch_level {
+                let patch_level = patch_level.parse::<usize>()?;
                 if patch_level <= self.info.patch_level {
                     patch_indices.push(PatchId::Patch(patch_level));
                 }
@@ -143,6 +182,33 @@ impl ResourcePartition {
     pub fn mount_resource_packages_in_partition_with_callback<F>(
         &mut self,
         runtime_path: &Path,
+        progress_callback: F,
+    ) -> Result<(), ResourcePartitionError>
+    where
+        F: FnMut(&PartitionState),
+    {
+        self.mount_packages(runtime_path, None, progress_callback)
+    }
+
+    /// Mounts resource packages in the partition, restoring unchanged packages from a mount cache.
+    ///
+    /// Packages which changed since the cache was written, or which aren't in the cache, are parsed from disk.
+    pub fn mount_resource_packages_in_partition_with_cache<F>(
+        &mut self,
+        runtime_path: &Path,
+        cache: &MountCache,
+        progress_callback: F,
+    ) -> Result<(), ResourcePartitionError>
+    where
+        F: FnMut(&PartitionState),
+    {
+        self.mount_packages(runtime_path, Some(cache), progress_callback)
+    }
+
+    fn mount_packages<F>(
+        &mut self,
+        runtime_path: &Path,
+        cache: Option<&MountCache>,
         mut progress_callback: F,
     ) -> Result<(), ResourcePartitionError>
     where
@@ -166,11 +232,11 @@ impl ResourcePartition {
         let patch_indices = patch_idx_result?;
 
         let base_package_path = runtime_path.join(self.info.filename(PatchId::Base));
-        self.mount_package(base_package_path.as_path(), PatchId::Base)?;
+        self.mount_package(base_package_path.as_path(), PatchId::Base, cache)?;
 
         for (index, patch_id) in patch_indices.clone().into_iter().enumerate() {
             let patch_package_path = runtime_path.join(self.info.filename(patch_id));
-            self.mount_package(patch_package_path.as_path(), patch_id)?;
+            self.mount_package(patch_package_path.as_path(), patch_id, cache)?;
 
             state.install_progress = index as f32 / patch_indices.len() as f32;
             progress_callback(&state);
@@ -187,8 +253,35 @@ impl ResourcePartition {
         &mut self,
         package_path: &Path,
         patch_index: PatchId,
+        cache: Option<&MountCache>,
     ) -> Result<(), ResourcePartitionError> {
-        let rpkg = ResourcePackage::from_file(package_path).map_err(|e| {
+        let (rpkg, stamp) = self.read_package(package_path, cache)?;
+        match stamp {
+            Some(stamp) => self.package_stamps.insert(patch_index, stamp),
+            None => self.package_stamps.remove(&patch_index),
+        };
+
+        Self::register_package(&mut self.resources, patch_index, &rpkg);
+        self.packages.insert(patch_index, rpkg);
+        Ok(())
+    }
+
+    /// Reads a package, together with the stamp the file had before it was read.
+    ///
+    /// The stamp is taken first, so a package which is replaced while it is read looks changed afterwards.
+    fn read_package(
+        &self,
+        package_path: &Path,
+        cache: Option<&MountCache>,
+    ) -> Result<(ResourcePackage, Option<FileStamp>), ResourcePartitionError> {
+        let stamp = FileStamp::from_path(package_path).ok();
+        let cached = cache.and_then(|cache| cache.restore_package(package_path, stamp.as_ref()));
+        let rpkg = match (cached, self.lazy_metadata) {
+            (Some(rpkg), _) => Ok(rpkg),
+            (None, true) => ResourcePackage::from_file_lazy(package_path),
+            (None, false) => ResourcePackage::from_file(package_path),
+        };
+        let rpkg = rpkg.map_err(|e| {
             ResourcePartitionError::ReadResourcePackageError(
                 e,
                 package_path
@@ -198,22 +291,126 @@ impl ResourcePartition {
                     .into_owned(),
             )
         })?;
+        Ok((rpkg, stamp))
+    }
 
+    /// Applies the deletions and resources of a package on top of the packages registered before it.
+    fn register_package(
+        resources: &mut HashMap<RuntimeResourceID, PatchId>,
+        patch_index: PatchId,
+        rpkg: &ResourcePackage,
+    ) {
         //remove the deletions if there are any
         for deletion in rpkg.unneeded_resource_ids() {
-            if self.resources.contains_key(deletion) {
-                self.resources.remove_entry(deletion);
+            if resources.contains_key(deletion) {
+                resources.remove_entry(deletion);
+            }
+        }
+
+        for rrid in rpkg.resource_ids() {
+            resources.insert(*rrid, patch_index);
+        }
+    }
+
+    /// Resolves which package provides each resource, from scratch.
+    fn rebuild_resources(&mut self) {
+        self.resources.clear();
+        for patch_id in self.packages.keys().copied().sorted() {
+            Self::register_package(&mut self.resources, patch_id, &self.packages[&patch_id]);
+        }
+    }
+
+    /// Brings the mounted packages in line with the packages in the runtime directory.
+    ///
+    /// Patch packages which appeared are mounted, packages which disappeared are unmounted and packages which
+    /// changed on disk since they were mounted are read again. The resources of the partition, including the
+    /// deletions made by patches, are resolved again afterwards. Nothing changes when an error is returned.
+    ///
+    /// # Arguments
+    /// - `runtime_path` - The runtime directory holding the packages of this partition.
+    pub fn remount(&mut self, runtime_path: &Path) -> Result<RemountChanges, ResourcePartitionError> {
+        let mut patch_ids = vec![PatchId::Base];
+        patch_ids.extend(self.read_patch_indices(runtime_path)?);
+
+        let mut changes = RemountChanges::default();
+        let mut updated = vec![];
+        for patch_id in &patch_ids {
+            let package_path = runtime_path.join(self.info.filename(*patch_id));
+            if self.packages.contains_key(patch_id) {
+                let stamp = FileStamp::from_path(&package_path).ok();
+                if stamp.is_some() && self.package_stamps.get(patch_id) == stamp.as_ref() {
+                    continue;
+                }
+                changes.remounted.push(*patch_id);
+            } else {
+                changes.mounted.push(*patch_id);
             }
+            let (rpkg, stamp) = self.read_package(&package_path, None)?;
+            updated.push((*patch_id, rpkg, stamp));
         }
 
-        for rrid in rpkg.resources.keys() {
-            self.resources.insert(*rrid, patch_index);
+        changes.unmounted = self
+            .packages
+            .keys()
+            .filter(|patch_id| !patch_ids.contains(patch_id))
+            .copied()
+            .sorted()
+            .collect();
+        for patch_id in &changes.unmounted {
+            self.packages.remove(patch_id);
+            self.package_stamps.remove(patch_id);
         }
 
-        self.packages.insert(patch_index, rpkg);
+        for (patch_id, rpkg, stamp) in updated {
+            self.packages.insert(patch_id, rpkg);
+            match stamp {
+                Some(stamp) => self.package_stamps.insert(patch_id, stamp),
+                None => self.package_stamps.remove(&patch_id),
+            };
+        }
+
+        self.rebuild_resources();
+        Ok(changes)
+    }
+
+    /// Mounts a single package of this partition, on top of or in between the packages which are already mounted.
+    ///
+    /// A package which is already mounted under the same patch id is replaced. Mounting a patch below the
+    /// current patch level resolves the resources of the partition again, so later patches keep precedence.
+    ///
+    /// # Arguments
+    /// - `runtime_path` - The runtime directory holding the packages of this partition.
+    /// - `patch_id` - The patch id of the package to mount.
+    pub fn mount_patch(&mut self, runtime_path: &Path, patch_id: PatchId) -> Result<(), ResourcePartitionError> {
+        let package_path = runtime_path.join(self.info.filename(patch_id));
+        let is_latest = self.packages.keys().all(|mounted| *mounted < patch_id);
+        if is_latest {
+            return self.mount_package(&package_path, patch_id, None);
+        }
+
+        let (rpkg, stamp) = self.read_package(&package_path, None)?;
+        match stamp {
+            Some(stamp) => self.package_stamps.insert(patch_id, stamp),
+            None => self.package_stamps.remove(&patch_id),
+        };
+        self.packages.insert(patch_id, rpkg);
+        self.rebuild_resources();
         Ok(())
     }
 
+    /// Unmounts a single package of this partition and returns it.
+    ///
+    /// Resources which were deleted by the package become available again if an earlier package provides them.
+    ///
+    /// # Arguments
+    /// - `patch_id` - The patch id of the package to unmount.
+    pub fn unmount_patch(&mut self, patch_id: PatchId) -> Option<ResourcePackage> {
+        let rpkg = self.packages.remove(&patch_id)?;
+        self.package_stamps.remove(&patch_id);
+        self.rebuild_resources();
+        Some(rpkg)
+    }
+
     pub fn contains(&self, rrid: &RuntimeResourceID) -> bool {
         self.resources.contains_key(rrid)
     }
@@ -226,33 +423,30 @@ impl ResourcePartition {
     ///
     /// This function iterates through the resources in the partition
     /// Will only contain the latest version of a resource and will ignore resources if they are removed by a package.
-    pub fn latest_resources(&self) -> Vec<(&ResourceInfo, PatchId)> {
+    /// Fails when the metadata of a lazily mounted package can't be decoded.
+    pub fn latest_resources(&self) -> Result<Vec<(&ResourceInfo, PatchId)>, ResourcePackageError> {
         self.resources
             .iter()
-            .flat_map(|(rrid, idx)| {
-                if let Ok(info) = self.resource_info_from(rrid, *idx) {
-                    Some((info, *idx))
-                } else {
-                    None
-                }
+            .map(|(rrid, patch_id)| {
+                let info = self
+                    .packages
+                    .get(patch_id)
+                    .ok_or(ResourcePackageError::ResourceNotFound)?
+                    .load_metadata()?
+                    .get(rrid)
+                    .ok_or(ResourcePackageError::ResourceNotFound)?;
+                Ok((info, *patch_id))
             })
             .collect()
     }
 
-    pub fn latest_resources_of_type(&self, resource_type: &str) -> Vec<(&ResourceInfo, PatchId)>{
-        self.resources
-            .iter()
-            .flat_map(|(rrid, idx)| {
-                if let Ok(info) = self.resource_info_from(rrid, *idx) {
-                    Some((info, *idx))
-                } else {
-                    None
-                }
-            }).filter(|(resource, _)| resource.data_type() == resource_type)
-            .collect()
+    pub fn latest_resources_of_type(&self, resource_type: &str) -> Result<Vec<(&ResourceInfo, PatchId)>, ResourcePackageError> {
+        let mut resources = self.latest_resources()?;
+        resources.retain(|(resource, _)| resource.data_type() == resource_type);
+        Ok(resources)
     }
     
-    pub fn latest_resources_of_glacier_type<G: GlacierResource>(&self) -> Vec<(&ResourceInfo, PatchId)>{
+    pub fn latest_resources_of_glacier_type<G: GlacierResource>(&self) -> Result<Vec<(&ResourceInfo, PatchId)>, ResourcePackageError> {
         let resource_type: String = String::from_utf8_lossy(&G::resource_type()).into_owned();
         self.latest_resources_of_type(resource_type.as_str())
     }
@@ -262,7 +456,8 @@ impl ResourcePartition {
     /// This function goes through the partition and returns a list of resource marked as unneeded (i.e., deleted). 
     /// Only resources actually deleted resources will be returned, if a resource is removed and the added again it will be ignored.
     /// If a package deletes a resource that was never present in any previous package, it will not be included in the returned list
-    pub fn removed_resources(&self) -> Vec<(&ResourceInfo, PatchId)> {
+    /// Fails when the metadata of a lazily mounted package can't be decoded.
+    pub fn removed_resources(&self) -> Result<Vec<(&ResourceInfo, PatchId)>, ResourcePackageError> {
 
         self.packages
             .iter()
@@ -275,18 +470,26 @@ impl ResourcePartition {
                 let patches = self.resource_patch_indices(&deletion_rid);
                 if patches.is_empty() { return None } //resource is not present in any patch
                 if self.resources.contains_key(&deletion_rid) { return None } //resource was deleted, but was added again
-                patches.iter().max().and_then(|&latest_patch| {
-                    self.resource_info_from(&deletion_rid, latest_patch)
-                        .ok().map(|resource_info| (resource_info, latest_patch))
+                patches.iter().max().map(|&latest_patch| {
+                    let info = self
+                        .packages
+                        .get(&latest_patch)
+                        .ok_or(ResourcePackageError::ResourceNotFound)?
+                        .load_metadata()?
+                        .get(&deletion_rid)
+                        .ok_or(ResourcePackageError::ResourceNotFound)?;
+                    Ok((info, latest_patch))
                 })
             }).collect()
     }
 
-    pub fn removed_resources_of_type(&self, resource_type: &str) -> Vec<(&ResourceInfo, PatchId)> {
-        self.removed_resources().iter().filter(|(res_info, _)| res_info.data_type() == resource_type).cloned().collect()
+    pub fn removed_resources_of_type(&self, resource_type: &str) -> Result<Vec<(&ResourceInfo, PatchId)>, ResourcePackageError> {
+        let mut resources = self.removed_resources()?;
+        resources.retain(|(res_info, _)| res_info.data_type() == resource_type);
+        Ok(resources)
     }
 
-    pub fn removed_resources_of_glacier_type<G: GlacierResource>(&self) -> Vec<(&ResourceInfo, PatchId)>{
+    pub fn removed_resources_of_glacier_type<G: GlacierResource>(&self) -> Result<Vec<(&ResourceInfo, PatchId)>, ResourcePackageError> {
         let resource_type: String = String::from_utf8_lossy(&G::resource_type()).into_owned();
         self.removed_resources_of_type(resource_type.as_str())
     }
@@ -310,6 +513,50 @@ impl ResourcePartition {
         })
     }
 
+    /// Reads the latest version of a resource, borrowing it from its package when possible.
+    ///
+    /// See [`ResourcePackage::read_resource_borrowed`].
+    pub fn read_resource_borrowed(
+        &self,
+        rrid: &RuntimeResourceID,
+    ) -> Result<Cow<'_, [u8]>, ResourcePartitionError> {
+        let package_index = *self
+            .resources
+            .get(rrid)
+            .ok_or(ResourcePartitionError::ResourceNotAvailable)?;
+
+        let rpkg = self
+            .packages
+            .get(&package_index)
+            .ok_or(ResourcePartitionError::NotMounted)?;
+
+        rpkg.read_resource_borrowed(rrid).map_err(|e| {
+            ResourcePartitionError::ReadResourcePackageError(e, self.info.filename(package_index))
+        })
+    }
+
+    /// Opens a streaming reader over the latest version of a resource.
+    ///
+    /// See [`ResourcePackage::resource_reader`].
+    pub fn resource_reader(
+        &self,
+        rrid: &RuntimeResourceID,
+    ) -> Result<ResourceReader<'_>, ResourcePartitionError> {
+        let package_index = *self
+            .resources
+            .get(rrid)
+            .ok_or(ResourcePartitionError::ResourceNotAvailable)?;
+
+        let rpkg = self
+            .packages
+            .get(&package_index)
+            .ok_or(ResourcePartitionError::NotMounted)?;
+
+        rpkg.resource_reader(rrid).map_err(|e| {
+            ResourcePartitionError::ReadResourcePackageError(e, self.info.filename(package_index))
+        })
+    }
+
     pub fn read_glacier_resource<T>(
         &self,
         woa_version: WoaVersion,
@@ -364,7 +611,8 @@ impl ResourcePartition {
             .get(package_index)
             .ok_or(ResourcePartitionError::NotMounted)?;
 
-        rpkg.resources
+        rpkg.load_metadata()
+            .map_err(|e| ResourcePartitionError::ReadResourcePackageError(e, self.info.filename(*package_index)))?
             .get(rrid)
             .ok_or(ResourcePartitionError::ResourceNotAvailable)
     }
@@ -379,7 +627,8 @@ impl ResourcePartition {
             .get(&patch_id)
             .ok_or(ResourcePartitionError::NotMounted)?;
 
-        rpkg.resources
+        rpkg.load_metadata()
+            .map_err(|e| ResourcePartitionError::ReadResourcePackageError(e, self.info.filename(patch_id)))?
             .get(rrid)
             .ok_or(ResourcePartitionError::ResourceNotAvailable)
     }
@@ -391,7 +640,7 @@ impl ResourcePartition {
     pub fn resource_patch_indices(&self, rrid: &RuntimeResourceID) -> Vec<PatchId> {
         self.packages
             .iter()
-            .filter(|(_, package)| package.resources.contains_key(rrid))
+            .filter(|(_, package)| package.contains_resource(rrid))
             .map(|(id, _)| *id)
             .collect::<Vec<PatchId>>()
     }
@@ -410,7 +659,7 @@ impl Debug for ResourcePartition {
         let total = self
             .packages
             .values()
-            .map(|v| v.resources.len())
+            .map(|v| v.resource_count())
             .sum::<usize>();
 
         write!(
@@ -0,0 +1,399 @@
+//! Streaming access to the data of a single resource.
+//!
+//! A [ResourceReader] descrambles and decompresses a resource while it is being read, so arbitrarily
+//! large resources can be piped into files, hashers or sockets without holding them in memory.
+
+use std::cmp::min;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+use crate::encryption::scramble::Scrambler;
+
+/// The largest distance an LZ4 match can reach back into the already decoded data.
+const LZ4_WINDOW_SIZE: usize = 0x10000;
+
+/// The maximum amount of bytes decoded in a single step, this bounds the memory used by a literal or match run.
+const LZ4_STEP_SIZE: usize = 0x10000;
+
+/// Where the packaged bytes of a resource are read from.
+enum PackagedData<'a> {
+    Slice(&'a [u8]),
+    File { file: BufReader<File>, offset: u64 },
+}
+
+/// Reads the packaged bytes of a resource, descrambling them on the fly.
+struct PackagedReader<'a> {
+    data: PackagedData<'a>,
+    size: u64,
+    position: u64,
+    is_scrambled: bool,
+}
+
+impl Read for PackagedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.size - self.position;
+        let to_read = min(remaining, buf.len() as u64) as usize;
+        let buf = &mut buf[..to_read];
+
+        let read = match &mut self.data {
+            PackagedData::Slice(data) => {
+                let start = self.position as usize;
+                buf.copy_from_slice(&data[start..start + to_read]);
+                to_read
+            }
+            PackagedData::File { file, .. } => file.read(buf)?,
+        };
+
+        if self.is_scrambled {
+            Scrambler::at_offset(self.position).apply(&mut buf[..read]);
+        }
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for PackagedReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = resolve_seek(pos, self.position, self.size)?;
+
+        if let PackagedData::File { file, offset } = &mut self.data {
+            file.seek(SeekFrom::Start(*offset + position))?;
+        }
+
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// Where the decoder is inside of the current LZ4 sequence.
+enum Lz4State {
+    Token,
+    Literals { remaining: usize, match_length: usize },
+    Match { offset: usize, remaining: usize },
+    Done,
+}
+
+/// An incremental decoder for a single raw LZ4 block.
+///
+/// Only the last 64KiB of decoded data is kept around, since that is as far back as a match can reach.
+struct Lz4BlockReader<R: Read> {
+    input: BufReader<R>,
+    window: Vec<u8>,
+    read_position: usize,
+    decoded: u64,
+    decompressed_size: u64,
+    state: Lz4State,
+}
+
+impl<R: Read> Lz4BlockReader<R> {
+    fn new(input: R, decompressed_size: u64) -> Self {
+        Self {
+            input: BufReader::new(input),
+            window: Vec::new(),
+            read_position: 0,
+            decoded: 0,
+            decompressed_size,
+            state: match decompressed_size {
+                0 => Lz4State::Done,
+                _ => Lz4State::Token,
+            },
+        }
+    }
+
+    fn into_inner(self) -> R {
+        self.input.into_inner()
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8];
+        self.input.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// Reads the additional length bytes which follow a saturated length nibble.
+    fn read_extended_length(&mut self) -> io::Result<usize> {
+        let mut length = 0usize;
+        loop {
+            let byte = self.read_u8()?;
+            length = length
+                .checked_add(byte as usize)
+                .ok_or_else(|| invalid_data("LZ4 sequence length overflows"))?;
+            if byte != 0xFF {
+                return Ok(length);
+            }
+        }
+    }
+
+    fn reserve_output(&mut self, length: usize) -> io::Result<()> {
+        if self.decoded + length as u64 > self.decompressed_size {
+            return Err(invalid_data("LZ4 block decodes to more data than expected"));
+        }
+        self.decoded += length as u64;
+        Ok(())
+    }
+
+    /// Decodes the next part of the block into the window.
+    fn step(&mut self) -> io::Result<()> {
+        match self.state {
+            Lz4State::Token => {
+                let token = self.read_u8()?;
+                let mut literal_length = (token >> 4) as usize;
+                if literal_length == 0xF {
+                    literal_length += self.read_extended_length()?;
+                }
+                self.state = Lz4State::Literals {
+                    remaining: literal_length,
+                    match_length: (token & 0xF) as usize,
+                };
+            }
+
+            Lz4State::Literals { remaining, match_length } if remaining > 0 => {
+                let length = min(remaining, LZ4_STEP_SIZE);
+                self.reserve_output(length)?;
+
+                let start = self.window.len();
+                self.window.resize(start + length, 0);
+                self.input.read_exact(&mut self.window[start..])?;
+
+                self.state = Lz4State::Literals {
+                    remaining: remaining - length,
+                    match_length,
+                };
+            }
+
+            Lz4State::Literals { match_length, .. } => {
+                // The last sequence of a block only contains literals.
+                if self.decoded == self.decompressed_size {
+                    self.state = Lz4State::Done;
+                    return Ok(());
+                }
+
+                let offset = u16::from_le_bytes([self.read_u8()?, self.read_u8()?]) as usize;
+                if offset == 0 || offset > self.window.len() {
+                    return Err(invalid_data("LZ4 match offset points outside of the decoded data"));
+                }
+
+                let mut match_length = match_length;
+                if match_length == 0xF {
+                    match_length += self.read_extended_length()?;
+                }
+
+                self.state = Lz4State::Match {
+                    offset,
+                    remaining: match_length + 4,
+                };
+            }
+
+            Lz4State::Match { offset, remaining } => {
+                let length = min(remaining, LZ4_STEP_SIZE);
+                self.reserve_output(length)?;
+
+                let start = self.window.len() - offset;
+                if offset >= length {
+                    self.window.extend_from_within(start..start + length);
+                } else {
+                    // The match overlaps with itself, so it has to be copied byte by byte.
+                    for index in start..start + length {
+                        let byte = self.window[index];
+                        self.window.push(byte);
+                    }
+                }
+
+                self.state = match remaining - length {
+                    0 => Lz4State::Token,
+                    remaining => Lz4State::Match { offset, remaining },
+                };
+            }
+
+            Lz4State::Done => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Lz4BlockReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_position == self.window.len() {
+            if let Lz4State::Done = self.state {
+                return Ok(0);
+            }
+            self.step()?;
+        }
+
+        let length = min(buf.len(), self.window.len() - self.read_position);
+        buf[..length].copy_from_slice(&self.window[self.read_position..self.read_position + length]);
+        self.read_position += length;
+
+        // Drop everything which can no longer be referenced by a match.
+        if self.read_position > 4 * LZ4_WINDOW_SIZE {
+            self.window.drain(..self.read_position - LZ4_WINDOW_SIZE);
+            self.read_position = LZ4_WINDOW_SIZE;
+        }
+
+        Ok(length)
+    }
+}
+
+enum ResourceReaderInner<'a> {
+    Stored(PackagedReader<'a>),
+    Compressed(Lz4BlockReader<PackagedReader<'a>>),
+}
+
+/// A streaming reader over the data of a single resource.
+///
+/// Scrambled data is descrambled and compressed data is decompressed while reading.
+/// Seeking is cheap for uncompressed resources. For compressed resources seeking forward decodes
+/// and discards the skipped data, while seeking backwards restarts decoding from the beginning.
+pub struct ResourceReader<'a> {
+    inner: Option<ResourceReaderInner<'a>>,
+    size: u64,
+    position: u64,
+}
+
+impl<'a> ResourceReader<'a> {
+    pub(crate) fn from_slice(
+        data: &'a [u8],
+        decompressed_size: Option<u32>,
+        is_scrambled: bool,
+    ) -> Self {
+        Self::new(
+            PackagedReader {
+                data: PackagedData::Slice(data),
+                size: data.len() as u64,
+                position: 0,
+                is_scrambled,
+            },
+            decompressed_size,
+        )
+    }
+
+    pub(crate) fn from_file(
+        mut file: File,
+        offset: u64,
+        size: u32,
+        decompressed_size: Option<u32>,
+        is_scrambled: bool,
+    ) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Self::new(
+            PackagedReader {
+                data: PackagedData::File {
+                    file: BufReader::new(file),
+                    offset,
+                },
+                size: size as u64,
+                position: 0,
+                is_scrambled,
+            },
+            decompressed_size,
+        ))
+    }
+
+    fn new(packaged: PackagedReader<'a>, decompressed_size: Option<u32>) -> Self {
+        match decompressed_size {
+            Some(decompressed_size) => Self {
+                inner: Some(ResourceReaderInner::Compressed(Lz4BlockReader::new(
+                    packaged,
+                    decompressed_size as u64,
+                ))),
+                size: decompressed_size as u64,
+                position: 0,
+            },
+            None => Self {
+                size: packaged.size,
+                inner: Some(ResourceReaderInner::Stored(packaged)),
+                position: 0,
+            },
+        }
+    }
+
+    /// The size of the resource data once it is fully read.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether the resource is decompressed while reading.
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.inner, Some(ResourceReaderInner::Compressed(_)))
+    }
+
+    fn inner_mut(&mut self) -> io::Result<&mut ResourceReaderInner<'a>> {
+        self.inner
+            .as_mut()
+            .ok_or_else(|| io::Error::other("resource reader is in an invalid state"))
+    }
+}
+
+impl Read for ResourceReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.inner_mut()? {
+            ResourceReaderInner::Stored(reader) => reader.read(buf)?,
+            ResourceReaderInner::Compressed(reader) => reader.read(buf)?,
+        };
+
+        if read == 0 && !buf.is_empty() && self.position < self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "resource data ended before its expected size",
+            ));
+        }
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ResourceReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = resolve_seek(pos, self.position, self.size)?;
+
+        if let ResourceReaderInner::Stored(reader) = self.inner_mut()? {
+            self.position = reader.seek(SeekFrom::Start(target))?;
+            return Ok(self.position);
+        }
+
+        if target < self.position {
+            if let Some(ResourceReaderInner::Compressed(reader)) = self.inner.take() {
+                let mut packaged = reader.into_inner();
+                packaged.seek(SeekFrom::Start(0))?;
+                self.inner = Some(ResourceReaderInner::Compressed(Lz4BlockReader::new(
+                    packaged, self.size,
+                )));
+                self.position = 0;
+            }
+        }
+
+        let to_skip = target - self.position;
+        let skipped = io::copy(&mut self.by_ref().take(to_skip), &mut io::sink())?;
+        if skipped != to_skip {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "resource data ended before the seek target",
+            ));
+        }
+
+        Ok(self.position)
+    }
+}
+
+fn resolve_seek(pos: SeekFrom, current: u64, size: u64) -> io::Result<u64> {
+    let target = match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(offset) => size.checked_add_signed(offset),
+        SeekFrom::Current(offset) => current.checked_add_signed(offset),
+    };
+
+    target.filter(|&target| target <= size).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "seek position is outside of the resource",
+        )
+    })
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
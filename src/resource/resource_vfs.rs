@@ -0,0 +1,368 @@
+//! A virtual filesystem view of the resources of a mounted game.
+//!
+//! A [ResourceVfs] lays the mounted resources out as a directory tree, keyed by their resolved resource paths.
+//! `[assembly:/_pro/scenes/test.entity].pc_entitytype` becomes `assembly/_pro/scenes/test.entity.pc_entitytype`,
+//! resources without a known path are listed as `unknown/<TYPE>/<hash>`. When a resource is mounted in several
+//! partitions the view shows the version the game would load: the one from the partition mounted last, in its
+//! latest patch.
+
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+use thiserror::Error;
+
+#[cfg(feature = "path-list")]
+use crate::misc::hash_path_list::PathList;
+use crate::resource::partition_manager::PartitionManager;
+use crate::resource::pdefs::PartitionId;
+use crate::resource::resource_partition::{PatchId, ResourcePartition, ResourcePartitionError};
+use crate::resource::resource_reader::ResourceReader;
+use crate::resource::runtime_resource_id::RuntimeResourceID;
+
+/// The top level directory holding every resource without a known path.
+pub const UNKNOWN_DIRECTORY: &str = "unknown";
+
+#[derive(Debug, Error)]
+pub enum VfsError {
+    #[error("No such file or directory: {0}")]
+    NotFound(String),
+
+    #[error("{0} is a directory")]
+    IsADirectory(String),
+
+    #[error("{0} is not a directory")]
+    NotADirectory(String),
+
+    #[error("Partition {0} is not mounted")]
+    PartitionNotFound(String),
+
+    #[error("Failed to read {0}: {1}")]
+    ReadError(String, ResourcePartitionError),
+
+    #[error("Failed to read the metadata of {0}: {1}")]
+    MetadataError(RuntimeResourceID, ResourcePartitionError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VfsFileType {
+    Directory,
+    File,
+}
+
+/// A resource as it is visible in the view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsFile {
+    pub rrid: RuntimeResourceID,
+    pub data_type: String,
+    /// The size of the resource data, after decompressing it.
+    pub size: u32,
+    /// The partition the visible version is read from.
+    pub partition_id: PartitionId,
+    /// The package the visible version is read from.
+    pub patch_id: PatchId,
+}
+
+/// The metadata of a file or directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VfsStat {
+    Directory { entry_count: usize },
+    File(VfsFile),
+}
+
+impl VfsStat {
+    pub fn file_type(&self) -> VfsFileType {
+        match self {
+            VfsStat::Directory { .. } => VfsFileType::Directory,
+            VfsStat::File(_) => VfsFileType::File,
+        }
+    }
+
+    /// The size of a file, directories have no size.
+    pub fn size(&self) -> u64 {
+        match self {
+            VfsStat::Directory { .. } => 0,
+            VfsStat::File(file) => file.size as u64,
+        }
+    }
+}
+
+/// A single entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsDirEntry {
+    pub name: String,
+    pub file_type: VfsFileType,
+}
+
+/// A directory tree of the resources in a partition manager.
+///
+/// Paths are separated by `/` and relative to the root of the view, a leading `/` is ignored. The root itself
+/// is the empty path.
+pub struct ResourceVfs<'a> {
+    partition_manager: &'a PartitionManager,
+    files: Vec<VfsFile>,
+    paths: HashMap<String, usize>,
+    resource_paths: HashMap<RuntimeResourceID, String>,
+    directories: HashMap<String, BTreeMap<String, VfsFileType>>,
+}
+
+impl<'a> ResourceVfs<'a> {
+    /// Builds a view of every mounted partition, layered the way the game loads them.
+    ///
+    /// Fails when the metadata of a resource can't be read, for example from a lazily mounted package whose metadata
+    /// table is corrupted.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - A partition manager with mounted partitions.
+    /// - `resolver` - Returns the resource path of a resource, like `[assembly:/path/file.ext].pc_type`, or None if
+    ///   it is unknown.
+    pub fn new<F>(partition_manager: &'a PartitionManager, resolver: F) -> Result<Self, VfsError>
+    where
+        F: Fn(&RuntimeResourceID) -> Option<String>,
+    {
+        Self::from_partitions(partition_manager, partition_manager.partitions.iter(), resolver)
+    }
+
+    /// Builds a view of every mounted partition, resolving paths from a path list.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - A partition manager with mounted partitions.
+    /// - `path_list` - A parsed path list.
+    #[cfg(feature = "path-list")]
+    pub fn from_path_list(partition_manager: &'a PartitionManager, path_list: &PathList) -> Result<Self, VfsError> {
+        Self::new(partition_manager, |rrid| path_list.get(rrid).map(|rid| rid.uri().to_string()))
+    }
+
+    /// Builds a view of a single partition, without the resources it inherits from its parents.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - A partition manager with mounted partitions.
+    /// - `partition_id` - The partition to show.
+    /// - `resolver` - Returns the resource path of a resource, or None if it is unknown.
+    pub fn for_partition<F>(
+        partition_manager: &'a PartitionManager,
+        partition_id: &PartitionId,
+        resolver: F,
+    ) -> Result<Self, VfsError>
+    where
+        F: Fn(&RuntimeResourceID) -> Option<String>,
+    {
+        let partition = partition_manager
+            .find_partition(partition_id.clone())
+            .ok_or_else(|| VfsError::PartitionNotFound(partition_id.to_string()))?;
+        Self::from_partitions(partition_manager, std::iter::once(partition), resolver)
+    }
+
+    fn from_partitions<'p, F>(
+        partition_manager: &'a PartitionManager,
+        partitions: impl Iterator<Item = &'p ResourcePartition>,
+        resolver: F,
+    ) -> Result<Self, VfsError>
+    where
+        F: Fn(&RuntimeResourceID) -> Option<String>,
+    {
+        let mut vfs = Self {
+            partition_manager,
+            files: vec![],
+            paths: HashMap::new(),
+            resource_paths: HashMap::new(),
+            directories: HashMap::from([(String::new(), BTreeMap::new())]),
+        };
+
+        // Later partitions override the resources of the partitions mounted before them.
+        let mut visible: HashMap<RuntimeResourceID, VfsFile> = HashMap::new();
+        let mut order = vec![];
+        for partition in partitions {
+            for (rrid, patch_id) in partition.resources.iter().sorted_by_key(|(rrid, _)| u64::from(**rrid)) {
+                let info = partition
+                    .get_resource_info(rrid)
+                    .map_err(|e| VfsError::MetadataError(*rrid, e))?;
+                let file = VfsFile {
+                    rrid: *rrid,
+                    data_type: info.data_type(),
+                    size: info.size(),
+                    partition_id: partition.partition_info().id.clone(),
+                    patch_id: *patch_id,
+                };
+                if visible.insert(*rrid, file).is_none() {
+                    order.push(*rrid);
+                }
+            }
+        }
+
+        let mut unresolved = vec![];
+        for rrid in order {
+            let file = visible.remove(&rrid).expect("every ordered resource is visible");
+            match resolver(&rrid).as_deref().and_then(resource_file_path) {
+                Some(path) if vfs.can_insert(&path) => vfs.insert(path, file),
+                _ => unresolved.push(file),
+            }
+        }
+        for file in unresolved {
+            let path = unknown_file_path(&file.data_type, &file.rrid);
+            vfs.insert(path, file);
+        }
+
+        Ok(vfs)
+    }
+
+    fn can_insert(&self, path: &str) -> bool {
+        if self.paths.contains_key(path) || self.directories.contains_key(path) {
+            return false;
+        }
+        ancestors(path).all(|directory| !self.paths.contains_key(directory))
+    }
+
+    fn insert(&mut self, path: String, file: VfsFile) {
+        let mut child = path.as_str();
+        let mut file_type = VfsFileType::File;
+        for directory in ancestors(&path) {
+            let name = child.rsplit('/').next().unwrap_or(child);
+            let entries = self.directories.entry(directory.to_string()).or_default();
+            let is_new = entries.insert(name.to_string(), file_type).is_none();
+            if !is_new && file_type == VfsFileType::Directory {
+                break;
+            }
+            child = directory;
+            file_type = VfsFileType::Directory;
+        }
+
+        self.resource_paths.insert(file.rrid, path.clone());
+        self.paths.insert(path, self.files.len());
+        self.files.push(file);
+    }
+
+    /// Returns the metadata of a file or directory.
+    ///
+    /// # Arguments
+    /// - `path` - The path of the file or directory.
+    pub fn stat(&self, path: &str) -> Result<VfsStat, VfsError> {
+        let path = normalize(path);
+        if let Some(index) = self.paths.get(path) {
+            return Ok(VfsStat::File(self.files[*index].clone()));
+        }
+        match self.directories.get(path) {
+            Some(entries) => Ok(VfsStat::Directory {
+                entry_count: entries.len(),
+            }),
+            None => Err(VfsError::NotFound(path.to_string())),
+        }
+    }
+
+    /// Lists the entries of a directory, sorted by name.
+    ///
+    /// # Arguments
+    /// - `path` - The path of the directory.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<VfsDirEntry>, VfsError> {
+        let path = normalize(path);
+        match self.directories.get(path) {
+            Some(entries) => Ok(entries
+                .iter()
+                .map(|(name, file_type)| VfsDirEntry {
+                    name: name.clone(),
+                    file_type: *file_type,
+                })
+                .collect()),
+            None if self.paths.contains_key(path) => Err(VfsError::NotADirectory(path.to_string())),
+            None => Err(VfsError::NotFound(path.to_string())),
+        }
+    }
+
+    /// Opens a streaming reader over the data of a file.
+    ///
+    /// # Arguments
+    /// - `path` - The path of the file.
+    pub fn open(&self, path: &str) -> Result<ResourceReader<'a>, VfsError> {
+        let file = self.file(path)?;
+        self.partition(file)?
+            .resource_reader(&file.rrid)
+            .map_err(|e| VfsError::ReadError(normalize(path).to_string(), e))
+    }
+
+    /// Reads the data of a file.
+    ///
+    /// # Arguments
+    /// - `path` - The path of the file.
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, VfsError> {
+        let file = self.file(path)?;
+        self.partition(file)?
+            .read_resource(&file.rrid)
+            .map_err(|e| VfsError::ReadError(normalize(path).to_string(), e))
+    }
+
+    /// Returns the path a resource is visible at, if it is part of the view.
+    pub fn path_of(&self, rrid: &RuntimeResourceID) -> Option<&str> {
+        self.resource_paths.get(rrid).map(String::as_str)
+    }
+
+    /// Iterates over every file in the view, together with its path.
+    pub fn files(&self) -> impl Iterator<Item = (&str, &VfsFile)> + '_ {
+        self.files
+            .iter()
+            .map(|file| (self.resource_paths[&file.rrid].as_str(), file))
+    }
+
+    /// The amount of files in the view.
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    fn file(&self, path: &str) -> Result<&VfsFile, VfsError> {
+        let path = normalize(path);
+        match self.paths.get(path) {
+            Some(index) => Ok(&self.files[*index]),
+            None if self.directories.contains_key(path) => Err(VfsError::IsADirectory(path.to_string())),
+            None => Err(VfsError::NotFound(path.to_string())),
+        }
+    }
+
+    fn partition(&self, file: &VfsFile) -> Result<&'a ResourcePartition, VfsError> {
+        self.partition_manager
+            .find_partition(file.partition_id.clone())
+            .ok_or_else(|| VfsError::PartitionNotFound(file.partition_id.to_string()))
+    }
+}
+
+fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+/// Iterates over the directories containing a path, from the innermost one up to the root.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    let mut remaining = Some(path);
+    std::iter::from_fn(move || {
+        let path = remaining?;
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        remaining = (!parent.is_empty()).then_some(parent);
+        Some(parent)
+    })
+}
+
+/// Turns a resource path like `[assembly:/path/file.ext].pc_type` into `assembly/path/file.ext.pc_type`.
+///
+/// The directories are taken from the innermost resource path, the brackets of derived resources are dropped from
+/// the file name. Returns None for paths which can't be laid out.
+pub fn resource_file_path(uri: &str) -> Option<String> {
+    let (path, rest) = uri.split_at(uri.find(']')?);
+    let (protocol, path) = path.trim_start_matches('[').split_once(":/")?;
+    let (directory, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let name = format!("{name}{}", rest.replace(['[', ']'], "")).replace('/', "_");
+
+    let segments = std::iter::once(protocol)
+        .chain(directory.split('/').filter(|segment| !segment.is_empty()))
+        .chain(std::iter::once(name.as_str()))
+        .collect::<Vec<_>>();
+    let is_valid = |segment: &&str| !segment.is_empty() && *segment != "." && *segment != "..";
+    if !segments.iter().all(is_valid) || protocol == UNKNOWN_DIRECTORY {
+        return None;
+    }
+    Some(segments.join("/"))
+}
+
+/// The path of a resource without a usable resource path, `unknown/<TYPE>/<hash>`.
+pub fn unknown_file_path(data_type: &str, rrid: &RuntimeResourceID) -> String {
+    let data_type = match data_type.trim() {
+        "" => "NONE",
+        data_type => data_type,
+    };
+    format!("{UNKNOWN_DIRECTORY}/{}/{}", data_type.replace('/', "_"), rrid)
+}
@@ -0,0 +1,303 @@
+//! Watching the runtime directory of a mounted game.
+//!
+//! A [RuntimeWatcher] listens, through inotify, for packages and the package definition being written, moved or
+//! deleted. The changes are applied to a [PartitionManager] by remounting the affected partitions, so tools can
+//! pick up rebuilt packages without mounting the game again. Only available on Linux, behind the `watch` feature.
+
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::resource::partition_manager::{PartitionManager, PartitionManagerError};
+use crate::resource::pdefs::PartitionId;
+use crate::resource::resource_partition::{PatchId, RemountChanges};
+
+/// The file name of the package definition, used when the partition manager doesn't know its own.
+const PACKAGE_DEFINITION_FILE_NAME: &str = "packagedefinition.txt";
+
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+
+#[derive(Debug, Error)]
+pub enum WatchError {
+    #[error("Error watching the runtime directory: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Error applying the changes: {0}")]
+    PartitionManagerError(#[from] PartitionManagerError),
+}
+
+/// A change to a file inside the watched directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileEvent {
+    /// A package was written, moved or deleted.
+    Package {
+        partition_id: PartitionId,
+        patch_id: PatchId,
+    },
+    /// The package definition was written, moved or deleted.
+    PackageDefinition,
+    /// The kernel dropped events, any package may have changed.
+    Overflow,
+}
+
+/// A change which was applied to the partition manager.
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// The packages of a partition changed and the partition was remounted.
+    PartitionChanged {
+        partition_id: PartitionId,
+        changes: RemountChanges,
+    },
+    /// A partition could not be remounted, it keeps the packages it had mounted before.
+    RemountFailed {
+        partition_id: PartitionId,
+        error: PartitionManagerError,
+    },
+    /// The package definition changed. It is only read again if the partition manager was created from a game
+    /// directory, the partitions it changed are reported separately.
+    PackageDefinitionChanged { reloaded: bool },
+}
+
+/// Watches the runtime directory of a game for changed packages.
+pub struct RuntimeWatcher {
+    inotify: OwnedFd,
+    package_definition_name: String,
+    buffer: Vec<u8>,
+}
+
+impl RuntimeWatcher {
+    /// Starts watching the runtime directory, and the directory of the package definition, of a partition manager.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - The partition manager whose directories should be watched.
+    pub fn from_partition_manager(partition_manager: &PartitionManager) -> Result<Self, WatchError> {
+        let mut watcher = Self::new(partition_manager.runtime_directory())?;
+        if let Some(path) = partition_manager.package_definition_path() {
+            if let Some(file_name) = path.file_name() {
+                watcher.package_definition_name = file_name.to_string_lossy().into_owned();
+            }
+            let directory = path.parent().filter(|directory| *directory != partition_manager.runtime_directory());
+            if let Some(directory) = directory {
+                watcher.add_directory(directory)?;
+            }
+        }
+        Ok(watcher)
+    }
+
+    /// Starts watching a runtime directory.
+    ///
+    /// # Arguments
+    /// - `runtime_directory` - The directory holding the packages.
+    pub fn new(runtime_directory: &Path) -> Result<Self, WatchError> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mut watcher = Self {
+            inotify: unsafe { OwnedFd::from_raw_fd(fd) },
+            package_definition_name: PACKAGE_DEFINITION_FILE_NAME.to_string(),
+            buffer: vec![0; 0x10000],
+        };
+        watcher.add_directory(runtime_directory)?;
+        Ok(watcher)
+    }
+
+    fn add_directory(&mut self, directory: &Path) -> Result<(), WatchError> {
+        let path = CString::new(directory.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let wd = unsafe { libc::inotify_add_watch(self.inotify.as_raw_fd(), path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// Waits for files to change and returns what changed, without duplicates.
+    ///
+    /// Returns an empty list when nothing changed before the timeout, waits indefinitely without a timeout.
+    ///
+    /// # Arguments
+    /// - `timeout` - How long to wait for the first change.
+    pub fn read_events(&mut self, timeout: Option<Duration>) -> Result<Vec<FileEvent>, WatchError> {
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis().min(i32::MAX as u128) as i32);
+        let mut poll_fd = libc::pollfd {
+            fd: self.inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout) };
+        if ready < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::Interrupted => Ok(vec![]),
+                _ => Err(error.into()),
+            };
+        }
+        if ready == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut events = vec![];
+        loop {
+            let read = unsafe {
+                libc::read(
+                    self.inotify.as_raw_fd(),
+                    self.buffer.as_mut_ptr() as *mut libc::c_void,
+                    self.buffer.len(),
+                )
+            };
+            if read < 0 {
+                let error = io::Error::last_os_error();
+                match error.kind() {
+                    io::ErrorKind::WouldBlock => break,
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(error.into()),
+                }
+            }
+            if read == 0 {
+                break;
+            }
+
+            for event in parse_events(&self.buffer[..read as usize], &self.package_definition_name) {
+                if !events.contains(&event) {
+                    events.push(event);
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Applies file changes to a partition manager, remounting every partition which is affected.
+    ///
+    /// Packages of partitions which aren't part of the package definition are ignored.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - The partition manager to update.
+    /// - `events` - The changes, as returned by [`RuntimeWatcher::read_events`].
+    pub fn apply_events(
+        partition_manager: &mut PartitionManager,
+        events: &[FileEvent],
+    ) -> Result<Vec<WatchEvent>, WatchError> {
+        let mut watch_events = vec![];
+
+        if events.contains(&FileEvent::PackageDefinition) {
+            match partition_manager.reload_package_definition() {
+                Ok(changes) => {
+                    watch_events.push(WatchEvent::PackageDefinitionChanged { reloaded: true });
+                    for (partition_id, changes) in changes {
+                        watch_events.push(WatchEvent::PartitionChanged { partition_id, changes });
+                    }
+                }
+                Err(PartitionManagerError::NoPackageDefinitionFile) => {
+                    watch_events.push(WatchEvent::PackageDefinitionChanged { reloaded: false })
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let defined = partition_manager
+            .partition_infos
+            .iter()
+            .map(|info| info.id.clone())
+            .collect::<Vec<_>>();
+        let mut partition_ids = vec![];
+        for event in events {
+            match event {
+                FileEvent::Overflow => partition_ids.extend(defined.iter().cloned()),
+                // Addon partitions share their file names with standard partitions, so match on the name.
+                FileEvent::Package { partition_id, .. } => partition_ids.extend(
+                    defined
+                        .iter()
+                        .find(|id| id.to_string() == partition_id.to_string())
+                        .cloned(),
+                ),
+                FileEvent::PackageDefinition => {}
+            }
+        }
+
+        let mut remounted: Vec<PartitionId> = vec![];
+        for partition_id in partition_ids {
+            if remounted.contains(&partition_id) {
+                continue;
+            }
+            match partition_manager.remount_partition(&partition_id) {
+                Ok(changes) if changes.is_empty() => {}
+                Ok(changes) => watch_events.push(WatchEvent::PartitionChanged {
+                    partition_id: partition_id.clone(),
+                    changes,
+                }),
+                Err(error) => watch_events.push(WatchEvent::RemountFailed {
+                    partition_id: partition_id.clone(),
+                    error,
+                }),
+            }
+            remounted.push(partition_id);
+        }
+
+        Ok(watch_events)
+    }
+
+    /// Waits for files to change and applies the changes to a partition manager.
+    ///
+    /// # Arguments
+    /// - `partition_manager` - The partition manager to update.
+    /// - `timeout` - How long to wait for the first change.
+    pub fn update(
+        &mut self,
+        partition_manager: &mut PartitionManager,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<WatchEvent>, WatchError> {
+        let events = self.read_events(timeout)?;
+        Self::apply_events(partition_manager, &events)
+    }
+}
+
+/// Parses a buffer of `inotify_event` records.
+fn parse_events(buffer: &[u8], package_definition_name: &str) -> Vec<FileEvent> {
+    const HEADER_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+    let mut events = vec![];
+    let mut offset = 0;
+    while offset + HEADER_SIZE <= buffer.len() {
+        let field = |index: usize| {
+            let start = offset + index * 4;
+            u32::from_ne_bytes([buffer[start], buffer[start + 1], buffer[start + 2], buffer[start + 3]])
+        };
+        let mask = field(1);
+        let name_length = field(3) as usize;
+
+        let name_start = offset + HEADER_SIZE;
+        let name_end = (name_start + name_length).min(buffer.len());
+        let name = &buffer[name_start..name_end];
+        let name = String::from_utf8_lossy(name.split(|c| *c == 0).next().unwrap_or_default());
+        offset = name_start + name_length;
+
+        if mask & libc::IN_Q_OVERFLOW != 0 {
+            events.push(FileEvent::Overflow);
+        } else if name == package_definition_name {
+            events.push(FileEvent::PackageDefinition);
+        } else if let Some((partition_id, patch_id)) = parse_package_file_name(&name) {
+            events.push(FileEvent::Package { partition_id, patch_id });
+        }
+    }
+    events
+}
+
+/// Splits a package file name like `chunk0patch2.rpkg` into its partition and patch id.
+fn parse_package_file_name(file_name: &str) -> Option<(PartitionId, PatchId)> {
+    let name = file_name.strip_suffix(".rpkg")?;
+    let (partition, patch_id) = match name.rsplit_once("patch") {
+        Some((partition, level)) if !level.is_empty() && level.bytes().all(|c| c.is_ascii_digit()) => {
+            (partition, PatchId::Patch(level.parse().ok()?))
+        }
+        _ => (name, PatchId::Base),
+    };
+    Some((partition.parse().ok()?, patch_id))
+}
@@ -62,7 +62,7 @@ fn test_package_with_resource(
     )?;
 
     for reference in &references {
-        resource.with_reference(*reference, resource_reference_flags.clone());
+        resource.with_reference(*reference, resource_reference_flags);
     }
 
     builder.with_resource(resource);
@@ -91,7 +91,7 @@ fn test_package_with_resource(
     assert_eq!(resource_data, fake_data, "Resource data doesn't match");
 
     // Check that the references are correct and in the right order.
-    let resource_info = package.resources().get(&rrid).unwrap();
+    let resource_info = package.resources()?.get(&rrid).unwrap();
 
     for (i, (rrid, flags)) in resource_info.references().iter().enumerate() {
         let reference = references[i];
@@ -205,3 +205,46 @@ fn test_legacy_patch_rpkg_v2() -> Result<(), Box<dyn std::error::Error>> {
 fn test_legacy_compressed_and_scrambled_patch_rpkg_v2() -> Result<(), Box<dyn std::error::Error>> {
     test_package_with_resource(Some(4), true, PackageVersion::RPKGv2, true, true)
 }
+
+fn build_mixed_package(version: PackageVersion, compression_memory_limit: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let mut builder = PackageBuilder::new(0, ChunkType::Standard);
+    builder.with_compression_memory_limit(compression_memory_limit);
+
+    for i in 0..24u64 {
+        let data: Vec<u8> = (0..(i * 997 + 13)).map(|j| (j * i / 7) as u8).collect();
+        let compression_level = if i % 3 == 0 { None } else { Some(4 + (i % 9) as i32) };
+        let should_scramble = i % 2 == 0;
+
+        let resource = match i % 4 {
+            0 | 1 => {
+                let path = temp_dir.path().join(format!("{i}.bin"));
+                std::fs::write(&path, &data)?;
+                PackageResourceBuilder::from_file(RuntimeResourceID::from(i), "TEMP", &path, compression_level, should_scramble)?
+            }
+            _ => PackageResourceBuilder::from_memory(RuntimeResourceID::from(i), "TEMP", data, compression_level, should_scramble)?,
+        };
+        builder.with_resource(resource);
+    }
+
+    Ok(builder.build_to_vec(version)?)
+}
+
+#[test]
+fn test_compression_memory_limit_does_not_change_output() -> Result<(), Box<dyn std::error::Error>> {
+    for version in [PackageVersion::RPKGv1, PackageVersion::RPKGv2] {
+        // A limit of 0 compresses the resources one by one, like a serial build.
+        let serial = build_mixed_package(version, 0)?;
+        for limit in [30_000, usize::MAX] {
+            assert_eq!(build_mixed_package(version, limit)?, serial);
+        }
+
+        let package = ResourcePackage::from_memory(serial, false)?;
+        assert_eq!(package.resources()?.len(), 24);
+        for i in 0..24u64 {
+            let data: Vec<u8> = (0..(i * 997 + 13)).map(|j| (j * i / 7) as u8).collect();
+            assert_eq!(package.read_resource(&RuntimeResourceID::from(i))?, data);
+        }
+    }
+    Ok(())
+}
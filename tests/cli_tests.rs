@@ -0,0 +1,214 @@
+#![cfg(feature = "cli")]
+
+mod common;
+
+use common::{path_rrid, rrid, write_package, write_resources};
+use rpkg_rs::encryption::xtea::Xtea;
+use rpkg_rs::resource::package_builder::PackageResourceBuilder;
+use rpkg_rs::resource::resource_package::{
+    ReferenceType, ResourcePackage, ResourceReferenceFlags, ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::PatchId;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+const ENTITY: &str = "[assembly:/_pro/scenes/test.entity].pc_entitytype";
+const TEXTURE: &str = "[assembly:/_pro/textures/wall.texture].pc_tex";
+
+fn install() -> ResourceReferenceFlags {
+    ResourceReferenceFlags::Standard(ResourceReferenceFlagsStandard::new().with_reference_type(ReferenceType::INSTALL))
+}
+
+/// Writes a package with an entity referencing a texture and an unresolved resource, together with a path list.
+fn write_package_with_path_list(directory: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entity = PackageResourceBuilder::from_memory(path_rrid(ENTITY)?, "TEMP", vec![1; 64], Some(4), true)?;
+    entity.with_reference(path_rrid(TEXTURE)?, install());
+    let resources = vec![
+        entity,
+        PackageResourceBuilder::from_memory(path_rrid(TEXTURE)?, "TEXT", vec![2; 300], None, false)?,
+        PackageResourceBuilder::from_memory(rrid(5), "TBLU", vec![3; 16], None, true)?,
+    ];
+    write_resources(directory, "chunk0", PatchId::Base, resources, &[])?;
+
+    let path_list = format!("{}.TEMP,{ENTITY}\n{}.TEXT,{TEXTURE}\n", path_rrid(ENTITY)?, path_rrid(TEXTURE)?);
+    fs::write(directory.join("hash_list.txt"), path_list)?;
+    Ok(())
+}
+
+/// Writes a game with a patched base partition and a second partition to the retail directory.
+fn write_game(retail_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime_path = retail_path.join("Game").join("Runtime");
+    fs::create_dir_all(&runtime_path)?;
+    let thumbs = "[application]\nPROJECT_PATH=Game\\\nRUNTIME_PATH=Runtime\n";
+    let package_definition = "@partition name=base parent=none type=standard patchlevel=10\n\
+        @partition name=boot parent=base type=standard patchlevel=10\n";
+    fs::write(retail_path.join("thumbs.dat"), Xtea::encrypt_bond_text_file(thumbs.to_string())?)?;
+    fs::write(
+        runtime_path.join("packagedefinition.txt"),
+        Xtea::encrypt_bond_text_file(package_definition.to_string())?,
+    )?;
+
+    write_package(&runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 64])], &[])?;
+    write_package(&runtime_path, "chunk0", PatchId::Patch(1), &[(1, vec![2; 32])], &[])?;
+    write_package(&runtime_path, "chunk1", PatchId::Base, &[(2, vec![3; 16])], &[])?;
+    Ok(())
+}
+
+fn rpkg(directory: &Path, args: &[&str]) -> Result<Output, Box<dyn std::error::Error>> {
+    Ok(Command::new(env!("CARGO_BIN_EXE_rpkg")).current_dir(directory).args(args).output()?)
+}
+
+fn rpkg_json(directory: &Path, args: &[&str]) -> Result<Value, Box<dyn std::error::Error>> {
+    let output = rpkg(directory, &[args, &["--json"]].concat())?;
+    assert!(output.status.success(), "rpkg failed: {}", String::from_utf8_lossy(&output.stderr));
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+#[test]
+fn test_cli_info_and_ls() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    write_package_with_path_list(temp_dir.path())?;
+
+    let info = rpkg_json(temp_dir.path(), &["info", "chunk0.rpkg"])?;
+    assert_eq!(info["resource_count"], 3);
+    assert_eq!(info["compressed_count"], 1);
+    assert_eq!(info["scrambled_count"], 2);
+    assert_eq!(info["size"], 64 + 300 + 16);
+    assert_eq!(info["types"]["TEXT"]["size"], 300);
+
+    let listing = rpkg_json(temp_dir.path(), &["ls", "chunk0.rpkg", "--type", "text", "--path-list", "hash_list.txt"])?;
+    let listing = listing.as_array().ok_or("the listing should be an array")?;
+    assert_eq!(listing.len(), 1);
+    assert_eq!(listing[0]["path"], "assembly/_pro/textures/wall.texture.tex");
+    assert_eq!(listing[0]["resource_path"], TEXTURE.replace(".pc_tex", ".tex"));
+
+    let output = rpkg(temp_dir.path(), &["ls", "chunk0.rpkg", "--partition", "chunk0"])?;
+    assert_eq!(output.status.code(), Some(1));
+    Ok(())
+}
+
+#[test]
+fn test_cli_extract() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    write_package_with_path_list(temp_dir.path())?;
+
+    let extracted = rpkg_json(
+        temp_dir.path(),
+        &["extract", "chunk0.rpkg", "out", "--glob", "assembly/**/*.tex", "--path-list", "hash_list.txt"],
+    )?;
+    assert_eq!(extracted.as_array().map(Vec::len), Some(1));
+    assert_eq!(fs::read(temp_dir.path().join("out/assembly/_pro/textures/wall.texture.tex"))?, vec![2; 300]);
+
+    rpkg_json(temp_dir.path(), &["extract", "chunk0.rpkg", "hashes", "--hash", "0x0000000000000005", "--hash", ENTITY])?;
+    let unknown = temp_dir.path().join("hashes/unknown");
+    assert_eq!(fs::read(unknown.join("TBLU").join(rrid(5).to_string()))?, vec![3; 16]);
+    assert_eq!(fs::read(unknown.join("TEMP").join(path_rrid(ENTITY)?.to_string()))?, vec![1; 64]);
+    assert!(!unknown.join("TEXT").exists());
+    Ok(())
+}
+
+#[test]
+fn test_cli_build_diff_and_verify() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    write_package_with_path_list(temp_dir.path())?;
+    fs::create_dir(temp_dir.path().join("resources"))?;
+    fs::write(temp_dir.path().join("resources/entity.temp"), vec![7; 100])?;
+    fs::write(temp_dir.path().join("resources/wall.text"), vec![2; 300])?;
+    let manifest = serde_json::json!({
+        "partition_id": "chunk0",
+        "patch_id": 1,
+        "resources": [
+            { "path": ENTITY, "type": "TEMP", "file": "entity.temp", "compression_level": 4, "scramble": true },
+            { "rrid": format!("0x{}", path_rrid(TEXTURE)?), "type": "TEXT", "file": "wall.text" },
+        ],
+    });
+    fs::write(temp_dir.path().join("manifest.json"), manifest.to_string())?;
+
+    let built = rpkg_json(temp_dir.path(), &["build", "resources", "--manifest", "manifest.json", "-o", "."])?;
+    assert_eq!(built["resource_count"], 2);
+    let package = ResourcePackage::from_file(&temp_dir.path().join("chunk0patch1.rpkg"))?;
+    assert!(package.is_patch());
+    assert_eq!(package.read_resource(&path_rrid(ENTITY)?)?, vec![7; 100]);
+
+    let verified = rpkg_json(temp_dir.path(), &["verify", "chunk0patch1.rpkg"])?;
+    assert_eq!(verified[0]["issues"].as_array().map(Vec::len), Some(0));
+
+    let diff = rpkg_json(temp_dir.path(), &["diff", "chunk0.rpkg", "chunk0patch1.rpkg", "--content"])?;
+    let changes = diff["changes"].as_array().ok_or("the diff should list changes")?;
+    assert_eq!(changes.len(), 2);
+    assert!(changes.iter().any(|change| change["Removed"]["data_type"] == "TBLU"));
+    assert!(changes.iter().any(|change| change["Modified"]["data_type"] == "TEMP"));
+
+    let mut data = fs::read(temp_dir.path().join("chunk0patch1.rpkg"))?;
+    data.truncate(data.len() - 10);
+    fs::write(temp_dir.path().join("chunk0patch2.rpkg"), data)?;
+    let output = rpkg(temp_dir.path(), &["verify", "chunk0patch2.rpkg"])?;
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = rpkg(temp_dir.path(), &["info", "missing.rpkg"])?;
+    assert_eq!(output.status.code(), Some(1));
+    Ok(())
+}
+
+#[test]
+fn test_cli_deps() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    write_package_with_path_list(temp_dir.path())?;
+
+    let dependencies = rpkg_json(temp_dir.path(), &["deps", "chunk0.rpkg", ENTITY, "--path-list", "hash_list.txt"])?;
+    assert_eq!(dependencies.as_array().map(Vec::len), Some(1));
+    assert_eq!(dependencies[0]["data_type"], "TEXT");
+
+    let dependents = rpkg_json(temp_dir.path(), &["deps", "chunk0.rpkg", TEXTURE, "--reverse"])?;
+    assert_eq!(dependents[0]["rrid"], serde_json::to_value(path_rrid(ENTITY)?)?);
+
+    let output = rpkg(temp_dir.path(), &["deps", "chunk0.rpkg", ENTITY, "--dot"])?;
+    assert!(String::from_utf8(output.stdout)?.starts_with("digraph dependencies"));
+    Ok(())
+}
+
+#[test]
+fn test_cli_game() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    write_game(&temp_dir.path().join("Retail"))?;
+
+    let partitions = rpkg_json(temp_dir.path(), &["info", "Retail", "--game-version", "Bond"])?;
+    assert_eq!(partitions[0]["id"], "chunk0");
+    assert_eq!(partitions[0]["packages"], serde_json::json!(["chunk0.rpkg", "chunk0patch1.rpkg"]));
+    assert_eq!(partitions[1]["parent"], "chunk0");
+
+    let listing = rpkg_json(temp_dir.path(), &["ls", "Retail", "--partition", "chunk0"])?;
+    assert_eq!(listing.as_array().map(Vec::len), Some(1));
+    assert_eq!(listing[0]["package"], "chunk0patch1.rpkg");
+    assert_eq!(listing[0]["size"], 32);
+
+    rpkg_json(temp_dir.path(), &["extract", "Retail", "out", "--type", "TEMP"])?;
+    let temp = temp_dir.path().join("out/unknown/TEMP");
+    assert_eq!(fs::read(temp.join(rrid(1).to_string()))?, vec![2; 32]);
+    assert_eq!(fs::read(temp.join(rrid(2).to_string()))?, vec![3; 16]);
+
+    let verified = rpkg_json(temp_dir.path(), &["verify", "Retail"])?;
+    assert_eq!(verified.as_array().map(Vec::len), Some(3));
+
+    let output = rpkg(temp_dir.path(), &["info", "Retail", "--game-version", "HM4"])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown game version HM4"));
+    Ok(())
+}
+
+#[test]
+fn test_cli_unpack_and_build() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    write_package_with_path_list(temp_dir.path())?;
+
+    let unpacked = rpkg_json(temp_dir.path(), &["unpack", "chunk0.rpkg", "resources", "--path-list", "hash_list.txt"])?;
+    assert_eq!(unpacked["resource_count"], 3);
+    assert!(temp_dir.path().join("resources/assembly/_pro/textures/wall.texture.tex").exists());
+
+    rpkg_json(temp_dir.path(), &["build", "resources", "-o", "rebuilt.rpkg"])?;
+    let diff = rpkg_json(temp_dir.path(), &["diff", "chunk0.rpkg", "rebuilt.rpkg", "--content"])?;
+    assert_eq!(diff["changes"].as_array().map(Vec::len), Some(0));
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Fixtures shared by the integration tests. Every test crate only uses some of them.
+#![allow(dead_code)]
+
+use rpkg_rs::misc::resource_id::ResourceID;
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder, PackageResourceBuilderError};
+use rpkg_rs::resource::pdefs::PartitionId;
+use rpkg_rs::resource::resource_package::PackageVersion;
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::{PlatformTag, RuntimeResourceID};
+use std::path::Path;
+use std::str::FromStr;
+
+pub fn rrid(id: u64) -> RuntimeResourceID {
+    RuntimeResourceID::from(id)
+}
+
+/// The id of the pc resource at the given path.
+pub fn path_rrid(path: &str) -> Result<RuntimeResourceID, Box<dyn std::error::Error>> {
+    let rid = ResourceID::from_str(path)?;
+    Ok(RuntimeResourceID::from_resource_id_with_platform(&rid, "pc", PlatformTag::None))
+}
+
+/// Resource data of the given size which differs per seed.
+pub fn test_data(seed: u8, size: u32) -> Vec<u8> {
+    (0..size).map(|j| (j % 251) as u8 ^ seed).collect()
+}
+
+/// A compressed and scrambled resource, so reading it goes through every decoding step.
+pub fn resource(id: u64, data_type: &str, data: Vec<u8>) -> Result<PackageResourceBuilder, PackageResourceBuilderError> {
+    PackageResourceBuilder::from_memory(rrid(id), data_type, data, Some(4), true)
+}
+
+/// The resources 1 to 4, stored plain, compressed, scrambled and compressed + scrambled.
+pub fn storage_variants(size: u32) -> Result<Vec<PackageResourceBuilder>, PackageResourceBuilderError> {
+    let variants = [(None, false), (Some(4), false), (None, true), (Some(4), true)];
+    variants
+        .into_iter()
+        .enumerate()
+        .map(|(i, (compression_level, should_scramble))| {
+            PackageResourceBuilder::from_memory(
+                rrid(i as u64 + 1),
+                "TEMP",
+                test_data(i as u8, size),
+                compression_level,
+                should_scramble,
+            )
+        })
+        .collect()
+}
+
+/// Builds a `chunk0` package of the given resources.
+pub fn build_resources(
+    version: PackageVersion,
+    patch_id: PatchId,
+    resources: Vec<PackageResourceBuilder>,
+    unneeded_resources: &[u64],
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut builder = PackageBuilder::new_with_patch_id(PartitionId::from_str("chunk0")?, patch_id);
+    for resource in resources {
+        builder.with_resource(resource);
+    }
+    builder.with_unneeded_resources(unneeded_resources.iter().map(|id| rrid(*id)));
+    Ok(builder.build_to_vec(version)?)
+}
+
+/// Builds a base package of the storage variants of 4096 bytes each.
+pub fn build_test_package(version: PackageVersion) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    build_resources(version, PatchId::Base, storage_variants(4096)?, &[])
+}
+
+/// Writes a v2 package of `TEMP` resources to the runtime directory.
+pub fn write_package(
+    runtime_path: &Path,
+    partition: &str,
+    patch_id: PatchId,
+    resources: &[(u64, Vec<u8>)],
+    unneeded_resources: &[u64],
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_package_as(PackageVersion::RPKGv2, runtime_path, partition, patch_id, resources, unneeded_resources)
+}
+
+/// Writes a package of `TEMP` resources in the given version to the runtime directory.
+pub fn write_package_as(
+    version: PackageVersion,
+    runtime_path: &Path,
+    partition: &str,
+    patch_id: PatchId,
+    resources: &[(u64, Vec<u8>)],
+    unneeded_resources: &[u64],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let resources = resources
+        .iter()
+        .map(|(id, data)| resource(*id, "TEMP", data.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    write_resources_as(version, runtime_path, partition, patch_id, resources, unneeded_resources)
+}
+
+/// Writes a v2 package of the given resources to the runtime directory.
+pub fn write_resources(
+    runtime_path: &Path,
+    partition: &str,
+    patch_id: PatchId,
+    resources: Vec<PackageResourceBuilder>,
+    unneeded_resources: &[u64],
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_resources_as(PackageVersion::RPKGv2, runtime_path, partition, patch_id, resources, unneeded_resources)
+}
+
+/// Writes a package of the given resources in the given version to the runtime directory.
+pub fn write_resources_as(
+    version: PackageVersion,
+    runtime_path: &Path,
+    partition: &str,
+    patch_id: PatchId,
+    resources: Vec<PackageResourceBuilder>,
+    unneeded_resources: &[u64],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut builder = PackageBuilder::new_with_patch_id(PartitionId::from_str(partition)?, patch_id);
+    for resource in resources {
+        builder.with_resource(resource);
+    }
+    builder.with_unneeded_resources(unneeded_resources.iter().map(|id| rrid(*id)));
+    builder.build_to_file(version, runtime_path)?;
+    Ok(())
+}
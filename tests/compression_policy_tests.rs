@@ -0,0 +1,186 @@
+use rpkg_rs::resource::compression_policy::{
+    Compression, CompressionBackend, CompressionCandidate, MimicPackage, MinimumSavings,
+};
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::resource_package::{ChunkType, PackageVersion, ResourcePackage};
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+
+fn compressible_data() -> Vec<u8> {
+    (0..4096u32).map(|i| (i / 64) as u8).collect()
+}
+
+fn incompressible_data() -> Vec<u8> {
+    let mut state = 0x2545f491u32;
+    (0..4096)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn build(
+    resources: Vec<(u64, &str, Vec<u8>, Option<i32>)>,
+    configure: impl FnOnce(&mut PackageBuilder),
+) -> Result<ResourcePackage, Box<dyn std::error::Error>> {
+    let mut builder = PackageBuilder::new(0, ChunkType::Standard);
+    for (rrid, resource_type, data, compression_level) in resources {
+        builder.with_resource(PackageResourceBuilder::from_memory(
+            RuntimeResourceID::from(rrid),
+            resource_type,
+            data,
+            compression_level,
+            false,
+        )?);
+    }
+    configure(&mut builder);
+    Ok(ResourcePackage::from_memory(builder.build_to_vec(PackageVersion::RPKGv2)?, false)?)
+}
+
+#[test]
+fn test_callback_policy() -> Result<(), Box<dyn std::error::Error>> {
+    let package = build(
+        vec![
+            (1, "TEMP", compressible_data(), None),
+            (2, "TEXT", compressible_data(), Some(12)),
+        ],
+        |builder| {
+            builder.with_compression_policy(|resource: &CompressionCandidate| {
+                (resource.resource_type == "TEMP").then(|| Compression::new(CompressionBackend::Lz4, 1))
+            });
+        },
+    )?;
+
+    let temp = package.resource_info(&RuntimeResourceID::from(1))?;
+    assert!(temp.is_compressed());
+    assert!(!package.resource_info(&RuntimeResourceID::from(2))?.is_compressed());
+    for rrid in [1, 2] {
+        assert_eq!(package.read_resource(&RuntimeResourceID::from(rrid))?, compressible_data());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_minimum_savings_policy() -> Result<(), Box<dyn std::error::Error>> {
+    let package = build(
+        vec![
+            (1, "TEMP", compressible_data(), Some(12)),
+            (2, "TEMP", incompressible_data(), Some(12)),
+        ],
+        |builder| {
+            builder.with_compression_policy(MinimumSavings::new(10));
+        },
+    )?;
+
+    assert!(package.resource_info(&RuntimeResourceID::from(1))?.is_compressed());
+    let incompressible = package.resource_info(&RuntimeResourceID::from(2))?;
+    assert!(!incompressible.is_compressed());
+    assert_eq!(package.read_resource(&RuntimeResourceID::from(2))?, incompressible_data());
+    Ok(())
+}
+
+#[test]
+fn test_mimic_package_policy() -> Result<(), Box<dyn std::error::Error>> {
+    let original = build(
+        vec![
+            (1, "TEMP", compressible_data(), Some(12)),
+            (2, "TEMP", compressible_data(), None),
+        ],
+        |_| {},
+    )?;
+
+    let policy = MimicPackage::new(&original)?;
+    let rebuilt = build(
+        vec![
+            (1, "TEMP", compressible_data(), None),
+            (2, "TEMP", compressible_data(), Some(12)),
+            (3, "TEMP", compressible_data(), Some(12)),
+        ],
+        |builder| {
+            builder.with_compression_policy(policy);
+        },
+    )?;
+
+    for (rrid, is_compressed) in [(1, true), (2, false), (3, true)] {
+        let info = rebuilt.resource_info(&RuntimeResourceID::from(rrid))?;
+        assert_eq!(info.is_compressed(), is_compressed);
+        assert_eq!(rebuilt.read_resource(&RuntimeResourceID::from(rrid))?, compressible_data());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mimic_package_policy_on_duplicated_packages() -> Result<(), Box<dyn std::error::Error>> {
+    let original = build(
+        vec![
+            (1, "TEMP", compressible_data(), Some(12)),
+            (2, "TEMP", compressible_data(), None),
+        ],
+        |_| {},
+    )?;
+
+    let mut builder = PackageBuilder::new(0, ChunkType::Standard);
+    for (rrid, compression_level) in [(1, None), (2, Some(12))] {
+        builder.with_resource(PackageResourceBuilder::from_memory(
+            RuntimeResourceID::from(rrid),
+            "TEMP",
+            compressible_data(),
+            compression_level,
+            true,
+        )?);
+    }
+    let temp_dir = tempfile::tempdir()?;
+    let package_path = temp_dir.path().join("chunk0.rpkg");
+    builder.build_to_file(PackageVersion::RPKGv2, &package_path)?;
+
+    let sources = [
+        ResourcePackage::from_file(&package_path)?,
+        ResourcePackage::from_memory(std::fs::read(&package_path)?, false)?,
+    ];
+    for source in &sources {
+        let mut builder = PackageBuilder::from_resource_package(source)?;
+        builder.with_compression_policy(MimicPackage::new(&original)?);
+        let rebuilt = ResourcePackage::from_memory(builder.build_to_vec(PackageVersion::RPKGv2)?, false)?;
+
+        for (rrid, is_compressed) in [(1, true), (2, false)] {
+            let info = rebuilt.resource_info(&RuntimeResourceID::from(rrid))?;
+            assert_eq!(info.is_compressed(), is_compressed);
+            assert!(info.is_scrambled());
+            assert_eq!(rebuilt.read_resource(&RuntimeResourceID::from(rrid))?, compressible_data());
+        }
+    }
+
+    // Data which is packaged the way the policy wants it is copied as is.
+    let mut builder = PackageBuilder::from_resource_package(&sources[0])?;
+    builder.with_compression_policy(MimicPackage::new(&sources[0])?);
+    assert_eq!(builder.build_to_vec(PackageVersion::RPKGv2)?, std::fs::read(&package_path)?);
+    Ok(())
+}
+
+/// Data whose compressed size depends on the backend and level.
+fn text_data() -> Vec<u8> {
+    (0..400u32).flat_map(|i| format!("resource {i} references {}\n", i * i % 997).into_bytes()).collect()
+}
+
+#[test]
+fn test_policy_recompresses_duplicated_packages() -> Result<(), Box<dyn std::error::Error>> {
+    let source = build(vec![(1, "TEMP", text_data(), Some(12))], |_| {})?;
+    let packaged_size = source.resource_info(&RuntimeResourceID::from(1))?.compressed_size();
+
+    let compressions = [Compression::new(CompressionBackend::Lz4, 65537), Compression::new(CompressionBackend::Lz4Hc, 1)];
+    for compression in compressions {
+        let expected = compression.backend.compress(&text_data(), compression.level)?;
+        assert_ne!(Some(expected.len() as u32), packaged_size);
+
+        let mut builder = PackageBuilder::from_resource_package(&source)?;
+        builder.with_compression_policy(move |_: &CompressionCandidate| Some(compression));
+        let rebuilt = ResourcePackage::from_memory(builder.build_to_vec(PackageVersion::RPKGv2)?, false)?;
+
+        let info = rebuilt.resource_info(&RuntimeResourceID::from(1))?;
+        assert_eq!(info.compressed_size(), Some(expected.len() as u32));
+        assert_eq!(rebuilt.read_resource(&RuntimeResourceID::from(1))?, text_data());
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::{resource, rrid, write_resources};
+use rpkg_rs::resource::content_index::{ContentIndex, ContentIndexOptions, ResourceLocation};
+use rpkg_rs::resource::package_builder::PackageResourceBuilder;
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::{PackageDefinitionSource, PartitionId, PartitionInfo};
+use rpkg_rs::resource::resource_diff::ContentHash;
+use rpkg_rs::resource::resource_partition::PatchId;
+use std::path::Path;
+use std::str::FromStr;
+
+fn location(partition: &str, patch_id: PatchId, id: u64) -> ResourceLocation {
+    ResourceLocation {
+        partition_id: PartitionId::from_str(partition).unwrap(),
+        patch_id,
+        rrid: rrid(id),
+    }
+}
+
+fn mount_game(runtime_path: &Path) -> Result<PartitionManager, Box<dyn std::error::Error>> {
+    let a = vec![0xAA; 256];
+    let b = vec![0xBB; 128];
+
+    // Resource 11 is the only compressed and scrambled copy of `a`.
+    let plain = |id: u64, data: &[u8]| PackageResourceBuilder::from_memory(rrid(id), "TEMP", data.to_vec(), None, false);
+    write_resources(runtime_path, "chunk0", PatchId::Base, vec![plain(1, &a)?, plain(2, &b)?], &[])?;
+    write_resources(runtime_path, "chunk0", PatchId::Patch(1), vec![plain(2, &b)?, plain(3, &[3; 64])?], &[])?;
+    write_resources(runtime_path, "chunk1", PatchId::Base, vec![plain(10, &a)?, resource(11, "TEMP", a)?], &[])?;
+
+    let mut partitions = vec![PartitionInfo::from_id("chunk0")?, PartitionInfo::from_id("chunk1")?];
+    for partition in partitions.iter_mut() {
+        partition.set_max_patch_level(9);
+    }
+
+    let mut partition_manager =
+        PartitionManager::new(runtime_path.to_path_buf(), &PackageDefinitionSource::Custom(partitions))?;
+    partition_manager.mount_partitions(|_, _| {})?;
+    Ok(partition_manager)
+}
+
+#[test]
+fn test_content_lookup() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let partition_manager = mount_game(temp_dir.path())?;
+    let index = ContentIndex::from_partition_manager(&partition_manager, ContentIndexOptions::default())?;
+
+    assert_eq!(index.occurrences().len(), 6);
+    assert_eq!(index.occurrences_of(&rrid(2)).len(), 2);
+    assert_eq!(
+        index.locations(&ContentHash::from_data(&[0xAA; 256])),
+        vec![
+            &location("chunk0", PatchId::Base, 1),
+            &location("chunk1", PatchId::Base, 10),
+            &location("chunk1", PatchId::Base, 11),
+        ]
+    );
+    assert!(index.raw_locations(&ContentHash::from_data(&[0xAA; 256])).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_raw_data_hashes() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let partition_manager = mount_game(temp_dir.path())?;
+    let options = ContentIndexOptions::default().with_raw_data(true);
+    let index = ContentIndex::from_partition_manager(&partition_manager, options)?;
+
+    // The compressed and scrambled copy is stored differently.
+    assert_eq!(
+        index.raw_locations(&ContentHash::from_data(&[0xAA; 256])),
+        vec![&location("chunk0", PatchId::Base, 1), &location("chunk1", PatchId::Base, 10)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_duplicates_and_reshipped() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let partition_manager = mount_game(temp_dir.path())?;
+    let index = ContentIndex::from_partition_manager(&partition_manager, ContentIndexOptions::default())?;
+
+    let duplicates = index.duplicates();
+    assert_eq!(duplicates.len(), 2);
+
+    let compressed_size = index.occurrences_of(&rrid(11))[0].packaged_size as u64;
+    assert!(compressed_size < 256);
+    assert_eq!(duplicates[0].content_hash, ContentHash::from_data(&[0xAA; 256]));
+    assert_eq!(duplicates[0].wasted_bytes, 256 * 2);
+    assert_eq!(duplicates[1].locations.len(), 2);
+    assert_eq!(duplicates[1].wasted_bytes, 128);
+    assert_eq!(index.wasted_bytes(), 256 * 2 + 128);
+
+    let reshipped = index
+        .reshipped()
+        .iter()
+        .map(|occurrence| occurrence.location.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(reshipped, vec![location("chunk0", PatchId::Patch(1), 2)]);
+
+    Ok(())
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use common::{build_resources, rrid, storage_variants};
+use rpkg_rs::resource::package_builder::PackageBuilder;
+use rpkg_rs::resource::package_verification::PackageIssue;
+use rpkg_rs::resource::pdefs::{PackageDefinitionError, PackageDefinitionSource};
+use rpkg_rs::resource::resource_package::{
+    PackageVersion, ReferenceType, ResourcePackage, ResourcePackageError, ResourceReferenceFlags,
+    ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use std::io::Read;
+
+fn build_package(version: PackageVersion, patch_id: PatchId) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut resources = storage_variants(512)?;
+    for (i, resource) in resources.iter_mut().enumerate() {
+        resource.with_reference(
+            rrid(i as u64 + 2),
+            ResourceReferenceFlags::Standard(ResourceReferenceFlagsStandard::new().with_reference_type(ReferenceType::INSTALL)),
+        );
+    }
+    let unneeded_resources: &[u64] = match patch_id.is_patch() {
+        true => &[10],
+        false => &[],
+    };
+
+    build_resources(version, patch_id, resources, unneeded_resources)
+}
+
+/// Package data together with whether it is a patch package.
+type Corpus = Vec<(Vec<u8>, bool)>;
+
+fn corpus() -> Result<Corpus, Box<dyn std::error::Error>> {
+    Ok(vec![
+        (build_package(PackageVersion::RPKGv1, PatchId::Base)?, false),
+        (build_package(PackageVersion::RPKGv2, PatchId::Base)?, false),
+        (build_package(PackageVersion::RPKGv2, PatchId::Patch(1))?, true),
+    ])
+}
+
+/// Parses the package and touches everything which could be derived from corrupted data.
+fn exercise(data: Vec<u8>, is_patch: bool) {
+    let Ok(package) = ResourcePackage::from_memory(data, is_patch) else {
+        return;
+    };
+
+    let _ = package.version();
+    let _ = package.verify();
+    if let Ok(builder) = PackageBuilder::from_resource_package(&package) {
+        let _ = builder.build_to_vec(package.version());
+    }
+    for (rrid, resource) in package.resources().into_iter().flatten() {
+        let _ = format!("{}", resource);
+        let _ = resource.data_type();
+        let _ = package.read_resource(rrid);
+        if let Ok(mut reader) = package.resource_reader(rrid) {
+            let _ = reader.read_to_end(&mut vec![]);
+        }
+    }
+}
+
+#[test]
+fn test_truncated_packages() -> Result<(), Box<dyn std::error::Error>> {
+    for (data, is_patch) in corpus()? {
+        for length in 0..data.len() {
+            exercise(data[..length].to_vec(), is_patch);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_corrupted_packages() -> Result<(), Box<dyn std::error::Error>> {
+    for (data, is_patch) in corpus()? {
+        for position in 0..data.len() {
+            for pattern in [0xFF, 0x80, 0x01] {
+                let mut corrupted = data.clone();
+                corrupted[position] ^= pattern;
+                exercise(corrupted, is_patch);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_errors_have_context() -> Result<(), Box<dyn std::error::Error>> {
+    let data = build_package(PackageVersion::RPKGv2, PatchId::Base)?;
+
+    let mut unknown_magic = data.clone();
+    unknown_magic[..4].copy_from_slice(b"ABCD");
+    assert!(matches!(
+        ResourcePackage::from_memory(unknown_magic, false),
+        Err(ResourcePackageError::UnknownMagic(magic)) if &magic == b"ABCD"
+    ));
+
+    // The offset table starts at 25, the data offset of the first entry at 33 is cut off.
+    let truncated = data[..40].to_vec();
+    let error = ResourcePackage::from_memory(truncated, false).err().ok_or("truncated package should not parse")?;
+    assert!(matches!(error, ResourcePackageError::UnexpectedEnd { offset: 33, .. }), "{error}");
+
+    Ok(())
+}
+
+/// The position of the metadata table of a package built by [build_package].
+fn metadata_table_offset(data: &[u8], is_patch: bool) -> usize {
+    let header = match &data[..4] {
+        b"2KPR" => 4 + 9,
+        _ => 4,
+    };
+    let read_u32 = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+    let unneeded_list_size = match is_patch {
+        true => 4 + 8 * read_u32(header + 12),
+        false => 0,
+    };
+    header + 12 + unneeded_list_size + 0x14 * read_u32(header)
+}
+
+#[test]
+fn test_large_references_chunk_sizes() -> Result<(), Box<dyn std::error::Error>> {
+    for (data, is_patch) in corpus()? {
+        let metadata_table = metadata_table_offset(&data, is_patch);
+        for size in [0xFFFF_FFF8u32, 0x7FFF_FFFF, 0x1000] {
+            let mut corrupted = data.clone();
+            corrupted[metadata_table + 4..metadata_table + 8].copy_from_slice(&size.to_le_bytes());
+            assert!(ResourcePackage::from_memory(corrupted.clone(), is_patch).is_err());
+
+            // The entries after the corrupted one can't be found, the corrupted one itself is intact.
+            let package = ResourcePackage::from_memory_lazy(corrupted, is_patch)?;
+            assert!(package.load_metadata().is_err());
+            assert!(package.resource_info(&RuntimeResourceID::from(4)).is_err());
+        }
+    }
+
+    // The input which made the states chunk size probe overflow while fuzzing. Its only entry has no references,
+    // so it parses and verifying it reports the wrong size.
+    let data = include_bytes!("../fuzz/corpus/resource_package/metadata_references_size_overflow");
+    let issues = ResourcePackage::from_memory(data.to_vec(), false)?.verify()?;
+    assert!(issues.iter().any(|issue| matches!(
+        issue,
+        PackageIssue::ReferencesChunkSizeMismatch { declared: 0xFFFF_FFF8, .. }
+    )));
+    exercise(data.to_vec(), true);
+    Ok(())
+}
+
+#[test]
+fn test_out_of_range_table_sizes() -> Result<(), Box<dyn std::error::Error>> {
+    for (data, is_patch) in corpus()? {
+        let header = metadata_table_offset(&data, is_patch) - 0x14 * 4 - if is_patch { 4 + 8 } else { 0 } - 12;
+
+        // The offset table is read entry by entry, so a huge file count runs out of data instead of allocating.
+        for file_count in [u32::MAX, 1000] {
+            let mut corrupted = data.clone();
+            corrupted[header..header + 4].copy_from_slice(&file_count.to_le_bytes());
+            let error = ResourcePackage::from_memory(corrupted, is_patch).err().ok_or("the file count is too large")?;
+            assert!(matches!(error, ResourcePackageError::UnexpectedEnd { .. }), "{error}");
+        }
+
+        // The declared offset table size isn't needed for parsing, verifying the package reports it.
+        let mut corrupted = data.clone();
+        corrupted[header + 4..header + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let issues = ResourcePackage::from_memory(corrupted, is_patch)?.verify()?;
+        assert!(issues.iter().any(|issue| matches!(
+            issue,
+            PackageIssue::OffsetTableSizeMismatch { declared: u32::MAX, .. }
+        )));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_invalid_patch_levels() -> Result<(), Box<dyn std::error::Error>> {
+    let hm3 = "@partition name=base parent=none type=standard patchlevel=1\n\
+               @partition name=boot parent=base type=standard patchlevel=x\n";
+    let bond = "@partition name=base parent=none type=standard patchlevel=99999999999999999999999\n";
+    let hm2 = "@chunk patchlevel=99999999999999999999999\n";
+    let h2016 = "#chunk patchlevel=1\n#dlc patchlevel=99999999999999999999999\n";
+
+    for (source, line) in [
+        (PackageDefinitionSource::HM3(hm3.into()), 2),
+        (PackageDefinitionSource::Bond(bond.into()), 1),
+        (PackageDefinitionSource::HM2(hm2.into()), 1),
+        (PackageDefinitionSource::HM2016(h2016.into()), 2),
+    ] {
+        let error = source.read().err().ok_or("invalid patch level should not parse")?;
+        assert!(
+            matches!(error, PackageDefinitionError::InvalidPatchLevel { line: l, .. } if l == line),
+            "{error}"
+        );
+    }
+
+    let languages = PackageDefinitionSource::HM2016("#chunk patchlevel=1\n#langdlc en\n".into()).read()?;
+    assert_eq!(languages.len(), 2);
+
+    Ok(())
+}
+
+#[cfg(feature = "path-list")]
+#[test]
+fn test_malformed_path_list() -> Result<(), Box<dyn std::error::Error>> {
+    use rpkg_rs::misc::hash_path_list::{PathList, PathListError};
+
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("hashlist.txt");
+    std::fs::write(
+        &path,
+        "#comment\n\
+         00546F0BD4E80484,[assembly:/any/path/here/file.jpg].pc_gfx\n\
+         \n\
+         0023456789ABCDEF,no_type_extension\n",
+    )?;
+
+    let mut path_list = PathList::new();
+    path_list.parse_into(&path)?;
+    assert_eq!(path_list.entries.len(), 2);
+    assert!(path_list.get(&RuntimeResourceID::from(0x00546F0BD4E80484)).is_some());
+
+    let result = path_list.parse_from_str("#comment\n003456789ABCDEF0.FXAS\nnot a hash,at all\nneither.TEMP\n");
+    assert!(matches!(
+        result,
+        Err(PathListError::InvalidLine { line: 3, ref content }) if content == "not a hash,at all"
+    ));
+    assert_eq!(path_list.entries.len(), 2);
+
+    Ok(())
+}
@@ -0,0 +1,236 @@
+mod common;
+
+use common::rrid;
+use rpkg_rs::misc::resource_id::ResourceID;
+use rpkg_rs::resource::dependency_graph::{DependencyGraph, DependencyGraphError, ReferenceFilter};
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::{PackageDefinitionSource, PartitionId, PartitionInfo};
+use rpkg_rs::resource::resource_package::{
+    ChunkType, PackageVersion, ReferenceType, ResourcePackage, ResourceReferenceFlags,
+    ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::{PlatformTag, RuntimeResourceID};
+use std::str::FromStr;
+
+fn reference(reference_type: ReferenceType, runtime_acquired: bool) -> ResourceReferenceFlags {
+    ResourceReferenceFlags::Standard(
+        ResourceReferenceFlagsStandard::new()
+            .with_reference_type(reference_type)
+            .with_runtime_acquired(runtime_acquired),
+    )
+}
+
+fn resource(
+    id: u64,
+    references: &[(u64, ResourceReferenceFlags)],
+) -> Result<PackageResourceBuilder, Box<dyn std::error::Error>> {
+    let mut resource =
+        PackageResourceBuilder::from_memory(rrid(id), "TEMP", vec![id as u8; 8], None, false)?;
+    for (target, flags) in references {
+        resource.with_reference(rrid(*target), *flags);
+    }
+    Ok(resource)
+}
+
+/// 1 -> 2 (install), 1 -> 3 (weak), 2 -> 4 (normal, acquired), 4 -> 2 (normal), 5 -> 5, 6 -> 99.
+fn build_graph() -> Result<DependencyGraph, Box<dyn std::error::Error>> {
+    let mut builder = PackageBuilder::new(0, ChunkType::Standard);
+    builder
+        .with_resource(resource(
+            1,
+            &[
+                (2, reference(ReferenceType::INSTALL, false)),
+                (3, reference(ReferenceType::WEAK, false)),
+            ],
+        )?)
+        .with_resource(resource(2, &[(4, reference(ReferenceType::NORMAL, true))])?)
+        .with_resource(resource(3, &[])?)
+        .with_resource(resource(4, &[(2, reference(ReferenceType::NORMAL, false))])?)
+        .with_resource(resource(5, &[(5, reference(ReferenceType::NORMAL, false))])?)
+        .with_resource(resource(6, &[(99, reference(ReferenceType::INSTALL, false))])?);
+
+    let package = ResourcePackage::from_memory(builder.build_to_vec(PackageVersion::RPKGv2)?, false)?;
+    Ok(DependencyGraph::from_resource_package(&package)?)
+}
+
+#[test]
+fn test_referenced_by() -> Result<(), Box<dyn std::error::Error>> {
+    let graph = build_graph()?;
+    assert_eq!(graph.len(), 6);
+
+    let referrers = graph
+        .referenced_by(&rrid(2))
+        .iter()
+        .map(|(source, _)| *source)
+        .collect::<Vec<_>>();
+    assert_eq!(referrers, vec![rrid(1), rrid(4)]);
+
+    assert_eq!(graph.referenced_by(&rrid(99)).len(), 1);
+    assert!(graph.referenced_by(&rrid(6)).is_empty());
+    assert_eq!(graph.unresolved_references(), vec![rrid(99)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_dependencies_respect_filter() -> Result<(), Box<dyn std::error::Error>> {
+    let graph = build_graph()?;
+
+    assert_eq!(
+        graph.dependencies(&rrid(1), ReferenceFilter::all())?,
+        vec![rrid(2), rrid(3), rrid(4)]
+    );
+    assert_eq!(
+        graph.dependencies(&rrid(1), ReferenceFilter::all().with_weak(false))?,
+        vec![rrid(2), rrid(4)]
+    );
+    assert_eq!(
+        graph.dependencies(&rrid(1), ReferenceFilter::all().with_runtime_acquired(false))?,
+        vec![rrid(2), rrid(3)]
+    );
+    assert_eq!(
+        graph.dependencies(&rrid(1), ReferenceFilter::install_only())?,
+        vec![rrid(2)]
+    );
+    assert_eq!(graph.dependencies(&rrid(6), ReferenceFilter::all())?, vec![rrid(99)]);
+
+    assert!(matches!(
+        graph.dependencies(&rrid(99), ReferenceFilter::all()),
+        Err(DependencyGraphError::ResourceNotFound(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_dependents() -> Result<(), Box<dyn std::error::Error>> {
+    let graph = build_graph()?;
+
+    assert_eq!(
+        graph.dependents(&rrid(4), ReferenceFilter::all())?,
+        vec![rrid(2), rrid(1)]
+    );
+    assert!(graph
+        .dependents(&rrid(4), ReferenceFilter::all().with_runtime_acquired(false))?
+        .is_empty());
+    assert_eq!(graph.dependents(&rrid(99), ReferenceFilter::all())?, vec![rrid(6)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_cycles_and_orphans() -> Result<(), Box<dyn std::error::Error>> {
+    let graph = build_graph()?;
+
+    assert_eq!(
+        graph.cycles(ReferenceFilter::all()),
+        vec![vec![rrid(2), rrid(4)], vec![rrid(5)]]
+    );
+    assert_eq!(
+        graph.cycles(ReferenceFilter::all().with_runtime_acquired(false)),
+        vec![vec![rrid(5)]]
+    );
+
+    assert_eq!(graph.orphans(), vec![rrid(1), rrid(5), rrid(6)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_graph_from_partition_manager() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+
+    let mut base = PackageBuilder::new_with_patch_id(PartitionId::from_str("chunk0")?, PatchId::Base);
+    base.with_resource(resource(1, &[(2, reference(ReferenceType::INSTALL, false))])?)
+        .with_resource(resource(2, &[])?);
+    base.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+    let mut dlc = PackageBuilder::new_with_patch_id(PartitionId::from_str("chunk1")?, PatchId::Base);
+    dlc.with_resource(resource(2, &[(3, reference(ReferenceType::NORMAL, false))])?)
+        .with_resource(resource(3, &[])?);
+    dlc.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+    let partitions = vec![PartitionInfo::from_id("chunk0")?, PartitionInfo::from_id("chunk1")?];
+    let mut partition_manager = PartitionManager::new(
+        temp_dir.path().to_path_buf(),
+        &PackageDefinitionSource::Custom(partitions),
+    )?;
+    partition_manager.mount_partitions(|_, _| {})?;
+
+    let graph = DependencyGraph::from_partition_manager(&partition_manager)?;
+    assert_eq!(graph.len(), 3);
+
+    let node = graph.node(&rrid(2)).ok_or("resource 2 is missing")?;
+    assert_eq!(node.partitions().len(), 2);
+    assert_eq!(node.data_type(), "TEMP");
+    assert_eq!(
+        graph.dependencies(&rrid(1), ReferenceFilter::all())?,
+        vec![rrid(2), rrid(3)]
+    );
+    assert_eq!(graph.orphans(), vec![rrid(1)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_subgraph_export() -> Result<(), Box<dyn std::error::Error>> {
+    let graph = build_graph()?;
+
+    let mut subgraph = graph.subgraph(&[rrid(1)], ReferenceFilter::all().with_weak(false))?;
+    let nodes = subgraph.nodes.iter().map(|node| node.rrid).collect::<Vec<_>>();
+    assert_eq!(nodes, vec![rrid(1), rrid(2), rrid(4)]);
+    assert_eq!(subgraph.edges.len(), 3);
+
+    subgraph.resolve_paths(|rrid| (*rrid == 1).then(|| "[assembly:/\"root\".brick].entitytype".to_string()));
+    assert!(subgraph.nodes[0].path.is_some());
+    assert!(subgraph.nodes[1].path.is_none());
+
+    let dot = subgraph.to_dot();
+    assert!(dot.starts_with("digraph dependencies {"));
+    assert!(dot.contains("[assembly:/\\\"root\\\".brick].entitytype\\n"));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"normal, acquired\", style=dashed];", rrid(2), rrid(4))));
+    assert!(dot.contains("TEMP, 8 bytes"));
+
+    let unresolved = graph.subgraph(&[rrid(6)], ReferenceFilter::all())?;
+    assert_eq!(unresolved.nodes[1].data_type, None);
+    assert!(unresolved.to_dot().contains("not mounted"));
+
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_subgraph_json() -> Result<(), Box<dyn std::error::Error>> {
+    let subgraph = build_graph()?.subgraph(&[rrid(1)], ReferenceFilter::all().with_weak(false))?;
+
+    let json = serde_json::to_value(&subgraph)?;
+    assert_eq!(json["nodes"][1]["data_type"], "TEMP");
+    assert_eq!(json["edges"][0]["reference_type"], "INSTALL");
+
+    Ok(())
+}
+
+#[test]
+fn test_partition_subgraph() -> Result<(), Box<dyn std::error::Error>> {
+    let root = ResourceID::from_str("[assembly:/_pro/scenes/bricks/base.brick].pc_entitytype")?;
+    let root_rrid = RuntimeResourceID::from_resource_id_with_platform(&root, "pc", PlatformTag::None);
+
+    let mut builder = PackageBuilder::new(0, ChunkType::Standard);
+    builder
+        .with_resource(resource(u64::from(root_rrid), &[(2, reference(ReferenceType::INSTALL, false))])?)
+        .with_resource(resource(2, &[])?);
+    let package = ResourcePackage::from_memory(builder.build_to_vec(PackageVersion::RPKGv2)?, false)?;
+    let graph = DependencyGraph::from_resource_package(&package)?;
+
+    let mut partition_info = PartitionInfo::from_id("chunk0")?;
+    partition_info.roots.push(root);
+    partition_info.roots.push(ResourceID::from_str("[assembly:/_pro/missing.brick].pc_entitytype")?);
+
+    let subgraph = graph.partition_subgraph(&partition_info, ReferenceFilter::all())?;
+    let nodes = subgraph.nodes.iter().map(|node| node.rrid).collect::<Vec<_>>();
+    assert_eq!(nodes, vec![root_rrid, rrid(2)]);
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+mod common;
+
+use common::rrid;
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::{PackageDefinitionSource, PartitionId, PartitionInfo};
+use rpkg_rs::resource::resource_diff::{
+    ContentHash, DiffOptions, GameDiff, PartitionStatus, ResourceChange, ResourceDiff,
+    ResourceModification,
+};
+use rpkg_rs::resource::resource_package::{
+    PackageVersion, ReferenceType, ResourcePackage, ResourceReferenceFlags,
+    ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::PatchId;
+use std::path::Path;
+use std::str::FromStr;
+
+fn reference(reference_type: ReferenceType) -> ResourceReferenceFlags {
+    ResourceReferenceFlags::Standard(ResourceReferenceFlagsStandard::new().with_reference_type(reference_type))
+}
+
+struct TestResource {
+    id: u64,
+    data: Vec<u8>,
+    memory_requirements: (u32, u32),
+    references: Vec<(u64, ReferenceType)>,
+}
+
+fn test_resource(id: u64, data: Vec<u8>) -> TestResource {
+    TestResource {
+        id,
+        data,
+        memory_requirements: (0, 0),
+        references: vec![],
+    }
+}
+
+fn build_package(
+    partition: &str,
+    resources: &[TestResource],
+) -> Result<PackageBuilder, Box<dyn std::error::Error>> {
+    let mut builder = PackageBuilder::new_with_patch_id(PartitionId::from_str(partition)?, PatchId::Base);
+    for resource in resources {
+        let mut package_resource =
+            PackageResourceBuilder::from_memory(rrid(resource.id), "TEMP", resource.data.clone(), Some(4), false)?;
+        package_resource.with_memory_requirements(resource.memory_requirements.0, resource.memory_requirements.1);
+        for (target, reference_type) in &resource.references {
+            package_resource.with_reference(rrid(*target), reference(*reference_type));
+        }
+        builder.with_resource(package_resource);
+    }
+    Ok(builder)
+}
+
+fn package(resources: &[TestResource]) -> Result<ResourcePackage, Box<dyn std::error::Error>> {
+    let data = build_package("chunk0", resources)?.build_to_vec(PackageVersion::RPKGv2)?;
+    Ok(ResourcePackage::from_memory(data, false)?)
+}
+
+fn old_resources() -> Vec<TestResource> {
+    vec![
+        test_resource(1, vec![1; 64]),
+        test_resource(2, vec![2; 64]),
+        test_resource(3, vec![3; 64]),
+        TestResource {
+            id: 5,
+            data: vec![5; 64],
+            memory_requirements: (64, 0),
+            references: vec![(1, ReferenceType::INSTALL), (2, ReferenceType::NORMAL), (3, ReferenceType::WEAK)],
+        },
+    ]
+}
+
+fn new_resources() -> Vec<TestResource> {
+    vec![
+        test_resource(1, vec![1; 64]),
+        // Same size, different content.
+        test_resource(2, vec![9; 64]),
+        test_resource(4, vec![4; 32]),
+        TestResource {
+            id: 5,
+            data: vec![5; 64],
+            memory_requirements: (128, 0),
+            references: vec![(2, ReferenceType::WEAK), (1, ReferenceType::INSTALL), (4, ReferenceType::NORMAL)],
+        },
+    ]
+}
+
+#[test]
+fn test_package_diff_headers() -> Result<(), Box<dyn std::error::Error>> {
+    let diff = ResourceDiff::from_packages(&package(&old_resources())?, &package(&new_resources())?, DiffOptions::default())?;
+
+    assert_eq!(diff.added().map(|change| *change.rrid()).collect::<Vec<_>>(), vec![rrid(4)]);
+    assert_eq!(diff.removed().map(|change| *change.rrid()).collect::<Vec<_>>(), vec![rrid(3)]);
+    // Without comparing content, resource 2 looks unchanged.
+    assert!(diff.change(&rrid(2)).is_none());
+    assert!(diff.change(&rrid(1)).is_none());
+
+    let Some(ResourceChange::Modified { modifications, .. }) = diff.change(&rrid(5)) else {
+        panic!("resource 5 should be modified");
+    };
+    assert_eq!(
+        modifications,
+        &vec![
+            ResourceModification::SystemMemoryRequirement { old: 64, new: 128 },
+            ResourceModification::ReferenceRemoved { rrid: rrid(3), flags: reference(ReferenceType::WEAK) },
+            ResourceModification::ReferenceFlags {
+                rrid: rrid(2),
+                old: reference(ReferenceType::NORMAL),
+                new: reference(ReferenceType::WEAK),
+            },
+            ResourceModification::ReferenceAdded { rrid: rrid(4), flags: reference(ReferenceType::NORMAL) },
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_package_diff_content() -> Result<(), Box<dyn std::error::Error>> {
+    let options = DiffOptions::default().with_content(true);
+    let diff = ResourceDiff::from_packages(&package(&old_resources())?, &package(&new_resources())?, options)?;
+
+    assert_eq!(
+        diff.change(&rrid(2)),
+        Some(&ResourceChange::Modified {
+            rrid: rrid(2),
+            data_type: "TEMP".to_string(),
+            modifications: vec![ResourceModification::Content {
+                old: ContentHash::from_data(&[2; 64]),
+                new: ContentHash::from_data(&[9; 64]),
+            }],
+        })
+    );
+    assert!(diff.change(&rrid(1)).is_none());
+
+    let unchanged = ResourceDiff::from_packages(&package(&old_resources())?, &package(&old_resources())?, options)?;
+    assert!(unchanged.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_reference_order() -> Result<(), Box<dyn std::error::Error>> {
+    let resource = |references: Vec<(u64, ReferenceType)>| TestResource {
+        id: 1,
+        data: vec![1; 8],
+        memory_requirements: (0, 0),
+        references,
+    };
+    let old = package(&[resource(vec![(2, ReferenceType::INSTALL), (3, ReferenceType::INSTALL)])])?;
+    let new = package(&[resource(vec![(3, ReferenceType::INSTALL), (2, ReferenceType::INSTALL)])])?;
+
+    let diff = ResourceDiff::from_packages(&old, &new, DiffOptions::default())?;
+    let Some(ResourceChange::Modified { modifications, .. }) = diff.change(&rrid(1)) else {
+        panic!("resource 1 should be modified");
+    };
+    assert_eq!(modifications, &vec![ResourceModification::ReferenceOrder]);
+
+    Ok(())
+}
+
+fn mount_game(
+    runtime_path: &Path,
+    partitions: &[(&str, Vec<TestResource>)],
+) -> Result<PartitionManager, Box<dyn std::error::Error>> {
+    let mut partition_infos = vec![];
+    for (partition, resources) in partitions {
+        build_package(partition, resources)?.build_to_file(PackageVersion::RPKGv2, runtime_path)?;
+        partition_infos.push(PartitionInfo::from_id(partition)?);
+    }
+
+    let mut partition_manager =
+        PartitionManager::new(runtime_path.to_path_buf(), &PackageDefinitionSource::Custom(partition_infos))?;
+    partition_manager.mount_partitions(|_, _| {})?;
+    Ok(partition_manager)
+}
+
+#[test]
+fn test_game_diff() -> Result<(), Box<dyn std::error::Error>> {
+    let old_dir = tempfile::tempdir()?;
+    let new_dir = tempfile::tempdir()?;
+
+    let old = mount_game(
+        old_dir.path(),
+        &[("chunk0", old_resources()), ("chunk1", vec![test_resource(10, vec![10; 8])])],
+    )?;
+    let new = mount_game(
+        new_dir.path(),
+        &[
+            ("chunk0", new_resources()),
+            ("chunk1", vec![test_resource(10, vec![10; 8])]),
+            ("chunk2", vec![test_resource(20, vec![20; 8])]),
+        ],
+    )?;
+
+    let diff = GameDiff::from_partition_managers(&old, &new, DiffOptions::default().with_content(true))?;
+    assert_eq!(diff.partitions.len(), 2);
+
+    let chunk0 = diff.partition(&PartitionId::from_str("chunk0")?).ok_or("chunk0 should have changed")?;
+    assert_eq!(chunk0.status, PartitionStatus::Present);
+    assert_eq!(
+        chunk0.diff,
+        ResourceDiff::from_packages(&package(&old_resources())?, &package(&new_resources())?, DiffOptions::default().with_content(true))?
+    );
+
+    assert!(diff.partition(&PartitionId::from_str("chunk1")?).is_none());
+
+    let chunk2 = diff.partition(&PartitionId::from_str("chunk2")?).ok_or("chunk2 should be added")?;
+    assert_eq!(chunk2.status, PartitionStatus::Added);
+    assert_eq!(chunk2.diff.added().count(), 1);
+
+    let reverse = GameDiff::from_partition_managers(&new, &old, DiffOptions::default())?;
+    let chunk2 = reverse.partition(&PartitionId::from_str("chunk2")?).ok_or("chunk2 should be removed")?;
+    assert_eq!(chunk2.status, PartitionStatus::Removed);
+    assert_eq!(chunk2.diff.removed().count(), 1);
+
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_game_diff_json() -> Result<(), Box<dyn std::error::Error>> {
+    let old_dir = tempfile::tempdir()?;
+    let new_dir = tempfile::tempdir()?;
+
+    let old = mount_game(old_dir.path(), &[("chunk0", old_resources())])?;
+    let new = mount_game(
+        new_dir.path(),
+        &[("chunk0", new_resources()), ("chunk2", vec![test_resource(20, vec![20; 8])])],
+    )?;
+
+    let diff = GameDiff::from_partition_managers(&old, &new, DiffOptions::default())?;
+    let json = serde_json::to_value(&diff)?;
+    assert_eq!(json["partitions"][0]["status"], "Present");
+    assert_eq!(json["partitions"][1]["status"], "Added");
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+#![cfg(all(feature = "fuse", target_os = "linux"))]
+
+mod common;
+
+use common::{rrid, test_data, write_package};
+use rpkg_rs::encryption::xtea::Xtea;
+use rpkg_rs::resource::resource_partition::PatchId;
+use std::fs;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const THUMBS: &str = "[application]\nPROJECT_PATH=Game\\\nRUNTIME_PATH=Runtime\n";
+
+const PACKAGE_DEFINITION: &str = "\
+@partition name=base parent=none type=standard patchlevel=10
+@partition name=boot parent=base type=standard patchlevel=10
+";
+
+fn create_game(retail_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime_path = retail_path.join("Game").join("Runtime");
+    fs::create_dir_all(&runtime_path)?;
+    fs::write(retail_path.join("thumbs.dat"), Xtea::encrypt_bond_text_file(THUMBS.to_string())?)?;
+    fs::write(
+        runtime_path.join("packagedefinition.txt"),
+        Xtea::encrypt_bond_text_file(PACKAGE_DEFINITION.to_string())?,
+    )?;
+
+    let large = test_data(0, 300_000);
+    write_package(&runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 64]), (2, large)], &[])?;
+    write_package(&runtime_path, "chunk0", PatchId::Patch(1), &[(1, vec![2; 32])], &[])?;
+    write_package(&runtime_path, "chunk1", PatchId::Base, &[(3, vec![3; 16])], &[])?;
+    Ok(())
+}
+
+/// Stops the filesystem and waits for it to unmount.
+fn stop(mut child: Child) -> Result<(), Box<dyn std::error::Error>> {
+    unsafe { libc::kill(child.id() as i32, libc::SIGTERM) };
+    let status = child.wait()?;
+    assert!(status.success(), "rpkg-fuse exited with {status}");
+    Ok(())
+}
+
+#[test]
+fn test_fuse_mount() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let retail_path = temp_dir.path().join("Retail");
+    let mount_point = temp_dir.path().join("mnt");
+    create_game(&retail_path)?;
+    fs::create_dir(&mount_point)?;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rpkg-fuse"))
+        .arg(&retail_path)
+        .arg(&mount_point)
+        .args(["--game-version", "Bond"])
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let start = Instant::now();
+    while !mount_point.join("all").exists() {
+        if let Some(status) = child.try_wait()? {
+            let mut error = String::new();
+            std::io::Read::read_to_string(&mut child.stderr.take().ok_or("no stderr")?, &mut error)?;
+            // Mounting needs access to /dev/fuse, which sandboxed environments may not provide.
+            eprintln!("skipping, rpkg-fuse could not mount ({status}): {error}");
+            return Ok(());
+        }
+        if start.elapsed() > Duration::from_secs(10) {
+            stop(child)?;
+            return Err("the filesystem was not mounted in time".into());
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut root = fs::read_dir(&mount_point)?.map(|entry| entry.map(|e| e.file_name())).collect::<Result<Vec<_>, _>>()?;
+        root.sort();
+        assert_eq!(root, vec!["all", "chunk0", "chunk1"]);
+
+        let temp = mount_point.join("all").join("unknown").join("TEMP");
+        assert_eq!(fs::read(temp.join(rrid(1).to_string()))?, vec![2; 32]);
+        assert_eq!(fs::read(temp.join(rrid(3).to_string()))?, vec![3; 16]);
+
+        let large = fs::read(temp.join(rrid(2).to_string()))?;
+        assert_eq!(large.len(), 300_000);
+        assert_eq!(large, test_data(0, 300_000));
+        assert_eq!(fs::metadata(temp.join(rrid(2).to_string()))?.len(), 300_000);
+
+        // Reads past the end of a file read nothing.
+        let file = fs::File::open(temp.join(rrid(3).to_string()))?;
+        let mut buffer = [0; 16];
+        assert_eq!(file.read_at(&mut buffer, 16)?, 0);
+        assert_eq!(file.read_at(&mut buffer, 1000)?, 0);
+
+        let chunk1 = fs::read_dir(mount_point.join("chunk1").join("unknown").join("TEMP"))?.count();
+        assert_eq!(chunk1, 1);
+        assert!(fs::write(temp.join(rrid(3).to_string()), b"read only").is_err());
+        assert!(fs::metadata(temp.join("missing")).is_err());
+        Ok(())
+    })();
+
+    stop(child)?;
+    assert!(!mount_point.join("all").exists());
+    result
+}
@@ -20,7 +20,7 @@ fn test_game_mounting(
     let package_manager =
         PartitionManager::from_game(game_retail_path, game_version, true)?;
 
-    assert!(package_manager.partitions.len() > 0);
+    assert!(!package_manager.partitions.is_empty());
 
     let packages = package_manager
         .partitions
@@ -34,7 +34,7 @@ fn test_game_mounting(
         for (patch_id, package) in &partition.packages {
             let package_name = partition.partition_info().filename(*patch_id);
 
-            for (rrid, resource) in package.resources() {
+            for (rrid, resource) in package.resources()? {
                 let data_size = resource.compressed_size().unwrap_or(resource.size());
 
                 let data_offset = resource.data_offset();
@@ -53,6 +53,18 @@ fn test_game_mounting(
                         }
                     }
 
+                    Some(ResourcePackageSource::MappedFile(_, mmap)) => {
+                        let mapped_size = mmap.len() as u64;
+
+                        if data_offset >= mapped_size {
+                            return Err(format!("Resource '{}' offset for package '{}' of game '{:?}' is greater than the mapped file size", rrid, package_name, game_version).into());
+                        }
+
+                        if data_offset + data_size as u64 > mapped_size {
+                            return Err(format!("Resource '{}' size for package '{}' of game '{:?}' is greater than the mapped file size", rrid, package_name, game_version).into());
+                        }
+                    }
+
                     Some(ResourcePackageSource::Memory(buffer)) => {
                         let buffer_size = buffer.len();
 
@@ -98,6 +110,12 @@ fn test_hm3_mounting() -> Result<(), Box<dyn std::error::Error>> {
     test_game_mounting("HM3_PATH", WoaVersion::HM3)
 }
 
+#[test]
+#[ignore]
+fn test_bond_mounting() -> Result<(), Box<dyn std::error::Error>> {
+    test_game_mounting("BOND_PATH", WoaVersion::Bond)
+}
+
 fn test_game_rebuild(
     path_env_var: &str,
     game_version: WoaVersion,
@@ -130,12 +148,12 @@ fn test_game_rebuild(
             );
 
             // Create a package builder to duplicate the package.
-            let mut builder = PackageBuilder::from_resource_package(&package)?;
+            let mut builder = PackageBuilder::from_resource_package(package)?;
 
             // Set the patch ID if it's a patch package.
             builder.with_patch_id(patch_id);
 
-            if package.has_legacy_references() {
+            if package.has_legacy_references()? {
                 builder.use_legacy_references();
             }
 
@@ -146,9 +164,9 @@ fn test_game_rebuild(
             )?;
 
             // After it's built, check if the generated file is the same as the original.
-            let original_file = match &package.source() {
-                Some(ResourcePackageSource::File(path)) => path,
-                _ => Err(format!(
+            let original_file = match package.source().and_then(ResourcePackageSource::path) {
+                Some(path) => path,
+                None => Err(format!(
                     "Package '{}' of game '{:?}' has no source",
                     output_name, game_version
                 ))?,
@@ -212,3 +230,9 @@ fn test_hm2_rebuild() -> Result<(), Box<dyn std::error::Error>> {
 fn test_hm3_rebuild() -> Result<(), Box<dyn std::error::Error>> {
     test_game_rebuild("HM3_PATH", WoaVersion::HM3)
 }
+
+#[test]
+#[ignore]
+fn test_bond_rebuild() -> Result<(), Box<dyn std::error::Error>> {
+    test_game_rebuild("BOND_PATH", WoaVersion::Bond)
+}
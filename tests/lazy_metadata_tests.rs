@@ -0,0 +1,196 @@
+mod common;
+
+use common::test_data;
+use rpkg_rs::resource::dependency_graph::{DependencyGraph, DependencyGraphError};
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::{PackageDefinitionSource, PartitionId, PartitionInfo};
+use rpkg_rs::resource::resource_package::{
+    PackageVersion, ReferenceType, ResourcePackage, ResourcePackageError, ResourceReferenceFlags,
+    ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_diff::{DiffOptions, ResourceDiff, ResourceDiffError};
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use std::str::FromStr;
+
+fn package_builder(patch_id: PatchId) -> Result<PackageBuilder, Box<dyn std::error::Error>> {
+    let mut builder = PackageBuilder::new_with_patch_id(PartitionId::from_str("chunk0")?, patch_id);
+    for i in 0..6u64 {
+        let mut resource = PackageResourceBuilder::from_memory(
+            RuntimeResourceID::from(i + 1),
+            "TEMP",
+            test_data(i as u8, 1024),
+            (i % 2 == 0).then_some(4),
+            i % 3 == 0,
+        )?;
+        for j in 0..i {
+            resource.with_reference(
+                RuntimeResourceID::from(0x100 + j),
+                ResourceReferenceFlags::Standard(ResourceReferenceFlagsStandard::new().with_reference_type(ReferenceType::WEAK)),
+            );
+        }
+        builder.with_resource(resource);
+    }
+    if patch_id.is_patch() {
+        builder.with_unneeded_resource(RuntimeResourceID::from(0x200));
+    }
+    Ok(builder)
+}
+
+#[test]
+fn test_lazy_package_matches_eager_package() -> Result<(), Box<dyn std::error::Error>> {
+    for version in [PackageVersion::RPKGv1, PackageVersion::RPKGv2] {
+        for patch_id in [PatchId::Base, PatchId::Patch(1)] {
+            let data = package_builder(patch_id)?.build_to_vec(version)?;
+            let eager = ResourcePackage::from_memory(data.clone(), patch_id.is_patch())?;
+            let lazy = ResourcePackage::from_memory_lazy(data, patch_id.is_patch())?;
+
+            assert!(eager.is_metadata_loaded());
+            assert!(!lazy.is_metadata_loaded());
+            assert_eq!(lazy.resource_count(), eager.resources()?.len());
+            assert!(lazy.resource_ids().eq(eager.resources()?.keys()));
+            assert_eq!(lazy.unneeded_resource_ids(), eager.unneeded_resource_ids());
+
+            for (rrid, info) in eager.resources()? {
+                assert!(lazy.contains_resource(rrid));
+                assert_eq!(lazy.offset_info(rrid).map(|entry| entry.compressed_size()), Some(info.compressed_size()));
+                assert_eq!(lazy.resource_info(rrid)?.as_ref(), info);
+                assert_eq!(lazy.read_resource(rrid)?, eager.read_resource(rrid)?);
+            }
+            assert!(!lazy.contains_resource(&RuntimeResourceID::from(0x100)));
+            assert!(matches!(
+                lazy.resource_info(&RuntimeResourceID::from(0x100)),
+                Err(ResourcePackageError::ResourceNotFound)
+            ));
+            assert!(!lazy.is_metadata_loaded());
+
+            assert_eq!(lazy.load_metadata()?, eager.resources()?);
+            assert!(lazy.is_metadata_loaded());
+            assert_eq!(lazy.verify()?, vec![]);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lazy_package_from_file() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    package_builder(PatchId::Base)?.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+    let path = temp_dir.path().join("chunk0.rpkg");
+
+    let package = ResourcePackage::from_file_lazy(&path)?;
+    let rrid = RuntimeResourceID::from(4);
+    assert_eq!(package.resource_info(&rrid)?.references().len(), 3);
+    assert_eq!(package.read_resource(&rrid)?, test_data(3, 1024));
+    assert!(!package.is_metadata_loaded());
+
+    assert_eq!(package.resources()?, ResourcePackage::from_file(&path)?.resources()?);
+    Ok(())
+}
+
+#[test]
+fn test_lazy_package_with_corrupted_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let data = package_builder(PatchId::Base)?.build_to_vec(PackageVersion::RPKGv2)?;
+    // The magic, metadata and header followed by six offset table entries.
+    let truncated = data[..0x19 + 6 * 0x14 + 0x20].to_vec();
+
+    assert!(matches!(
+        ResourcePackage::from_memory(truncated.clone(), false),
+        Err(ResourcePackageError::UnexpectedEnd { .. })
+    ));
+
+    let package = ResourcePackage::from_memory_lazy(truncated, false)?;
+    assert_eq!(package.resource_count(), 6);
+    assert!(package.contains_resource(&RuntimeResourceID::from(6)));
+
+    let error = package.load_metadata().err().ok_or("truncated metadata should not load")?;
+    assert!(matches!(error, ResourcePackageError::UnexpectedEnd { .. }), "{error}");
+    assert!(matches!(
+        package.resource_info(&RuntimeResourceID::from(6)),
+        Err(ResourcePackageError::UnexpectedEnd { .. })
+    ));
+    assert!(matches!(package.has_legacy_references(), Err(ResourcePackageError::UnexpectedEnd { .. })));
+    assert!(matches!(package.resources(), Err(ResourcePackageError::UnexpectedEnd { .. })));
+    assert!(!package.is_metadata_loaded());
+
+    // Consumers report the broken metadata instead of treating the package as empty.
+    let intact = ResourcePackage::from_memory(data, false)?;
+    assert!(matches!(
+        ResourceDiff::from_packages(&intact, &package, DiffOptions::default()),
+        Err(ResourceDiffError::ReadPackageError(ResourcePackageError::UnexpectedEnd { .. }))
+    ));
+    assert!(matches!(
+        DependencyGraph::from_resource_package(&package),
+        Err(DependencyGraphError::ReadPackageError(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_lazy_partition_mount() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    package_builder(PatchId::Base)?.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+    package_builder(PatchId::Patch(1))?.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+    let mut partition_info = PartitionInfo::from_id("chunk0")?;
+    partition_info.set_max_patch_level(9);
+    let mut partition_manager = PartitionManager::new(
+        temp_dir.path().to_path_buf(),
+        &PackageDefinitionSource::Custom(vec![partition_info]),
+    )?;
+    partition_manager.set_lazy_metadata(true);
+    partition_manager.mount_partitions(|_, _| {})?;
+
+    let partition = partition_manager
+        .find_partition(PartitionId::from_str("chunk0")?)
+        .ok_or("partition should be mounted")?;
+    let rrid = RuntimeResourceID::from(2);
+    assert!(partition.contains(&rrid));
+    assert_eq!(partition.read_resource(&rrid)?, test_data(1, 1024));
+    assert!(partition.packages.values().all(|package| !package.is_metadata_loaded()));
+
+    assert_eq!(partition.get_resource_info(&rrid)?.size(), 1024);
+    assert!(partition.packages[&PatchId::Patch(1)].is_metadata_loaded());
+    assert!(!partition.packages[&PatchId::Base].is_metadata_loaded());
+    Ok(())
+}
+
+#[test]
+fn test_lazy_partition_with_corrupted_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let data = package_builder(PatchId::Base)?.build_to_vec(PackageVersion::RPKGv2)?;
+    std::fs::write(temp_dir.path().join("chunk0.rpkg"), &data[..0x19 + 6 * 0x14 + 0x20])?;
+    let mut patch = PackageBuilder::new_with_patch_id(PartitionId::from_str("chunk0")?, PatchId::Patch(1));
+    patch.with_unneeded_resource(RuntimeResourceID::from(6));
+    patch.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+    let mut partition_info = PartitionInfo::from_id("chunk0")?;
+    partition_info.set_max_patch_level(9);
+    let mut partition_manager = PartitionManager::new(
+        temp_dir.path().to_path_buf(),
+        &PackageDefinitionSource::Custom(vec![partition_info]),
+    )?;
+    partition_manager.set_lazy_metadata(true);
+    partition_manager.mount_partitions(|_, _| {})?;
+
+    // The resources are mounted from the offset table, listing them needs the broken metadata.
+    let partition = partition_manager
+        .find_partition(PartitionId::from_str("chunk0")?)
+        .ok_or("partition should be mounted")?;
+    assert!(partition.contains(&RuntimeResourceID::from(5)));
+    assert!(!partition.contains(&RuntimeResourceID::from(6)));
+    assert!(matches!(partition.latest_resources(), Err(ResourcePackageError::UnexpectedEnd { .. })));
+    assert!(partition.latest_resources_of_type("TEMP").is_err());
+    assert!(matches!(partition.removed_resources(), Err(ResourcePackageError::UnexpectedEnd { .. })));
+    assert!(partition.removed_resources_of_type("TEMP").is_err());
+    assert!(matches!(
+        ResourceDiff::from_partitions(partition, partition, DiffOptions::default()),
+        Err(ResourceDiffError::ReadPackageError(_))
+    ));
+    assert!(matches!(
+        DependencyGraph::from_partition_manager(&partition_manager),
+        Err(DependencyGraphError::ReadPackageError(_))
+    ));
+    Ok(())
+}
@@ -0,0 +1,346 @@
+use rpkg_rs::resource::compression_policy::CompressionBackend;
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageBuilderError, PackageResourceBuilder};
+use rpkg_rs::resource::resource_package::{PackageVersion, ResourcePackage};
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+
+/// A small xorshift generator, so every case can be reproduced from its seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    fn chance(&mut self) -> bool {
+        self.next() & 1 == 1
+    }
+
+    fn bytes(&mut self, length: usize) -> Vec<u8> {
+        (0..length).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// The format variants a synthetic package is written with.
+#[derive(Debug, Clone, Copy)]
+struct Variant {
+    version: PackageVersion,
+    is_patch: bool,
+    legacy_references: bool,
+    has_states_size: bool,
+}
+
+impl Variant {
+    fn all() -> Vec<Variant> {
+        let mut variants = vec![];
+        for version in [PackageVersion::RPKGv1, PackageVersion::RPKGv2] {
+            for is_patch in [false, true] {
+                for legacy_references in [false, true] {
+                    for has_states_size in [false, true] {
+                        variants.push(Variant { version, is_patch, legacy_references, has_states_size });
+                    }
+                }
+            }
+        }
+        variants
+    }
+}
+
+struct SyntheticResource {
+    rrid: u64,
+    resource_type: [u8; 4],
+    states_chunk_size: u32,
+    /// The packaged data, as stored inside the package.
+    data: Vec<u8>,
+    decompressed_size: Option<u32>,
+    is_scrambled: bool,
+    system_memory_requirement: u32,
+    video_memory_requirement: u32,
+    references: Vec<(u64, u8)>,
+    /// The bytes following the data of the resource.
+    padding: Vec<u8>,
+}
+
+/// Writes the bytes of a package the way the game's own tools lay it out, independently of the builder.
+struct SyntheticPackage {
+    variant: Variant,
+    /// The unknown field, chunk id, chunk type, patch id and language tag of the metadata.
+    metadata: (u32, u8, u8, u8, [u8; 2]),
+    unneeded_resources: Vec<u64>,
+    resources: Vec<SyntheticResource>,
+    /// The order the data of the resources is stored in, as indices into `resources`.
+    data_order: Vec<usize>,
+    leading_padding: Vec<u8>,
+}
+
+impl SyntheticPackage {
+    fn generate(variant: Variant, rng: &mut Rng) -> Self {
+        let resource_count = rng.below(8) as usize;
+        let mut resources = vec![];
+        for index in 0..resource_count {
+            let size = rng.below(600) as usize;
+            let decompressed = rng.bytes(size).iter().map(|byte| byte % 4).collect::<Vec<_>>();
+            let (mut data, decompressed_size) = match rng.chance() && !decompressed.is_empty() {
+                true => (CompressionBackend::Lz4Hc.compress(&decompressed, 9).unwrap(), Some(decompressed.len() as u32)),
+                false => (decompressed, None),
+            };
+
+            let is_scrambled = rng.chance();
+            if is_scrambled {
+                rpkg_rs::encryption::scramble::scramble(&mut data);
+            }
+
+            let references = (0..rng.below(4))
+                .map(|_| {
+                    let flags = match variant.legacy_references {
+                        true => rng.next() as u8 & 0xF6,
+                        false => rng.next() as u8,
+                    };
+                    (rng.next() & 0x00FF_FFFF_FFFF_FFFF, flags)
+                })
+                .collect();
+
+            let padding_length = match rng.below(3) {
+                0 => 0,
+                1 => (16 - data.len() % 16) % 16,
+                _ => rng.below(40) as usize,
+            };
+
+            resources.push(SyntheticResource {
+                rrid: 0x0010_0000_0000_0000 + index as u64 * 0x1234_5677,
+                resource_type: *[b"PMET", b"TXET", b"DLBT", b"DPCG"][rng.below(4) as usize],
+                states_chunk_size: match variant.has_states_size && rng.chance() {
+                    true => rng.below(0x1000) as u32,
+                    false => 0,
+                },
+                data,
+                decompressed_size,
+                is_scrambled,
+                system_memory_requirement: rng.next() as u32,
+                video_memory_requirement: rng.next() as u32,
+                references,
+                padding: rng.bytes(padding_length),
+            });
+        }
+
+        let mut data_order = (0..resource_count).collect::<Vec<_>>();
+        for index in (1..data_order.len()).rev() {
+            data_order.swap(index, rng.below(index as u64 + 1) as usize);
+        }
+
+        // Some patches list an unneeded resource more than once.
+        let mut unneeded_resources = match variant.is_patch {
+            true => (0..rng.below(4)).map(|_| rng.next() & 0x00FF_FFFF_FFFF_FFFF).collect::<Vec<_>>(),
+            false => vec![],
+        };
+        if let Some(first) = unneeded_resources.first().copied().filter(|_| rng.chance()) {
+            unneeded_resources.push(first);
+        }
+
+        let leading_padding = rng.below(3) as usize * 8;
+        SyntheticPackage {
+            variant,
+            metadata: (rng.next() as u32, rng.below(30) as u8, rng.below(2) as u8, rng.below(10) as u8, *b"xx"),
+            unneeded_resources,
+            resources,
+            data_order,
+            leading_padding: rng.bytes(leading_padding),
+        }
+    }
+
+    fn metadata_entry(&self, resource: &SyntheticResource) -> Vec<u8> {
+        let mut references = vec![];
+        if !resource.references.is_empty() {
+            let count_and_flags = resource.references.len() as u32
+                | (!self.variant.legacy_references as u32) << 30
+                | 1 << 31;
+            references.extend(count_and_flags.to_le_bytes());
+            let ids = resource.references.iter().flat_map(|(rrid, _)| rrid.to_le_bytes());
+            let flags = resource.references.iter().map(|(_, flags)| *flags);
+            match self.variant.legacy_references {
+                true => references.extend(ids.chain(flags)),
+                false => references.extend(flags.chain(ids)),
+            }
+        }
+
+        let mut entry = resource.resource_type.to_vec();
+        entry.extend((references.len() as u32).to_le_bytes());
+        if self.variant.has_states_size {
+            entry.extend(resource.states_chunk_size.to_le_bytes());
+        }
+        let data_size = resource.decompressed_size.unwrap_or(resource.data.len() as u32);
+        entry.extend(data_size.to_le_bytes());
+        entry.extend(resource.system_memory_requirement.to_le_bytes());
+        entry.extend(resource.video_memory_requirement.to_le_bytes());
+        entry.extend(references);
+        entry
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let metadata_table = self.resources.iter().flat_map(|resource| self.metadata_entry(resource)).collect::<Vec<_>>();
+
+        let mut header = vec![];
+        match self.variant.version {
+            PackageVersion::RPKGv1 => header.extend(b"GKPR"),
+            PackageVersion::RPKGv2 => {
+                let (unknown, chunk_id, chunk_type, patch_id, language_tag) = self.metadata;
+                header.extend(b"2KPR");
+                header.extend(unknown.to_le_bytes());
+                header.extend([chunk_id, chunk_type, patch_id]);
+                header.extend(language_tag);
+            }
+        }
+        header.extend((self.resources.len() as u32).to_le_bytes());
+        header.extend((self.resources.len() as u32 * 0x14).to_le_bytes());
+        header.extend((metadata_table.len() as u32).to_le_bytes());
+        if self.variant.is_patch {
+            header.extend((self.unneeded_resources.len() as u32).to_le_bytes());
+            header.extend(self.unneeded_resources.iter().flat_map(|rrid| rrid.to_le_bytes()));
+        }
+
+        // Lay out the data to know where every resource starts.
+        let mut offset = (header.len() + self.resources.len() * 0x14 + metadata_table.len()) as u64;
+        let mut data = self.leading_padding.clone();
+        offset += data.len() as u64;
+        let mut offsets = vec![0; self.resources.len()];
+        for &index in &self.data_order {
+            let resource = &self.resources[index];
+            offsets[index] = offset;
+            data.extend(&resource.data);
+            data.extend(&resource.padding);
+            offset += (resource.data.len() + resource.padding.len()) as u64;
+        }
+
+        let mut bytes = header;
+        for (resource, offset) in self.resources.iter().zip(offsets) {
+            let flags = resource.decompressed_size.map_or(0, |_| resource.data.len() as u32)
+                | (resource.is_scrambled as u32) << 31;
+            bytes.extend(resource.rrid.to_le_bytes());
+            bytes.extend(offset.to_le_bytes());
+            bytes.extend(flags.to_le_bytes());
+        }
+        bytes.extend(metadata_table);
+        bytes.extend(data);
+        bytes
+    }
+}
+
+#[test]
+fn test_lossless_rebuild_of_synthetic_packages() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    for (case, variant) in Variant::all().into_iter().enumerate() {
+        for seed in 1..=8u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) + case as u64);
+            let synthetic = SyntheticPackage::generate(variant, &mut rng);
+            let bytes = synthetic.to_bytes();
+            let context = format!("{variant:?}, seed {seed}");
+
+            let package = ResourcePackage::from_memory(bytes.clone(), variant.is_patch)?;
+            for resource in &synthetic.resources {
+                let info = package.resource_info(&RuntimeResourceID::from(resource.rrid))?;
+                assert_eq!(info.states_chunk_size(), resource.states_chunk_size as usize, "{context}");
+            }
+            let rebuilt = PackageBuilder::from_resource_package_lossless(&package)?.build_to_vec(package.version())?;
+            assert!(rebuilt == bytes, "rebuilding from memory changed the package, {context}");
+
+            // Packages read from files are duplicated from the file instead of from memory.
+            let file_name = match variant.is_patch {
+                true => "chunk0patch1.rpkg",
+                false => "chunk0.rpkg",
+            };
+            let path = temp_dir.path().join(file_name);
+            std::fs::write(&path, &bytes)?;
+            let package = ResourcePackage::from_file(&path)?;
+            let rebuilt = PackageBuilder::from_resource_package_lossless(&package)?.build_to_vec(package.version())?;
+            assert!(rebuilt == bytes, "rebuilding from a file changed the package, {context}");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lossless_rebuild_with_changes() -> Result<(), Box<dyn std::error::Error>> {
+    let variant = Variant {
+        version: PackageVersion::RPKGv2,
+        is_patch: true,
+        legacy_references: false,
+        has_states_size: true,
+    };
+    let mut rng = Rng(42);
+    let mut synthetic = SyntheticPackage::generate(variant, &mut rng);
+    while synthetic.resources.len() < 2 {
+        synthetic = SyntheticPackage::generate(variant, &mut rng);
+    }
+    synthetic.unneeded_resources = vec![0x00AA_0001, 0x00AA_0002, 0x00AA_0001];
+    let package = ResourcePackage::from_memory(synthetic.to_bytes(), true)?;
+
+    // Replacing a resource keeps it in its place in the data order, new resources go at the end.
+    let replaced = RuntimeResourceID::from(synthetic.resources[0].rrid);
+    let added = RuntimeResourceID::from(0x00AB_CDEF);
+    let mut builder = PackageBuilder::from_resource_package_lossless(&package)?;
+    builder.with_resource(PackageResourceBuilder::from_memory(replaced, "TEMP", vec![7; 100], None, false)?);
+    builder.with_resource(PackageResourceBuilder::from_memory(added, "TEMP", vec![8; 10], None, true)?);
+    builder.with_unneeded_resource(RuntimeResourceID::from(0x00AA_0003));
+    let rebuilt = ResourcePackage::from_memory(builder.build_to_vec(PackageVersion::RPKGv2)?, true)?;
+
+    assert_eq!(rebuilt.read_resource(&replaced)?, vec![7; 100]);
+    assert_eq!(rebuilt.read_resource(&added)?, vec![8; 10]);
+    let unneeded = [0x00AA_0001, 0x00AA_0002, 0x00AA_0001, 0x00AA_0003].map(RuntimeResourceID::from);
+    assert_eq!(rebuilt.unneeded_resource_ids(), unneeded.iter().collect::<Vec<_>>());
+    let last = rebuilt.resources()?.values().max_by_key(|info| info.data_offset()).map(|info| *info.rrid());
+    assert_eq!(last, Some(added));
+    for resource in &synthetic.resources[1..] {
+        let rrid = RuntimeResourceID::from(resource.rrid);
+        assert_eq!(rebuilt.read_resource(&rrid)?, package.read_resource(&rrid)?);
+        assert_eq!(rebuilt.resource_info(&rrid)?.states_chunk_size(), resource.states_chunk_size as usize);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lossless_rebuild_rejects_overlapping_data() -> Result<(), Box<dyn std::error::Error>> {
+    let variant = Variant {
+        version: PackageVersion::RPKGv1,
+        is_patch: false,
+        legacy_references: false,
+        has_states_size: true,
+    };
+    let mut rng = Rng(7);
+    let mut synthetic = SyntheticPackage::generate(variant, &mut rng);
+    synthetic.resources.truncate(0);
+    synthetic.data_order.clear();
+    for rrid in [1, 2] {
+        synthetic.resources.push(SyntheticResource {
+            rrid,
+            resource_type: *b"PMET",
+            states_chunk_size: 0,
+            data: vec![rrid as u8; 16],
+            decompressed_size: None,
+            is_scrambled: false,
+            system_memory_requirement: 0,
+            video_memory_requirement: 0,
+            references: vec![],
+            padding: vec![],
+        });
+    }
+    synthetic.data_order = vec![0, 1];
+    let mut bytes = synthetic.to_bytes();
+
+    // Point the second resource into the middle of the first one.
+    let second_entry = bytes.len() - 32 - 2 * 0x18 - 0x14 + 8;
+    let first_offset = u64::from_le_bytes(bytes[second_entry - 0x14..second_entry - 0xC].try_into()?);
+    bytes[second_entry..second_entry + 8].copy_from_slice(&(first_offset + 8).to_le_bytes());
+
+    let package = ResourcePackage::from_memory(bytes, false)?;
+    assert!(matches!(
+        PackageBuilder::from_resource_package_lossless(&package),
+        Err(PackageBuilderError::OverlappingResourceData(rrid)) if rrid == RuntimeResourceID::from(2)
+    ));
+    Ok(())
+}
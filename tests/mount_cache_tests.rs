@@ -0,0 +1,179 @@
+mod common;
+
+use common::{resource, rrid, write_package, write_resources};
+use rpkg_rs::misc::resource_id::ResourceID;
+use rpkg_rs::resource::mount_cache::{MountCache, MountCacheError};
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::{PackageDefinitionSource, PartitionId, PartitionInfo};
+use rpkg_rs::resource::resource_package::{ReferenceType, ResourceReferenceFlags, ResourceReferenceFlagsStandard};
+use rpkg_rs::resource::resource_partition::PatchId;
+use std::path::Path;
+use std::str::FromStr;
+
+fn partition_infos() -> Result<Vec<PartitionInfo>, Box<dyn std::error::Error>> {
+    let mut chunk0 = PartitionInfo::from_id("chunk0")?;
+    chunk0.name = Some("base".to_string());
+    chunk0.roots.push(ResourceID::from_str("[assembly:/_pro/scenes/test.entity].pc_entitytype")?);
+    let mut chunk1 = PartitionInfo::from_id("chunk1")?;
+    chunk1.parent = Some(chunk0.id.clone());
+
+    let mut infos = vec![chunk0, chunk1];
+    for info in infos.iter_mut() {
+        info.set_max_patch_level(9);
+    }
+    Ok(infos)
+}
+
+fn write_game(runtime_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    write_package(runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 256]), (2, vec![2; 128])], &[])?;
+    write_package(runtime_path, "chunk0", PatchId::Patch(1), &[(2, vec![3; 64])], &[1])?;
+    let mut entity = resource(10, "TEMP", vec![10; 512])?;
+    let flags = ResourceReferenceFlagsStandard::new().with_reference_type(ReferenceType::WEAK).with_runtime_acquired(true);
+    entity.with_reference(rrid(1), ResourceReferenceFlags::Standard(flags));
+    write_resources(runtime_path, "chunk1", PatchId::Base, vec![entity], &[])?;
+    Ok(())
+}
+
+fn mount_and_save(runtime_path: &Path, cache_path: &Path) -> Result<PartitionManager, Box<dyn std::error::Error>> {
+    let mut partition_manager =
+        PartitionManager::new(runtime_path.to_path_buf(), &PackageDefinitionSource::Custom(partition_infos()?))?;
+    partition_manager.mount_partitions(|_, _| {})?;
+    MountCache::from_partition_manager(&partition_manager)?.save_to_file(cache_path)?;
+    Ok(partition_manager)
+}
+
+#[test]
+fn test_mount_from_cache() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let cache_path = temp_dir.path().join("mount.cache");
+    let runtime_path = temp_dir.path().join("Runtime");
+    std::fs::create_dir(&runtime_path)?;
+    write_game(&runtime_path)?;
+    let mounted = mount_and_save(&runtime_path, &cache_path)?;
+
+    let cache = MountCache::from_file(&cache_path)?;
+    let infos = cache.partition_infos();
+    assert_eq!(infos.len(), 2);
+    assert_eq!(infos[0].name.as_deref(), Some("base"));
+    assert_eq!(infos[0].roots, partition_infos()?[0].roots);
+    assert_eq!(infos[1].parent, Some(infos[0].id.clone()));
+    assert_eq!(infos[1].patch_level, 9);
+
+    let mut partition_manager = PartitionManager::from_mount_cache(runtime_path.clone(), &cache)?;
+    partition_manager.mount_partitions_with_cache(&cache, |_, _| {})?;
+    assert_eq!((cache.hits(), cache.misses()), (3, 0));
+
+    let chunk0 = PartitionId::from_str("chunk0")?;
+    assert_eq!(partition_manager.read_resource_from(chunk0, rrid(2))?, vec![3; 64]);
+    assert!(!partition_manager.resource_mounted(&rrid(1)));
+    assert_eq!(partition_manager.read_resource_from(PartitionId::from_str("chunk1")?, rrid(10))?, vec![10; 512]);
+
+    for (cached, parsed) in partition_manager.partitions.iter().zip(&mounted.partitions) {
+        for (patch_id, package) in &parsed.packages {
+            assert_eq!(cached.packages[patch_id].resources()?, package.resources()?);
+            assert_eq!(cached.packages[patch_id].unneeded_resource_ids(), package.unneeded_resource_ids());
+        }
+        assert_eq!(cached.latest_resources()?.len(), parsed.latest_resources()?.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mount_cache_reparses_changed_packages() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let cache_path = temp_dir.path().join("mount.cache");
+    write_game(temp_dir.path())?;
+    mount_and_save(temp_dir.path(), &cache_path)?;
+
+    write_package(temp_dir.path(), "chunk0", PatchId::Patch(1), &[(2, vec![4; 100]), (3, vec![5; 10])], &[1])?;
+    write_package(temp_dir.path(), "chunk0", PatchId::Patch(2), &[(4, vec![6; 10])], &[1])?;
+
+    let cache = MountCache::from_file(&cache_path)?;
+    let mut partition_manager =
+        PartitionManager::new(temp_dir.path().to_path_buf(), &PackageDefinitionSource::Custom(partition_infos()?))?;
+    partition_manager.set_lazy_metadata(true);
+    partition_manager.mount_partitions_with_cache(&cache, |_, _| {})?;
+    assert_eq!((cache.hits(), cache.misses()), (2, 2));
+
+    let chunk0 = PartitionId::from_str("chunk0")?;
+    assert_eq!(partition_manager.read_resource_from(chunk0.clone(), rrid(2))?, vec![4; 100]);
+    assert_eq!(partition_manager.read_resource_from(chunk0.clone(), rrid(3))?, vec![5; 10]);
+    assert_eq!(partition_manager.read_resource_from(chunk0, rrid(4))?, vec![6; 10]);
+    assert!(!partition_manager.resource_mounted(&rrid(1)));
+
+    let chunk0 = &partition_manager.partitions[0];
+    assert!(chunk0.packages[&PatchId::Base].is_metadata_loaded());
+    assert!(!chunk0.packages[&PatchId::Patch(2)].is_metadata_loaded());
+    Ok(())
+}
+
+#[test]
+fn test_mount_cache_uses_the_stamps_from_mounting() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let cache_path = temp_dir.path().join("mount.cache");
+    write_game(temp_dir.path())?;
+
+    let mut partition_manager =
+        PartitionManager::new(temp_dir.path().to_path_buf(), &PackageDefinitionSource::Custom(partition_infos()?))?;
+    partition_manager.mount_partitions(|_, _| {})?;
+    write_package(temp_dir.path(), "chunk0", PatchId::Patch(1), &[(2, vec![4; 100])], &[1])?;
+    MountCache::from_partition_manager(&partition_manager)?.save_to_file(&cache_path)?;
+
+    let cache = MountCache::from_file(&cache_path)?;
+    let mut partition_manager =
+        PartitionManager::new(temp_dir.path().to_path_buf(), &PackageDefinitionSource::Custom(partition_infos()?))?;
+    partition_manager.mount_partitions_with_cache(&cache, |_, _| {})?;
+    assert_eq!((cache.hits(), cache.misses()), (2, 1));
+    assert_eq!(partition_manager.read_resource_from(PartitionId::from_str("chunk0")?, rrid(2))?, vec![4; 100]);
+    Ok(())
+}
+
+#[test]
+fn test_mount_cache_keeps_directories_apart() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let cache_path = temp_dir.path().join("mount.cache");
+    let first_path = temp_dir.path().join("first");
+    let second_path = temp_dir.path().join("second");
+    std::fs::create_dir(&first_path)?;
+    std::fs::create_dir(&second_path)?;
+    write_game(&first_path)?;
+    mount_and_save(&first_path, &cache_path)?;
+
+    // The same game with other data, stamped like the first one.
+    write_package(&second_path, "chunk0", PatchId::Base, &[(1, vec![7; 256]), (2, vec![8; 128])], &[])?;
+    write_package(&second_path, "chunk0", PatchId::Patch(1), &[(2, vec![9; 64])], &[1])?;
+    write_package(&second_path, "chunk1", PatchId::Base, &[(10, vec![11; 512])], &[])?;
+    for entry in std::fs::read_dir(&first_path)? {
+        let entry = entry?;
+        let modified = entry.metadata()?.modified()?;
+        std::fs::File::options().write(true).open(second_path.join(entry.file_name()))?.set_modified(modified)?;
+    }
+
+    let cache = MountCache::from_file(&cache_path)?;
+    assert!(cache.package(first_path.join("chunk0.rpkg")).is_some());
+    assert!(cache.package(second_path.join("chunk0.rpkg")).is_none());
+
+    let mut partition_manager =
+        PartitionManager::new(second_path, &PackageDefinitionSource::Custom(partition_infos()?))?;
+    partition_manager.mount_partitions_with_cache(&cache, |_, _| {})?;
+    assert_eq!((cache.hits(), cache.misses()), (0, 3));
+    assert_eq!(partition_manager.read_resource_from(PartitionId::from_str("chunk0")?, rrid(2))?, vec![9; 64]);
+    assert_eq!(partition_manager.read_resource_from(PartitionId::from_str("chunk1")?, rrid(10))?, vec![11; 512]);
+    Ok(())
+}
+
+#[test]
+fn test_invalid_mount_cache() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let cache_path = temp_dir.path().join("mount.cache");
+
+    std::fs::write(&cache_path, b"RPMC\x01\x00\x00\x00")?;
+    assert!(matches!(MountCache::from_file(&cache_path), Err(MountCacheError::UnsupportedVersion(1))));
+
+    std::fs::write(&cache_path, b"RPMC\x02\x00\x00\x00\x05\x00")?;
+    assert!(matches!(MountCache::from_file(&cache_path), Err(MountCacheError::ParsingError(_))));
+
+    std::fs::write(&cache_path, b"not a cache")?;
+    assert!(matches!(MountCache::from_file(&cache_path), Err(MountCacheError::ParsingError(_))));
+    Ok(())
+}
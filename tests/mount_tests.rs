@@ -0,0 +1,220 @@
+mod common;
+
+use common::write_package_as;
+use rpkg_rs::encryption::xtea::{Xtea, XteaError};
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::game_detection::{
+    DetectionConfidence, DetectionEvidence, EncryptionKey, GameDetection, GameDetectionError,
+};
+use rpkg_rs::resource::pdefs::{GameDiscoveryError, GamePaths, PackageDefinitionSource, PartitionId, PartitionType};
+use rpkg_rs::resource::resource_package::PackageVersion;
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use rpkg_rs::WoaVersion;
+use std::fs;
+use std::path::Path;
+
+const THUMBS: &str = "[application]\nPROJECT_PATH=Game\\\nRUNTIME_PATH=Runtime\n";
+
+const PARTITIONED_PACKAGE_DEFINITION: &str = "\
+@partition name=base parent=none type=standard patchlevel=10
+[assembly:/_pro/scenes/bricks/base.brick].pc_entitytype
+@partition name=boot parent=base type=standard patchlevel=10
+[assembly:/_pro/scenes/bricks/boot.brick].pc_entitytype
+";
+
+const CHUNK_PACKAGE_DEFINITION: &str = "\
+// --- Chunk 00 BASE
+@chunk patchlevel=10
+[assembly:/_pro/scenes/bricks/base.brick].pc_entitytype
+// --- Chunk 01 BOOT
+@chunk patchlevel=10
+[assembly:/_pro/scenes/bricks/boot.brick].pc_entitytype
+";
+
+const LEGACY_CHUNK_PACKAGE_DEFINITION: &str = "\
+## --- Chunk 00 BASE
+#chunk patchlevel=10
+[assembly:/_pro/scenes/bricks/base.brick].pc_entitytype
+## --- Chunk 01 BOOT
+#chunk patchlevel=10
+[assembly:/_pro/scenes/bricks/boot.brick].pc_entitytype
+";
+
+type Encryption = fn(String) -> Result<Vec<u8>, XteaError>;
+
+fn no_encryption(text: String) -> Result<Vec<u8>, XteaError> {
+    Ok(text.into_bytes())
+}
+
+/// Lays out a retail directory with two partitions, the first one has a patch overriding one of its resources.
+fn create_retail_directory(
+    retail_path: &Path,
+    encrypt: Encryption,
+    package_definition: &str,
+    version: PackageVersion,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime_path = retail_path.join("Game").join("Runtime");
+    fs::create_dir_all(&runtime_path)?;
+
+    fs::write(retail_path.join("thumbs.dat"), encrypt(THUMBS.to_string())?)?;
+    fs::write(
+        runtime_path.join("packagedefinition.txt"),
+        encrypt(package_definition.to_string())?,
+    )?;
+
+    write_package_as(version, &runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 16]), (2, vec![2; 16])], &[])?;
+    write_package_as(version, &runtime_path, "chunk0", PatchId::Patch(1), &[(2, vec![3; 16])], &[])?;
+    write_package_as(version, &runtime_path, "chunk1", PatchId::Base, &[(4, vec![4; 16])], &[])?;
+    Ok(())
+}
+
+fn create_bond_directory(retail_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    create_retail_directory(
+        retail_path,
+        Xtea::encrypt_bond_text_file,
+        PARTITIONED_PACKAGE_DEFINITION,
+        PackageVersion::RPKGv2,
+    )
+}
+
+#[test]
+fn test_bond_mounting() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    create_bond_directory(temp_dir.path())?;
+
+    let game_paths = GamePaths::from_retail_directory(temp_dir.path().to_path_buf())?;
+    assert!(game_paths.package_definition_path.exists());
+
+    let partition_manager =
+        PartitionManager::from_game(temp_dir.path().to_path_buf(), WoaVersion::Bond, true)?;
+    assert_eq!(partition_manager.partitions.len(), 2);
+
+    let base = PartitionId::default();
+    let boot = PartitionId {
+        part_type: PartitionType::Standard,
+        index: 1,
+    };
+
+    assert_eq!(
+        partition_manager.read_resource_from(base.clone(), RuntimeResourceID::from(2))?,
+        vec![3; 16]
+    );
+    assert_eq!(
+        partition_manager.read_resource_from(boot.clone(), RuntimeResourceID::from(4))?,
+        vec![4; 16]
+    );
+
+    let (_, resolved_in) =
+        partition_manager.resolve_resource_from(boot, &RuntimeResourceID::from(1))?;
+    assert_eq!(resolved_in, base);
+
+    Ok(())
+}
+
+#[test]
+fn test_bond_package_definition_needs_bond_version() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    create_bond_directory(temp_dir.path())?;
+
+    // The HM2 parser doesn't understand @partition lines, so nothing gets mounted.
+    let partition_manager =
+        PartitionManager::from_game(temp_dir.path().to_path_buf(), WoaVersion::HM2, true)?;
+    assert!(partition_manager.partitions.is_empty());
+
+    Ok(())
+}
+
+fn detect(
+    encrypt: Encryption,
+    package_definition: &str,
+    version: PackageVersion,
+) -> Result<GameDetection, Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    create_retail_directory(temp_dir.path(), encrypt, package_definition, version)?;
+    Ok(GameDetection::from_retail_directory(temp_dir.path())?)
+}
+
+#[test]
+fn test_detect_bond() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    create_bond_directory(temp_dir.path())?;
+
+    let detection = GameDetection::from_retail_directory(temp_dir.path())?;
+    assert_eq!(detection.version, WoaVersion::Bond);
+    assert_eq!(detection.confidence, DetectionConfidence::High);
+    assert!(detection
+        .evidence
+        .contains(&DetectionEvidence::ThumbsEncryption(EncryptionKey::Bond)));
+    assert!(detection
+        .evidence
+        .contains(&DetectionEvidence::PackageMagic(*b"2KPR")));
+
+    let partition_manager = PartitionManager::from_game_auto(temp_dir.path().to_path_buf(), true)?;
+    assert_eq!(partition_manager.partitions.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_detect_woa_games() -> Result<(), Box<dyn std::error::Error>> {
+    let cases = [
+        (PARTITIONED_PACKAGE_DEFINITION, PackageVersion::RPKGv2, WoaVersion::HM3),
+        (CHUNK_PACKAGE_DEFINITION, PackageVersion::RPKGv1, WoaVersion::HM2),
+        (LEGACY_CHUNK_PACKAGE_DEFINITION, PackageVersion::RPKGv1, WoaVersion::HM2016),
+    ];
+
+    for (package_definition, package_version, game_version) in cases {
+        let detection = detect(Xtea::encrypt_woa_text_file, package_definition, package_version)?;
+        assert_eq!(detection.version, game_version);
+        assert_eq!(detection.candidates, vec![game_version]);
+        assert_eq!(detection.confidence, DetectionConfidence::High);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_detect_ambiguous_and_conflicting() -> Result<(), Box<dyn std::error::Error>> {
+    // Without any encryption @partition lines fit both HM3 and Bond.
+    let detection = detect(no_encryption, PARTITIONED_PACKAGE_DEFINITION, PackageVersion::RPKGv2)?;
+    assert_eq!(detection.version, WoaVersion::HM3);
+    assert_eq!(detection.candidates, vec![WoaVersion::HM3, WoaVersion::Bond]);
+    assert_eq!(detection.confidence, DetectionConfidence::Low);
+
+    // HM2 style partitions can't live inside RPKGv2 packages.
+    assert!(detect(no_encryption, CHUNK_PACKAGE_DEFINITION, PackageVersion::RPKGv2).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_detect_without_application_section() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    fs::write(temp_dir.path().join("thumbs.dat"), "[hitman5]\nPROJECT_PATH=HITMAN3\n")?;
+
+    assert!(matches!(
+        GamePaths::from_retail_directory(temp_dir.path().to_path_buf()),
+        Err(GameDiscoveryError::NoProjectPath)
+    ));
+    assert!(matches!(
+        GameDetection::from_retail_directory(temp_dir.path()),
+        Err(GameDetectionError::GameDiscoveryError(GameDiscoveryError::NoProjectPath))
+    ));
+    assert!(PartitionManager::from_game_auto(temp_dir.path().to_path_buf(), true).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_package_definition_from_file_auto() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("packagedefinition.txt");
+    fs::write(&path, Xtea::encrypt_woa_text_file(CHUNK_PACKAGE_DEFINITION.to_string())?)?;
+
+    let source = PackageDefinitionSource::from_file_auto(path)?;
+    assert!(matches!(source, PackageDefinitionSource::HM2(_)));
+    assert_eq!(source.read()?.len(), 2);
+
+    Ok(())
+}
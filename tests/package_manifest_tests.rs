@@ -0,0 +1,129 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use common::path_rrid;
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::package_manifest::{PackageManifest, PackageManifestError};
+use rpkg_rs::resource::pdefs::PartitionId;
+use rpkg_rs::resource::resource_package::{
+    PackageVersion, ReferenceType, ResourcePackage, ResourceReferenceFlags, ResourceReferenceFlagsLegacy,
+    ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use std::fs;
+use std::str::FromStr;
+
+const ENTITY: &str = "[assembly:/_pro/scenes/test.entity].pc_entitytype";
+
+fn build_patch(legacy_references: bool) -> Result<ResourcePackage, Box<dyn std::error::Error>> {
+    let flags = match legacy_references {
+        true => ResourceReferenceFlags::Legacy(ResourceReferenceFlagsLegacy::new().with_install_dependency(true)),
+        false => ResourceReferenceFlags::Standard(
+            ResourceReferenceFlagsStandard::new().with_reference_type(ReferenceType::WEAK).with_runtime_acquired(true),
+        ),
+    };
+
+    let mut entity = PackageResourceBuilder::from_memory(path_rrid(ENTITY)?, "TEMP", vec![1; 2048], Some(4), true)?;
+    entity.with_memory_requirements(4096, 12);
+    entity.with_references([(RuntimeResourceID::from(2), flags), (RuntimeResourceID::from(1), flags)]);
+
+    let mut builder = PackageBuilder::new_with_patch_id(PartitionId::from_str("chunk3")?, PatchId::Patch(2));
+    if legacy_references {
+        builder.use_legacy_references();
+    }
+    builder.with_resource(entity);
+    builder.with_resource(PackageResourceBuilder::from_memory(RuntimeResourceID::from(1), "TEXT", vec![2; 64], None, false)?);
+    builder.with_unneeded_resource(RuntimeResourceID::from(3));
+    Ok(ResourcePackage::from_memory(builder.build_to_vec(PackageVersion::RPKGv2)?, true)?)
+}
+
+#[test]
+fn test_manifest_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    for legacy_references in [false, true] {
+        let temp_dir = tempfile::tempdir()?;
+        let package = build_patch(legacy_references)?;
+
+        let manifest = PackageManifest::from_resource_package(&package, temp_dir.path())?;
+        assert_eq!(manifest.partition_id, PartitionId::from_str("chunk3")?);
+        assert_eq!(manifest.patch_id, PatchId::Patch(2));
+        assert_eq!(manifest.legacy_references, legacy_references);
+        assert_eq!(manifest.unneeded_resources, vec![RuntimeResourceID::from(3)]);
+        assert_eq!(fs::read(temp_dir.path().join(&manifest.resources[0].file))?, vec![1; 2048]);
+
+        let json = serde_json::to_string_pretty(&manifest)?;
+        let parsed: PackageManifest = serde_json::from_str(&json)?;
+        assert_eq!(parsed, manifest);
+
+        let rebuilt = PackageBuilder::from_manifest(&parsed, temp_dir.path())?.build_to_vec(PackageVersion::RPKGv2)?;
+        let rebuilt = ResourcePackage::from_memory(rebuilt, true)?;
+        assert_eq!(rebuilt.resources()?.len(), 2);
+        assert_eq!(rebuilt.unneeded_resource_ids(), package.unneeded_resource_ids());
+        assert_eq!(rebuilt.has_legacy_references()?, legacy_references);
+        for (rrid, info) in package.resources()? {
+            let rebuilt_info = rebuilt.resource_info(rrid)?;
+            assert_eq!(rebuilt_info.data_type(), info.data_type());
+            assert_eq!(rebuilt_info.references(), info.references());
+            assert_eq!(rebuilt_info.system_memory_requirement(), info.system_memory_requirement());
+            assert_eq!(rebuilt_info.video_memory_requirement(), info.video_memory_requirement());
+            assert_eq!((rebuilt_info.is_compressed(), rebuilt_info.is_scrambled()), (info.is_compressed(), info.is_scrambled()));
+            assert_eq!(rebuilt.read_resource(rrid)?, package.read_resource(rrid)?);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_manifest_from_json() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    fs::write(temp_dir.path().join("entity.temp"), vec![7; 100])?;
+    let manifest: PackageManifest = serde_json::from_str(&format!(
+        r#"{{
+            "partition_id": "chunk0",
+            "resources": [
+                {{
+                    "path": "{}",
+                    "type": "TEMP",
+                    "file": "entity.temp",
+                    "references": [{{ "rrid": "0x0000000000000002", "flags": {{ "Standard": 31 }} }}]
+                }}
+            ]
+        }}"#,
+        ENTITY.replace('\\', "\\\\")
+    ))?;
+    assert_eq!(manifest.patch_id, PatchId::Base);
+
+    let package = PackageBuilder::from_manifest(&manifest, temp_dir.path())?.build_to_vec(PackageVersion::RPKGv2)?;
+    let package = ResourcePackage::from_memory(package, false)?;
+    let info = package.resource_info(&path_rrid(ENTITY)?)?;
+    assert_eq!(info.size(), 100);
+    assert_eq!(info.system_memory_requirement(), 100);
+    assert_eq!(info.video_memory_requirement(), u32::MAX);
+    assert_eq!(info.references()[0].0, RuntimeResourceID::from(2));
+
+    let mut missing_id = manifest.clone();
+    missing_id.resources[0].path = None;
+    assert!(matches!(
+        PackageBuilder::from_manifest(&missing_id, temp_dir.path()),
+        Err(PackageManifestError::MissingResourceId(_))
+    ));
+    assert!(serde_json::from_str::<PackageManifest>(r#"{ "partition_id": "chunk", "resources": [] }"#).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_manifest_with_paths() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let package = build_patch(false)?;
+    let entity = path_rrid(ENTITY)?;
+
+    let resolve = |id: &RuntimeResourceID| (*id == entity).then(|| ENTITY.to_string());
+    let manifest = PackageManifest::from_resource_package_with_paths(&package, temp_dir.path(), resolve)?;
+    let resource = &manifest.resources[0];
+    assert_eq!(resource.path.as_deref(), Some(ENTITY));
+    assert_eq!(resource.file.to_str(), Some("assembly/_pro/scenes/test.entity.pc_entitytype"));
+    assert_eq!(manifest.resources[1].file.to_str(), Some(format!("unknown/TEXT/{}", RuntimeResourceID::from(1)).as_str()));
+    assert!(temp_dir.path().join(&resource.file).exists());
+    Ok(())
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::{build_resources, rrid, storage_variants, test_data};
+use rpkg_rs::resource::package_builder::PackageResourceBuilder;
+use rpkg_rs::resource::package_patcher::{PackagePatcher, PackagePatcherError};
+use rpkg_rs::resource::resource_package::{
+    PackageVersion, ResourcePackage, ResourceReferenceFlags, ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use std::path::Path;
+
+fn reference(id: u64) -> (RuntimeResourceID, ResourceReferenceFlags) {
+    (rrid(id), ResourceReferenceFlags::Standard(ResourceReferenceFlagsStandard::new().with_runtime_acquired(true)))
+}
+
+/// Writes the storage variants to the given path, the first of which has a reference.
+fn write_test_package(path: &Path, version: PackageVersion, patch_id: PatchId) -> Result<(), Box<dyn std::error::Error>> {
+    let mut resources = storage_variants(4096)?;
+    resources[0].with_references([reference(2)]);
+    std::fs::write(path, build_resources(version, patch_id, resources, &[])?)?;
+    Ok(())
+}
+
+/// Checks that the package at the given path is valid and contains the given resources.
+fn assert_contents(path: &Path, expected: &[(u64, Vec<u8>)]) -> Result<(), Box<dyn std::error::Error>> {
+    let package = ResourcePackage::from_file(path)?;
+    assert_eq!(package.verify()?, vec![]);
+    assert_eq!(package.resource_count(), expected.len());
+    for (id, data) in expected {
+        assert_eq!(&package.read_resource(&rrid(*id))?, data, "resource {id}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_replace_resource() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let cases = [
+        (PackageVersion::RPKGv1, PatchId::Base, "chunk0.rpkg"),
+        (PackageVersion::RPKGv2, PatchId::Patch(1), "chunk0patch1.rpkg"),
+    ];
+    for (version, patch_id, file_name) in cases {
+        let path = temp_dir.path().join(file_name);
+        write_test_package(&path, version, patch_id)?;
+        let mut expected = (0..4).map(|i| (i as u64 + 1, test_data(i, 4096))).collect::<Vec<_>>();
+
+        // Replace a compressed resource with larger uncompressed data and keep its size and references otherwise.
+        let mut patcher = PackagePatcher::open(&path)?;
+        let mut resource = PackageResourceBuilder::from_memory(rrid(1), "TEMP", test_data(9, 10000), None, true)?;
+        resource.with_references([reference(3)]);
+        patcher.replace_resource(resource)?;
+        patcher.replace_resource(PackageResourceBuilder::from_memory(rrid(4), "TEMP", test_data(8, 100), Some(9), false)?)?;
+        expected[0].1 = test_data(9, 10000);
+        expected[3].1 = test_data(8, 100);
+        assert_contents(&path, &expected)?;
+
+        let package = ResourcePackage::from_file(&path)?;
+        let info = package.resource_info(&rrid(1))?;
+        assert_eq!(info.references(), &vec![reference(3)]);
+        assert!(info.is_scrambled() && !info.is_compressed());
+        assert!(package.resource_info(&rrid(4))?.is_compressed());
+
+        let missing = PackageResourceBuilder::from_memory(rrid(5), "TEMP", vec![1], None, false)?;
+        assert!(matches!(
+            patcher.replace_resource(missing),
+            Err(PackagePatcherError::ResourceNotFound(id)) if id == rrid(5)
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_replace_resource_with_different_references() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("chunk0.rpkg");
+    write_test_package(&path, PackageVersion::RPKGv2, PatchId::Base)?;
+    let mut expected = (0..4).map(|i| (i as u64 + 1, test_data(i, 4096))).collect::<Vec<_>>();
+
+    // Growing the metadata table moves the data of the first resources out of its way.
+    let mut patcher = PackagePatcher::open(&path)?;
+    let mut resource = PackageResourceBuilder::from_memory(rrid(3), "TEMP", test_data(7, 64), None, false)?;
+    resource.with_references((10..200).map(reference));
+    patcher.replace_resource(resource)?;
+    expected[2].1 = test_data(7, 64);
+    assert_contents(&path, &expected)?;
+    let package = ResourcePackage::from_file(&path)?;
+    assert_eq!(package.resource_info(&rrid(3))?.references().len(), 190);
+    assert_eq!(package.resource_info(&rrid(1))?.references(), &vec![reference(2)]);
+
+    // Shrinking it leaves the data where it is.
+    let resource = PackageResourceBuilder::from_memory(rrid(1), "TEMP", test_data(6, 64), None, false)?;
+    patcher.replace_resource(resource)?;
+    expected[0].1 = test_data(6, 64);
+    assert_contents(&path, &expected)?;
+    assert!(ResourcePackage::from_file(&path)?.resource_info(&rrid(1))?.references().is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_replace_resource_growing_tables_past_the_end() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("chunk0.rpkg");
+    let mut resources = storage_variants(4)?;
+    resources[0].with_references([reference(2)]);
+    std::fs::write(&path, build_resources(PackageVersion::RPKGv2, PatchId::Base, resources, &[])?)?;
+    let mut expected = (0..4).map(|i| (i as u64 + 1, test_data(i, 4))).collect::<Vec<_>>();
+
+    // The grown tables end after the data of all resources, the new and moved data has to follow them.
+    let original_size = std::fs::metadata(&path)?.len();
+    let mut patcher = PackagePatcher::open(&path)?;
+    let mut resource = PackageResourceBuilder::from_memory(rrid(2), "TEMP", test_data(5, 4), None, false)?;
+    resource.with_references((10..20).map(reference));
+    patcher.replace_resource(resource)?;
+    expected[1].1 = test_data(5, 4);
+    assert_contents(&path, &expected)?;
+    assert_eq!(ResourcePackage::from_file(&path)?.resource_info(&rrid(2))?.references().len(), 10);
+    assert!(std::fs::metadata(&path)?.len() > original_size);
+    Ok(())
+}
+
+#[test]
+fn test_compact() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("chunk0patch1.rpkg");
+    write_test_package(&path, PackageVersion::RPKGv2, PatchId::Patch(1))?;
+    let original_size = std::fs::metadata(&path)?.len();
+    let mut expected = (0..4).map(|i| (i as u64 + 1, test_data(i, 4096))).collect::<Vec<_>>();
+
+    let mut patcher = PackagePatcher::open(&path)?;
+    assert_eq!(patcher.unused_size()?, 0);
+    for id in [2, 2, 3] {
+        let data = test_data(id as u8 + 20, 4096);
+        patcher.replace_resource(PackageResourceBuilder::from_memory(rrid(id), "TEMP", data.clone(), None, false)?)?;
+        expected[id as usize - 1].1 = data;
+    }
+    assert!(patcher.unused_size()? >= 4096 * 2);
+
+    let unused = patcher.unused_size()?;
+    let patched_size = std::fs::metadata(&path)?.len();
+    assert_eq!(patcher.compact()?, unused);
+    assert_eq!(patcher.unused_size()?, 0);
+    assert_eq!(std::fs::metadata(&path)?.len(), patched_size - unused);
+    assert!(std::fs::metadata(&path)?.len() < original_size + 4096 * 2);
+    assert_contents(&path, &expected)?;
+
+    // The patcher keeps working on the compacted package.
+    patcher.replace_resource(PackageResourceBuilder::from_memory(rrid(4), "TEMP", vec![3; 10], None, true)?)?;
+    expected[3].1 = vec![3; 10];
+    assert_contents(&path, &expected)?;
+    assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+    Ok(())
+}
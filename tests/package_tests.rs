@@ -0,0 +1,178 @@
+mod common;
+
+use common::{build_test_package, test_data};
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::resource_package::{
+    ChunkType, PackageVersion, ResourcePackage, ResourcePackageSource,
+};
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use std::borrow::Cow;
+use std::io::{Read, Seek, SeekFrom};
+
+#[test]
+fn test_mapped_file_reads() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let package_path = temp_dir.path().join("chunk0.rpkg");
+    std::fs::write(&package_path, build_test_package(PackageVersion::RPKGv2)?)?;
+
+    let package = ResourcePackage::from_file(&package_path)?;
+    assert!(matches!(
+        package.source(),
+        Some(ResourcePackageSource::MappedFile(_, _))
+    ));
+
+    for i in 0..4u64 {
+        let rrid = RuntimeResourceID::from(i + 1);
+        let data = package.read_resource_borrowed(&rrid)?;
+        assert_eq!(data.as_ref(), test_data(i as u8, 4096).as_slice());
+        assert_eq!(package.read_resource(&rrid)?, test_data(i as u8, 4096));
+    }
+
+    // Plain resources are served straight from the map.
+    let plain = package.read_resource_borrowed(&RuntimeResourceID::from(1))?;
+    assert!(matches!(plain, Cow::Borrowed(_)));
+    assert_eq!(
+        package.raw_resource_data(&RuntimeResourceID::from(1))?,
+        test_data(0, 4096).as_slice()
+    );
+
+    // Everything else has to be transformed first.
+    for i in 2..=4u64 {
+        let data = package.read_resource_borrowed(&RuntimeResourceID::from(i))?;
+        assert!(matches!(data, Cow::Owned(_)));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_reads_are_borrowed() -> Result<(), Box<dyn std::error::Error>> {
+    let package = ResourcePackage::from_memory(build_test_package(PackageVersion::RPKGv1)?, false)?;
+
+    let plain = package.read_resource_borrowed(&RuntimeResourceID::from(1))?;
+    assert!(matches!(plain, Cow::Borrowed(_)));
+    assert_eq!(plain.as_ref(), test_data(0, 4096).as_slice());
+
+    Ok(())
+}
+
+/// Data which compresses into long literal runs, long matches and overlapping matches.
+fn compressible_data() -> Vec<u8> {
+    let mut data = Vec::new();
+    for block in 0..64u32 {
+        data.extend((0..1000u32).map(|j| (j.wrapping_mul(2654435761) >> 13) as u8 ^ block as u8));
+        data.extend(std::iter::repeat_n(block as u8, 70_000));
+        data.extend(b"abcabcabcabcabcabcabc".iter().cycle().take(5_000));
+    }
+    data
+}
+
+#[test]
+fn test_resource_reader() -> Result<(), Box<dyn std::error::Error>> {
+    let package = ResourcePackage::from_memory(build_test_package(PackageVersion::RPKGv2)?, false)?;
+
+    for i in 0..4u64 {
+        let rrid = RuntimeResourceID::from(i + 1);
+        let mut reader = package.resource_reader(&rrid)?;
+        assert_eq!(reader.size(), 4096);
+
+        // Read with an awkward buffer size, so the scrambling key has to wrap between reads.
+        let mut data = vec![];
+        let mut buffer = [0u8; 7];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            data.extend_from_slice(&buffer[..read]);
+        }
+
+        assert_eq!(data, test_data(i as u8, 4096));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_resource_reader_large_compressed() -> Result<(), Box<dyn std::error::Error>> {
+    let original = compressible_data();
+    let rrid = RuntimeResourceID::from(1);
+
+    let mut builder = PackageBuilder::new(0, ChunkType::Standard);
+    builder.with_resource(PackageResourceBuilder::from_memory(
+        rrid,
+        "TEMP",
+        original.clone(),
+        Some(9),
+        true,
+    )?);
+
+    let temp_dir = tempfile::tempdir()?;
+    let package_path = temp_dir.path().join("chunk0.rpkg");
+    builder.build_to_file(PackageVersion::RPKGv2, &package_path)?;
+    let package = ResourcePackage::from_file(&package_path)?;
+
+    assert!(package.resources()?[&rrid].compressed_size().unwrap() < original.len() as u32);
+
+    let mut reader = package.resource_reader(&rrid)?;
+    assert!(reader.is_compressed());
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    assert_eq!(data.len(), original.len());
+    assert!(data == original);
+
+    // Seek backwards and forwards through the compressed stream.
+    let mut chunk = [0u8; 100];
+    for position in [3_000_000u64, 12, 4_500_000, 70_999] {
+        reader.seek(SeekFrom::Start(position))?;
+        reader.read_exact(&mut chunk)?;
+        let start = position as usize;
+        assert_eq!(&chunk[..], &original[start..start + 100]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_resource_reader_seek() -> Result<(), Box<dyn std::error::Error>> {
+    let package = ResourcePackage::from_memory(build_test_package(PackageVersion::RPKGv1)?, false)?;
+    let expected = test_data(2, 4096);
+
+    // The scrambled, uncompressed resource.
+    let mut reader = package.resource_reader(&RuntimeResourceID::from(3))?;
+    assert!(!reader.is_compressed());
+
+    let mut chunk = [0u8; 13];
+    for position in [4000u64, 5, 1023, 0] {
+        reader.seek(SeekFrom::Start(position))?;
+        reader.read_exact(&mut chunk)?;
+        let start = position as usize;
+        assert_eq!(&chunk[..], &expected[start..start + 13]);
+    }
+
+    assert_eq!(reader.seek(SeekFrom::End(-10))?, 4086);
+    assert!(reader.seek(SeekFrom::Current(100)).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_rebuilding_a_mapped_package() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let package_path = temp_dir.path().join("chunk0.rpkg");
+    std::fs::write(&package_path, build_test_package(PackageVersion::RPKGv2)?)?;
+    let package = ResourcePackage::from_file_lazy(&package_path)?;
+
+    // Rebuilding the package from itself replaces the file, the mapped package keeps reading the old one.
+    let mut builder = PackageBuilder::from_resource_package(&package)?;
+    builder.with_resource(PackageResourceBuilder::from_memory(RuntimeResourceID::from(1), "TEMP", vec![7; 16], None, false)?);
+    builder.build_to_file(PackageVersion::RPKGv2, &package_path)?;
+
+    assert_eq!(package.read_resource(&RuntimeResourceID::from(1))?, test_data(0, 4096));
+    assert_eq!(package.load_metadata()?.len(), 4);
+    let rebuilt = ResourcePackage::from_file(&package_path)?;
+    assert_eq!(rebuilt.read_resource(&RuntimeResourceID::from(1))?, vec![7; 16]);
+    assert_eq!(rebuilt.read_resource(&RuntimeResourceID::from(4))?, test_data(3, 4096));
+    assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+    Ok(())
+}
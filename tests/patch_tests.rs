@@ -0,0 +1,98 @@
+mod common;
+
+use common::rrid;
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::pdefs::{PartitionId, PartitionInfo};
+use rpkg_rs::resource::resource_package::{
+    PackageVersion, ResourcePackage, ResourceReferenceFlags, ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::{PatchId, ResourcePartition};
+use std::path::Path;
+use std::str::FromStr;
+
+fn resource(
+    id: u64,
+    data: Vec<u8>,
+    compression_level: Option<i32>,
+    should_scramble: bool,
+) -> Result<PackageResourceBuilder, Box<dyn std::error::Error>> {
+    Ok(PackageResourceBuilder::from_memory(rrid(id), "TEMP", data, compression_level, should_scramble)?)
+}
+
+fn mount(runtime_path: &Path) -> Result<ResourcePartition, Box<dyn std::error::Error>> {
+    let mut partition_info = PartitionInfo::from_id("chunk0")?;
+    partition_info.set_max_patch_level(9);
+
+    let mut partition = ResourcePartition::new(partition_info);
+    partition.mount_resource_packages_in_partition(runtime_path)?;
+    Ok(partition)
+}
+
+#[test]
+fn test_patch_from_partition() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let partition_id = PartitionId::from_str("chunk0")?;
+
+    let mut base = PackageBuilder::new_with_patch_id(partition_id.clone(), PatchId::Base);
+    base.with_resource(resource(1, vec![1; 64], None, false)?)
+        .with_resource(resource(2, vec![2; 64], None, false)?)
+        .with_resource(resource(3, vec![3; 64], None, false)?)
+        .with_resource(resource(5, vec![5; 64], Some(4), true)?);
+    base.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+    let mut patch = PackageBuilder::new_with_patch_id(partition_id, PatchId::Patch(1));
+    patch.with_resource(resource(2, vec![6; 64], None, false)?);
+    patch.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+    let partition = mount(temp_dir.path())?;
+
+    let mut reference_changed = resource(5, vec![5; 64], None, false)?;
+    reference_changed.with_reference(
+        rrid(1),
+        ResourceReferenceFlags::Standard(ResourceReferenceFlagsStandard::new()),
+    );
+
+    let desired = vec![
+        // Unchanged, even though it is now compressed and scrambled.
+        resource(1, vec![1; 64], Some(4), true)?,
+        // Matches the version from patch 1, not the one from the base package.
+        resource(2, vec![6; 64], None, false)?,
+        resource(4, vec![4; 64], None, false)?,
+        reference_changed,
+    ];
+
+    let patch = PackageBuilder::patch_from_partition(&partition, desired)?;
+    patch.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+    let patch_path = temp_dir.path().join("chunk0patch2.rpkg");
+    let package = ResourcePackage::from_file(&patch_path)?;
+    assert_eq!(package.resources()?.keys().copied().collect::<Vec<_>>(), vec![rrid(4), rrid(5)]);
+    assert_eq!(package.unneeded_resource_ids(), vec![&rrid(3)]);
+
+    let patched = mount(temp_dir.path())?;
+    let mut mounted = patched.latest_resources()?.iter().map(|(info, _)| *info.rrid()).collect::<Vec<_>>();
+    mounted.sort_by_key(|rrid| u64::from(*rrid));
+    assert_eq!(mounted, vec![rrid(1), rrid(2), rrid(4), rrid(5)]);
+    assert_eq!(patched.read_resource(&rrid(2))?, vec![6; 64]);
+    assert_eq!(patched.get_resource_info(&rrid(5))?.references().len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_patch_from_unchanged_partition() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+
+    let mut base = PackageBuilder::new_with_patch_id(PartitionId::from_str("chunk0")?, PatchId::Base);
+    base.with_resource(resource(1, vec![1; 64], Some(4), false)?);
+    base.build_to_file(PackageVersion::RPKGv2, temp_dir.path())?;
+
+    let partition = mount(temp_dir.path())?;
+    let patch = PackageBuilder::patch_from_partition(&partition, vec![resource(1, vec![1; 64], None, true)?])?;
+
+    let package = ResourcePackage::from_memory(patch.build_to_vec(PackageVersion::RPKGv2)?, true)?;
+    assert!(package.resources()?.is_empty());
+    assert!(package.unneeded_resource_ids().is_empty());
+
+    Ok(())
+}
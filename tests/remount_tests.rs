@@ -0,0 +1,164 @@
+mod common;
+
+use common::{rrid, write_package};
+use rpkg_rs::resource::partition_manager::{PartitionManager, PartitionManagerError};
+use rpkg_rs::resource::pdefs::{PackageDefinitionSource, PartitionId, PartitionInfo};
+use rpkg_rs::resource::resource_partition::{PatchId, RemountChanges, ResourcePartition};
+use std::str::FromStr;
+
+fn partition_info(id: &str) -> Result<PartitionInfo, Box<dyn std::error::Error>> {
+    let mut partition_info = PartitionInfo::from_id(id)?;
+    partition_info.set_max_patch_level(9);
+    Ok(partition_info)
+}
+
+fn changes(mounted: &[PatchId], remounted: &[PatchId], unmounted: &[PatchId]) -> RemountChanges {
+    RemountChanges {
+        mounted: mounted.to_vec(),
+        remounted: remounted.to_vec(),
+        unmounted: unmounted.to_vec(),
+    }
+}
+
+#[test]
+fn test_remount_partition() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let runtime_path = temp_dir.path();
+    write_package(runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 16]), (2, vec![2; 16])], &[])?;
+    write_package(runtime_path, "chunk0", PatchId::Patch(1), &[(3, vec![3; 16])], &[1])?;
+
+    let mut partition = ResourcePartition::new(partition_info("chunk0")?);
+    partition.mount_resource_packages_in_partition(runtime_path)?;
+    assert!(!partition.contains(&rrid(1)));
+    assert!(partition.remount(runtime_path)?.is_empty());
+
+    write_package(runtime_path, "chunk0", PatchId::Patch(2), &[(1, vec![4; 32])], &[2])?;
+    assert_eq!(partition.remount(runtime_path)?, changes(&[PatchId::Patch(2)], &[], &[]));
+    assert_eq!(partition.read_resource(&rrid(1))?, vec![4; 32]);
+    assert!(!partition.contains(&rrid(2)));
+
+    std::fs::remove_file(runtime_path.join("chunk0patch1.rpkg"))?;
+    write_package(runtime_path, "chunk0", PatchId::Patch(2), &[(1, vec![5; 48])], &[])?;
+    assert_eq!(partition.remount(runtime_path)?, changes(&[], &[PatchId::Patch(2)], &[PatchId::Patch(1)]));
+    assert_eq!(partition.read_resource(&rrid(1))?, vec![5; 48]);
+    assert_eq!(partition.read_resource(&rrid(2))?, vec![2; 16]);
+    assert!(!partition.contains(&rrid(3)));
+    assert_eq!(partition.num_patches(), 1);
+
+    std::fs::remove_file(runtime_path.join("chunk0.rpkg"))?;
+    assert!(partition.remount(runtime_path).is_err());
+    assert_eq!(partition.read_resource(&rrid(1))?, vec![5; 48]);
+    Ok(())
+}
+
+#[test]
+fn test_mount_and_unmount_single_patches() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let runtime_path = temp_dir.path();
+    write_package(runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 16]), (2, vec![2; 16])], &[])?;
+    write_package(runtime_path, "chunk0", PatchId::Patch(2), &[(2, vec![6; 16])], &[])?;
+
+    let mut partition = ResourcePartition::new(partition_info("chunk0")?);
+    partition.mount_resource_packages_in_partition(runtime_path)?;
+
+    write_package(runtime_path, "chunk0", PatchId::Patch(1), &[(2, vec![3; 16]), (4, vec![4; 16])], &[1])?;
+    partition.mount_patch(runtime_path, PatchId::Patch(1))?;
+    assert_eq!(partition.read_resource(&rrid(2))?, vec![6; 16]);
+    assert_eq!(partition.read_resource(&rrid(4))?, vec![4; 16]);
+    assert!(!partition.contains(&rrid(1)));
+
+    write_package(runtime_path, "chunk0", PatchId::Patch(3), &[(1, vec![7; 16])], &[])?;
+    partition.mount_patch(runtime_path, PatchId::Patch(3))?;
+    assert_eq!(partition.read_resource(&rrid(1))?, vec![7; 16]);
+    assert!(partition.remount(runtime_path)?.is_empty());
+
+    assert!(partition.unmount_patch(PatchId::Patch(3)).is_some());
+    assert!(!partition.contains(&rrid(1)));
+    assert!(partition.unmount_patch(PatchId::Patch(1)).is_some());
+    assert_eq!(partition.read_resource(&rrid(1))?, vec![1; 16]);
+    assert!(!partition.contains(&rrid(4)));
+    assert!(partition.unmount_patch(PatchId::Patch(1)).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_remount_partitions_in_manager() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let runtime_path = temp_dir.path();
+    write_package(runtime_path, "chunk1", PatchId::Base, &[(10, vec![10; 16])], &[])?;
+
+    let mut partition_manager = PartitionManager::new(
+        runtime_path.to_path_buf(),
+        &PackageDefinitionSource::Custom(vec![partition_info("chunk0")?, partition_info("chunk1")?]),
+    )?;
+    partition_manager.mount_partitions(|_, _| {})?;
+    assert_eq!(partition_manager.partitions.len(), 1);
+
+    let chunk0 = PartitionId::from_str("chunk0")?;
+    let chunk1 = PartitionId::from_str("chunk1")?;
+    write_package(runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 16])], &[])?;
+    write_package(runtime_path, "chunk0", PatchId::Patch(1), &[(2, vec![2; 16])], &[])?;
+    assert_eq!(
+        partition_manager.remount_partition(&chunk0)?,
+        changes(&[PatchId::Base, PatchId::Patch(1)], &[], &[])
+    );
+    let ids = partition_manager.partitions.iter().map(|p| p.partition_info().id.clone()).collect::<Vec<_>>();
+    assert_eq!(ids, vec![chunk0.clone(), chunk1.clone()]);
+    assert_eq!(partition_manager.read_resource_from(chunk0.clone(), rrid(2))?, vec![2; 16]);
+
+    std::fs::remove_file(runtime_path.join("chunk1.rpkg"))?;
+    assert_eq!(partition_manager.remount_partition(&chunk1)?, changes(&[], &[], &[PatchId::Base]));
+    assert!(partition_manager.find_partition(chunk1.clone()).is_none());
+    assert!(partition_manager.remount_partition(&chunk1)?.is_empty());
+
+    assert!(matches!(
+        partition_manager.remount_partition(&PartitionId::from_str("dlc3")?),
+        Err(PartitionManagerError::PartitionNotFound(_))
+    ));
+
+    assert!(partition_manager.unmount_partition(&chunk0).is_some());
+    assert!(!partition_manager.resource_mounted(&rrid(1)));
+    Ok(())
+}
+
+#[test]
+fn test_update_partition_infos() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let runtime_path = temp_dir.path();
+    write_package(runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 16])], &[])?;
+    write_package(runtime_path, "chunk0", PatchId::Patch(1), &[(2, vec![2; 16])], &[])?;
+    write_package(runtime_path, "chunk1", PatchId::Base, &[(10, vec![10; 16])], &[])?;
+    write_package(runtime_path, "chunk2", PatchId::Base, &[(20, vec![20; 16])], &[])?;
+
+    let mut partition_manager = PartitionManager::new(
+        runtime_path.to_path_buf(),
+        &PackageDefinitionSource::Custom(vec![partition_info("chunk0")?, partition_info("chunk1")?]),
+    )?;
+    partition_manager.mount_partitions(|_, _| {})?;
+
+    let mut chunk0 = partition_info("chunk0")?;
+    chunk0.set_max_patch_level(0);
+    let chunk0_id = chunk0.id.clone();
+    let chunk2_id = PartitionId::from_str("chunk2")?;
+    let changes_by_partition = partition_manager.update_partition_infos(vec![chunk0, partition_info("chunk2")?])?;
+    assert_eq!(
+        changes_by_partition,
+        vec![
+            (chunk0_id.clone(), changes(&[], &[PatchId::Base], &[PatchId::Patch(1)])),
+            (PartitionId::from_str("chunk1")?, changes(&[], &[], &[PatchId::Base])),
+            (chunk2_id.clone(), changes(&[PatchId::Base], &[], &[])),
+        ]
+    );
+
+    let ids = partition_manager.partitions.iter().map(|p| p.partition_info().id.clone()).collect::<Vec<_>>();
+    assert_eq!(ids, vec![chunk0_id, chunk2_id]);
+    assert!(!partition_manager.resource_mounted(&rrid(2)));
+    assert!(!partition_manager.resource_mounted(&rrid(10)));
+    assert!(partition_manager.resource_mounted(&rrid(20)));
+
+    assert!(matches!(
+        partition_manager.reload_package_definition(),
+        Err(PartitionManagerError::NoPackageDefinitionFile)
+    ));
+    Ok(())
+}
@@ -0,0 +1,166 @@
+#![cfg(feature = "path-list")]
+
+mod common;
+
+use common::{resource, rrid, write_resources};
+use rpkg_rs::misc::hash_path_list::PathList;
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::{PackageDefinitionSource, PartitionId, PartitionInfo};
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::resource_vfs::{ResourceVfs, VfsDirEntry, VfsError, VfsFileType, VfsStat};
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+const ENTITY: &str = "[assembly:/_pro/scenes/test.entity].pc_entitytype";
+const BLUEPRINT: &str = "[[assembly:/_pro/scenes/test.brick].pc_entitytype](dx12).pc_entityblueprint";
+const TEXTURE: &str = "[assembly:/_pro/textures/wall.texture].pc_tex";
+
+fn mount_game(runtime_path: &Path) -> Result<PartitionManager, Box<dyn std::error::Error>> {
+    let chunk0 = vec![resource(1, "TEMP", vec![1; 64])?, resource(2, "TBLU", vec![2; 64])?, resource(3, "TEXT", vec![3; 64])?];
+    write_resources(runtime_path, "chunk0", PatchId::Base, chunk0, &[])?;
+    write_resources(runtime_path, "chunk0", PatchId::Patch(1), vec![resource(2, "TBLU", vec![4; 80])?], &[])?;
+    let chunk1 = vec![resource(1, "TEMP", vec![5; 96])?, resource(4, "TEMP", vec![6; 16])?];
+    write_resources(runtime_path, "chunk1", PatchId::Base, chunk1, &[])?;
+
+    let mut chunk0 = PartitionInfo::from_id("chunk0")?;
+    chunk0.set_max_patch_level(9);
+    let mut chunk1 = PartitionInfo::from_id("chunk1")?;
+    chunk1.parent = Some(chunk0.id.clone());
+
+    let mut partition_manager =
+        PartitionManager::new(runtime_path.to_path_buf(), &PackageDefinitionSource::Custom(vec![chunk0, chunk1]))?;
+    partition_manager.mount_partitions(|_, _| {})?;
+    Ok(partition_manager)
+}
+
+fn resolve(rrid: &RuntimeResourceID) -> Option<String> {
+    match u64::from(*rrid) {
+        1 => Some(ENTITY.to_string()),
+        2 => Some(BLUEPRINT.to_string()),
+        3 => Some(TEXTURE.to_string()),
+        // Claims the path of another resource and has to fall back to its hash.
+        4 => Some(ENTITY.to_string()),
+        _ => None,
+    }
+}
+
+fn entry(name: &str, file_type: VfsFileType) -> VfsDirEntry {
+    VfsDirEntry {
+        name: name.to_string(),
+        file_type,
+    }
+}
+
+#[test]
+fn test_vfs_layout() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let partition_manager = mount_game(temp_dir.path())?;
+    let vfs = ResourceVfs::new(&partition_manager, resolve)?;
+
+    assert_eq!(vfs.file_count(), 4);
+    assert_eq!(
+        vfs.read_dir("/")?,
+        vec![entry("assembly", VfsFileType::Directory), entry("unknown", VfsFileType::Directory)]
+    );
+    assert_eq!(
+        vfs.read_dir("assembly/_pro")?,
+        vec![entry("scenes", VfsFileType::Directory), entry("textures", VfsFileType::Directory)]
+    );
+    assert_eq!(
+        vfs.read_dir("/assembly/_pro/scenes/")?,
+        vec![
+            entry("test.brick.pc_entitytype(dx12).pc_entityblueprint", VfsFileType::File),
+            entry("test.entity.pc_entitytype", VfsFileType::File),
+        ]
+    );
+    assert_eq!(vfs.read_dir("unknown/TEMP")?, vec![entry(&rrid(4).to_string(), VfsFileType::File)]);
+    assert_eq!(vfs.path_of(&rrid(3)), Some("assembly/_pro/textures/wall.texture.pc_tex"));
+    assert_eq!(vfs.stat("assembly/_pro/scenes")?, VfsStat::Directory { entry_count: 2 });
+
+    assert!(matches!(vfs.read_dir("assembly/_pro/textures/wall.texture.pc_tex"), Err(VfsError::NotADirectory(_))));
+    assert!(matches!(vfs.read("assembly/_pro"), Err(VfsError::IsADirectory(_))));
+    assert!(matches!(vfs.stat("assembly/missing"), Err(VfsError::NotFound(_))));
+    Ok(())
+}
+
+#[test]
+fn test_vfs_respects_partition_layering() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let partition_manager = mount_game(temp_dir.path())?;
+    let vfs = ResourceVfs::new(&partition_manager, resolve)?;
+    let chunk0 = PartitionId::from_str("chunk0")?;
+    let chunk1 = PartitionId::from_str("chunk1")?;
+
+    let VfsStat::File(entity) = vfs.stat("assembly/_pro/scenes/test.entity.pc_entitytype")? else {
+        return Err("the entity should be a file".into());
+    };
+    assert_eq!((entity.partition_id, entity.patch_id, entity.size), (chunk1.clone(), PatchId::Base, 96));
+    assert_eq!(entity.data_type, "TEMP");
+    assert_eq!(vfs.read("assembly/_pro/scenes/test.entity.pc_entitytype")?, vec![5; 96]);
+
+    let blueprint_path = vfs.path_of(&rrid(2)).ok_or("the blueprint should be visible")?;
+    let stat = vfs.stat(blueprint_path)?;
+    assert_eq!(stat.size(), 80);
+    assert!(matches!(stat, VfsStat::File(file) if file.partition_id == chunk0 && file.patch_id == PatchId::Patch(1)));
+
+    let mut data = vec![];
+    vfs.open(blueprint_path)?.read_to_end(&mut data)?;
+    assert_eq!(data, vec![4; 80]);
+
+    let base = ResourceVfs::for_partition(&partition_manager, &chunk0, resolve)?;
+    assert_eq!(base.file_count(), 3);
+    assert_eq!(base.read("assembly/_pro/scenes/test.entity.pc_entitytype")?, vec![1; 64]);
+    assert!(matches!(
+        ResourceVfs::for_partition(&partition_manager, &PartitionId::from_str("chunk5")?, resolve),
+        Err(VfsError::PartitionNotFound(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_vfs_from_path_list() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let partition_manager = mount_game(temp_dir.path())?;
+
+    let mut path_list = PathList::new();
+    path_list.parse_from_str(&format!("{}.TEXT,{TEXTURE}\n{}.TEMP\n", rrid(3), rrid(1)))?;
+    let vfs = ResourceVfs::from_path_list(&partition_manager, &path_list)?;
+
+    // Path lists store platform agnostic paths, without the `pc_` prefix.
+    assert_eq!(vfs.read("assembly/_pro/textures/wall.texture.tex")?, vec![3; 64]);
+    assert_eq!(vfs.read(&format!("unknown/TEMP/{}", rrid(1)))?, vec![5; 96]);
+    assert_eq!(vfs.read(&format!("unknown/TBLU/{}", rrid(2)))?, vec![4; 80]);
+    assert_eq!(vfs.files().count(), 4);
+    Ok(())
+}
+
+#[test]
+fn test_vfs_with_corrupted_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let resources = vec![resource(1, "TEMP", vec![1; 64])?, resource(2, "TEMP", vec![2; 64])?];
+    write_resources(temp_dir.path(), "chunk0", PatchId::Base, resources, &[])?;
+    let package_path = temp_dir.path().join("chunk0.rpkg");
+    let data = std::fs::read(&package_path)?;
+    std::fs::write(&package_path, &data[..0x19 + 2 * 0x14 + 0x10])?;
+
+    // The resources are mounted from the offset table, building the view needs their broken metadata.
+    let mut partition_manager = PartitionManager::new(
+        temp_dir.path().to_path_buf(),
+        &PackageDefinitionSource::Custom(vec![PartitionInfo::from_id("chunk0")?]),
+    )?;
+    partition_manager.set_lazy_metadata(true);
+    partition_manager.mount_partitions(|_, _| {})?;
+    assert!(partition_manager.partitions[0].contains(&rrid(1)));
+
+    assert!(matches!(
+        ResourceVfs::new(&partition_manager, resolve),
+        Err(VfsError::MetadataError(id, _)) if id == rrid(1)
+    ));
+    assert!(matches!(
+        ResourceVfs::for_partition(&partition_manager, &PartitionId::from_str("chunk0")?, resolve),
+        Err(VfsError::MetadataError(..))
+    ));
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use rpkg_rs::resource::package_builder::{PackageBuilder, PackageResourceBuilder};
+use rpkg_rs::resource::pdefs::PartitionId;
+use rpkg_rs::resource::resource_package::{
+    PackageVersion, ReferenceType, ResourcePackage, ResourceReferenceFlags,
+    ResourceReferenceFlagsStandard,
+};
+use rpkg_rs::resource::resource_partition::PatchId;
+use rpkg_rs::resource::runtime_resource_id::RuntimeResourceID;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// A small xorshift generator, so every case can be reproduced from its seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    fn chance(&mut self) -> bool {
+        self.next() & 1 == 1
+    }
+
+    fn rrid(&mut self) -> RuntimeResourceID {
+        RuntimeResourceID::from(self.next() & 0x00FF_FFFF_FFFF_FFFE)
+    }
+
+    fn data(&mut self) -> Vec<u8> {
+        let mut data = vec![];
+        let length = self.below(4096) as usize;
+        while data.len() < length {
+            let byte = self.next() as u8;
+            let run = match self.chance() {
+                true => self.below(64) as usize + 1,
+                false => 1,
+            };
+            data.extend(std::iter::repeat_n(byte, run));
+        }
+        data.truncate(length);
+        data
+    }
+}
+
+struct ResourceSpec {
+    rrid: RuntimeResourceID,
+    data_type: String,
+    data: Vec<u8>,
+    compression_level: Option<i32>,
+    should_scramble: bool,
+    memory_requirements: (u32, u32),
+    references: Vec<(RuntimeResourceID, ResourceReferenceFlags)>,
+}
+
+struct PackageSpec {
+    version: PackageVersion,
+    patch_id: PatchId,
+    legacy_references: bool,
+    resources: Vec<ResourceSpec>,
+    unneeded_resources: Vec<RuntimeResourceID>,
+}
+
+fn random_package(rng: &mut Rng) -> PackageSpec {
+    let version = match rng.chance() {
+        true => PackageVersion::RPKGv1,
+        false => PackageVersion::RPKGv2,
+    };
+    let patch_id = match rng.chance() {
+        true => PatchId::Patch(rng.below(9) as usize + 1),
+        false => PatchId::Base,
+    };
+
+    let mut rrids = HashSet::new();
+    let mut resources = vec![];
+    for _ in 0..rng.below(8) {
+        let rrid = rng.rrid();
+        if !rrids.insert(rrid) {
+            continue;
+        }
+
+        let data_type = (0..4).map(|_| (b'A' + rng.below(26) as u8) as char).collect();
+        let references = (0..rng.below(5))
+            .map(|_| {
+                let reference_type = match rng.below(3) {
+                    0 => ReferenceType::INSTALL,
+                    1 => ReferenceType::NORMAL,
+                    _ => ReferenceType::WEAK,
+                };
+                let flags = ResourceReferenceFlagsStandard::new()
+                    .with_reference_type(reference_type)
+                    .with_runtime_acquired(rng.chance())
+                    .with_language_code(rng.below(0x20) as u8);
+                (rng.rrid(), ResourceReferenceFlags::Standard(flags))
+            })
+            .collect();
+
+        resources.push(ResourceSpec {
+            rrid,
+            data_type,
+            data: rng.data(),
+            compression_level: rng.chance().then(|| rng.below(12) as i32 + 1),
+            should_scramble: rng.chance(),
+            memory_requirements: (rng.next() as u32, rng.next() as u32),
+            references,
+        });
+    }
+
+    let unneeded_resources = match patch_id {
+        PatchId::Patch(_) => (0..rng.below(4)).map(|_| rng.rrid()).collect(),
+        PatchId::Base => vec![],
+    };
+
+    PackageSpec {
+        version,
+        patch_id,
+        legacy_references: rng.chance(),
+        resources,
+        unneeded_resources,
+    }
+}
+
+fn build(spec: &PackageSpec) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut builder = PackageBuilder::new_with_patch_id(PartitionId::from_str("chunk0")?, spec.patch_id);
+    if spec.legacy_references {
+        builder.use_legacy_references();
+    }
+
+    for resource in &spec.resources {
+        let mut package_resource = PackageResourceBuilder::from_memory(
+            resource.rrid,
+            &resource.data_type,
+            resource.data.clone(),
+            resource.compression_level,
+            resource.should_scramble,
+        )?;
+        package_resource.with_memory_requirements(resource.memory_requirements.0, resource.memory_requirements.1);
+        for (rrid, flags) in &resource.references {
+            package_resource.with_reference(*rrid, *flags);
+        }
+        builder.with_resource(package_resource);
+    }
+    builder.with_unneeded_resources(spec.unneeded_resources.iter().copied());
+
+    Ok(builder.build_to_vec(spec.version)?)
+}
+
+fn check_matches_spec(spec: &PackageSpec, package: &ResourcePackage) -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(package.version(), spec.version);
+    assert_eq!(package.verify()?, vec![]);
+    assert_eq!(
+        package.unneeded_resource_ids().into_iter().copied().collect::<Vec<_>>(),
+        spec.unneeded_resources
+    );
+    assert_eq!(package.resources()?.len(), spec.resources.len());
+
+    for (resource, (rrid, info)) in spec.resources.iter().zip(package.resources()?) {
+        assert_eq!(*rrid, resource.rrid);
+        assert_eq!(info.data_type(), resource.data_type);
+        assert_eq!(info.size(), resource.data.len() as u32);
+        assert_eq!(info.is_scrambled(), resource.should_scramble);
+        assert_eq!(info.system_memory_requirement(), resource.memory_requirements.0);
+        assert_eq!(info.video_memory_requirement(), resource.memory_requirements.1);
+        assert_eq!(package.read_resource(rrid)?, resource.data);
+
+        let references = info
+            .references()
+            .iter()
+            .map(|(rrid, flags)| (*rrid, flags.to_legacy()))
+            .collect::<Vec<_>>();
+        let expected = resource
+            .references
+            .iter()
+            .map(|(rrid, flags)| (*rrid, flags.to_legacy()))
+            .collect::<Vec<_>>();
+        assert_eq!(references, expected);
+        if !spec.legacy_references {
+            assert_eq!(info.references(), &resource.references);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_build_then_parse() -> Result<(), Box<dyn std::error::Error>> {
+    for seed in 1..=200u64 {
+        let spec = random_package(&mut Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15)));
+        let package = ResourcePackage::from_memory(build(&spec)?, spec.patch_id.is_patch())?;
+        check_matches_spec(&spec, &package).map_err(|e| format!("seed {seed}: {e}"))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_then_rebuild() -> Result<(), Box<dyn std::error::Error>> {
+    for seed in 1..=200u64 {
+        let spec = random_package(&mut Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15)));
+        let data = build(&spec)?;
+        let package = ResourcePackage::from_memory(data.clone(), spec.patch_id.is_patch())?;
+
+        let mut builder = PackageBuilder::from_resource_package(&package)?;
+        builder.with_patch_id(&spec.patch_id);
+        if package.has_legacy_references()? {
+            builder.use_legacy_references();
+        }
+        let rebuilt = ResourcePackage::from_memory(builder.build_to_vec(spec.version)?, spec.patch_id.is_patch())?;
+
+        assert_eq!(
+            rebuilt.resources()?.values().collect::<Vec<_>>(),
+            package.resources()?.values().collect::<Vec<_>>(),
+            "seed {seed}"
+        );
+        check_matches_spec(&spec, &rebuilt).map_err(|e| format!("seed {seed}: {e}"))?;
+    }
+    Ok(())
+}
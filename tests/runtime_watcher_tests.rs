@@ -0,0 +1,114 @@
+#![cfg(all(feature = "watch", target_os = "linux"))]
+
+mod common;
+
+use common::{rrid, write_package};
+use rpkg_rs::encryption::xtea::Xtea;
+use rpkg_rs::resource::partition_manager::PartitionManager;
+use rpkg_rs::resource::pdefs::PartitionId;
+use rpkg_rs::resource::resource_partition::{PatchId, RemountChanges};
+use rpkg_rs::resource::runtime_watcher::{FileEvent, RuntimeWatcher, WatchEvent};
+use rpkg_rs::WoaVersion;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+const THUMBS: &str = "[application]\nPROJECT_PATH=Game\\\nRUNTIME_PATH=Runtime\n";
+
+const PACKAGE_DEFINITION: &str = "\
+@partition name=base parent=none type=standard patchlevel=10
+@partition name=boot parent=base type=standard patchlevel=10
+";
+
+const TIMEOUT: Option<Duration> = Some(Duration::from_secs(5));
+
+fn write_package_definition(runtime_path: &Path, package_definition: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let data = Xtea::encrypt_bond_text_file(package_definition.to_string())?;
+    fs::write(runtime_path.join("packagedefinition.txt"), data)?;
+    Ok(())
+}
+
+fn create_game(retail_path: &Path) -> Result<PartitionManager, Box<dyn std::error::Error>> {
+    let runtime_path = retail_path.join("Game").join("Runtime");
+    fs::create_dir_all(&runtime_path)?;
+    fs::write(retail_path.join("thumbs.dat"), Xtea::encrypt_bond_text_file(THUMBS.to_string())?)?;
+    write_package_definition(&runtime_path, PACKAGE_DEFINITION)?;
+    write_package(&runtime_path, "chunk0", PatchId::Base, &[(1, vec![1; 16]), (2, vec![2; 16])], &[])?;
+    write_package(&runtime_path, "chunk1", PatchId::Base, &[(3, vec![3; 16])], &[])?;
+    Ok(PartitionManager::from_game(retail_path.to_path_buf(), WoaVersion::Bond, true)?)
+}
+
+#[test]
+fn test_watch_packages() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let mut partition_manager = create_game(temp_dir.path())?;
+    let runtime_path = partition_manager.runtime_directory().to_path_buf();
+    let mut watcher = RuntimeWatcher::from_partition_manager(&partition_manager)?;
+    let chunk0 = PartitionId::from_str("chunk0")?;
+    let chunk1 = PartitionId::from_str("chunk1")?;
+
+    assert!(watcher.read_events(Some(Duration::ZERO))?.is_empty());
+
+    write_package(&runtime_path, "chunk0", PatchId::Patch(1), &[(1, vec![4; 32])], &[])?;
+    fs::write(runtime_path.join("notes.txt"), "not a package")?;
+    assert_eq!(
+        watcher.read_events(TIMEOUT)?,
+        vec![FileEvent::Package {
+            partition_id: chunk0.clone(),
+            patch_id: PatchId::Patch(1),
+        }]
+    );
+    assert_eq!(partition_manager.read_resource_from(chunk0.clone(), rrid(1))?, vec![1; 16]);
+
+    write_package(&runtime_path, "chunk0", PatchId::Patch(1), &[(1, vec![5; 32])], &[])?;
+    let events = watcher.update(&mut partition_manager, TIMEOUT)?;
+    assert!(matches!(
+        events.as_slice(),
+        [WatchEvent::PartitionChanged { partition_id, changes }]
+            if *partition_id == chunk0 && changes.mounted == vec![PatchId::Patch(1)]
+    ));
+    assert_eq!(partition_manager.read_resource_from(chunk0.clone(), rrid(1))?, vec![5; 32]);
+
+    fs::remove_file(runtime_path.join("chunk1.rpkg"))?;
+    let events = watcher.update(&mut partition_manager, TIMEOUT)?;
+    assert!(matches!(
+        events.as_slice(),
+        [WatchEvent::PartitionChanged { partition_id, changes }]
+            if *partition_id == chunk1 && *changes == RemountChanges { unmounted: vec![PatchId::Base], ..Default::default() }
+    ));
+    assert!(!partition_manager.resource_mounted(&rrid(3)));
+
+    fs::write(runtime_path.join("chunk0patch2.rpkg"), b"not a package")?;
+    let events = watcher.update(&mut partition_manager, TIMEOUT)?;
+    assert!(matches!(events.as_slice(), [WatchEvent::RemountFailed { partition_id, .. }] if *partition_id == chunk0));
+    assert_eq!(partition_manager.read_resource_from(chunk0, rrid(1))?, vec![5; 32]);
+    Ok(())
+}
+
+#[test]
+fn test_watch_package_definition() -> Result<(), Box<dyn std::error::Error>> {
+    let temp_dir = tempfile::tempdir()?;
+    let mut partition_manager = create_game(temp_dir.path())?;
+    let runtime_path = partition_manager.runtime_directory().to_path_buf();
+    let mut watcher = RuntimeWatcher::from_partition_manager(&partition_manager)?;
+    let chunk2 = PartitionId::from_str("chunk2")?;
+
+    write_package(&runtime_path, "chunk2", PatchId::Base, &[(6, vec![6; 16])], &[])?;
+    write_package_definition(
+        &runtime_path,
+        &format!("{PACKAGE_DEFINITION}@partition name=extra parent=boot type=standard patchlevel=10\n"),
+    )?;
+
+    let events = watcher.update(&mut partition_manager, TIMEOUT)?;
+    assert!(matches!(
+        events.as_slice(),
+        [
+            WatchEvent::PackageDefinitionChanged { reloaded: true },
+            WatchEvent::PartitionChanged { partition_id, changes },
+        ] if *partition_id == chunk2 && changes.mounted == vec![PatchId::Base]
+    ));
+    assert_eq!(partition_manager.partitions.len(), 3);
+    assert_eq!(partition_manager.read_resource_from(chunk2, rrid(6))?, vec![6; 16]);
+    Ok(())
+}
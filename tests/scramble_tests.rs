@@ -0,0 +1,66 @@
+use rpkg_rs::encryption::scramble::{self, ScrambleReader, ScrambleWriter, Scrambler, SCRAMBLE_KEY};
+use std::io::{Read, Write};
+
+fn naive_scramble(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(index, byte)| byte ^ SCRAMBLE_KEY[index % SCRAMBLE_KEY.len()])
+        .collect()
+}
+
+fn test_data() -> Vec<u8> {
+    (0..1000u32).map(|i| (i * 31 + 7) as u8).collect()
+}
+
+#[test]
+fn test_scramble_in_parts() -> Result<(), Box<dyn std::error::Error>> {
+    let data = test_data();
+    let expected = naive_scramble(&data);
+
+    let mut scrambled = data.clone();
+    scramble::scramble(&mut scrambled);
+    assert_eq!(scrambled, expected);
+
+    for part_size in [1, 3, 7, 8, 9, 100] {
+        let mut scrambled = data.clone();
+        let mut scrambler = Scrambler::new();
+        for part in scrambled.chunks_mut(part_size) {
+            scrambler.apply(part);
+        }
+        assert_eq!(scrambled, expected);
+        assert_eq!(scrambler.phase(), data.len() % SCRAMBLE_KEY.len());
+    }
+
+    let mut tail = data[13..].to_vec();
+    Scrambler::at_offset(13).apply(&mut tail);
+    assert_eq!(tail, expected[13..]);
+
+    scramble::descramble(&mut scrambled);
+    assert_eq!(scrambled, data);
+    Ok(())
+}
+
+#[test]
+fn test_scramble_writer_and_reader() -> Result<(), Box<dyn std::error::Error>> {
+    let data = test_data();
+
+    let mut writer = ScrambleWriter::new(vec![]);
+    for part in data.chunks(5) {
+        writer.write_all(part)?;
+    }
+    let scrambled = writer.into_inner();
+    assert_eq!(scrambled, naive_scramble(&data));
+
+    let mut reader = ScrambleReader::new(scrambled.as_slice());
+    let mut descrambled = vec![];
+    let mut buffer = [0; 13];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        descrambled.extend_from_slice(&buffer[..read]);
+    }
+    assert_eq!(descrambled, data);
+    Ok(())
+}
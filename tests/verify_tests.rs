@@ -0,0 +1,132 @@
+mod common;
+
+use common::{build_resources, build_test_package, rrid, storage_variants};
+use rpkg_rs::resource::package_verification::PackageIssue;
+use rpkg_rs::resource::resource_package::{PackageVersion, ResourcePackage};
+use rpkg_rs::resource::resource_partition::PatchId;
+
+/// The offset table of a v2 base package starts after the magic, the metadata and the header.
+const OFFSET_TABLE_START: usize = 4 + 9 + 12;
+
+fn verify(data: Vec<u8>) -> Result<Vec<PackageIssue>, Box<dyn std::error::Error>> {
+    Ok(ResourcePackage::from_memory(data, false)?.verify()?)
+}
+
+fn data_offset(data: &[u8], index: usize) -> u64 {
+    let start = OFFSET_TABLE_START + index * 20 + 8;
+    u64::from_le_bytes(data[start..start + 8].try_into().unwrap())
+}
+
+fn set_data_offset(data: &mut [u8], index: usize, offset: u64) {
+    let start = OFFSET_TABLE_START + index * 20 + 8;
+    data[start..start + 8].copy_from_slice(&offset.to_le_bytes());
+}
+
+#[test]
+fn test_valid_packages() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(verify(build_test_package(PackageVersion::RPKGv1)?)?, vec![]);
+    assert_eq!(verify(build_test_package(PackageVersion::RPKGv2)?)?, vec![]);
+
+    let data = build_resources(PackageVersion::RPKGv2, PatchId::Patch(1), storage_variants(4096)?, &[10, 11])?;
+    let package = ResourcePackage::from_memory(data, true)?;
+    assert!(package.is_patch());
+    assert_eq!(package.verify()?, vec![]);
+
+    Ok(())
+}
+
+#[test]
+fn test_truncated_package() -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = build_test_package(PackageVersion::RPKGv2)?;
+    data.truncate(data.len() - 16);
+
+    let issues = verify(data)?;
+    assert_eq!(issues.len(), 1);
+    assert!(matches!(issues[0], PackageIssue::ResourceOutOfBounds { rrid: id, .. } if id == rrid(4)));
+
+    Ok(())
+}
+
+#[test]
+fn test_overlapping_resources() -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = build_test_package(PackageVersion::RPKGv2)?;
+    let offset = data_offset(&data, 0);
+    set_data_offset(&mut data, 2, offset + 8);
+
+    let issues = verify(data)?;
+    assert!(issues.contains(&PackageIssue::OverlappingResources { first: rrid(1), second: rrid(3) }));
+
+    // The third resource overlaps the first one, but not the small one starting between them.
+    let mut data = build_test_package(PackageVersion::RPKGv2)?;
+    let offset = data_offset(&data, 0);
+    set_data_offset(&mut data, 1, offset + 8);
+    set_data_offset(&mut data, 3, offset + 2048);
+    let issues = verify(data)?;
+    assert!(issues.contains(&PackageIssue::OverlappingResources { first: rrid(1), second: rrid(2) }));
+    assert!(issues.contains(&PackageIssue::OverlappingResources { first: rrid(1), second: rrid(4) }));
+    assert!(!issues.contains(&PackageIssue::OverlappingResources { first: rrid(2), second: rrid(4) }));
+
+    let mut data = build_test_package(PackageVersion::RPKGv2)?;
+    set_data_offset(&mut data, 0, 0);
+    let issues = verify(data)?;
+    assert!(issues
+        .iter()
+        .any(|issue| matches!(issue, PackageIssue::ResourceInsideTables { rrid: id, offset: 0, .. } if *id == rrid(1))));
+
+    Ok(())
+}
+
+#[test]
+fn test_corrupted_compressed_data() -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = build_test_package(PackageVersion::RPKGv2)?;
+    let offset = data_offset(&data, 1) as usize;
+    data[offset..offset + 32].fill(0xFF);
+
+    let issues = verify(data)?;
+    assert_eq!(issues.len(), 1);
+    assert!(matches!(
+        &issues[0],
+        PackageIssue::DecompressionFailed { rrid: id, .. } | PackageIssue::DecompressedSizeMismatch { rrid: id, .. }
+            if *id == rrid(2)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_table_size_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = build_test_package(PackageVersion::RPKGv2)?;
+    // The offset table size follows the file count inside the header.
+    data[4 + 9 + 4..4 + 9 + 8].copy_from_slice(&100u32.to_le_bytes());
+
+    let issues = verify(data)?;
+    assert!(issues.contains(&PackageIssue::OffsetTableSizeMismatch { declared: 100, computed: 80 }));
+
+    let mut data = build_test_package(PackageVersion::RPKGv2)?;
+    data[4 + 9 + 8..4 + 9 + 12].copy_from_slice(&1u32.to_le_bytes());
+
+    let issues = verify(data)?;
+    assert!(issues
+        .iter()
+        .any(|issue| matches!(issue, PackageIssue::MetadataTableSizeMismatch { declared: 1, .. })));
+
+    Ok(())
+}
+
+#[test]
+fn test_unreadable_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = build_test_package(PackageVersion::RPKGv2)?;
+    // The references chunk size of the first metadata entry moves all entries after it past the end of the package.
+    let metadata_table = OFFSET_TABLE_START + 4 * 20;
+    data[metadata_table + 4..metadata_table + 8].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+    set_data_offset(&mut data, 1, 0);
+    assert!(ResourcePackage::from_memory(data.clone(), false).is_err());
+
+    let issues = ResourcePackage::from_memory_lazy(data, false)?.verify()?;
+    assert!(matches!(issues[0], PackageIssue::UnreadableMetadata { .. }));
+    assert!(issues
+        .iter()
+        .any(|issue| matches!(issue, PackageIssue::ResourceInsideTables { rrid: id, offset: 0, .. } if *id == rrid(2))));
+
+    Ok(())
+}